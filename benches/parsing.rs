@@ -0,0 +1,159 @@
+// Parser and iterator benchmarks over generated output, run with `cargo bench [-- <filter>]`, e.g.
+// `cargo bench -- describe`. Throughput is the bytes of p4 output read.
+
+// == Std crates
+use std::hint::black_box;
+
+// == Internal crates
+use p4_helper::{
+    P4File,
+    changes::P4ChangesIterator,
+    describe::P4DescribeIterator,
+    dict::P4DictIterator,
+    error::P4Error,
+    parsers::{py_dict::*, ztag::*},
+};
+
+// == External crates
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const CHANGES: usize = 50_000;
+const DESCRIBE_FILES: usize = 200_000;
+
+fn bench_parsers(c: &mut Criterion) {
+    let changes_py_dict = changes_py_dict(CHANGES);
+    let changes_ztag = changes_ztag(CHANGES);
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(changes_py_dict.len() as u64));
+    group.bench_function("py_dict/changes", |b| {
+        b.iter(|| {
+            let mut parser = P4PyDictParser::new(&changes_py_dict[..]);
+            while let Some(kvp) = parser.get_next_kvp().unwrap() {
+                black_box(kvp);
+            }
+        })
+    });
+    group.throughput(Throughput::Bytes(changes_ztag.len() as u64));
+    group.bench_function("ztag/changes", |b| {
+        b.iter(|| {
+            let mut parser = P4ZtagParser::new(changes_ztag.as_bytes(), Some("change"));
+            while let Some(kvp) = parser.get_next_kvp().unwrap() {
+                black_box(kvp);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_iterators(c: &mut Criterion) {
+    let changes_py_dict = changes_py_dict(CHANGES);
+    let describe_py_dict = describe_py_dict(DESCRIBE_FILES);
+
+    let mut group = c.benchmark_group("iter");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(changes_py_dict.len() as u64));
+    group.bench_function("dict/changes", |b| {
+        b.iter(|| drain(P4DictIterator::new_from_reader(&changes_py_dict[..])))
+    });
+    group.bench_function("changes", |b| {
+        b.iter(|| drain(P4ChangesIterator::new_from_reader(&changes_py_dict[..])))
+    });
+    group.throughput(Throughput::Bytes(describe_py_dict.len() as u64));
+    group.bench_function("describe", |b| {
+        b.iter(|| drain(P4DescribeIterator::new_from_reader(&describe_py_dict[..]).unwrap()))
+    });
+    group.bench_function("describe_next_into", |b| {
+        b.iter(|| {
+            let mut describe = P4DescribeIterator::new_from_reader(&describe_py_dict[..]).unwrap();
+            let mut file = P4File::default();
+            while let Some(result) = describe.next_into(&mut file) {
+                result.unwrap();
+                black_box(&file);
+            }
+        })
+    });
+    group.finish();
+}
+
+// Reads every item, failing the bench on an error
+fn drain<T>(iterator: impl Iterator<Item = Result<T, P4Error>>) {
+    for item in iterator {
+        black_box(item.unwrap());
+    }
+}
+
+fn change_fields(change: usize) -> Vec<(String, String)> {
+    [
+        ("code", "stat".to_string()),
+        ("change", change.to_string()),
+        ("time", (1_700_000_000 + change).to_string()),
+        ("user", format!("user{}", change % 50)),
+        ("client", format!("user{}-ws", change % 50)),
+        ("status", "submitted".to_string()),
+        ("changeType", "public".to_string()),
+        (
+            "desc",
+            format!(
+                "Fix the build on every platform JIRA-{}\n\nMore details\n",
+                change
+            ),
+        ),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value))
+    .collect()
+}
+
+fn changes_py_dict(changes: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for change in (1..=changes).rev() {
+        write_py_dict(&mut data, change_fields(change)).unwrap();
+    }
+    data
+}
+
+fn changes_ztag(changes: usize) -> String {
+    let mut data = String::new();
+    for change in (1..=changes).rev() {
+        // ztag puts the description last, followed by a blank line
+        for (key, value) in change_fields(change).into_iter().skip(1) {
+            data.push_str(&format!("... {} {}\n", key, value.trim_end()));
+        }
+        data.push('\n');
+    }
+    data
+}
+
+fn describe_py_dict(files: usize) -> Vec<u8> {
+    let mut record = change_fields(12345);
+    for index in 0..files {
+        record.extend([
+            (
+                format!("depotFile{}", index),
+                format!(
+                    "//depot/main/Engine/Source/Module{}/File{}.cpp",
+                    index / 100,
+                    index
+                ),
+            ),
+            (format!("action{}", index), "add".to_string()),
+            (format!("type{}", index), "text".to_string()),
+            (format!("rev{}", index), "1".to_string()),
+            (
+                format!("fileSize{}", index),
+                (index * 7 % 100_000).to_string(),
+            ),
+            (
+                format!("digest{}", index),
+                "A9E93320E1FC469228D707C9124C878C".to_string(),
+            ),
+        ]);
+    }
+    let mut data = Vec::new();
+    write_py_dict(&mut data, record).unwrap();
+    data
+}
+
+criterion_group!(benches, bench_parsers, bench_iterators);
+criterion_main!(benches);
@@ -0,0 +1,87 @@
+/* C interface to p4_helper, built with `cargo build --features ffi`.
+ * Regenerate with `cbindgen --config cbindgen.toml --output include/p4_helper.h`. */
+
+#ifndef P4_HELPER_H
+#define P4_HELPER_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct P4hChangesIterator P4hChangesIterator;
+
+typedef struct P4hDescribeIterator P4hDescribeIterator;
+
+typedef struct P4hChangelist {
+  uint32_t changelist;
+  uint32_t time;
+  const char *user;
+  const char *description;
+} P4hChangelist;
+
+typedef struct P4hFile {
+  const char *depot_path;
+  const char *action;
+  uint32_t revision;
+  uint64_t file_size;
+  uint8_t digest[16];
+} P4hFile;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+const char *p4h_last_error(void);
+
+struct P4hChangesIterator *p4h_changes_open(uint32_t from, uint32_t to);
+
+/**
+ * # Safety
+ * `path` must be a valid, nul terminated string.
+ */
+struct P4hChangesIterator *p4h_changes_open_file(const char *path);
+
+/**
+ * # Safety
+ * `iter` must come from one of the p4h_changes_open functions and `out` must point to writable memory.
+ */
+int32_t p4h_changes_next(struct P4hChangesIterator *iter, struct P4hChangelist *out);
+
+/**
+ * # Safety
+ * `iter` must come from one of the p4h_changes_open functions (or be null) and not be used afterwards.
+ */
+void p4h_changes_free(struct P4hChangesIterator *iter);
+
+struct P4hDescribeIterator *p4h_describe_open(uint32_t changelist);
+
+/**
+ * # Safety
+ * `path` must be a valid, nul terminated string.
+ */
+struct P4hDescribeIterator *p4h_describe_open_file(const char *path);
+
+/**
+ * # Safety
+ * `iter` must come from one of the p4h_describe_open functions and `out` must point to writable memory.
+ */
+int32_t p4h_describe_changelist(struct P4hDescribeIterator *iter, struct P4hChangelist *out);
+
+/**
+ * # Safety
+ * `iter` must come from one of the p4h_describe_open functions and `out` must point to writable memory.
+ */
+int32_t p4h_describe_next(struct P4hDescribeIterator *iter, struct P4hFile *out);
+
+/**
+ * # Safety
+ * `iter` must come from one of the p4h_describe_open functions (or be null) and not be used afterwards.
+ */
+void p4h_describe_free(struct P4hDescribeIterator *iter);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* P4_HELPER_H */
@@ -0,0 +1,372 @@
+// == Std crates
+use std::{collections::BTreeMap, io};
+
+// == Internal crates
+use crate::cancel::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+use crate::metrics::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::parsers::decode::*;
+use crate::parsers::py_dict::*;
+use crate::process_state::*;
+use crate::records::*;
+use crate::*;
+
+// A line of a file as reported by `p4 annotate -c -u`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4AnnotatedLine {
+    pub depot_path: String,
+    // The changelist that added the line
+    pub first_changelist: u32,
+    // The last changelist the line is in, the file's newest for the head revision
+    pub last_changelist: u32,
+    // Who submitted first_changelist
+    pub user: String,
+    // Including the line ending
+    pub text: String,
+}
+
+// The options of a `p4 annotate` command, always with -c -u so lines are attributed to changelists and users
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4AnnotateQuery {
+    filespecs: Vec<String>,
+    follow_integrations: bool,
+    ignore_whitespace: bool,
+}
+
+impl P4AnnotateQuery {
+    pub fn new(filespec: &str) -> Self {
+        P4AnnotateQuery {
+            filespecs: vec![filespec.to_string()],
+            ..Default::default()
+        }
+    }
+
+    pub fn with_filespec(mut self, filespec: &str) -> Self {
+        self.filespecs.push(filespec.to_string());
+        self
+    }
+
+    // -I, lines merged in are credited to the changelists they were originally made in
+    pub fn with_follow_integrations(mut self) -> Self {
+        self.follow_integrations = true;
+        self
+    }
+
+    // -dw, whitespace only changes don't take over a line
+    pub fn with_ignore_whitespace(mut self) -> Self {
+        self.ignore_whitespace = true;
+        self
+    }
+
+    #[cfg(any(feature = "process", test))]
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["annotate".to_string(), "-c".to_string(), "-u".to_string()];
+        if self.follow_integrations {
+            args.push("-I".to_string());
+        }
+        if self.ignore_whitespace {
+            args.push("-dw".to_string());
+        }
+        args.extend(self.filespecs.iter().cloned());
+        args
+    }
+}
+
+impl From<&str> for P4AnnotateQuery {
+    fn from(filespec: &str) -> Self {
+        P4AnnotateQuery::new(filespec)
+    }
+}
+
+// Lines owned per user and per changelist, e.g. for a code ownership dashboard
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4LineOwnership {
+    pub files: u64,
+    pub lines: u64,
+    pub lines_by_user: BTreeMap<String, u64>,
+    pub lines_by_changelist: BTreeMap<u32, u64>,
+}
+
+impl P4LineOwnership {
+    pub fn add_line(&mut self, line: &P4AnnotatedLine) {
+        self.lines += 1;
+        *self.lines_by_user.entry(line.user.clone()).or_default() += 1;
+        *self
+            .lines_by_changelist
+            .entry(line.first_changelist)
+            .or_default() += 1;
+    }
+
+    // Users with the most lines first
+    pub fn top_users(&self) -> Vec<(&str, u64)> {
+        let mut users = self
+            .lines_by_user
+            .iter()
+            .map(|(user, lines)| (user.as_str(), *lines))
+            .collect::<Vec<_>>();
+        users.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        users
+    }
+
+    // Adds up the lines of an annotate, counting a file for each depot path
+    pub fn from_lines<ErrorT>(
+        lines: impl IntoIterator<Item = Result<P4AnnotatedLine, ErrorT>>,
+    ) -> Result<Self, ErrorT> {
+        let mut ownership = P4LineOwnership::default();
+        let mut previous_path = None;
+        for line in lines {
+            let line = line?;
+            if previous_path.as_ref() != Some(&line.depot_path) {
+                ownership.files += 1;
+                previous_path = Some(line.depot_path.clone());
+            }
+            ownership.add_line(&line);
+        }
+        Ok(ownership)
+    }
+}
+
+// Annotates the files, e.g. //depot/main/src/..., and counts who owns their lines. Binary files aren't annotated.
+#[cfg(feature = "spawn")]
+pub fn aggregate(filespec: &str) -> Result<P4LineOwnership, P4Error> {
+    aggregate_from_context(&P4Context::default(), filespec)
+}
+
+#[cfg(feature = "process")]
+pub fn aggregate_from_context(
+    context: &P4Context,
+    query: impl Into<P4AnnotateQuery>,
+) -> Result<P4LineOwnership, P4Error> {
+    P4LineOwnership::from_lines(P4AnnotateIterator::new_from_context(context, query)?)
+}
+
+pub struct P4AnnotateIterator<ReadT: io::Read> {
+    process_state: P4ProcessState,
+    records: P4RecordReader<ReadT, InterimP4AnnotateRecord>,
+    // From the header record before each file's lines
+    depot_path: String,
+}
+
+#[cfg(feature = "process")]
+impl P4AnnotateIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(query: impl Into<P4AnnotateQuery>) -> Result<P4AnnotateIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), query)
+    }
+
+    // `query` is a P4AnnotateQuery, or just a filespec
+    pub fn new_from_context(
+        context: &P4Context,
+        query: impl Into<P4AnnotateQuery>,
+    ) -> Result<P4AnnotateIterator<P4Output>, P4Error> {
+        let query_args = query.into().args();
+        let args = query_args.iter().map(String::as_str).collect::<Vec<_>>();
+        let (p4_process, reader) = context.spawn(args.clone())?;
+
+        // One line in another encoding shouldn't lose the whole file
+        let parser = context
+            .parser(reader)
+            .with_decoding(context.string_decoding().with_lossy_fallback());
+        let mut result = P4AnnotateIterator::new_from_parser(parser);
+        result.process_state.attach(p4_process, context, &args);
+
+        Ok(result)
+    }
+}
+
+impl<ReadT: io::Read> P4AnnotateIterator<ReadT> {
+    pub fn new_from_reader(reader: ReadT) -> P4AnnotateIterator<ReadT> {
+        Self::new_from_parser(
+            P4PyDictParser::new(reader)
+                .with_decoding(P4StringDecoding::new().with_lossy_fallback()),
+        )
+    }
+
+    pub fn new_from_parser(parser: P4PyDictParser<ReadT>) -> P4AnnotateIterator<ReadT> {
+        P4AnnotateIterator {
+            process_state: P4ProcessState::default(),
+            records: P4RecordReader::new(parser),
+            depot_path: String::new(),
+        }
+    }
+
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.process_state.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn records_yielded(&self) -> u64 {
+        self.process_state.records_yielded()
+    }
+
+    // Totals for the command, None until the iterator is exhausted
+    pub fn summary(&self) -> Option<P4IteratorSummary> {
+        self.process_state.summary(self.records.records_skipped())
+    }
+
+    fn next_line(&mut self) -> Result<Option<P4AnnotatedLine>, P4Error> {
+        while let Some(record) = self.records.next_output()? {
+            match record {
+                P4AnnotateRecord::File(depot_path) => self.depot_path = depot_path,
+                P4AnnotateRecord::Line(mut line) => {
+                    line.depot_path.clone_from(&self.depot_path);
+                    return Ok(Some(line));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<ReadT: io::Read> Iterator for P4AnnotateIterator<ReadT> {
+    type Item = Result<P4AnnotatedLine, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(result) = self.process_state.before_next() {
+            return result;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = self.process_state.span().entered();
+
+        let result = self.next_line();
+        let bytes_read = self.records.bytes_read();
+        self.process_state.after_next(result, bytes_read)
+    }
+}
+
+// annotate gives a header record for each file followed by a record for each of its lines
+enum P4AnnotateRecord {
+    File(String),
+    Line(P4AnnotatedLine),
+}
+
+#[derive(Debug, Default)]
+struct InterimP4AnnotateRecord {
+    depot_path: Option<String>,
+    lower: Option<u32>,
+    upper: Option<u32>,
+    user: Option<String>,
+    data: Option<String>,
+}
+
+impl P4RecordFields for InterimP4AnnotateRecord {
+    type Output = P4AnnotateRecord;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "depotFile" => self.depot_path = Some(value.to_string()),
+            "lower" => self.lower = Some(parse_field(value, "Invalid lower changelist")?),
+            "upper" => self.upper = Some(parse_field(value, "Invalid upper changelist")?),
+            "user" => self.user = Some(value.to_string()),
+            "data" => self.data = Some(value.to_string()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4AnnotateRecord, P4Error> {
+        if let Some(depot_path) = self.depot_path {
+            return Ok(P4AnnotateRecord::File(depot_path));
+        }
+        let first_changelist = self.lower.ok_or(P4Error::InvalidRecord("Missing lower"))?;
+        Ok(P4AnnotateRecord::Line(P4AnnotatedLine {
+            depot_path: String::new(),
+            first_changelist,
+            last_changelist: self.upper.unwrap_or(first_changelist),
+            user: self.user.unwrap_or_default(),
+            text: self.data.ok_or(P4Error::InvalidRecord("Missing data"))?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_aggregate() {
+        assert_eq!(
+            P4AnnotateQuery::new("//depot/main/...")
+                .with_follow_integrations()
+                .args(),
+            ["annotate", "-c", "-u", "-I", "//depot/main/..."]
+        );
+
+        let line = |lower: &'static str, user: &'static str, data: &'static str| {
+            [
+                ("code", "stat"),
+                ("lower", lower),
+                ("upper", "12"),
+                ("user", user),
+                ("data", data),
+            ]
+            .to_vec()
+        };
+        let file = |depot_path: &'static str| {
+            [
+                ("code", "stat"),
+                ("depotFile", depot_path),
+                ("rev", "3"),
+                ("change", "12"),
+            ]
+            .to_vec()
+        };
+        let data = to_py_dict_bytes(&[
+            &file("//depot/main/a.rs"),
+            &line("10", "alice", "fn main() {\n"),
+            &line("12", "bob", "    run();\n"),
+            &line("10", "alice", "}\n"),
+            &file("//depot/main/b.rs"),
+            &line("11", "bob", "fn run() {}\n"),
+        ]);
+
+        let lines = P4AnnotateIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[3].depot_path, "//depot/main/b.rs");
+        assert_eq!(lines[1].text, "    run();\n");
+
+        let ownership =
+            P4LineOwnership::from_lines(lines.into_iter().map(Ok::<_, P4Error>)).unwrap();
+        assert_eq!((ownership.files, ownership.lines), (2, 4));
+        assert_eq!(ownership.top_users(), [("alice", 2), ("bob", 2)]);
+        assert_eq!(
+            ownership.lines_by_changelist,
+            BTreeMap::from([(10, 2), (11, 1), (12, 1)])
+        );
+    }
+
+    #[test]
+    fn test_non_utf8_line() {
+        let mut data = to_py_dict_bytes(&[
+            &[("code", "stat"), ("depotFile", "//depot/main/a.txt")],
+            &[
+                ("code", "stat"),
+                ("lower", "10"),
+                ("user", "alice"),
+                ("data", "cafe\n"),
+            ],
+            &[
+                ("code", "stat"),
+                ("lower", "11"),
+                ("user", "bob"),
+                ("data", "tea\n"),
+            ],
+        ]);
+        let at = data.windows(5).position(|w| w == b"cafe\n").unwrap();
+        data[at + 3] = 0xe9;
+
+        let lines = P4AnnotateIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(lines[0].text, "caf\u{fffd}\n");
+        assert_eq!(lines[1].text, "tea\n");
+    }
+}
@@ -0,0 +1,220 @@
+// == Std crates
+use std::collections::BTreeMap;
+
+// == Internal crates
+use crate::*;
+
+// The annotations the default scanner fills in
+pub const ISSUES_ANNOTATION: &str = "issues";
+pub const REVIEWS_ANNOTATION: &str = "reviews";
+pub const TAGS_ANNOTATION: &str = "tags";
+
+// Annotation name to values, in the order they appear in the description without duplicates
+pub type P4Annotations = BTreeMap<String, Vec<String>>;
+
+// Pulls metadata out of changelist descriptions: issue keys (JIRA-1234), numbered markers such as
+// Swarm's #review-12345, and [tag] markers
+#[derive(Debug, Clone)]
+pub struct DescriptionScanner {
+    issue_keys: bool,
+    // Only the keys of these projects, e.g. JIRA, or any project if empty
+    issue_projects: Vec<String>,
+    // (annotation, prefix) pairs, where the prefix is followed by a number
+    numbered_markers: Vec<(String, String)>,
+    tags: bool,
+}
+
+impl Default for DescriptionScanner {
+    fn default() -> Self {
+        DescriptionScanner {
+            issue_keys: true,
+            issue_projects: Vec::new(),
+            numbered_markers: vec![(REVIEWS_ANNOTATION.to_string(), "#review-".to_string())],
+            tags: true,
+        }
+    }
+}
+
+impl DescriptionScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A scanner that extracts nothing, to add only the rules wanted
+    pub fn empty() -> Self {
+        DescriptionScanner {
+            issue_keys: false,
+            issue_projects: Vec::new(),
+            numbered_markers: Vec::new(),
+            tags: false,
+        }
+    }
+
+    pub fn with_issue_keys(mut self, issue_keys: bool) -> Self {
+        self.issue_keys = issue_keys;
+        self
+    }
+
+    // Restricting the projects avoids picking up other KEY-123 words, such as part or model numbers
+    pub fn with_issue_project(mut self, project: &str) -> Self {
+        self.issue_keys = true;
+        self.issue_projects.push(project.to_string());
+        self
+    }
+
+    // e.g. ("reviews", "#review-") or ("bugs", "BUG=")
+    pub fn with_numbered_marker(mut self, annotation: &str, prefix: &str) -> Self {
+        self.numbered_markers
+            .push((annotation.to_string(), prefix.to_string()));
+        self
+    }
+
+    pub fn with_tags(mut self, tags: bool) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn scan(&self, description: &str) -> P4Annotations {
+        let mut annotations = P4Annotations::new();
+        if self.issue_keys {
+            for key in issue_keys(description) {
+                let project = &key[..key.find('-').unwrap_or(key.len())];
+                if self.issue_projects.is_empty()
+                    || self.issue_projects.iter().any(|allowed| allowed == project)
+                {
+                    add(&mut annotations, ISSUES_ANNOTATION, key);
+                }
+            }
+        }
+        for (annotation, prefix) in &self.numbered_markers {
+            for number in numbered_markers(description, prefix) {
+                add(&mut annotations, annotation, number);
+            }
+        }
+        if self.tags {
+            for tag in tags(description) {
+                add(&mut annotations, TAGS_ANNOTATION, tag);
+            }
+        }
+        annotations
+    }
+
+    pub fn annotate(&self, changelist: &mut P4Changelist) {
+        changelist.annotations = self.scan(&changelist.description);
+    }
+}
+
+fn add(annotations: &mut P4Annotations, annotation: &str, value: &str) {
+    let values = annotations.entry(annotation.to_string()).or_default();
+    if !values.iter().any(|existing| existing == value) {
+        values.push(value.to_string());
+    }
+}
+
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+// Names of encodings and hashes that look like issue keys, e.g. UTF-8 and SHA-256
+const NOT_ISSUE_PROJECTS: [&str; 5] = ["CP", "ISO", "SHA", "UCS", "UTF"];
+
+// PROJECT-123, where the project is two or more upper case letters
+fn issue_keys(text: &str) -> impl Iterator<Item = &str> {
+    let bytes = text.as_bytes();
+    (0..bytes.len()).filter_map(move |start| {
+        if !bytes[start].is_ascii_uppercase() || (start > 0 && is_word_byte(bytes[start - 1])) {
+            return None;
+        }
+        let project_len = bytes[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_uppercase())
+            .count();
+        let dash = start + project_len;
+        if project_len < 2
+            || bytes.get(dash) != Some(&b'-')
+            || NOT_ISSUE_PROJECTS.contains(&&text[start..dash])
+        {
+            return None;
+        }
+        let digits = bytes[dash + 1..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        let end = dash + 1 + digits;
+        if digits == 0 || bytes.get(end).is_some_and(|&b| is_word_byte(b)) {
+            return None;
+        }
+        Some(&text[start..end])
+    })
+}
+
+fn numbered_markers<'a>(text: &'a str, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+    let bytes = text.as_bytes();
+    text.match_indices(prefix).filter_map(move |(start, _)| {
+        // A prefix starting with a word character has to start a word
+        if prefix.as_bytes().first().is_some_and(|&b| is_word_byte(b))
+            && start > 0
+            && is_word_byte(bytes[start - 1])
+        {
+            return None;
+        }
+        let number_start = start + prefix.len();
+        let digits = bytes[number_start..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        (digits > 0).then(|| &text[number_start..number_start + digits])
+    })
+}
+
+// [tag], on one line and not too long to be a tag rather than bracketed prose
+fn tags(text: &str) -> impl Iterator<Item = &str> {
+    const MAX_TAG_LEN: usize = 32;
+
+    text.match_indices('[').filter_map(move |(start, _)| {
+        let rest = &text[start + 1..];
+        let end = rest.find([']', '[', '\n'])?;
+        let tag = rest[..end].trim();
+        (rest[end..].starts_with(']') && !tag.is_empty() && tag.len() <= MAX_TAG_LEN).then_some(tag)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::*;
+
+    #[test]
+    fn test_description_scanner() {
+        let description = "[hotfix][UI] Fix the login crash JIRA-1234, see also PROJ-7 and JIRA-1234\n\
+                           #review-12345 Encoded as UTF-8, not a-1, XJIRA-1x, SHA-256 or ABC2-3\n";
+
+        let annotations = DescriptionScanner::new().scan(description);
+        assert_eq!(annotations[ISSUES_ANNOTATION], ["JIRA-1234", "PROJ-7"]);
+        assert_eq!(annotations[REVIEWS_ANNOTATION], ["12345"]);
+        assert_eq!(annotations[TAGS_ANNOTATION], ["hotfix", "UI"]);
+
+        let annotations = DescriptionScanner::empty()
+            .with_issue_project("JIRA")
+            .with_numbered_marker("bugs", "BUG=")
+            .scan("JIRA-1 PROJ-2 BUG=77 [tag]");
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[ISSUES_ANNOTATION], ["JIRA-1"]);
+        assert_eq!(annotations["bugs"], ["77"]);
+
+        let data = crate::parsers::py_dict::to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("change", "12"),
+            ("time", "1743724741"),
+            ("user", "alice"),
+            ("desc", "[WIP] JIRA-1234\n"),
+        ]]);
+        let changelist = P4ChangesIterator::new_from_reader(&data[..])
+            .with_description_scanner(DescriptionScanner::new())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(changelist.annotations[ISSUES_ANNOTATION], ["JIRA-1234"]);
+        assert_eq!(changelist.annotations[TAGS_ANNOTATION], ["WIP"]);
+    }
+}
@@ -0,0 +1,398 @@
+// == Std crates
+#[cfg(not(feature = "spawn"))]
+use std::io;
+use std::{env, fmt, fs, path::PathBuf};
+#[cfg(feature = "spawn")]
+use std::{
+    io::{Read, Write},
+    process,
+};
+
+// == Internal crates
+use crate::context::*;
+use crate::dict::*;
+use crate::error::*;
+
+// What a context logs in with when its session has expired
+#[derive(Clone, PartialEq, Eq)]
+pub enum P4Credential {
+    // Given to `p4 login` on stdin
+    Password(String),
+    // Used as P4PASSWD by the commands that follow, without logging in
+    Ticket(String),
+    // The response to an SSO login, given to `p4 login` like a password
+    SsoToken(String),
+}
+
+// Secrets stay out of logs
+impl fmt::Debug for P4Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            P4Credential::Password(_) => "Password",
+            P4Credential::Ticket(_) => "Ticket",
+            P4Credential::SsoToken(_) => "SsoToken",
+        };
+        write!(f, "{}(<redacted>)", kind)
+    }
+}
+
+// Supplies credentials when a context has to log in again, e.g. from a secrets manager. Called each time, so
+// a rotated secret is picked up.
+pub trait CredentialProvider: fmt::Debug + Send + Sync {
+    fn credential(&self) -> Result<P4Credential, P4Error>;
+}
+
+// Reads the secret from an environment variable, e.g.
+// EnvCredentials::new("BUILD_P4_TICKET", P4Credential::Ticket)
+#[derive(Debug, Clone)]
+pub struct EnvCredentials {
+    variable: String,
+    kind: fn(String) -> P4Credential,
+}
+
+impl EnvCredentials {
+    pub fn new(variable: &str, kind: fn(String) -> P4Credential) -> Self {
+        EnvCredentials {
+            variable: variable.to_string(),
+            kind,
+        }
+    }
+}
+
+impl CredentialProvider for EnvCredentials {
+    fn credential(&self) -> Result<P4Credential, P4Error> {
+        let secret = env::var(&self.variable)
+            .map_err(|_| P4Error::Credentials(format!("{} is not set", self.variable)))?;
+        Ok((self.kind)(secret))
+    }
+}
+
+// Reads the secret from a file, e.g. one mounted by the secrets manager, ignoring a trailing newline
+#[derive(Debug, Clone)]
+pub struct FileCredentials {
+    path: PathBuf,
+    kind: fn(String) -> P4Credential,
+}
+
+impl FileCredentials {
+    pub fn new(path: impl Into<PathBuf>, kind: fn(String) -> P4Credential) -> Self {
+        FileCredentials {
+            path: path.into(),
+            kind,
+        }
+    }
+}
+
+impl CredentialProvider for FileCredentials {
+    fn credential(&self) -> Result<P4Credential, P4Error> {
+        let secret = fs::read_to_string(&self.path).map_err(|e| {
+            P4Error::Credentials(format!("Can't read {}: {}", self.path.display(), e))
+        })?;
+        Ok((self.kind)(
+            secret.trim_end_matches(['\n', '\r']).to_string(),
+        ))
+    }
+}
+
+// Calls back into the application, e.g. to fetch the secret from a vault client
+pub struct CallbackCredentials {
+    callback: Box<dyn Fn() -> Result<P4Credential, P4Error> + Send + Sync>,
+}
+
+impl CallbackCredentials {
+    pub fn new(
+        callback: impl Fn() -> Result<P4Credential, P4Error> + Send + Sync + 'static,
+    ) -> Self {
+        CallbackCredentials {
+            callback: Box::new(callback),
+        }
+    }
+}
+
+impl fmt::Debug for CallbackCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CallbackCredentials")
+    }
+}
+
+impl CredentialProvider for CallbackCredentials {
+    fn credential(&self) -> Result<P4Credential, P4Error> {
+        (self.callback)()
+    }
+}
+
+// Whether the command failed because the ticket has expired or there isn't one, so logging in would fix it
+pub fn is_login_required(message: &P4ServerMessage) -> bool {
+    message.kind() == P4ErrorKind::LoginRequired
+}
+
+// Runs `p4 login`, passing the password on stdin so it never appears on a command line
+#[cfg(feature = "spawn")]
+pub fn login(password: &str) -> Result<(), P4Error> {
+    login_from_context(&P4Context::default(), password)
+}
+
+pub fn login_from_context(context: &P4Context, password: &str) -> Result<(), P4Error> {
+    let input = format!("{}\n", password);
+    P4DictIterator::new_from_context_with_input(context, vec!["login"], input.as_bytes())?
+        .try_for_each(|dict| dict.map(drop))
+}
+
+// Logs in with a password or SSO token, or starts using a ticket for the context and its clones
+pub fn login_with_credential(
+    context: &P4Context,
+    credential: &P4Credential,
+) -> Result<(), P4Error> {
+    match credential {
+        P4Credential::Password(secret) | P4Credential::SsoToken(secret) => {
+            login_from_context(context, secret)
+        }
+        P4Credential::Ticket(ticket) => {
+            context.set_ticket(ticket);
+            Ok(())
+        }
+    }
+}
+
+// Gets the token for an SSO login, e.g. by opening the URL in the prompt in a browser and waiting for the
+// identity provider to call back
+pub trait SsoHandler: fmt::Debug + Send + Sync {
+    // `prompt` is what p4 printed before waiting for input, e.g. "Navigate to URL: https://..."
+    fn token(&self, prompt: &str) -> Result<String, P4Error>;
+}
+
+// Runs `p4 login` and, when it stops at an SSO prompt, hands the prompt to the handler and gives p4 the token.
+// Logins that need no input, e.g. because P4LOGINSSO is set on the machine, complete without the handler.
+#[cfg(feature = "spawn")]
+pub fn login_sso_from_context(
+    context: &P4Context,
+    handler: &dyn SsoHandler,
+) -> Result<(), P4Error> {
+    let mut child = context.spawn_plain(vec!["login"])?;
+    let result = drive_sso_login(&mut child, handler);
+    if result.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait();
+    context.plain_command_finished();
+    let status = status?;
+
+    let output = result?;
+    if !status.success() {
+        return Err(P4Error::Credentials(format!(
+            "p4 login failed: {}",
+            output.trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "spawn"))]
+pub fn login_sso_from_context(
+    _context: &P4Context,
+    _handler: &dyn SsoHandler,
+) -> Result<(), P4Error> {
+    Err(P4Error::Spawn(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Built without the spawn feature, SSO logins need a p4 process",
+    )))
+}
+
+// Returns everything p4 printed
+#[cfg(feature = "spawn")]
+fn drive_sso_login(
+    child: &mut process::Child,
+    handler: &dyn SsoHandler,
+) -> Result<String, P4Error> {
+    let missing = || P4Error::InvalidRecord("Failed to get the pipes of p4 login");
+    let mut stdin = child.stdin.take().ok_or_else(missing)?;
+    let mut stdout = child.stdout.take().ok_or_else(missing)?;
+    let mut stderr = child.stderr.take().ok_or_else(missing)?;
+
+    // The prompt has no newline after it, so read what is there rather than whole lines
+    let mut output = Vec::new();
+    let mut chunk = [0; 1024];
+    loop {
+        let read = stdout.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        output.extend_from_slice(&chunk[..read]);
+        if let Some(prompt) = find_sso_prompt(&String::from_utf8_lossy(&output)) {
+            let token = handler.token(&prompt)?;
+            writeln!(stdin, "{}", token)?;
+            break;
+        }
+    }
+    // Closing stdin stops p4 waiting if it asks again
+    drop(stdin);
+
+    stdout.read_to_end(&mut output)?;
+    stderr.read_to_end(&mut output)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+// A line with the URL to log in at, or a question such as "Enter password: " that p4 is waiting on
+#[cfg(feature = "spawn")]
+fn find_sso_prompt(output: &str) -> Option<String> {
+    let (complete, pending) = output.rsplit_once('\n').unwrap_or(("", output));
+    if pending.trim_end().ends_with(':') && pending.ends_with(' ') {
+        return Some(pending.trim().to_string());
+    }
+    complete
+        .lines()
+        .rev()
+        .find(|line| line.contains("https://") || line.contains("http://"))
+        .map(|line| line.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::*, changes::*, parsers::py_dict::to_py_dict_bytes};
+    use std::{
+        io,
+        sync::{
+            Arc,
+            atomic::{AtomicU32, Ordering},
+        },
+    };
+
+    // A server whose ticket has expired until `p4 login` is given the right password
+    #[derive(Debug, Default)]
+    struct ExpiringServer {
+        logins: AtomicU32,
+    }
+
+    impl P4Backend for ExpiringServer {
+        fn run(&self, args: &[&str]) -> Result<Box<dyn io::Read + Send>, P4Error> {
+            let response = if self.logins.load(Ordering::SeqCst) == 0 {
+                to_py_dict_bytes(&[&[
+                    ("code", "error"),
+                    ("data", "Your session has expired, please login again.\n"),
+                    ("severity", "3"),
+                    ("generic", "7"),
+                ]])
+            } else {
+                assert_eq!(args[0], "changes");
+                to_py_dict_bytes(&[&[
+                    ("code", "stat"),
+                    ("change", "12"),
+                    ("time", "0"),
+                    ("user", "alice"),
+                    ("desc", "Fix the build\n"),
+                ]])
+            };
+            Ok(Box::new(io::Cursor::new(response)))
+        }
+
+        fn run_with_input(
+            &self,
+            args: &[&str],
+            input: &[u8],
+        ) -> Result<Box<dyn io::Read + Send>, P4Error> {
+            assert_eq!(args, ["login"]);
+            assert_eq!(input, b"secret\n");
+            self.logins.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(io::Cursor::new(to_py_dict_bytes(&[&[
+                ("code", "stat"),
+                ("User", "alice"),
+                ("TicketExpiration", "43200"),
+            ]]))))
+        }
+    }
+
+    #[test]
+    fn test_relogin_on_expired_ticket() {
+        let server = Arc::new(ExpiringServer::default());
+        let context = P4Context::new().with_backend(server.clone());
+        assert!(matches!(
+            P4ChangesIterator::new_from_context(&context, None)
+                .unwrap()
+                .next(),
+            Some(Err(P4Error::Server(message))) if is_login_required(&message)
+        ));
+        assert_eq!(server.logins.load(Ordering::SeqCst), 0);
+
+        let context = context.with_credential_provider(Arc::new(CallbackCredentials::new(|| {
+            Ok(P4Credential::Password("secret".to_string()))
+        })));
+        let changes = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(changes[0].changelist, 12);
+        assert_eq!(server.logins.load(Ordering::SeqCst), 1);
+
+        let provider = EnvCredentials::new("P4_HELPER_TEST_UNSET_TICKET", P4Credential::Ticket);
+        assert!(matches!(
+            provider.credential(),
+            Err(P4Error::Credentials(_))
+        ));
+
+        let path = env::temp_dir().join(format!("p4_helper_ticket_{}", std::process::id()));
+        fs::write(&path, "ABCDEF0123456789\n").unwrap();
+        let credential = FileCredentials::new(&path, P4Credential::Ticket)
+            .credential()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            credential,
+            P4Credential::Ticket("ABCDEF0123456789".to_string())
+        );
+        assert_eq!(format!("{:?}", credential), "Ticket(<redacted>)");
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "spawn"))]
+    fn test_sso_login() {
+        // Asks for the token the way the Helix Authentication Service's login does
+        let script = env::temp_dir().join(format!("p4_helper_sso_{}.sh", std::process::id()));
+        fs::write(
+            &script,
+            "printf 'Navigate to URL: https://sso.example.com/login/1234\\n'\n\
+             read token\n\
+             if [ \"$token\" = tok-123 ]; then echo 'User alice logged in.'; exit 0; fi\n\
+             echo 'Single sign-on failed.' >&2\n\
+             exit 1\n",
+        )
+        .unwrap();
+
+        #[derive(Debug)]
+        struct Browser(&'static str);
+
+        impl SsoHandler for Browser {
+            fn token(&self, prompt: &str) -> Result<String, P4Error> {
+                assert_eq!(
+                    prompt,
+                    "Navigate to URL: https://sso.example.com/login/1234"
+                );
+                Ok(self.0.to_string())
+            }
+        }
+
+        // Run through sh, so "p4 login" becomes "sh <script> login"
+        let context = P4Context::new()
+            .with_p4_path("/bin/sh")
+            .with_global_args([script.to_str().unwrap()])
+            .with_command_log();
+        let logged_in = login_sso_from_context(&context, &Browser("tok-123"));
+        let failed = login_sso_from_context(&context, &Browser("tok-456"));
+        fs::remove_file(&script).unwrap();
+
+        logged_in.unwrap();
+        assert!(
+            matches!(failed, Err(P4Error::Credentials(message)) if message.contains("Single sign-on failed"))
+        );
+        let snapshot = context.metrics().snapshot();
+        assert_eq!((snapshot.commands_run, snapshot.processes_spawned), (2, 2));
+        assert_eq!(
+            context.command_log().unwrap().entries()[0],
+            format!("/bin/sh {} login", script.display())
+        );
+        assert_eq!(
+            find_sso_prompt("Enter password: ").as_deref(),
+            Some("Enter password:")
+        );
+    }
+}
@@ -0,0 +1,23 @@
+// == Std crates
+use std::{fmt, io};
+
+// == Internal crates
+use crate::error::*;
+
+// Something other than a local p4 executable that can produce -G output for a command, e.g. a mock
+pub trait P4Backend: fmt::Debug + Send + Sync {
+    // `args` are the command arguments, without the global -ztag -G flags
+    fn run(&self, args: &[&str]) -> Result<Box<dyn io::Read + Send>, P4Error>;
+
+    // Runs a command that reads `input` from stdin, e.g. the password for `p4 login`
+    fn run_with_input(
+        &self,
+        _args: &[&str],
+        _input: &[u8],
+    ) -> Result<Box<dyn io::Read + Send>, P4Error> {
+        Err(P4Error::Spawn(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The backend can't send input to a command",
+        )))
+    }
+}
@@ -0,0 +1,668 @@
+// An example CLI with a subcommand for each of the crate's read-only command iterators. What changes the
+// workspace or the server (sync, shelve, property -a) and the exporters are left to the library.
+
+// == Std crates
+use std::{
+    env, fmt,
+    io::{self, Write},
+    process::ExitCode,
+    time::Duration,
+};
+
+// == Internal crates
+use p4_helper::{
+    P4Changelist, P4File,
+    annotate::P4AnnotateIterator,
+    changes::P4ChangesIterator,
+    context::P4Context,
+    depots::P4DepotsIterator,
+    describe::P4DescribeIterator,
+    diff2::{P4Diff2Iterator, P4Diff2Revision},
+    dirs::P4DirsIterator,
+    error::P4Error,
+    filelog::P4FilelogIterator,
+    files::P4FilesIterator,
+    fstat::P4FstatIterator,
+    have::P4HaveIterator,
+    print::P4PrintIterator,
+    property::{P4PropertyIterator, P4PropertyQuery},
+    retry::RetryPolicy,
+    sizes::P4SizesIterator,
+};
+
+const USAGE: &str = "\
+Usage: p4h [global options] <command> [command options]
+
+Commands:
+    changes [<from> <to>]    List submitted changelists in the range
+    describe <changelist>    List the files in a changelist
+    annotate <filespec>      Show the changelist and user of each line
+    depots                   List the depots
+    diff2 <left> <right>     Compare two sets of revisions
+    dirs <filespec>          List the subdirectories
+    filelog <filespec>       List the revisions of each file
+    files <filespec>         List the files
+    fstat <filespec>         Show the head and have revision of each file
+    have <filespec>          List the revisions synced to the workspace
+    print <filespec>         Print the content of each file, or list the revisions with --json or --csv
+    property [<name>]        List the server properties
+    sizes <filespec>         Show the size of each file
+
+Global options:
+    --timeout <secs>         Kill p4 if it produces no output for this long
+    --retries <n>            Retry transient failures up to n times
+    --p4 <path>              The p4 executable to run, instead of the one on PATH
+    --capture <dir>          Save the raw p4 output of every command to dir
+    --replay <dir>           Serve commands from output saved with --capture
+
+Command options:
+    --json                   Print one JSON object per line
+    --csv                    Print CSV with a header row
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+fn main() -> ExitCode {
+    match run(env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("p4h: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut context = P4Context::new();
+    let mut format = OutputFormat::Text;
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "--timeout" => {
+                let secs = value("--timeout")?
+                    .parse()
+                    .map_err(|_| "Invalid --timeout")?;
+                context = context.with_timeout(Duration::from_secs(secs));
+            }
+            "--retries" => {
+                let retries: u32 = value("--retries")?
+                    .parse()
+                    .map_err(|_| "Invalid --retries")?;
+                context = context
+                    .with_retry_policy(RetryPolicy::new(retries + 1, Duration::from_millis(500)));
+            }
+            "--p4" => context = context.with_p4_path(value("--p4")?),
+            "--capture" => {
+                context = context
+                    .with_capture_dir(value("--capture")?)
+                    .map_err(|e| e.to_string())?;
+            }
+            "--replay" => {
+                context = context
+                    .with_replay_dir(value("--replay")?)
+                    .map_err(|e| e.to_string())?;
+            }
+            "--json" => format = OutputFormat::Json,
+            "--csv" => format = OutputFormat::Csv,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = match positional
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["changes"] => run_changes(&context, None, format, &mut out),
+        ["changes", from, to] => {
+            let from = from.parse().map_err(|_| "Invalid changelist")?;
+            let to = to.parse().map_err(|_| "Invalid changelist")?;
+            run_changes(&context, Some(from..to), format, &mut out)
+        }
+        ["describe", changelist] => {
+            let changelist = changelist.parse().map_err(|_| "Invalid changelist")?;
+            run_describe(&context, changelist, format, &mut out)
+        }
+        ["print", filespec] if format == OutputFormat::Text => {
+            run_print(&context, filespec, &mut out)
+        }
+        [command, args @ ..] => {
+            let mut rows = RowWriter::new(&mut out, format);
+            match (*command, args) {
+                ("annotate", [filespec]) => run_annotate(&context, filespec, &mut rows),
+                ("depots", []) => run_depots(&context, &mut rows),
+                ("diff2", [left, right]) => run_diff2(&context, left, right, &mut rows),
+                ("dirs", [filespec]) => run_dirs(&context, filespec, &mut rows),
+                ("filelog", [filespec]) => run_filelog(&context, filespec, &mut rows),
+                ("files", [filespec]) => run_files(&context, filespec, &mut rows),
+                ("fstat", [filespec]) => run_fstat(&context, filespec, &mut rows),
+                ("have", [filespec]) => run_have(&context, filespec, &mut rows),
+                ("print", [filespec]) => run_print_revisions(&context, filespec, &mut rows),
+                ("property", []) => run_property(&context, P4PropertyQuery::new(), &mut rows),
+                ("property", [name]) => run_property(&context, (*name).into(), &mut rows),
+                ("sizes", [filespec]) => run_sizes(&context, filespec, &mut rows),
+                _ => return Err(format!("Invalid arguments\n\n{}", USAGE)),
+            }
+        }
+        _ => return Err(format!("Invalid arguments\n\n{}", USAGE)),
+    };
+
+    match result {
+        // Output piped into something like head, which stopped reading
+        Err(P4Error::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(|e| e.to_string()),
+    }
+}
+
+fn run_changes(
+    context: &P4Context,
+    cl_range: Option<std::ops::Range<u32>>,
+    format: OutputFormat,
+    out: &mut impl Write,
+) -> Result<(), P4Error> {
+    if format == OutputFormat::Csv {
+        writeln!(out, "change,time,user,description")?;
+    }
+
+    for change in P4ChangesIterator::new_from_context(context, cl_range)? {
+        write_changelist(&change?, format, out)?;
+    }
+    Ok(())
+}
+
+fn run_describe(
+    context: &P4Context,
+    changelist: u32,
+    format: OutputFormat,
+    out: &mut impl Write,
+) -> Result<(), P4Error> {
+    let describe = P4DescribeIterator::new_from_context(context, changelist)?;
+
+    match format {
+        OutputFormat::Csv => writeln!(out, "depot_path,action,revision,file_size,digest")?,
+        _ => write_changelist(describe.get_changelist(), format, out)?,
+    }
+
+    for file in describe {
+        write_file(&file?, format, out)?;
+    }
+    Ok(())
+}
+
+fn run_annotate(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for line in P4AnnotateIterator::new_from_context(context, filespec)? {
+        let line = line?;
+        rows.write(
+            format_args!(
+                "{} {}: {}",
+                line.first_changelist,
+                line.user,
+                line.text.trim_end()
+            ),
+            &[
+                ("depot_path", line.depot_path.as_str().into()),
+                ("first_change", line.first_changelist.into()),
+                ("last_change", line.last_changelist.into()),
+                ("user", line.user.as_str().into()),
+                ("text", line.text.as_str().into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_depots(context: &P4Context, rows: &mut RowWriter<impl Write>) -> Result<(), P4Error> {
+    for depot in P4DepotsIterator::new_from_context(context)? {
+        let depot = depot?;
+        rows.write(
+            format_args!("Depot {} {} {}", depot.name, depot.depot_type, depot.map),
+            &[
+                ("name", depot.name.as_str().into()),
+                ("type", depot.depot_type.as_str().into()),
+                ("map", depot.map.as_str().into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_diff2(
+    context: &P4Context,
+    left: &str,
+    right: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for difference in P4Diff2Iterator::new_from_context(context, left, right)? {
+        let difference = difference?;
+        let side = |revision: &Option<P4Diff2Revision>| {
+            revision.as_ref().map_or("<none>".to_string(), |revision| {
+                format!("{}#{}", revision.depot_path, revision.revision)
+            })
+        };
+        let (left, right) = (side(&difference.left), side(&difference.right));
+        rows.write(
+            format_args!(
+                "==== {} - {} ==== {}",
+                left,
+                right,
+                difference.status.as_str()
+            ),
+            &[
+                ("status", difference.status.as_str().into()),
+                ("left", left.as_str().into()),
+                ("right", right.as_str().into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_dirs(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for dir in P4DirsIterator::new_from_context(context, filespec)? {
+        let dir = dir?;
+        rows.write(&dir, &[("dir", dir.as_str().into())])?;
+    }
+    Ok(())
+}
+
+fn run_filelog(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for filelog in P4FilelogIterator::new_from_context(context, filespec)? {
+        for revision in filelog?.revisions {
+            rows.write(
+                format_args!(
+                    "{}#{} change {} {} on {} by {}@{} ({})",
+                    revision.depot_path,
+                    revision.revision,
+                    revision.changelist,
+                    revision.action,
+                    revision.time,
+                    revision.user,
+                    revision.client,
+                    revision.file_type
+                ),
+                &[
+                    ("depot_path", revision.depot_path.as_str().into()),
+                    ("revision", revision.revision.into()),
+                    ("change", revision.changelist.into()),
+                    ("action", revision.action.as_str().into()),
+                    ("type", revision.file_type.as_str().into()),
+                    ("time", revision.time.into()),
+                    ("user", revision.user.as_str().into()),
+                    ("client", revision.client.as_str().into()),
+                    ("description", revision.description.as_str().into()),
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn run_files(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for file in P4FilesIterator::new_from_context(context, filespec)? {
+        let file = file?;
+        let revision = file.revision.to_string();
+        rows.write(
+            format_args!(
+                "{}#{} - {} change {} ({})",
+                file.depot_path, revision, file.action, file.change, file.file_type
+            ),
+            &[
+                ("depot_path", file.depot_path.as_str().into()),
+                ("revision", revision.as_str().into()),
+                ("change", file.change.into()),
+                ("action", file.action.as_str().into()),
+                ("type", file.file_type.as_str().into()),
+                ("time", file.time.into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_fstat(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for entry in P4FstatIterator::new_from_context(context, filespec)? {
+        let entry = entry?;
+        let have_rev = entry.have_rev.map(|revision| revision.to_string());
+        rows.write(
+            format_args!(
+                "{}#{} have #{}{}",
+                entry.depot_path,
+                entry
+                    .head_rev
+                    .map_or("none".to_string(), |rev| rev.to_string()),
+                have_rev.as_deref().unwrap_or("none"),
+                entry
+                    .action
+                    .as_ref()
+                    .map_or(String::new(), |action| format!(", opened for {}", action))
+            ),
+            &[
+                ("depot_path", entry.depot_path.as_str().into()),
+                ("client_path", entry.client_path.as_deref().into()),
+                ("head_action", entry.head_action.as_deref().into()),
+                ("head_type", entry.head_type.as_deref().into()),
+                ("head_rev", entry.head_rev.into()),
+                ("head_change", entry.head_change.into()),
+                ("have_rev", have_rev.as_deref().into()),
+                ("action", entry.action.as_deref().into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_have(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for file in P4HaveIterator::new_from_context(context, filespec)? {
+        let file = file?;
+        let revision = file.revision.to_string();
+        rows.write(
+            format_args!("{}#{} - {}", file.depot_path, revision, file.client_path),
+            &[
+                ("depot_path", file.depot_path.as_str().into()),
+                ("revision", revision.as_str().into()),
+                ("client_path", file.client_path.as_str().into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// Like `p4 print`, a header line then the content of each file
+fn run_print(context: &P4Context, filespec: &str, out: &mut impl Write) -> Result<(), P4Error> {
+    for file in P4PrintIterator::new_from_context(context, &[filespec])? {
+        let file = file?;
+        writeln!(
+            out,
+            "{}#{} - {} change {} ({})",
+            file.depot_path, file.revision, file.action, file.change, file.file_type
+        )?;
+        out.write_all(&file.content)?;
+    }
+    Ok(())
+}
+
+fn run_print_revisions(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for file in P4PrintIterator::new_from_context(context, &[filespec])? {
+        let file = file?;
+        rows.write(
+            &file.depot_path,
+            &[
+                ("depot_path", file.depot_path.as_str().into()),
+                ("revision", file.revision.into()),
+                ("change", file.change.into()),
+                ("action", file.action.as_str().into()),
+                ("type", file.file_type.as_str().into()),
+                ("time", file.time.into()),
+                ("file_size", (file.content.len() as u64).into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_property(
+    context: &P4Context,
+    query: P4PropertyQuery,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for property in P4PropertyIterator::new_from_context(context, query)? {
+        let property = property?;
+        rows.write(
+            format_args!("{} = {}", property.name, property.value),
+            &[
+                ("name", property.name.as_str().into()),
+                ("value", property.value.as_str().into()),
+                ("sequence", property.sequence.into()),
+                ("user", property.user.as_deref().into()),
+                ("group", property.group.as_deref().into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_sizes(
+    context: &P4Context,
+    filespec: &str,
+    rows: &mut RowWriter<impl Write>,
+) -> Result<(), P4Error> {
+    for size in P4SizesIterator::new_from_context(context, filespec)? {
+        let size = size?;
+        rows.write(
+            format_args!(
+                "{}#{} {} bytes",
+                size.depot_path, size.revision, size.file_size
+            ),
+            &[
+                ("depot_path", size.depot_path.as_str().into()),
+                ("revision", size.revision.into()),
+                ("file_size", size.file_size.into()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// A value in a JSON object or CSV row, a missing one is null in JSON and empty in CSV
+enum Field<'a> {
+    Text(&'a str),
+    Number(u64),
+    Missing,
+}
+
+impl<'a> From<&'a str> for Field<'a> {
+    fn from(text: &'a str) -> Self {
+        Field::Text(text)
+    }
+}
+
+impl From<u32> for Field<'_> {
+    fn from(number: u32) -> Self {
+        Field::Number(number.into())
+    }
+}
+
+impl From<u64> for Field<'_> {
+    fn from(number: u64) -> Self {
+        Field::Number(number)
+    }
+}
+
+impl<'a, T: Into<Field<'a>>> From<Option<T>> for Field<'a> {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Field::Missing, Into::into)
+    }
+}
+
+// Writes the items of the commands other than changes and describe, with a CSV header before the first
+struct RowWriter<'a, WriteT: Write> {
+    out: &'a mut WriteT,
+    format: OutputFormat,
+    header_written: bool,
+}
+
+impl<'a, WriteT: Write> RowWriter<'a, WriteT> {
+    fn new(out: &'a mut WriteT, format: OutputFormat) -> Self {
+        RowWriter {
+            out,
+            format,
+            header_written: false,
+        }
+    }
+
+    // `text` is the line in the text format
+    fn write(&mut self, text: impl fmt::Display, fields: &[(&str, Field)]) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => writeln!(self.out, "{}", text),
+            OutputFormat::Json => {
+                let members = fields
+                    .iter()
+                    .map(|(name, field)| {
+                        let value = match field {
+                            Field::Text(text) => json_string(text),
+                            Field::Number(number) => number.to_string(),
+                            Field::Missing => "null".to_string(),
+                        };
+                        format!("{}:{}", json_string(name), value)
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(self.out, "{{{}}}", members.join(","))
+            }
+            OutputFormat::Csv => {
+                if !self.header_written {
+                    let names = fields.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+                    writeln!(self.out, "{}", names.join(","))?;
+                    self.header_written = true;
+                }
+                let values = fields
+                    .iter()
+                    .map(|(_, field)| match field {
+                        Field::Text(text) => csv_field(text),
+                        Field::Number(number) => number.to_string(),
+                        Field::Missing => String::new(),
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(self.out, "{}", values.join(","))
+            }
+        }
+    }
+}
+
+fn write_changelist(
+    change: &P4Changelist,
+    format: OutputFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => writeln!(
+            out,
+            "Change {} on {} by {}\n\t{}",
+            change.changelist,
+            change.time,
+            change.user,
+            change.description.trim_end().replace('\n', "\n\t")
+        ),
+        OutputFormat::Json => writeln!(
+            out,
+            "{{\"change\":{},\"time\":{},\"user\":{},\"description\":{}}}",
+            change.changelist,
+            change.time,
+            json_string(&change.user),
+            json_string(&change.description)
+        ),
+        OutputFormat::Csv => writeln!(
+            out,
+            "{},{},{},{}",
+            change.changelist,
+            change.time,
+            csv_field(&change.user),
+            csv_field(&change.description)
+        ),
+    }
+}
+
+// The revision, size and digest p4 left out, e.g. for a file opened for add, are empty, or null in JSON
+fn write_file(file: &P4File, format: OutputFormat, out: &mut impl Write) -> io::Result<()> {
+    let digest = file.digest.map(const_hex::encode_upper);
+    let text = |value: Option<String>| value.unwrap_or_default();
+    match format {
+        OutputFormat::Text => match file.file_size {
+            Some(file_size) => writeln!(out, "... {} ({} bytes)", file, file_size),
+            None => writeln!(out, "... {}", file),
+        },
+        OutputFormat::Json => {
+            let json = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+            writeln!(
+                out,
+                "{{\"depot_path\":{},\"action\":{},\"revision\":{},\"file_size\":{},\"digest\":{}}}",
+                json_string(&file.depot_path),
+                json_string(&file.action),
+                json(file.revision.map(|revision| revision.to_string())),
+                json(file.file_size.map(|file_size| file_size.to_string())),
+                json(digest.map(|digest| format!("\"{}\"", digest)))
+            )
+        }
+        OutputFormat::Csv => writeln!(
+            out,
+            "{},{},{},{},{}",
+            csv_field(&file.depot_path),
+            csv_field(&file.action),
+            text(file.revision.map(|revision| revision.to_string())),
+            text(file.file_size.map(|file_size| file_size.to_string())),
+            text(digest)
+        ),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,132 @@
+// == Internal crates
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+use crate::error::*;
+use crate::view::*;
+
+// A branch spec's name and view, for working out where files will be integrated to without asking the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4BranchMap {
+    pub name: String,
+    pub view: P4View,
+}
+
+impl P4BranchMap {
+    // From the fields of `p4 branch -o`, i.e. Branch and View0 to ViewN
+    pub fn from_spec<'a>(
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, P4ValueParseError> {
+        let mut name = String::new();
+        let mut lines = Vec::new();
+        for (key, value) in fields {
+            if key == "Branch" {
+                name = value.to_string();
+            } else if let Some(index) = key.strip_prefix("View")
+                && let Ok(index) = index.parse::<usize>()
+            {
+                lines.push((index, value.parse()?));
+            }
+        }
+        lines.sort_by_key(|(index, _)| *index);
+        Ok(P4BranchMap {
+            name,
+            view: P4View {
+                lines: lines.into_iter().map(|(_, line)| line).collect(),
+            },
+        })
+    }
+
+    // The target of a source depot path, None if the branch doesn't map it
+    pub fn translate(&self, path: &str) -> Option<String> {
+        self.view.translate(path)
+    }
+
+    // The source of a target depot path, as for `p4 integrate -r`
+    pub fn translate_reverse(&self, path: &str) -> Option<String> {
+        self.view.reversed().translate(path)
+    }
+}
+
+#[cfg(feature = "spawn")]
+pub fn fetch(name: &str) -> Result<P4BranchMap, P4Error> {
+    fetch_from_context(&P4Context::default(), name)
+}
+
+// `p4 branch -o` describes a default spec for a branch that doesn't exist, which has no Access time
+#[cfg(feature = "process")]
+pub fn fetch_from_context(context: &P4Context, name: &str) -> Result<P4BranchMap, P4Error> {
+    let specs = P4DictIterator::new_from_context(context, vec!["branch", "-o", name])?
+        .collect::<Result<Vec<_>, _>>()?;
+    let spec = specs
+        .into_iter()
+        .find(|spec| spec.get("Access").is_some())
+        .ok_or_else(|| P4Error::NoSuchBranch(name.to_string()))?;
+    P4BranchMap::from_spec(spec.iter())
+        .map_err(|_| P4Error::InvalidRecord("Invalid branch view line"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_translate() {
+        let branch = P4BranchMap::from_spec([
+            ("Branch", "main-to-rel"),
+            ("View1", "-//depot/main/tools/... //depot/rel/tools/..."),
+            ("View0", "//depot/main/... //depot/rel/..."),
+        ])
+        .unwrap();
+        assert_eq!(branch.name, "main-to-rel");
+        assert_eq!(
+            branch.translate("//depot/main/src/lib.rs").as_deref(),
+            Some("//depot/rel/src/lib.rs")
+        );
+        assert_eq!(branch.translate("//depot/main/tools/build.py"), None);
+        assert_eq!(
+            branch
+                .translate_reverse("//depot/rel/src/lib.rs")
+                .as_deref(),
+            Some("//depot/main/src/lib.rs")
+        );
+        assert!(P4BranchMap::from_spec([("View0", "//depot/main/...")]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_fetch() {
+        use crate::testing::*;
+
+        let context = MockP4::new()
+            .with_records(
+                "branch -o main-to-rel",
+                [[
+                    ("code", "stat"),
+                    ("Branch", "main-to-rel"),
+                    ("Access", "1743984000"),
+                    ("View0", "//depot/main/... //depot/rel/..."),
+                ]],
+            )
+            .with_records(
+                "branch -o missing",
+                [[
+                    ("code", "stat"),
+                    ("Branch", "missing"),
+                    ("View0", "//depot/... //depot/..."),
+                ]],
+            )
+            .into_context();
+
+        let branch = fetch_from_context(&context, "main-to-rel").unwrap();
+        assert_eq!(
+            branch.translate("//depot/main/a.txt").as_deref(),
+            Some("//depot/rel/a.txt")
+        );
+        assert!(matches!(
+            fetch_from_context(&context, "missing"),
+            Err(P4Error::NoSuchBranch(name)) if name == "missing"
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+// == Std crates
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+// == External crates
+use thiserror::Error;
+
+// Limits on what the iterators sharing a P4Context may hold in memory at once, so a pathological
+// p4 output ends the iteration with an error instead of the process running out of memory
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    max_buffered_bytes: Option<u64>,
+    max_queued_records: Option<u64>,
+    buffered_bytes: AtomicU64,
+    queued_records: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum P4BudgetResource {
+    // Keys, values and file contents held by the parsers
+    BufferedBytes,
+    // Records read ahead of the consumer, e.g. by the depot walker
+    QueuedRecords,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Memory budget exceeded: {requested} {resource} requested, {in_use} in use, limit {limit}")]
+pub struct P4BudgetExceeded {
+    pub resource: P4BudgetResource,
+    pub requested: u64,
+    pub in_use: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for P4BudgetResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            P4BudgetResource::BufferedBytes => "buffered bytes",
+            P4BudgetResource::QueuedRecords => "queued records",
+        })
+    }
+}
+
+impl MemoryBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_buffered_bytes(mut self, max_buffered_bytes: u64) -> Self {
+        self.max_buffered_bytes = Some(max_buffered_bytes);
+        self
+    }
+
+    pub fn with_max_queued_records(mut self, max_queued_records: u64) -> Self {
+        self.max_queued_records = Some(max_queued_records);
+        self
+    }
+
+    pub fn buffered_bytes(&self) -> u64 {
+        self.buffered_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn queued_records(&self) -> u64 {
+        self.queued_records.load(Ordering::Relaxed)
+    }
+
+    fn counter(&self, resource: P4BudgetResource) -> (&AtomicU64, Option<u64>) {
+        match resource {
+            P4BudgetResource::BufferedBytes => (&self.buffered_bytes, self.max_buffered_bytes),
+            P4BudgetResource::QueuedRecords => (&self.queued_records, self.max_queued_records),
+        }
+    }
+
+    fn acquire(&self, resource: P4BudgetResource, amount: u64) -> Result<(), P4BudgetExceeded> {
+        let (counter, limit) = self.counter(resource);
+        counter
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_use| {
+                let total = in_use.saturating_add(amount);
+                limit.is_none_or(|limit| total <= limit).then_some(total)
+            })
+            .map(|_| ())
+            .map_err(|in_use| P4BudgetExceeded {
+                resource,
+                requested: amount,
+                in_use,
+                limit: limit.unwrap_or(u64::MAX),
+            })
+    }
+
+    fn release(&self, resource: P4BudgetResource, amount: u64) {
+        self.counter(resource)
+            .0
+            .fetch_sub(amount, Ordering::Relaxed);
+    }
+}
+
+// What one parser or pipeline stage currently holds of a budget, given back when dropped.
+// The default reservation has no budget and never fails.
+#[derive(Debug, Default)]
+pub(crate) struct BudgetReservation {
+    budget: Option<Arc<MemoryBudget>>,
+    resource: Option<P4BudgetResource>,
+    amount: u64,
+}
+
+impl BudgetReservation {
+    pub(crate) fn new(budget: Option<Arc<MemoryBudget>>, resource: P4BudgetResource) -> Self {
+        BudgetReservation {
+            budget,
+            resource: Some(resource),
+            amount: 0,
+        }
+    }
+
+    // Grows or shrinks the reservation to `amount`, leaving it unchanged if that is over budget
+    pub(crate) fn resize(&mut self, amount: u64) -> Result<(), P4BudgetExceeded> {
+        let (Some(budget), Some(resource)) = (self.budget.as_ref(), self.resource) else {
+            return Ok(());
+        };
+
+        if amount > self.amount {
+            budget.acquire(resource, amount - self.amount)?;
+        } else {
+            budget.release(resource, self.amount - amount);
+        }
+        self.amount = amount;
+        Ok(())
+    }
+
+    // Takes over what another reservation on the same budget holds, without acquiring it again
+    #[cfg(feature = "process")]
+    pub(crate) fn absorb(&mut self, mut other: BudgetReservation) {
+        self.amount += other.amount;
+        other.amount = 0;
+    }
+
+    pub(crate) fn release(&mut self) {
+        if let (Some(budget), Some(resource)) = (self.budget.as_ref(), self.resource) {
+            budget.release(resource, self.amount);
+        }
+        self.amount = 0;
+    }
+}
+
+impl Drop for BudgetReservation {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::*;
+    use crate::parsers::py_dict::*;
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = Arc::new(MemoryBudget::new().with_max_buffered_bytes(100));
+
+        let mut first =
+            BudgetReservation::new(Some(budget.clone()), P4BudgetResource::BufferedBytes);
+        let mut second =
+            BudgetReservation::new(Some(budget.clone()), P4BudgetResource::BufferedBytes);
+        first.resize(60).unwrap();
+        let error = second.resize(50).unwrap_err();
+        assert_eq!((error.in_use, error.limit), (60, 100));
+        second.resize(40).unwrap();
+        assert_eq!(budget.buffered_bytes(), 100);
+        drop(first);
+        assert_eq!(budget.buffered_bytes(), 40);
+
+        // A value bigger than the budget is an error before it is read into memory
+        let mut data = Vec::new();
+        let desc = "x".repeat(200);
+        write_py_dict(&mut data, [("code", "stat"), ("desc", desc.as_str())]).unwrap();
+        let mut parser = P4PyDictParser::new(&data[..]).with_memory_budget(budget.clone());
+        assert_eq!(parser.get_next_kvp().unwrap().unwrap().key, "code");
+        let error = P4Error::from(parser.get_next_kvp().unwrap_err());
+        assert!(matches!(error, P4Error::BudgetExceeded(_)));
+
+        drop(parser);
+        assert_eq!(budget.buffered_bytes(), 40);
+    }
+}
@@ -0,0 +1,23 @@
+// == Std crates
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+// A cheap, cloneable flag used to ask long running iterators to stop early
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
@@ -0,0 +1,172 @@
+// == Std crates
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// == Internal crates
+use crate::backend::*;
+use crate::command_log::redact_args;
+use crate::error::*;
+
+const OUTPUT_EXTENSION: &str = "pyc";
+const ARGS_EXTENSION: &str = "args";
+
+// Writes the raw output of every command to `<dir>/<timestamp>_<sequence>_<command>.pyc`, next to a
+// `.args` file holding the (redacted) arguments, one per line
+#[derive(Debug)]
+pub struct CaptureWriter {
+    dir: PathBuf,
+    sequence: AtomicU32,
+}
+
+impl CaptureWriter {
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(CaptureWriter {
+            dir,
+            sequence: AtomicU32::new(0),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Creates the capture file for a command, the output is written to it as it is read
+    pub(crate) fn create(&self, args: &[&str]) -> io::Result<fs::File> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let command = args
+            .first()
+            .map(|command| sanitize_file_name(command))
+            .unwrap_or_default();
+
+        let stem = format!("{}_{:04}_{}", timestamp, sequence, command);
+        fs::write(
+            self.dir.join(&stem).with_extension(ARGS_EXTENSION),
+            redact_args(args).join("\n"),
+        )?;
+        fs::File::create(self.dir.join(stem).with_extension(OUTPUT_EXTENSION))
+    }
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[derive(Debug)]
+struct ReplayEntry {
+    args: Vec<String>,
+    output: PathBuf,
+    used: bool,
+}
+
+// Serves commands from a directory written by CaptureWriter, in the order they were captured.
+// Once all captures of a command have been used, the last one is served again.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    entries: Mutex<Vec<ReplayEntry>>,
+}
+
+impl ReplayBackend {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|ext| ext == ARGS_EXTENSION) {
+                let output = path.with_extension(OUTPUT_EXTENSION);
+                if output.exists() {
+                    entries.push(ReplayEntry {
+                        args: fs::read_to_string(&path)?
+                            .lines()
+                            .map(str::to_string)
+                            .collect(),
+                        output,
+                        used: false,
+                    });
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.output.cmp(&b.output));
+
+        Ok(ReplayBackend {
+            entries: Mutex::new(entries),
+        })
+    }
+}
+
+impl P4Backend for ReplayBackend {
+    fn run(&self, args: &[&str]) -> Result<Box<dyn io::Read + Send>, P4Error> {
+        let args = redact_args(args);
+        let mut entries = self.entries.lock().unwrap();
+
+        let mut matching = entries
+            .iter_mut()
+            .filter(|entry| entry.args == args)
+            .peekable();
+        let mut last = None;
+        let entry = loop {
+            match matching.next() {
+                Some(entry) if !entry.used => break Some(entry),
+                Some(entry) if matching.peek().is_none() => break Some(entry),
+                Some(entry) => last = Some(entry),
+                None => break last,
+            }
+        };
+
+        let entry = entry.ok_or_else(|| {
+            P4Error::Spawn(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No capture for '{}'", args.join(" ")),
+            ))
+        })?;
+        entry.used = true;
+
+        Ok(Box::new(io::BufReader::new(fs::File::open(&entry.output)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{changes::*, context::*};
+
+    #[test]
+    fn test_capture_and_replay() {
+        let dir = std::env::temp_dir().join(format!("p4_helper_capture_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        // Capture the output of a (mocked) command
+        let mock = crate::testing::MockP4::new()
+            .with_fixture("changes", "./test_data/changes.pyc")
+            .unwrap();
+        let context = mock.into_context().with_capture_dir(&dir).unwrap();
+        let captured = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // Then feed it back through the iterators without the mock
+        let context = P4Context::new().with_replay_dir(&dir).unwrap();
+        let replayed = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(captured, replayed);
+        assert!(P4ChangesIterator::new_from_context(&context, Some(1..2)).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,114 +1,670 @@
 // == Std crates
-use std::{io, ops::Range};
+#[cfg(feature = "process")]
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(feature = "process")]
+use std::{
+    ops::Deref,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+};
 
 // == Internal crates
-use crate::parsers::py_dict::*;
+use crate::annotations::*;
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::describe::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+#[cfg(feature = "process")]
+use crate::progress::*;
 use crate::*;
 
-pub struct P4ChangesIterator<ReadT: io::Read> {
-    p4_process: Option<process::Child>,
-    parser: P4PyDictParser<ReadT>,
-    // Storage for various state variables
-    previous_dict_index: Option<u32>,
-    current_change: InterimP4Changelist,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P4ChangeStatus {
+    #[default]
+    Submitted,
+    Pending,
+    Shelved,
 }
 
-impl<ReadT: io::Read> P4ChangesIterator<ReadT> {
-    pub fn new_from_p4_exe(
-        cl_range: Option<Range<u32>>,
-    ) -> Result<P4ChangesIterator<process::ChildStdout>, &'static str> {
-        let cl_range = cl_range.unwrap_or(0..u32::MAX);
-
-        let mut p4_process = get_p4_cmd(vec![
-            "changes",
-            "-s",
-            "submitted",
-            "-l",
-            &format!("@{},{}", cl_range.start, cl_range.end),
-        ])
-        .spawn()
-        .expect("Failed to start p4 command");
-
-        let reader = p4_process
-            .stdout
-            .take()
-            .expect("Failed to get stdout of p4 command");
-        let parser = P4PyDictParser::new(reader);
-
-        Ok(P4ChangesIterator {
-            p4_process: Some(p4_process),
-            parser,
-            previous_dict_index: Some(0),
-            current_change: InterimP4Changelist::default(),
+impl P4ChangeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            P4ChangeStatus::Submitted => "submitted",
+            P4ChangeStatus::Pending => "pending",
+            P4ChangeStatus::Shelved => "shelved",
+        }
+    }
+}
+
+impl FromStr for P4ChangeStatus {
+    type Err = P4ValueParseError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        [
+            P4ChangeStatus::Submitted,
+            P4ChangeStatus::Pending,
+            P4ChangeStatus::Shelved,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == status)
+        .ok_or_else(|| P4ValueParseError::UnknownChangeStatus(status.to_string()))
+    }
+}
+
+// How much of each description `p4 changes` returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P4DescriptionMode {
+    // The first 31 characters
+    Short,
+    // -L, the first 250 characters
+    Truncated,
+    // -l, the whole description
+    #[default]
+    Long,
+}
+
+impl P4DescriptionMode {
+    // In characters, p4 doesn't say whether it cut a description so one this long may have been
+    pub fn limit(&self) -> Option<usize> {
+        match self {
+            P4DescriptionMode::Short => Some(31),
+            P4DescriptionMode::Truncated => Some(250),
+            P4DescriptionMode::Long => None,
+        }
+    }
+}
+
+// Where the changelists listed start and end, both included
+#[derive(Debug, Clone, PartialEq)]
+enum P4ChangesBounds {
+    Changes(Range<u32>),
+    Labels(String, String),
+}
+
+impl P4ChangesBounds {
+    // e.g. @5,10 or @release-1.0,@release-1.1
+    fn to_arg(&self) -> String {
+        match self {
+            P4ChangesBounds::Changes(range) => format!("@{},{}", range.start, range.end),
+            P4ChangesBounds::Labels(from, to) => format!("@{},@{}", from, to),
+        }
+    }
+}
+
+// The options of a `p4 changes` command, the defaults list every submitted changelist with its full description
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4ChangesQuery {
+    bounds: Option<P4ChangesBounds>,
+    status: P4ChangeStatus,
+    user: Option<String>,
+    client: Option<String>,
+    max: Option<u32>,
+    paths: Vec<String>,
+    description_mode: P4DescriptionMode,
+    include_integrations: bool,
+    oldest_first: bool,
+}
+
+impl P4ChangesQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Changelists from `range.start` to `range.end`, both included as p4 does
+    pub fn with_range(mut self, range: Range<u32>) -> Self {
+        self.bounds = Some(P4ChangesBounds::Changes(range));
+        self
+    }
+
+    // The changelists between two labels, e.g. two releases, replacing any range. The iterators check both
+    // labels exist before listing anything, as p4 would list nothing for a misspelt one.
+    pub fn with_label_range(mut self, from_label: &str, to_label: &str) -> Self {
+        self.bounds = Some(P4ChangesBounds::Labels(
+            from_label.to_string(),
+            to_label.to_string(),
+        ));
+        self
+    }
+
+    #[cfg(feature = "process")]
+    fn labels(&self) -> Vec<&str> {
+        match &self.bounds {
+            Some(P4ChangesBounds::Labels(from, to)) => vec![from, to],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn with_status(mut self, status: P4ChangeStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn with_client(mut self, client: &str) -> Self {
+        self.client = Some(client.to_string());
+        self
+    }
+
+    // The most recent `max` changelists
+    pub fn with_max(mut self, max: u32) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    // Only changelists affecting files under the path, e.g. //depot/main/...
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.paths.push(path.to_string());
+        self
+    }
+
+    pub fn with_description_mode(mut self, description_mode: P4DescriptionMode) -> Self {
+        self.description_mode = description_mode;
+        self
+    }
+
+    // -i, also the changelists integrated into the paths, not only the ones submitted to them
+    pub fn with_include_integrations(mut self) -> Self {
+        self.include_integrations = true;
+        self
+    }
+
+    // -r, oldest first instead of newest first, with_max then takes the oldest changelists
+    pub fn with_oldest_first(mut self) -> Self {
+        self.oldest_first = true;
+        self
+    }
+}
+
+impl P4Command for P4ChangesQuery {
+    type Item = P4Changelist;
+    type Fields = InterimP4ChangesEntry;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "changes".to_string(),
+            "-s".to_string(),
+            self.status.as_str().to_string(),
+        ];
+        match self.description_mode {
+            P4DescriptionMode::Short => {}
+            P4DescriptionMode::Truncated => args.push("-L".to_string()),
+            P4DescriptionMode::Long => args.push("-l".to_string()),
+        }
+        if self.include_integrations {
+            args.push("-i".to_string());
+        }
+        if self.oldest_first {
+            args.push("-r".to_string());
+        }
+        for (flag, value) in [("-u", &self.user), ("-c", &self.client)] {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value.clone()]);
+            }
+        }
+        if let Some(max) = self.max {
+            args.extend(["-m".to_string(), max.to_string()]);
+        }
+
+        let range = self
+            .bounds
+            .as_ref()
+            .map(P4ChangesBounds::to_arg)
+            .unwrap_or_default();
+        if self.paths.is_empty() {
+            if !range.is_empty() {
+                args.push(range);
+            }
+        } else {
+            args.extend(self.paths.iter().map(|path| format!("{}{}", path, range)));
+        }
+
+        args
+    }
+
+    fn new_fields(&self) -> Option<P4FieldsFactory<InterimP4ChangesEntry>> {
+        let description_limit = self.description_mode.limit();
+        Some(Box::new(move || InterimP4ChangesEntry {
+            description_limit,
+            ..Default::default()
+        }))
+    }
+}
+
+// The changelist range the iterators used to take directly
+impl From<Option<Range<u32>>> for P4ChangesQuery {
+    fn from(range: Option<Range<u32>>) -> Self {
+        P4ChangesQuery {
+            bounds: range.map(P4ChangesBounds::Changes),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Range<u32>> for P4ChangesQuery {
+    fn from(range: Range<u32>) -> Self {
+        P4ChangesQuery::new().with_range(range)
+    }
+}
+
+// The newest submitted changelist affecting files under the path, e.g. //depot/main/..., None if there are none
+#[cfg(feature = "spawn")]
+pub fn latest(path: &str) -> Result<Option<P4Changelist>, P4Error> {
+    latest_from_context(&P4Context::default(), path)
+}
+
+#[cfg(feature = "process")]
+pub fn latest_from_context(
+    context: &P4Context,
+    path: &str,
+) -> Result<Option<P4Changelist>, P4Error> {
+    first_change(context, P4ChangesQuery::new().with_path(path))
+}
+
+// The oldest submitted changelist affecting files under the path, None if there are none
+#[cfg(feature = "spawn")]
+pub fn earliest(path: &str) -> Result<Option<P4Changelist>, P4Error> {
+    earliest_from_context(&P4Context::default(), path)
+}
+
+#[cfg(feature = "process")]
+pub fn earliest_from_context(
+    context: &P4Context,
+    path: &str,
+) -> Result<Option<P4Changelist>, P4Error> {
+    first_change(
+        context,
+        P4ChangesQuery::new().with_path(path).with_oldest_first(),
+    )
+}
+
+// Every submitted changelist in the range, newest first, listed by one `p4 changes` per partition with up to
+// one per CPU running at a time. For exporting long histories, where a single command spends most of its time
+// on the server.
+#[cfg(feature = "spawn")]
+pub fn parallel(range: Range<u32>, partitions: u32) -> Result<Vec<P4Changelist>, P4Error> {
+    parallel_from_context(
+        &P4Context::default(),
+        P4ChangesQuery::new(),
+        range,
+        partitions,
+    )
+}
+
+// The other options of `query` apply to every partition, its range or labels are replaced by the partition's.
+// The results are merged in the order a single command would return them, then cut down to with_max.
+#[cfg(feature = "process")]
+pub fn parallel_from_context(
+    context: &P4Context,
+    query: P4ChangesQuery,
+    range: Range<u32>,
+    partitions: u32,
+) -> Result<Vec<P4Changelist>, P4Error> {
+    let ranges = partition_range(range, partitions);
+
+    // Partitions beyond the number of workers wait for one to be free. A failure stops the workers taking more.
+    let workers = thread::available_parallelism()
+        .map_or(1, |threads| threads.get())
+        .min(ranges.len());
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    while !failed.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(range) = ranges.get(index) else {
+                            break;
+                        };
+                        let query = query.clone().with_range(range.clone());
+                        let result = P4ChangesIterator::new_from_context(context, query)
+                            .and_then(|changes| changes.collect::<Result<Vec<_>, _>>());
+                        failed.fetch_or(result.is_err(), Ordering::Relaxed);
+                        results.push((index, result));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Changes thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    let results = results.into_iter().map(|(_, result)| result);
+
+    // Partitions are in ascending order, the changelists in each newest first unless -r was given
+    let mut changelists = Vec::new();
+    if query.oldest_first {
+        for result in results {
+            changelists.extend(result?);
+        }
+    } else {
+        for result in results.into_iter().rev() {
+            changelists.extend(result?);
+        }
+    }
+    if let Some(max) = query.max {
+        changelists.truncate(max as usize);
+    }
+    Ok(changelists)
+}
+
+// Splits the inclusive range into at most `partitions` contiguous inclusive ranges of about the same size
+#[cfg(feature = "process")]
+fn partition_range(range: Range<u32>, partitions: u32) -> Vec<Range<u32>> {
+    if range.end < range.start {
+        return Vec::new();
+    }
+    let count = u64::from(range.end - range.start) + 1;
+    let size = count.div_ceil(u64::from(partitions.max(1)));
+    (0..count.div_ceil(size))
+        .map(|index| {
+            let start = u64::from(range.start) + index * size;
+            let end = (start + size - 1).min(u64::from(range.end));
+            start as u32..end as u32
         })
+        .collect()
+}
+
+#[cfg(feature = "process")]
+pub(crate) fn check_label_exists(context: &P4Context, label: &str) -> Result<(), P4Error> {
+    let mut labels =
+        P4DictIterator::new_from_context(context, vec!["labels", "-e", label, "-m", "1"])?;
+    let found = labels.next().transpose()?.is_some();
+    labels.try_for_each(|label| label.map(drop))?;
+    if !found {
+        return Err(P4Error::NoSuchLabel(label.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "process")]
+pub(crate) fn first_change(
+    context: &P4Context,
+    query: P4ChangesQuery,
+) -> Result<Option<P4Changelist>, P4Error> {
+    let mut changes = P4ChangesIterator::new_from_context(context, query.with_max(1))?;
+    let change = changes.next().transpose()?;
+    // Read to the end so a server error after the record isn't missed
+    changes.by_ref().try_for_each(|change| change.map(drop))?;
+    Ok(change)
+}
+
+pub type P4ChangesIterator<ReadT> = P4CommandIterator<P4ChangesQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4ChangesIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new_from_p4_exe(
+        query: impl Into<P4ChangesQuery>,
+    ) -> Result<P4ChangesIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), query)
+    }
+
+    // `query` is a P4ChangesQuery, or the changelist range as an Option<Range<u32>>
+    pub fn new_from_context(
+        context: &P4Context,
+        query: impl Into<P4ChangesQuery>,
+    ) -> Result<P4ChangesIterator<P4Output>, P4Error> {
+        let query = query.into();
+        for label in query.labels() {
+            check_label_exists(context, label)?;
+        }
+
+        let mut result = Self::spawn(context, query)?;
+        result.fields_mut().description_scanner = context.description_scanner();
+        Ok(result)
+    }
+}
+
+impl<ReadT: io::Read> P4ChangesIterator<ReadT> {
+    // The mode the output was listed with, so descriptions p4 may have cut are flagged. The iterators
+    // started from a query set it themselves.
+    pub fn with_description_mode(mut self, description_mode: P4DescriptionMode) -> Self {
+        self.fields_mut().description_limit = description_mode.limit();
+        self
     }
 
-    pub fn new_from_reader(reader: ReadT) -> P4ChangesIterator<ReadT> {
-        let parser = P4PyDictParser::new(reader);
+    // Fills in the annotations of each changelist from its description
+    pub fn with_description_scanner(mut self, scanner: DescriptionScanner) -> Self {
+        self.fields_mut().description_scanner = Some(Arc::new(scanner));
+        self
+    }
+
+    // Yields handles that describe their changelist through `context` the first time the files are needed
+    #[cfg(feature = "process")]
+    pub fn with_lazy_files(
+        self,
+        context: &P4Context,
+    ) -> impl Iterator<Item = Result<P4ChangelistHandle, P4Error>> {
+        let context = Arc::new(context.clone());
+        self.map(move |changelist| {
+            changelist.map(|header| P4ChangelistHandle {
+                header,
+                context: context.clone(),
+                files: OnceLock::new(),
+            })
+        })
+    }
 
-        P4ChangesIterator {
-            p4_process: None,
-            parser,
-            previous_dict_index: Some(0),
-            current_change: InterimP4Changelist::default(),
+    // Yields each changelist with its files, running `p4 describe` for it when it is reached. with_prefetch
+    // starts the describes of the next few changelists early, so they run while the caller works.
+    #[cfg(feature = "process")]
+    pub fn describe_each(self, context: &P4Context) -> P4DescribeEach<ReadT> {
+        P4DescribeEach {
+            changes: self,
+            context: context.clone(),
+            prefetch: 0,
+            pending: VecDeque::new(),
+            progress: ProgressTracker::default(),
         }
     }
+}
+
+// The parsing state of a changelist listed by `p4 changes`. The scanner and limit carry over from one record
+// to the next, as do the buffers of the changelist.
+#[derive(Default)]
+pub struct InterimP4ChangesEntry {
+    change: InterimP4Changelist,
+    description_scanner: Option<Arc<DescriptionScanner>>,
+    // The length p4 cuts descriptions to, None when they are complete
+    description_limit: Option<usize>,
+}
+
+impl P4RecordFields for InterimP4ChangesEntry {
+    type Output = P4Changelist;
 
-    fn populate_field(change: &mut InterimP4Changelist, key: &str, value: &str) {
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        let change = &mut self.change;
         match key {
             "change" => {
-                change.change = Some(value.parse().unwrap());
+                change.change = Some(parse_field(value, "Invalid changelist")?);
             }
             "time" => {
-                change.time = Some(value.parse().unwrap());
+                change.time = Some(parse_field(value, "Invalid time")?);
             }
             "user" => {
-                change.user = Some(value.to_string());
+                change.user.set(value);
             }
             "desc" => {
-                change.description = Some(value.to_string());
+                change.description.set(value);
+            }
+            "status" => {
+                change.status = Some(parse_field(value, "Invalid changelist status")?);
             }
             _ => {}
         };
+
+        Ok(())
     }
-}
 
-impl<ReadT: io::Read> Iterator for P4ChangesIterator<ReadT> {
-    type Item = P4Changelist;
+    fn finish(mut self) -> Result<P4Changelist, P4Error> {
+        let mut changelist = None;
+        self.finish_into(&mut changelist)?;
+        changelist.ok_or(P4Error::InvalidRecord("Missing changelist"))
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(kvp) = self.parser.get_next_kvp().unwrap() {
-            if Some(kvp.dict_index) != self.previous_dict_index {
-                // We are done with the current record, so we can store it
-                let change = std::mem::take(&mut self.current_change).try_into().unwrap();
-                self.previous_dict_index = Some(kvp.dict_index);
+    fn finish_into(&mut self, output: &mut Option<P4Changelist>) -> Result<bool, P4Error> {
+        let change = output.get_or_insert_with(P4Changelist::default);
+        self.change.finish_into(change)?;
+        change.description_truncated = self
+            .description_limit
+            .is_some_and(|limit| change.description.chars().count() >= limit);
+        if let Some(scanner) = &self.description_scanner {
+            scanner.annotate(change);
+        }
 
-                Self::populate_field(&mut self.current_change, kvp.key, kvp.value);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(changelist = change.changelist, "parsed changelist");
 
-                return Some(change);
-            }
+        Ok(true)
+    }
 
-            Self::populate_field(&mut self.current_change, kvp.key, kvp.value);
+    fn next_fields(&mut self) -> Option<Self> {
+        Some(InterimP4ChangesEntry {
+            change: std::mem::take(&mut self.change),
+            description_scanner: self.description_scanner.clone(),
+            description_limit: self.description_limit,
+        })
+    }
+}
+
+#[cfg(feature = "process")]
+impl P4Changelist {
+    // The whole description, from `p4 describe` when the one listed may have been cut short
+    pub fn full_description(&self, context: &P4Context) -> Result<String, P4Error> {
+        if !self.description_truncated {
+            return Ok(self.description.clone());
         }
 
-        // Yield the final CL
-        if self.previous_dict_index.is_some() {
-            let change = std::mem::take(&mut self.current_change).try_into().unwrap();
-            self.previous_dict_index = None;
-            return Some(change);
+        let query = P4DescribeQuery::new(self.changelist).with_max_files(1);
+        let mut describe = P4DescribeIterator::new_from_context(context, query)?;
+        describe.by_ref().try_for_each(|file| file.map(drop))?;
+        Ok(describe.get_changelist().description.clone())
+    }
+}
+
+#[cfg(feature = "process")]
+type PendingDescribe = Result<(P4Changelist, P4DescribeIterator<P4Output>), P4Error>;
+
+#[cfg(feature = "process")]
+pub struct P4DescribeEach<ReadT: io::Read> {
+    changes: P4ChangesIterator<ReadT>,
+    context: P4Context,
+    prefetch: usize,
+    // Changelists whose describe has been started, oldest first
+    pending: VecDeque<PendingDescribe>,
+    progress: ProgressTracker,
+}
+
+#[cfg(feature = "process")]
+impl<ReadT: io::Read> P4DescribeEach<ReadT> {
+    // How many describes to keep running ahead of the changelist being yielded
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    // Reports each changelist as it is yielded
+    pub fn with_progress(mut self, sink: impl ProgressSink + 'static) -> Self {
+        self.progress = ProgressTracker::new(Some(Arc::new(sink)));
+        self
+    }
+
+    fn start_describes(&mut self) {
+        while self.pending.len() <= self.prefetch {
+            let Some(changelist) = self.changes.next() else {
+                return;
+            };
+            self.pending.push_back(changelist.and_then(|changelist| {
+                let describe =
+                    P4DescribeIterator::new_from_context(&self.context, changelist.changelist)?;
+                Ok((changelist, describe))
+            }));
         }
+    }
+}
+
+#[cfg(feature = "process")]
+impl<ReadT: io::Read> Iterator for P4DescribeEach<ReadT> {
+    type Item = Result<P4Changelist, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.start_describes();
+        let pending = self.pending.pop_front()?;
+        self.progress.ensure_phase("describe");
+        Some(pending.and_then(|(mut changelist, describe)| {
+            changelist.files = describe.collect::<Result<_, _>>()?;
+            let item = changelist.changelist.to_string();
+            self.progress.record(&item, 0);
+            Ok(changelist)
+        }))
+    }
+}
 
-        // Ensure the process is cleaned up
-        if let Some(mut p4_process) = self.p4_process.take() {
-            p4_process.wait().expect("Failed to wait for p4 process");
+// A changelist from `p4 changes`, which doesn't list files, with the files described on first use.
+// Derefs to the changelist, whose own `files` stays empty.
+#[cfg(feature = "process")]
+pub struct P4ChangelistHandle {
+    header: P4Changelist,
+    context: Arc<P4Context>,
+    files: OnceLock<Vec<P4File>>,
+}
+
+#[cfg(feature = "process")]
+impl P4ChangelistHandle {
+    // Runs `p4 describe` the first time, errors aren't cached so a later call tries again
+    pub fn files(&self) -> Result<&[P4File], P4Error> {
+        if let Some(files) = self.files.get() {
+            return Ok(files);
         }
-        None
+
+        let files = P4DescribeIterator::new_from_context(&self.context, self.header.changelist)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.files.get_or_init(|| files))
+    }
+
+    pub fn into_changelist(self) -> Result<P4Changelist, P4Error> {
+        self.files()?;
+        let mut changelist = self.header;
+        changelist.files = self.files.into_inner().unwrap_or_default();
+        Ok(changelist)
+    }
+}
+
+#[cfg(feature = "process")]
+impl Deref for P4ChangelistHandle {
+    type Target = P4Changelist;
+
+    fn deref(&self) -> &P4Changelist {
+        &self.header
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cancel::*;
     use std::fs;
 
     #[test]
@@ -123,6 +679,7 @@ mod tests {
                 user: "david".into(),
                 description: "Long yeet\n".into(),
                 files: vec![],
+                ..Default::default()
             },
             P4Changelist {
                 changelist: 9,
@@ -130,6 +687,7 @@ mod tests {
                 user: "david".into(),
                 description: "Description\n".into(),
                 files: vec![],
+                ..Default::default()
             },
             P4Changelist {
                 changelist: 8,
@@ -137,6 +695,7 @@ mod tests {
                 user: "david".into(),
                 description: "Another description\n".into(),
                 files: vec![],
+                ..Default::default()
             },
             P4Changelist {
                 changelist: 7,
@@ -144,6 +703,7 @@ mod tests {
                 user: "david".into(),
                 description: "Another change\n".into(),
                 files: vec![],
+                ..Default::default()
             },
             P4Changelist {
                 changelist: 6,
@@ -151,6 +711,7 @@ mod tests {
                 user: "david".into(),
                 description: "Yo what\n".into(),
                 files: vec![],
+                ..Default::default()
             },
             P4Changelist {
                 changelist: 5,
@@ -158,6 +719,7 @@ mod tests {
                 user: "david".into(),
                 description: "Test3".into(),
                 files: vec![],
+                ..Default::default()
             },
             P4Changelist {
                 changelist: 2,
@@ -165,6 +727,7 @@ mod tests {
                 user: "david".into(),
                 description: "Test delete\n".into(),
                 files: vec![],
+                ..Default::default()
             },
             P4Changelist {
                 changelist: 1,
@@ -172,15 +735,436 @@ mod tests {
                 user: "david".into(),
                 description: "Test submit\n".into(),
                 files: vec![],
+                ..Default::default()
             },
         ];
 
-        let mut expected_iter = expected.into_iter();
-        while let Some(change) = expected_iter.next() {
-            assert_eq!(changes_iter.next(), Some(change), "Change mismatch");
+        for change in expected {
+            assert_eq!(
+                changes_iter.next().unwrap().unwrap(),
+                change,
+                "Change mismatch"
+            );
         }
 
         // Make sure there are no more records
-        assert_eq!(changes_iter.next(), None, "Expected no more changes");
+        assert!(changes_iter.next().is_none(), "Expected no more changes");
+    }
+
+    #[test]
+    fn test_changes_server_error() {
+        let data = crate::parsers::py_dict::to_py_dict_bytes(&[&[
+            ("code", "error"),
+            ("data", "Path '//nope/...' is not under client's root.\n"),
+            ("severity", "3"),
+            ("generic", "2"),
+        ]]);
+        let mut changes_iter = P4ChangesIterator::new_from_reader(&data[..]);
+
+        match changes_iter.next() {
+            Some(Err(P4Error::Server(message))) => {
+                assert_eq!(message.severity, E_FAILED);
+                assert!(message.data.starts_with("Path '//nope/...'"));
+            }
+            other => panic!("Expected a server error, got {:?}", other),
+        }
+        assert!(changes_iter.next().is_none());
+
+        // A warning is skipped and counted, as for the other commands
+        let data = crate::parsers::py_dict::to_py_dict_bytes(&[
+            &[
+                ("code", "error"),
+                ("data", "//depot/nope/... - no such file(s).\n"),
+                ("severity", "2"),
+                ("generic", "17"),
+            ],
+            &[
+                ("code", "stat"),
+                ("change", "9"),
+                ("time", "1743724741"),
+                ("user", "david"),
+                ("desc", "Fix the build\n"),
+                ("status", "submitted"),
+            ],
+        ]);
+        let mut changes_iter = P4ChangesIterator::new_from_reader(&data[..]);
+        let mut changelist = P4Changelist::default();
+        assert!(matches!(
+            changes_iter.next_into(&mut changelist),
+            Some(Ok(()))
+        ));
+        assert_eq!(changelist.changelist, 9);
+        assert!(changes_iter.next_into(&mut changelist).is_none());
+        assert_eq!(changes_iter.summary().unwrap().records_skipped, 1);
+
+        // Empty output is just an empty iterator
+        assert!(
+            P4ChangesIterator::new_from_reader(&b""[..])
+                .next()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_changes_cancellation() {
+        let test_file = fs::File::open("./test_data/changes.pyc").unwrap();
+        let cancellation = CancellationToken::new();
+        let mut changes_iter = P4ChangesIterator::new_from_reader(test_file)
+            .with_cancellation_token(cancellation.clone());
+
+        assert_eq!(changes_iter.next().unwrap().unwrap().changelist, 10);
+        assert_eq!(changes_iter.next().unwrap().unwrap().changelist, 9);
+
+        cancellation.cancel();
+        match changes_iter.next() {
+            Some(Err(P4Error::Cancelled { records_yielded })) => assert_eq!(records_yielded, 2),
+            other => panic!("Expected cancellation, got {:?}", other),
+        }
+        assert!(changes_iter.next().is_none());
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_lazy_files() {
+        use crate::testing::*;
+
+        let mock = Arc::new(
+            MockP4::new()
+                .with_fixture("changes", "./test_data/changes.pyc")
+                .unwrap()
+                .with_fixture("describe -s 5", "./test_data/describe.pyc")
+                .unwrap(),
+        );
+        let context = P4Context::new().with_backend(mock.clone());
+
+        let handles = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .with_lazy_files(&context)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(handles.len(), 8);
+        assert_eq!(mock.calls().len(), 1);
+
+        let handle = handles
+            .iter()
+            .find(|handle| handle.changelist == 5)
+            .unwrap();
+        assert_eq!(handle.files().unwrap().len(), 10);
+        assert_eq!(handle.files().unwrap().len(), 10);
+        assert_eq!(mock.calls().len(), 2);
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_describe_each() {
+        use crate::testing::*;
+
+        let change = |change: &'static str| {
+            [
+                ("code", "stat"),
+                ("change", change),
+                ("time", "1743724741"),
+                ("user", "alice"),
+                ("desc", "Fix the build\n"),
+            ]
+            .to_vec()
+        };
+        let describe = |number: &'static str| {
+            let mut record = change(number);
+            record.extend([
+                ("depotFile0", "//depot/a.txt"),
+                ("action0", "edit"),
+                ("rev0", "2"),
+                ("fileSize0", "4"),
+                ("digest0", "00000000000000000000000000000000"),
+            ]);
+            [record]
+        };
+        let mock = Arc::new(
+            MockP4::new()
+                .with_records("changes -s submitted -l", [change("7"), change("6")])
+                .with_records("describe -s 7", describe("7"))
+                .with_records("describe -s 6", describe("6")),
+        );
+        let context = P4Context::new().with_backend(mock.clone());
+
+        let mut changelists = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .describe_each(&context);
+        assert_eq!(mock.calls().len(), 1);
+        let changelist = changelists.next().unwrap().unwrap();
+        assert_eq!((changelist.changelist, changelist.files.len()), (7, 1));
+        assert_eq!(mock.calls().len(), 2);
+        let changelist = changelists.next().unwrap().unwrap();
+        assert_eq!(changelist.changelist, 6);
+        assert!(changelists.next().is_none());
+
+        let mut changelists = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .describe_each(&context)
+            .with_prefetch(1);
+        changelists.next().unwrap().unwrap();
+        assert_eq!(mock.calls().len(), 6);
+        assert_eq!(changelists.count(), 1);
+
+        let progress = ProgressLog::new();
+        let changelists = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .describe_each(&context)
+            .with_progress(progress.clone());
+        assert_eq!(changelists.count(), 2);
+        assert_eq!(
+            progress.updates(),
+            [
+                ("describe", 0, 0, None),
+                ("describe", 1, 0, Some("7".to_string())),
+                ("describe", 2, 0, Some("6".to_string()))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_changes_query() {
+        assert_eq!(
+            P4ChangesQuery::new().args(),
+            ["changes", "-s", "submitted", "-l"]
+        );
+        assert_eq!(
+            P4ChangesQuery::from(Some(5..10)).args(),
+            ["changes", "-s", "submitted", "-l", "@5,10"]
+        );
+        assert_eq!(
+            P4ChangesQuery::new()
+                .with_range(5..10)
+                .with_status(P4ChangeStatus::Pending)
+                .with_user("alice")
+                .with_client("alice_ws")
+                .with_max(20)
+                .with_path("//depot/main/...")
+                .with_path("//depot/rel/...")
+                .with_description_mode(P4DescriptionMode::Truncated)
+                .args(),
+            [
+                "changes",
+                "-s",
+                "pending",
+                "-L",
+                "-u",
+                "alice",
+                "-c",
+                "alice_ws",
+                "-m",
+                "20",
+                "//depot/main/...@5,10",
+                "//depot/rel/...@5,10"
+            ]
+        );
+        assert_eq!(
+            P4ChangesQuery::new()
+                .with_include_integrations()
+                .with_path("//depot/rel/...")
+                .args(),
+            ["changes", "-s", "submitted", "-l", "-i", "//depot/rel/..."]
+        );
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_earliest_and_latest() {
+        use crate::testing::*;
+
+        let change = |change: &'static str| {
+            [[
+                ("code", "stat"),
+                ("change", change),
+                ("time", "1743724741"),
+                ("user", "alice"),
+                ("desc", "Fix the build\n"),
+            ]]
+        };
+        let context = MockP4::new()
+            .with_records(
+                "changes -s submitted -l -m 1 //depot/main/...",
+                change("42"),
+            )
+            .with_records(
+                "changes -s submitted -l -r -m 1 //depot/main/...",
+                change("3"),
+            )
+            .with_records(
+                "changes -s submitted -l -m 1 //depot/empty/...",
+                [] as [[(&str, &str); 0]; 0],
+            )
+            .into_context();
+
+        let latest = latest_from_context(&context, "//depot/main/...").unwrap();
+        assert_eq!(latest.unwrap().changelist, 42);
+        let earliest = earliest_from_context(&context, "//depot/main/...").unwrap();
+        assert_eq!(earliest.unwrap().changelist, 3);
+        assert!(
+            latest_from_context(&context, "//depot/empty/...")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_parallel() {
+        use crate::testing::*;
+
+        assert_eq!(partition_range(1..10, 3), vec![1..4, 5..8, 9..10]);
+        assert_eq!(partition_range(5..6, 4), vec![5..5, 6..6]);
+        assert!(partition_range(Range { start: 6, end: 5 }, 2).is_empty());
+
+        let change = |change: &'static str| {
+            [
+                ("code", "stat"),
+                ("change", change),
+                ("time", "1743724741"),
+                ("user", "alice"),
+                ("desc", "Fix the build\n"),
+            ]
+        };
+        let context = MockP4::new()
+            .with_records("changes -s submitted -l @1,5", [change("5"), change("2")])
+            .with_records("changes -s submitted -l @6,10", [change("9")])
+            .with_records(
+                "changes -s submitted -l -r @1,5",
+                [change("2"), change("5")],
+            )
+            .with_records("changes -s submitted -l -r @6,10", [change("9")])
+            .into_context();
+
+        let numbers = |changelists: Vec<P4Changelist>| {
+            changelists
+                .iter()
+                .map(|changelist| changelist.changelist)
+                .collect::<Vec<_>>()
+        };
+        let newest_first =
+            parallel_from_context(&context, P4ChangesQuery::new(), 1..10, 2).unwrap();
+        assert_eq!(numbers(newest_first), vec![9, 5, 2]);
+        let oldest_first = parallel_from_context(
+            &context,
+            P4ChangesQuery::new().with_oldest_first(),
+            1..10,
+            2,
+        )
+        .unwrap();
+        assert_eq!(numbers(oldest_first), vec![2, 5, 9]);
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_label_range() {
+        use crate::testing::*;
+
+        let query = P4ChangesQuery::new()
+            .with_path("//depot/main/...")
+            .with_label_range("release-1.0", "release-1.1");
+        assert_eq!(
+            query.args(),
+            [
+                "changes",
+                "-s",
+                "submitted",
+                "-l",
+                "//depot/main/...@release-1.0,@release-1.1"
+            ]
+        );
+
+        let mock = MockP4::new()
+            .with_records(
+                "labels -e release-1.0 -m 1",
+                [[("code", "stat"), ("label", "release-1.0")]],
+            )
+            .with_records(
+                "changes -s submitted -l //depot/main/...@release-1.0,@release-1.1",
+                [[
+                    ("code", "stat"),
+                    ("change", "7"),
+                    ("time", "1743724741"),
+                    ("user", "alice"),
+                    ("desc", "Fix the build\n"),
+                ]],
+            )
+            .with_records(
+                "labels -e release-1.1 -m 1",
+                [[("code", "stat"), ("label", "release-1.1")]],
+            )
+            .with_records("labels -e release-2.0 -m 1", [] as [[(&str, &str); 0]; 0]);
+        let context = mock.into_context();
+        let changes = P4ChangesIterator::new_from_context(&context, query)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(changes[0].changelist, 7);
+
+        let misspelt = P4ChangesQuery::new().with_label_range("release-1.0", "release-2.0");
+        assert!(matches!(
+            P4ChangesIterator::new_from_context(&context, misspelt),
+            Err(P4Error::NoSuchLabel(label)) if label == "release-2.0"
+        ));
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_full_description() {
+        use crate::testing::*;
+
+        let long_description = format!("{}\n", "Rework the build. ".repeat(20));
+        let short_description = &long_description[..31];
+        let mock = Arc::new(
+            MockP4::new()
+                .with_records(
+                    "changes -s submitted",
+                    [
+                        [
+                            ("code", "stat"),
+                            ("change", "8"),
+                            ("time", "1743724741"),
+                            ("user", "alice"),
+                            ("desc", short_description),
+                        ],
+                        [
+                            ("code", "stat"),
+                            ("change", "7"),
+                            ("time", "1743723360"),
+                            ("user", "alice"),
+                            ("desc", "Fix the build\n"),
+                        ],
+                    ],
+                )
+                .with_records(
+                    "describe -s -m 1 8",
+                    [[
+                        ("code", "stat"),
+                        ("change", "8"),
+                        ("time", "1743724741"),
+                        ("user", "alice"),
+                        ("desc", long_description.as_str()),
+                    ]],
+                ),
+        );
+        let context = P4Context::new().with_backend(mock.clone());
+        let query = P4ChangesQuery::new().with_description_mode(P4DescriptionMode::Short);
+        let changes = P4ChangesIterator::new_from_context(&context, query)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(changes[0].description_truncated);
+        assert!(!changes[1].description_truncated);
+
+        assert_eq!(
+            changes[1].full_description(&context).unwrap(),
+            "Fix the build\n"
+        );
+        assert_eq!(mock.calls().len(), 1);
+        assert_eq!(
+            changes[0].full_description(&context).unwrap(),
+            long_description
+        );
+        assert_eq!(mock.calls().len(), 2);
     }
 }
@@ -0,0 +1,476 @@
+// == Std crates
+use std::{fmt, str::FromStr};
+#[cfg(feature = "process")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// == Internal crates
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+use crate::error::*;
+use crate::view::*;
+
+// The Type field of a client spec
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum P4ClientType {
+    #[default]
+    Writeable,
+    // Can sync but not open files, with its have list kept in a separate db table
+    Readonly,
+    // Like readonly, but files can be opened for edit
+    Partitioned,
+    Graph,
+}
+
+impl P4ClientType {
+    const NAMES: [(P4ClientType, &'static str); 4] = [
+        (P4ClientType::Writeable, "writeable"),
+        (P4ClientType::Readonly, "readonly"),
+        (P4ClientType::Partitioned, "partitioned"),
+        (P4ClientType::Graph, "graph"),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(client_type, _)| client_type == self)
+            .map_or("writeable", |(_, name)| name)
+    }
+}
+
+impl FromStr for P4ClientType {
+    type Err = P4ValueParseError;
+
+    fn from_str(client_type: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| *name == client_type)
+            .map(|(client_type, _)| *client_type)
+            .ok_or_else(|| P4ValueParseError::UnknownClientType(client_type.to_string()))
+    }
+}
+
+impl fmt::Display for P4ClientType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// The LineEnd field of a client spec, how line endings of text files are written in the workspace
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum P4LineEnd {
+    // The client platform's, CRLF on Windows and LF elsewhere
+    #[default]
+    Local,
+    Unix,
+    Mac,
+    Win,
+    // Written as LF, with CRLF converted to LF when submitting
+    Share,
+}
+
+impl P4LineEnd {
+    const NAMES: [(P4LineEnd, &'static str); 5] = [
+        (P4LineEnd::Local, "local"),
+        (P4LineEnd::Unix, "unix"),
+        (P4LineEnd::Mac, "mac"),
+        (P4LineEnd::Win, "win"),
+        (P4LineEnd::Share, "share"),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(line_end, _)| line_end == self)
+            .map_or("local", |(_, name)| name)
+    }
+
+    // The line ending text files have on disk
+    pub fn bytes(&self) -> &'static [u8] {
+        match self {
+            P4LineEnd::Local if cfg!(windows) => b"\r\n",
+            P4LineEnd::Local | P4LineEnd::Unix | P4LineEnd::Share => b"\n",
+            P4LineEnd::Mac => b"\r",
+            P4LineEnd::Win => b"\r\n",
+        }
+    }
+}
+
+impl FromStr for P4LineEnd {
+    type Err = P4ValueParseError;
+
+    fn from_str(line_end: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| *name == line_end)
+            .map(|(line_end, _)| *line_end)
+            .ok_or_else(|| P4ValueParseError::UnknownLineEnd(line_end.to_string()))
+    }
+}
+
+impl fmt::Display for P4LineEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// The fields of a client spec this crate reads and writes, as `p4 client -o` prints them. The right side of
+// each view line starts with //<name>/.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4ClientSpec {
+    pub name: String,
+    pub owner: String,
+    pub root: String,
+    pub client_type: P4ClientType,
+    // e.g. allwrite clobber nocompress unlocked nomodtime rmdir
+    pub options: String,
+    pub line_end: P4LineEnd,
+    // A stream client's view comes from the stream rather than `view`
+    pub stream: Option<String>,
+    pub view: P4View,
+    // The fields this crate doesn't model, e.g. Description, Host, SubmitOptions and AltRoots0, written back
+    // as they were read
+    pub other_fields: Vec<(String, String)>,
+}
+
+impl P4ClientSpec {
+    pub fn new(name: &str, root: &str) -> Self {
+        P4ClientSpec {
+            name: name.to_string(),
+            root: root.to_string(),
+            options: "allwrite clobber nocompress unlocked nomodtime rmdir".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_type(mut self, client_type: P4ClientType) -> Self {
+        self.client_type = client_type;
+        self
+    }
+
+    pub fn with_stream(mut self, stream: &str) -> Self {
+        self.stream = Some(stream.to_string());
+        self
+    }
+
+    // Takes a view whose right sides are relative to the root, e.g. `//depot/main/... main/...`
+    pub fn with_relative_view(mut self, view: &P4View) -> Self {
+        self.view = P4View {
+            lines: view
+                .lines
+                .iter()
+                .map(|line| P4ViewLine {
+                    right: format!("//{}/{}", self.name, line.right.trim_start_matches('/')),
+                    ..line.clone()
+                })
+                .collect(),
+        };
+        self
+    }
+
+    // From the fields of `p4 client -o`
+    pub fn from_spec<'a>(
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, P4ValueParseError> {
+        let mut spec = P4ClientSpec::default();
+        let mut lines = Vec::new();
+        for (key, value) in fields {
+            match key {
+                "Client" => spec.name = value.to_string(),
+                "Owner" => spec.owner = value.to_string(),
+                "Root" => spec.root = value.to_string(),
+                "Type" => spec.client_type = value.parse()?,
+                "Options" => spec.options = value.to_string(),
+                "LineEnd" => spec.line_end = value.parse()?,
+                "Stream" => spec.stream = Some(value.to_string()),
+                _ => {
+                    if let Some(index) = key.strip_prefix("View")
+                        && let Ok(index) = index.parse::<usize>()
+                    {
+                        lines.push((index, value.parse()?));
+                    } else {
+                        spec.other_fields.push((key.to_string(), value.to_string()));
+                    }
+                }
+            }
+        }
+        lines.sort_by_key(|(index, _)| *index);
+        spec.view.lines = lines.into_iter().map(|(_, line)| line).collect();
+        Ok(spec)
+    }
+
+    // The fields for `p4 client -i`
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = vec![
+            ("Client".to_string(), self.name.clone()),
+            ("Root".to_string(), self.root.clone()),
+            ("Type".to_string(), self.client_type.to_string()),
+            ("Options".to_string(), self.options.clone()),
+            ("LineEnd".to_string(), self.line_end.to_string()),
+        ];
+        if !self.owner.is_empty() {
+            fields.push(("Owner".to_string(), self.owner.clone()));
+        }
+        match &self.stream {
+            Some(stream) => fields.push(("Stream".to_string(), stream.clone())),
+            None => fields.extend(
+                self.view
+                    .lines
+                    .iter()
+                    .enumerate()
+                    .map(|(index, line)| (format!("View{}", index), line.to_string())),
+            ),
+        }
+        fields.extend(self.other_fields.iter().cloned());
+        fields
+    }
+}
+
+// The narrowest view that maps the depot paths, each a file or a directory ending in /..., to the same place
+// under the root, e.g. //depot/main/src/... to depot/main/src/... Paths under another path in the list are
+// dropped.
+pub fn sparse_view<'a>(depot_paths: impl IntoIterator<Item = &'a str>) -> P4View {
+    let mut paths = depot_paths.into_iter().collect::<Vec<_>>();
+    paths.sort_unstable();
+    paths.dedup();
+
+    let mut lines: Vec<P4ViewLine> = Vec::new();
+    for path in paths {
+        let covered = lines.iter().any(|line| {
+            line.left
+                .strip_suffix("...")
+                .is_some_and(|dir| path.starts_with(dir))
+        });
+        if !covered {
+            lines.push(P4ViewLine::new(path, path.trim_start_matches('/')));
+        }
+    }
+    P4View { lines }
+}
+
+#[cfg(feature = "spawn")]
+pub fn fetch(name: &str) -> Result<P4ClientSpec, P4Error> {
+    fetch_from_context(&P4Context::default(), name)
+}
+
+#[cfg(feature = "process")]
+pub fn fetch_from_context(context: &P4Context, name: &str) -> Result<P4ClientSpec, P4Error> {
+    fetch_spec(context, vec!["client", "-o", name])
+}
+
+// The spec of the client commands run in, the context's or P4CLIENT
+#[cfg(feature = "process")]
+pub fn fetch_current_from_context(context: &P4Context) -> Result<P4ClientSpec, P4Error> {
+    fetch_spec(context, vec!["client", "-o"])
+}
+
+#[cfg(feature = "process")]
+fn fetch_spec(context: &P4Context, args: Vec<&str>) -> Result<P4ClientSpec, P4Error> {
+    let spec = P4DictIterator::new_from_context(context, args)?
+        .next()
+        .ok_or(P4Error::InvalidRecord("Missing client spec"))??;
+    P4ClientSpec::from_spec(spec.iter()).map_err(|_| P4Error::InvalidRecord("Invalid client spec"))
+}
+
+// Creates or updates the client with `p4 client -i`
+#[cfg(feature = "spawn")]
+pub fn save(spec: &P4ClientSpec) -> Result<(), P4Error> {
+    save_from_context(&P4Context::default(), spec)
+}
+
+#[cfg(feature = "process")]
+pub fn save_from_context(context: &P4Context, spec: &P4ClientSpec) -> Result<(), P4Error> {
+    run_with_input_from_context(context, vec!["client", "-i"], [spec.fields()])?
+        .try_for_each(|dict| dict.map(drop))
+}
+
+#[cfg(feature = "process")]
+static NEXT_CLIENT: AtomicU64 = AtomicU64::new(0);
+
+// A uniquely named workspace under the temp dir that is deleted, with its root, when dropped. Meant for CI
+// jobs that need a throwaway view.
+#[cfg(feature = "process")]
+#[derive(Debug)]
+pub struct P4TempClient {
+    name: String,
+    root: PathBuf,
+    context: P4Context,
+}
+
+#[cfg(feature = "process")]
+impl P4TempClient {
+    // The right side of each view line is relative to the workspace root, e.g. `//depot/main/... main/...`
+    pub fn create(context: &P4Context, view: &P4View) -> Result<Self, P4Error> {
+        Self::create_of_type(context, view, P4ClientType::Writeable)
+    }
+
+    // e.g. a readonly client for a build agent that only syncs, see also sparse_view
+    pub fn create_of_type(
+        context: &P4Context,
+        view: &P4View,
+        client_type: P4ClientType,
+    ) -> Result<Self, P4Error> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        let name = format!(
+            "p4_helper_{}_{}_{}",
+            process::id(),
+            nanos,
+            NEXT_CLIENT.fetch_add(1, Ordering::Relaxed)
+        );
+        let root = std::env::temp_dir().join(&name);
+        fs::create_dir_all(&root)?;
+
+        // Owns the root from here, so a failure below still removes it
+        let client = P4TempClient {
+            context: context.clone().with_client(&name),
+            name,
+            root,
+        };
+
+        let spec = P4ClientSpec::new(&client.name, &client.root.to_string_lossy())
+            .with_type(client_type)
+            .with_relative_view(view);
+        save_from_context(&client.context, &spec)?;
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // The context this was created with, using the workspace
+    pub fn context(&self) -> &P4Context {
+        &self.context
+    }
+}
+
+#[cfg(feature = "process")]
+impl Drop for P4TempClient {
+    // Best effort, also while unwinding from a panic. Fails if files are still open in the workspace.
+    fn drop(&mut self) {
+        if let Ok(output) =
+            P4DictIterator::new_from_context(&self.context, vec!["client", "-d", &self.name])
+        {
+            output.for_each(drop);
+        }
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_view() {
+        let view = sparse_view([
+            "//depot/main/src/...",
+            "//depot/main/src/lib/util.rs",
+            "//depot/main/build.py",
+            "//depot/main/src/...",
+            "//depot/tools/...",
+        ]);
+        let lines = view
+            .lines
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "//depot/main/build.py depot/main/build.py",
+                "//depot/main/src/... depot/main/src/...",
+                "//depot/tools/... depot/tools/...",
+            ]
+        );
+
+        let spec = P4ClientSpec::new("agent-1", "/build")
+            .with_type(P4ClientType::Readonly)
+            .with_relative_view(&view);
+        assert_eq!(
+            spec.view.translate("//depot/main/src/a.rs").as_deref(),
+            Some("//agent-1/depot/main/src/a.rs")
+        );
+        let fields = spec.fields();
+        let parsed = P4ClientSpec::from_spec(fields.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        assert_eq!(parsed.unwrap(), spec);
+
+        assert_eq!("partitioned".parse(), Ok(P4ClientType::Partitioned));
+        assert!("virtual".parse::<P4ClientType>().is_err());
+    }
+
+    #[test]
+    fn test_client_spec_round_trip() {
+        let fields = [
+            ("Client", "alice-ws"),
+            ("Owner", "alice"),
+            ("Host", "build-01"),
+            ("Description", "Created by alice.\n"),
+            ("Root", "/work"),
+            ("AltRoots0", "C:\\work"),
+            (
+                "Options",
+                "noallwrite noclobber nocompress unlocked nomodtime normdir",
+            ),
+            ("SubmitOptions", "revertunchanged"),
+            ("LineEnd", "share"),
+            ("View0", "//depot/main/... //alice-ws/main/..."),
+        ];
+        let spec = P4ClientSpec::from_spec(fields).unwrap();
+        assert_eq!(spec.line_end, P4LineEnd::Share);
+        assert_eq!(spec.line_end.bytes(), b"\n");
+
+        let written = spec.fields();
+        for (key, value) in fields {
+            assert!(
+                written.contains(&(key.to_string(), value.to_string())),
+                "{} was not written back",
+                key
+            );
+        }
+        assert!(P4ClientSpec::from_spec([("LineEnd", "amiga")]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_temp_client() {
+        use crate::testing::*;
+        use std::sync::Arc;
+
+        let mock = Arc::new(
+            MockP4::new().with_records("client", [[("code", "info"), ("data", "Client saved.")]]),
+        );
+        let context = P4Context::new().with_backend(mock.clone());
+        let view = P4View::from_lines([
+            "//depot/main/... main/...",
+            "-//depot/main/tmp/... main/tmp/...",
+        ])
+        .unwrap();
+
+        let client = P4TempClient::create(&context, &view).unwrap();
+        let name = client.name().to_string();
+        let root = client.root().to_path_buf();
+        assert!(root.is_dir());
+        assert_eq!(client.context().client(), Some(name.as_str()));
+        assert_ne!(P4TempClient::create(&context, &view).unwrap().name(), name);
+        drop(client);
+
+        assert!(!root.exists());
+        let calls = mock.calls();
+        assert_eq!(calls[0], ["client", "-i"]);
+        assert!(calls.contains(&vec!["client".to_string(), "-d".to_string(), name]));
+    }
+}
@@ -0,0 +1,450 @@
+// == Std crates
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+// == Internal crates
+use crate::cancel::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::dict::*;
+use crate::error::*;
+use crate::metrics::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::parsers::py_dict::*;
+use crate::process_state::*;
+use crate::records::*;
+pub use crate::records::{P4FieldsFactory, P4RecordFields};
+
+// A command whose records each become one item, or several with P4RecordFields::finish_item_into. The query
+// types implement it, e.g. P4FstatQuery, so a new wrapper only needs its arguments and how to fill in its
+// fields; P4CommandIterator does the rest.
+pub trait P4Command {
+    type Item;
+    type Fields: P4RecordFields<Output = Self::Item>;
+
+    // The command and its arguments, e.g. ["fstat", "-Ol", "//depot/..."]
+    fn args(&self) -> Vec<String>;
+
+    // Where each record's fields start, None for Fields::default()
+    fn new_fields(&self) -> Option<P4FieldsFactory<Self::Fields>> {
+        None
+    }
+}
+
+// Runs a P4Command and yields its items, skipping warnings. Spawning, retries, `-x -` paths, cancellation and
+// metrics are the same for every command.
+pub struct P4CommandIterator<CommandT: P4Command, ReadT: io::Read> {
+    process_state: P4ProcessState,
+    records: P4RecordReader<ReadT, CommandT::Fields>,
+}
+
+#[cfg(feature = "process")]
+impl<CommandT: P4Command> P4CommandIterator<CommandT, P4Output> {
+    pub fn spawn(context: &P4Context, command: CommandT) -> Result<Self, P4Error> {
+        let command_args = command.args();
+        let args = command_args.iter().map(String::as_str).collect::<Vec<_>>();
+        let (p4_process, reader) = context.spawn(args.clone())?;
+
+        let parser = context.parser(reader);
+        let mut result = Self::new_from_parser(parser).for_command(&command);
+        result.process_state.attach(p4_process, context, &args);
+
+        Ok(result)
+    }
+
+    // Any number of paths after the command's own arguments, streamed to `p4 -x -` rather than put on the
+    // command line
+    pub fn spawn_with_paths(
+        context: &P4Context,
+        command: CommandT,
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+    ) -> Result<Self, P4Error> {
+        let command_args = command.args();
+        let args = command_args.iter().map(String::as_str).collect::<Vec<_>>();
+        let (p4_process, reader) = context.spawn_with_filespecs(args.clone(), paths.into_iter())?;
+
+        let parser = context.parser(reader);
+        let mut result = Self::new_from_parser(parser).for_command(&command);
+        result.process_state.attach(p4_process, context, &args);
+
+        Ok(result)
+    }
+}
+
+impl<CommandT: P4Command, ReadT: io::Read> P4CommandIterator<CommandT, ReadT> {
+    pub fn new_from_reader(reader: ReadT) -> Self {
+        Self::new_from_parser(P4PyDictParser::new(reader))
+    }
+
+    pub fn new_from_parser(parser: P4PyDictParser<ReadT>) -> Self {
+        P4CommandIterator {
+            process_state: P4ProcessState::default(),
+            records: P4RecordReader::new(parser),
+        }
+    }
+
+    // Parses the records the way `command` does, for output saved from it. The spawned iterators do this
+    // themselves.
+    pub fn for_command(mut self, command: &CommandT) -> Self {
+        if let Some(new_fields) = command.new_fields() {
+            self.records = self.records.with_new_fields(new_fields);
+        }
+        self
+    }
+
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.process_state.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn records_yielded(&self) -> u64 {
+        self.process_state.records_yielded()
+    }
+
+    // Totals for the command, None until the iterator is exhausted
+    pub fn summary(&self) -> Option<P4IteratorSummary> {
+        self.process_state.summary(self.records.records_skipped())
+    }
+
+    // Same as next(), reading into `item` instead of a new value. Items whose fields keep their buffers, e.g.
+    // changelists, don't allocate per record when read into the same one over and over.
+    pub fn next_into(&mut self, item: &mut CommandT::Item) -> Option<Result<(), P4Error>>
+    where
+        CommandT::Item: Default,
+    {
+        if let Some(result) = self.process_state.before_next() {
+            return result;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = self.process_state.span().entered();
+
+        let mut output = Some(std::mem::take(item));
+        let result = self.records.next_output_into(&mut output);
+        *item = output.unwrap_or_default();
+        let bytes_read = self.records.bytes_read();
+        self.process_state
+            .after_next(result.map(|found| found.then_some(())), bytes_read)
+    }
+
+    pub(crate) fn fields(&self) -> &CommandT::Fields {
+        self.records.fields()
+    }
+
+    pub(crate) fn fields_mut(&mut self) -> &mut CommandT::Fields {
+        self.records.fields_mut()
+    }
+}
+
+impl<CommandT: P4Command, ReadT: io::Read> Iterator for P4CommandIterator<CommandT, ReadT> {
+    type Item = Result<CommandT::Item, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(result) = self.process_state.before_next() {
+            return result;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = self.process_state.span().entered();
+
+        let result = self.records.next_output();
+        let bytes_read = self.records.bytes_read();
+        self.process_state.after_next(result, bytes_read)
+    }
+}
+
+// Turns one record of a command the crate doesn't wrap into an item, e.g. for site-specific extension or broker
+// commands. P4Dict::indexed_fields groups indexed fields such as how0,1. Closures taking a &P4Dict are mappers.
+pub trait P4RecordMapper: Send + Sync {
+    type Item;
+
+    fn map_record(&self, record: &P4Dict) -> Result<Self::Item, P4Error>;
+}
+
+impl<FunctionT, ItemT> P4RecordMapper for FunctionT
+where
+    FunctionT: Fn(&P4Dict) -> Result<ItemT, P4Error> + Send + Sync,
+{
+    type Item = ItemT;
+
+    fn map_record(&self, record: &P4Dict) -> Result<ItemT, P4Error> {
+        self(record)
+    }
+}
+
+impl<MapperT: P4RecordMapper + ?Sized> P4RecordMapper for Arc<MapperT> {
+    type Item = MapperT::Item;
+
+    fn map_record(&self, record: &P4Dict) -> Result<Self::Item, P4Error> {
+        (**self).map_record(record)
+    }
+}
+
+// A command the crate doesn't wrap, each record collected into a P4Dict and handed to the mapper
+pub struct P4MappedCommand<MapperT> {
+    args: Vec<String>,
+    mapper: MapperT,
+}
+
+impl<MapperT> P4MappedCommand<MapperT> {
+    // `args` is the command and its arguments, e.g. ["ext-audit", "-a"]
+    pub fn new(args: &[&str], mapper: MapperT) -> Self {
+        P4MappedCommand {
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            mapper,
+        }
+    }
+}
+
+impl<MapperT: P4RecordMapper + Clone + 'static> P4Command for P4MappedCommand<MapperT> {
+    type Item = MapperT::Item;
+    type Fields = P4MappedFields<MapperT>;
+
+    fn args(&self) -> Vec<String> {
+        self.args.clone()
+    }
+
+    fn new_fields(&self) -> Option<P4FieldsFactory<Self::Fields>> {
+        let mapper = self.mapper.clone();
+        let keys = Arc::new(Mutex::new(KeyInterner::default()));
+        Some(Box::new(move || P4MappedFields {
+            record: P4Dict::default(),
+            keys: Some(keys.clone()),
+            mapper: Some(mapper.clone()),
+        }))
+    }
+}
+
+// The parsing state of a mapped record. The default one has no mapper, records read without
+// P4CommandIterator::for_command are invalid.
+pub struct P4MappedFields<MapperT> {
+    record: P4Dict,
+    keys: Option<Arc<Mutex<KeyInterner>>>,
+    mapper: Option<MapperT>,
+}
+
+impl<MapperT> Default for P4MappedFields<MapperT> {
+    fn default() -> Self {
+        P4MappedFields {
+            record: P4Dict::default(),
+            keys: None,
+            mapper: None,
+        }
+    }
+}
+
+impl<MapperT: P4RecordMapper> P4RecordFields for P4MappedFields<MapperT> {
+    type Output = MapperT::Item;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        let key = match &self.keys {
+            Some(keys) => keys.lock().unwrap().intern(key),
+            None => Arc::from(key),
+        };
+        self.record.push(key, value.to_string());
+        Ok(())
+    }
+
+    fn finish(self) -> Result<MapperT::Item, P4Error> {
+        let mapper = self
+            .mapper
+            .ok_or(P4Error::InvalidRecord("No mapper for the command"))?;
+        mapper.map_record(&self.record)
+    }
+}
+
+// The records of any command through a mapper
+pub type P4MappedIterator<MapperT, ReadT> = P4CommandIterator<P4MappedCommand<MapperT>, ReadT>;
+
+// Mappers by command name, so downstream code can run its own commands by name like the built-in ones. The
+// items are usually an enum with a variant per command.
+pub struct P4CommandRegistry<ItemT> {
+    mappers: HashMap<String, Arc<dyn P4RecordMapper<Item = ItemT>>>,
+}
+
+impl<ItemT> Default for P4CommandRegistry<ItemT> {
+    fn default() -> Self {
+        P4CommandRegistry {
+            mappers: HashMap::new(),
+        }
+    }
+}
+
+impl<ItemT: 'static> P4CommandRegistry<ItemT> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces any mapper already registered for the command
+    pub fn with_command(
+        mut self,
+        command: &str,
+        mapper: impl P4RecordMapper<Item = ItemT> + 'static,
+    ) -> Self {
+        self.mappers.insert(command.to_string(), Arc::new(mapper));
+        self
+    }
+
+    pub fn is_registered(&self, command: &str) -> bool {
+        self.mappers.contains_key(command)
+    }
+
+    // For records read some other way, e.g. from a capture, through P4MappedCommand and
+    // P4MappedIterator::for_command
+    pub fn mapper(&self, command: &str) -> Option<Arc<dyn P4RecordMapper<Item = ItemT>>> {
+        self.mappers.get(command).cloned()
+    }
+
+    // `args` is the command and its arguments, e.g. ["ext-audit", "-a"]
+    #[cfg(feature = "spawn")]
+    pub fn run(
+        &self,
+        args: Vec<&str>,
+    ) -> Result<P4MappedIterator<Arc<dyn P4RecordMapper<Item = ItemT>>, P4Output>, P4Error> {
+        self.run_from_context(&P4Context::default(), args)
+    }
+
+    #[cfg(feature = "process")]
+    pub fn run_from_context(
+        &self,
+        context: &P4Context,
+        args: Vec<&str>,
+    ) -> Result<P4MappedIterator<Arc<dyn P4RecordMapper<Item = ItemT>>, P4Output>, P4Error> {
+        let command = args.first().copied().unwrap_or_default();
+        let mapper = self
+            .mapper(command)
+            .ok_or_else(|| P4Error::UnregisteredCommand(command.to_string()))?;
+        P4MappedIterator::spawn(context, P4MappedCommand::new(&args, mapper))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+    use crate::*;
+
+    // A wrapper is the query, its fields and nothing else
+    struct P4CountersQuery;
+
+    #[derive(Default)]
+    struct InterimCounter(Option<String>, Option<u64>);
+
+    impl P4RecordFields for InterimCounter {
+        type Output = (String, u64);
+
+        fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+            match key {
+                "counter" => self.0 = Some(value.to_string()),
+                "value" => self.1 = Some(parse_field(value, "Invalid counter value")?),
+                _ => {}
+            }
+            Ok(())
+        }
+
+        fn finish(self) -> Result<(String, u64), P4Error> {
+            Ok((
+                self.0.ok_or(P4Error::InvalidRecord("Missing counter"))?,
+                self.1.unwrap_or_default(),
+            ))
+        }
+    }
+
+    impl P4Command for P4CountersQuery {
+        type Item = (String, u64);
+        type Fields = InterimCounter;
+
+        fn args(&self) -> Vec<String> {
+            vec!["counters".to_string()]
+        }
+    }
+
+    #[test]
+    fn test_command_iterator() {
+        let data = to_py_dict_bytes(&[
+            &[("code", "stat"), ("counter", "change"), ("value", "12")],
+            &[
+                ("code", "error"),
+                ("data", "Protections table is empty.\n"),
+                ("severity", "2"),
+            ],
+            &[("code", "stat"), ("counter", "journal"), ("value", "3")],
+        ]);
+
+        let mut counters = P4CommandIterator::<P4CountersQuery, _>::new_from_reader(&data[..]);
+        let items = counters.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            items,
+            [("change".to_string(), 12), ("journal".to_string(), 3)]
+        );
+        assert_eq!(counters.records_yielded(), 2);
+        assert_eq!(counters.summary().unwrap().records_skipped, 1);
+        assert_eq!(P4CountersQuery.args(), ["counters"]);
+
+        // The same records through a mapper, as the registry reads them
+        let command = P4MappedCommand::new(&["counters"], |record: &P4Dict| {
+            Ok(record.get("counter").unwrap_or_default().to_string())
+        });
+        let names = P4MappedIterator::new_from_reader(&data[..])
+            .for_command(&command)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(names, ["change", "journal"]);
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_command_registry() {
+        use crate::testing::*;
+
+        #[derive(Debug, PartialEq)]
+        enum SiteRecord {
+            Audit { user: String, paths: Vec<String> },
+        }
+
+        let context = MockP4::new()
+            .with_records(
+                "ext-audit",
+                [[
+                    ("code", "stat"),
+                    ("user", "alice"),
+                    ("path0", "//depot/a.txt"),
+                    ("path1", "//depot/b.txt"),
+                ]],
+            )
+            .into_context();
+        let registry = P4CommandRegistry::new().with_command("ext-audit", |record: &P4Dict| {
+            let fields = record.indexed_fields()?;
+            Ok(SiteRecord::Audit {
+                user: record.get("user").unwrap_or_default().to_string(),
+                paths: fields
+                    .values()
+                    .filter_map(|group| group.get("path"))
+                    .map(|path| path.to_string())
+                    .collect(),
+            })
+        });
+        assert!(registry.is_registered("ext-audit"));
+
+        let records = registry
+            .run_from_context(&context, vec!["ext-audit", "-a"])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            records,
+            [SiteRecord::Audit {
+                user: "alice".to_string(),
+                paths: vec!["//depot/a.txt".to_string(), "//depot/b.txt".to_string()],
+            }]
+        );
+        assert!(matches!(
+            registry.run_from_context(&context, vec!["ext-other"]),
+            Err(P4Error::UnregisteredCommand(_))
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+// == Std crates
+use std::{ffi::OsStr, process, sync::Mutex};
+
+const REDACTED: &str = "********";
+
+// Keeps the (redacted) command lines run through a context, for reproducing issues in bug reports
+#[derive(Debug, Default)]
+pub struct CommandLog {
+    entries: Mutex<Vec<String>>,
+}
+
+impl CommandLog {
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub(crate) fn record(&self, command_line: String) {
+        self.entries.lock().unwrap().push(command_line);
+    }
+}
+
+// Renders a command the way it would be typed into a shell, with secrets redacted
+pub fn format_command_line(command: &process::Command) -> String {
+    format_args_line(command.get_program(), command.get_args())
+}
+
+// Same as format_command_line, for commands that are not (or can not be) built as a process::Command
+pub fn format_args_line<I, S>(program: impl AsRef<OsStr>, args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    std::iter::once(program.as_ref().to_string_lossy().into_owned())
+        .chain(redact_args(args))
+        .map(|arg| quote_arg(&arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Hides the values of -P (password or ticket) and anything that looks like a ticket
+pub fn redact_args<I, S>(args: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut result = Vec::new();
+    let mut redact_next = false;
+
+    for arg in args {
+        let arg = arg.as_ref().to_string_lossy();
+        if redact_next {
+            result.push(REDACTED.to_string());
+            redact_next = false;
+        } else if arg == "-P" {
+            result.push(arg.into_owned());
+            redact_next = true;
+        } else if arg.starts_with("-P") {
+            result.push(format!("-P{}", REDACTED));
+        } else if is_ticket(&arg) {
+            result.push(REDACTED.to_string());
+        } else {
+            result.push(arg.into_owned());
+        }
+    }
+
+    result
+}
+
+// Tickets are 32 upper case hex digits
+fn is_ticket(arg: &str) -> bool {
+    arg.len() == 32
+        && arg
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'A'..=b'F').contains(&b))
+}
+
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '$' | '`'))
+    {
+        return arg.to_string();
+    }
+
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let mut command = process::Command::new("p4");
+        command.args([
+            "-u",
+            "david",
+            "-P",
+            "hunter2",
+            "-Psecret",
+            "describe",
+            "-s",
+            "1234",
+            "//depot/some path/...",
+            "A9E93320E1FC469228D707C9124C878C",
+        ]);
+
+        assert_eq!(
+            format_command_line(&command),
+            "p4 -u david -P ******** -P******** describe -s 1234 '//depot/some path/...' ********"
+        );
+    }
+}
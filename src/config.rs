@@ -0,0 +1,100 @@
+// == Std crates
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+const P4CONFIG_VAR: &str = "P4CONFIG";
+
+// The settings from a P4CONFIG file that matter to us, anything else in the file is ignored
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4Config {
+    pub path: PathBuf,
+    pub port: Option<String>,
+    pub user: Option<String>,
+    pub client: Option<String>,
+    pub charset: Option<String>,
+}
+
+impl P4Config {
+    // Looks for the file named by the P4CONFIG environment variable in `dir` and its parents, like p4 does.
+    // Returns None if P4CONFIG isn't set or no file was found.
+    pub fn find(dir: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        match env::var(P4CONFIG_VAR) {
+            Ok(file_name) if !file_name.is_empty() => Self::find_named(dir, &file_name),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn find_named(dir: impl AsRef<Path>, file_name: &str) -> io::Result<Option<Self>> {
+        let dir = dir.as_ref().canonicalize()?;
+        for dir in dir.ancestors() {
+            let path = dir.join(file_name);
+            if path.is_file() {
+                return Self::read(path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn read(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let text = fs::read_to_string(&path)?;
+        Ok(Self::parse(path, &text))
+    }
+
+    // Lines are VAR=value, blank lines and # comments are skipped
+    fn parse(path: PathBuf, text: &str) -> Self {
+        let mut config = P4Config {
+            path,
+            ..Default::default()
+        };
+
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let value = Some(value.trim().to_string());
+            match name.trim() {
+                "P4PORT" => config.port = value,
+                "P4USER" => config.user = value,
+                "P4CLIENT" => config.client = value,
+                "P4CHARSET" => config.charset = value,
+                _ => {}
+            }
+        }
+
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_p4config() {
+        let root = env::temp_dir().join(format!("p4_helper_config_{}", std::process::id()));
+        let nested = root.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(
+            root.join(".p4config"),
+            "# Workspace settings\nP4PORT=ssl:perforce:1666\nP4USER = david\nP4CLIENT=david_ws\nP4DIFF=meld\n",
+        )
+        .unwrap();
+
+        let config = P4Config::find_named(&nested, ".p4config").unwrap().unwrap();
+        assert_eq!(config.port.as_deref(), Some("ssl:perforce:1666"));
+        assert_eq!(config.user.as_deref(), Some("david"));
+        assert_eq!(config.client.as_deref(), Some("david_ws"));
+        assert_eq!(config.charset, None);
+
+        assert_eq!(P4Config::find_named(&nested, ".missing").unwrap(), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,944 @@
+// == Std crates
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// == Internal crates
+use crate::annotations::*;
+use crate::auth::*;
+use crate::backend::*;
+use crate::budget::*;
+use crate::cancel::*;
+use crate::capture::*;
+use crate::command_log::*;
+use crate::config::*;
+use crate::dict::*;
+use crate::error::*;
+use crate::metrics::*;
+use crate::output::*;
+use crate::parsers::decode::*;
+use crate::parsers::py_dict::{P4DuplicateKeys, P4PyDictParser};
+use crate::port::*;
+use crate::retry::*;
+use crate::*;
+
+// Shared configuration for every p4 command spawned on behalf of the caller
+#[derive(Debug, Clone, Default)]
+pub struct P4Context {
+    retry_policy: RetryPolicy,
+    // Kill the command if it produces no output for this long
+    timeout: Option<Duration>,
+    // Kill the command if it hasn't finished after this long
+    total_timeout: Option<Duration>,
+    // How far reading p4's output may get ahead of a slow consumer, in bytes
+    output_high_water_mark: Option<usize>,
+    cancellation: Option<CancellationToken>,
+    // Shared by every iterator created from this context and its clones
+    memory_budget: Option<Arc<MemoryBudget>>,
+    metrics: Arc<Metrics>,
+    command_log: Option<Arc<CommandLog>>,
+    // When set, commands are served by the backend instead of spawning p4
+    backend: Option<Arc<dyn P4Backend>>,
+    capture: Option<Arc<CaptureWriter>>,
+    // The p4 executable to run, found on PATH if not set
+    p4_path: Option<PathBuf>,
+    // Passed as -p, -u and -c, p4 falls back to its own settings when they are not set
+    port: Option<String>,
+    user: Option<String>,
+    client: Option<String>,
+    // Passed as -C, unicode enabled servers send garbled strings without it
+    charset: Option<String>,
+    // Passed as -e, so error records carry their unique code and arguments
+    extended_errors: bool,
+    // Any other global options, placed after the ones above
+    extra_global_args: Vec<String>,
+    // Set on the p4 process on top of the inherited environment
+    env: Vec<(String, String)>,
+    string_decoding: P4StringDecoding,
+    duplicate_keys: P4DuplicateKeys,
+    description_scanner: Option<Arc<DescriptionScanner>>,
+    // Consulted to log in again when a command fails because the ticket has expired
+    credential_provider: Option<Arc<dyn CredentialProvider>>,
+    // Drives `p4 login` on servers that require single sign-on
+    sso_handler: Option<Arc<dyn SsoHandler>>,
+    // A ticket from the credential provider, passed to p4 as P4PASSWD. Shared with clones.
+    ticket: SharedTicket,
+}
+
+// Debug shows whether there is a ticket but never the ticket itself, so contexts can be logged
+#[derive(Clone, Default)]
+struct SharedTicket(Arc<Mutex<Option<String>>>);
+
+impl fmt::Debug for SharedTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self.0.lock().unwrap() {
+            Some(_) => f.write_str("Some(<redacted>)"),
+            None => f.write_str("None"),
+        }
+    }
+}
+
+impl P4Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Applies the P4CONFIG file found from `dir` the same way p4 run from `dir` would, if there is one
+    pub fn from_p4config(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let context = Self::new();
+        Ok(match P4Config::find(dir)? {
+            Some(config) => context.with_p4config(&config),
+            None => context,
+        })
+    }
+
+    // Only fills in what hasn't been set on the context, like p4 where -p, -u and -c override P4CONFIG. A port
+    // that doesn't parse is left out, p4 then reads it from the file itself and reports it.
+    pub fn with_p4config(mut self, config: &P4Config) -> Self {
+        self.port = self.port.or_else(|| {
+            let port = config.port.as_deref()?.parse::<P4Port>().ok()?;
+            Some(port.to_string())
+        });
+        self.user = self.user.or_else(|| config.user.clone());
+        self.client = self.client.or_else(|| config.client.clone());
+        self.charset = self.charset.or_else(|| config.charset.clone());
+        self
+    }
+
+    // For machines with several p4 clients installed side by side
+    pub fn with_p4_path(mut self, p4_path: impl Into<PathBuf>) -> Self {
+        self.p4_path = Some(p4_path.into());
+        self
+    }
+
+    pub fn p4_path(&self) -> &Path {
+        self.p4_path.as_deref().unwrap_or(Path::new(P4_EXE))
+    }
+
+    // Runs `p4 -V`, checking the executable can be started and reporting its version
+    #[cfg(feature = "spawn")]
+    pub fn verify_binary(&self) -> Result<P4ClientVersion, P4Error> {
+        let output = process::Command::new(self.p4_path())
+            .arg("-V")
+            .stdin(process::Stdio::null())
+            .output()
+            .map_err(P4Error::Spawn)?;
+        if !output.status.success() {
+            return Err(P4Error::Spawn(io::Error::other(format!(
+                "{} -V failed with {}",
+                self.p4_path().display(),
+                output.status
+            ))));
+        }
+
+        P4ClientVersion::parse(&String::from_utf8_lossy(&output.stdout))
+            .ok_or(P4Error::InvalidRecord("Unexpected p4 -V output"))
+    }
+
+    pub fn with_port(mut self, port: &P4Port) -> Self {
+        self.port = Some(port.to_string());
+        self
+    }
+
+    // Checks the port first, so a typo fails here rather than as a connect error from the first command
+    pub fn try_with_port(self, port: &str) -> Result<Self, P4ValueParseError> {
+        Ok(self.with_port(&port.parse::<P4Port>()?))
+    }
+
+    pub fn port(&self) -> Option<&str> {
+        self.port.as_deref()
+    }
+
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub fn with_client(mut self, client: &str) -> Self {
+        self.client = Some(client.to_string());
+        self
+    }
+
+    pub fn client(&self) -> Option<&str> {
+        self.client.as_deref()
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_total_timeout(mut self, total_timeout: Duration) -> Self {
+        self.total_timeout = Some(total_timeout);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn total_timeout(&self) -> Option<Duration> {
+        self.total_timeout
+    }
+
+    // Only applies when output is read on a background thread, i.e. with a timeout or cancellation
+    pub fn with_output_high_water_mark(mut self, bytes: usize) -> Self {
+        self.output_high_water_mark = Some(bytes);
+        self
+    }
+
+    pub fn output_high_water_mark(&self) -> usize {
+        self.output_high_water_mark
+            .unwrap_or(P4Output::DEFAULT_HIGH_WATER_MARK)
+    }
+
+    // Iterators created from this context stop with P4Error::Cancelled once the token is cancelled
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn cancellation_token(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    pub fn with_memory_budget(mut self, memory_budget: MemoryBudget) -> Self {
+        self.memory_budget = Some(Arc::new(memory_budget));
+        self
+    }
+
+    pub fn memory_budget(&self) -> Option<&MemoryBudget> {
+        self.memory_budget.as_deref()
+    }
+
+    pub(crate) fn budget_reservation(&self, resource: P4BudgetResource) -> BudgetReservation {
+        BudgetReservation::new(self.memory_budget.clone(), resource)
+    }
+
+    // Changelists from the changes and describe iterators get their annotations filled in
+    pub fn with_description_scanner(mut self, scanner: DescriptionScanner) -> Self {
+        self.description_scanner = Some(Arc::new(scanner));
+        self
+    }
+
+    pub(crate) fn description_scanner(&self) -> Option<Arc<DescriptionScanner>> {
+        self.description_scanner.clone()
+    }
+
+    // A parser for the output of a command run through this context
+    pub(crate) fn parser<ReadT: io::Read>(&self, reader: ReadT) -> P4PyDictParser<ReadT> {
+        let parser = P4PyDictParser::new(reader)
+            .with_decoding(self.string_decoding())
+            .with_duplicate_keys(self.duplicate_keys);
+        match &self.memory_budget {
+            Some(budget) => parser.with_memory_budget(budget.clone()),
+            None => parser,
+        }
+    }
+
+    // Counters are shared between clones of the context
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub(crate) fn shared_metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn with_metrics_callback(
+        self,
+        callback: impl Fn(&MetricsSnapshot) + Send + Sync + 'static,
+    ) -> Self {
+        self.metrics.set_callback(Some(Box::new(callback)));
+        self
+    }
+
+    // Record the (redacted) command line of every command run through this context
+    pub fn with_command_log(mut self) -> Self {
+        self.command_log = Some(Arc::default());
+        self
+    }
+
+    pub fn command_log(&self) -> Option<&CommandLog> {
+        self.command_log.as_deref()
+    }
+
+    // A command that fails because the session has expired, or there is no ticket, logs in with the
+    // provider's credential and is run again, once
+    pub fn with_credential_provider(mut self, provider: Arc<dyn CredentialProvider>) -> Self {
+        self.credential_provider = Some(provider);
+        self
+    }
+
+    // Logs in again through SSO when a command fails because the session has expired, unless there is also a
+    // credential provider. Needs the spawn feature, the SSO prompt comes from the p4 process.
+    pub fn with_sso_handler(mut self, handler: Arc<dyn SsoHandler>) -> Self {
+        self.sso_handler = Some(handler);
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Arc<dyn P4Backend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    // Tee the raw output of every command into fixture files in `dir`
+    pub fn with_capture_dir(mut self, dir: impl Into<PathBuf>) -> io::Result<Self> {
+        self.capture = Some(Arc::new(CaptureWriter::new(dir)?));
+        Ok(self)
+    }
+
+    // Serve every command from fixture files previously written with `with_capture_dir`
+    pub fn with_replay_dir(self, dir: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(self.with_backend(Arc::new(ReplayBackend::open(dir)?)))
+    }
+
+    // e.g. "utf8", see `p4 help charset`
+    pub fn with_charset(mut self, charset: &str) -> Self {
+        self.charset = Some(charset.to_string());
+        self
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.charset.as_deref()
+    }
+
+    // Error records then carry a unique code, format and arguments (see P4ServerMessage::id) that are the
+    // same whatever language the server speaks
+    pub fn with_extended_errors(mut self) -> Self {
+        self.extended_errors = true;
+        self
+    }
+
+    // Global options passed as is before every command, e.g. ["-d", dir], ["-v", "net=3"] or ["-r", "3"]
+    pub fn with_global_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extra_global_args
+            .extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    // e.g. P4EDITOR=/bin/true so nothing waits on input on CI agents. For a single command, pass a clone of the
+    // context with the extra variables set.
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // Applied to values that aren't valid UTF-8, by default they are reported as parse errors
+    pub fn with_string_decoding(mut self, string_decoding: P4StringDecoding) -> Self {
+        self.string_decoding = string_decoding;
+        self
+    }
+
+    pub fn string_decoding(&self) -> P4StringDecoding {
+        self.string_decoding
+    }
+
+    // Applied to every dict the iterators read, by default the last of a repeated key wins
+    pub fn with_duplicate_keys(mut self, duplicate_keys: P4DuplicateKeys) -> Self {
+        self.duplicate_keys = duplicate_keys;
+        self
+    }
+
+    pub fn duplicate_keys(&self) -> P4DuplicateKeys {
+        self.duplicate_keys
+    }
+
+    // Global options that go before the command on every command line
+    fn global_args(&self) -> Vec<&str> {
+        let mut args = Vec::new();
+        for (flag, value) in [("-p", &self.port), ("-u", &self.user), ("-c", &self.client)] {
+            if let Some(value) = value {
+                args.extend([flag, value]);
+            }
+        }
+        if let Some(charset) = &self.charset {
+            args.extend(["-C", charset]);
+        }
+        if self.extended_errors {
+            args.push("-e");
+        }
+        args.extend(self.extra_global_args.iter().map(String::as_str));
+        args
+    }
+
+    // Spawns p4 without -ztag -G, for commands that talk to the user such as `p4 login`. Counted and logged like
+    // any other command, the caller calls plain_command_finished once it has waited for it.
+    #[cfg(feature = "spawn")]
+    pub(crate) fn spawn_plain(&self, args: Vec<&str>) -> Result<process::Child, P4Error> {
+        self.metrics.add_command();
+        self.log_command_line(&[], &args);
+
+        let mut command = process::Command::new(self.p4_path());
+        command
+            .args(self.global_args())
+            .args(args)
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .stdin(process::Stdio::piped());
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        let child = command.spawn().map_err(P4Error::Spawn)?;
+        self.metrics.add_process();
+        Ok(child)
+    }
+
+    #[cfg(feature = "spawn")]
+    pub(crate) fn plain_command_finished(&self) {
+        self.metrics.command_finished();
+    }
+
+    #[cfg(feature = "spawn")]
+    pub fn command(&self, args: Vec<&str>) -> process::Command {
+        let mut command = get_p4_cmd_at(
+            self.p4_path(),
+            self.global_args().into_iter().chain(args).collect(),
+        );
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(ticket) = self.ticket.0.lock().unwrap().as_deref() {
+            command.env("P4PASSWD", ticket);
+        }
+        command
+    }
+
+    // Used by every command from then on, including those of clones of this context
+    pub(crate) fn set_ticket(&self, ticket: &str) {
+        *self.ticket.0.lock().unwrap() = Some(ticket.to_string());
+    }
+
+    // Runs any command, e.g. ["counters"] or ["monitor", "show", "-al"], for what the crate doesn't wrap. Each
+    // record is a P4Dict, warnings are skipped and errors end the iteration like for the typed iterators.
+    pub fn run_raw<I, S>(&self, args: I) -> Result<P4DictIterator<P4Output>, P4Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let args = args.into_iter().collect::<Vec<_>>();
+        P4DictIterator::new_from_context(self, args.iter().map(AsRef::as_ref).collect())
+    }
+
+    // Spawns the command, retrying according to the retry policy if it fails to start or the server
+    // reports a transient error as its first record. There is no process if a backend is in use.
+    pub fn spawn(&self, args: Vec<&str>) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("p4_spawn", args = ?args).entered();
+
+        self.metrics.add_command();
+        self.log_command(&args);
+
+        let (p4_process, mut output) = match self.spawn_with_retries(&args) {
+            Err(P4Error::Server(message)) if self.should_login(&message) => {
+                self.login()?;
+                self.spawn_with_retries(&args)?
+            }
+            result => result?,
+        };
+        if let Some(capture) = &self.capture {
+            output.set_capture(capture.create(&args)?);
+        }
+
+        Ok((p4_process, output))
+    }
+
+    fn spawn_with_retries(
+        &self,
+        args: &[&str],
+    ) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        if let Some(backend) = &self.backend {
+            let reader = backend.run(args)?;
+            let mut output = P4Output::new(reader, self.output_limits());
+            if self.can_login() {
+                let (peeked, first_error) = peek_first_error(&mut output)?;
+                output.set_peeked(peeked);
+                if let Some(message) = first_error.filter(|message| self.should_login(message)) {
+                    return Err(P4Error::Server(message));
+                }
+            }
+            return Ok((None, output));
+        }
+
+        self.spawn_p4_with_retries(args)
+    }
+
+    // Runs the command with `input` on its stdin, without retries. There is no process if a backend is in use.
+    pub(crate) fn spawn_with_input(
+        &self,
+        args: Vec<&str>,
+        input: &[u8],
+    ) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        self.metrics.add_command();
+        self.log_command(&args);
+
+        if let Some(backend) = &self.backend {
+            let reader = backend.run_with_input(&args, input)?;
+            return Ok((None, P4Output::new(reader, self.output_limits())));
+        }
+
+        let input = input.to_vec();
+        self.spawn_p4_with_input(&args, move |stdin| stdin.write_all(&input))
+    }
+
+    // Runs the command with `p4 -x -`, streaming the filespecs to its stdin one per line, so there can be far
+    // more of them than fit on a command line. They are written as p4 reads them, a backend gets them all at once.
+    pub(crate) fn spawn_with_filespecs(
+        &self,
+        args: Vec<&str>,
+        filespecs: impl Iterator<Item = String> + Send + 'static,
+    ) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        let mut args_with_file = vec!["-x", "-"];
+        args_with_file.extend(args);
+        let args = args_with_file;
+        self.metrics.add_command();
+        self.log_command(&args);
+
+        let write_filespecs = |writer: &mut dyn io::Write| {
+            for filespec in filespecs {
+                writer.write_all(filespec.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()
+        };
+        if let Some(backend) = &self.backend {
+            let mut input = Vec::new();
+            write_filespecs(&mut input)?;
+            let reader = backend.run_with_input(&args, &input)?;
+            return Ok((None, P4Output::new(reader, self.output_limits())));
+        }
+
+        self.spawn_p4_with_input(&args, move |stdin| {
+            write_filespecs(&mut io::BufWriter::new(stdin))
+        })
+    }
+
+    // Fails rather than running the command without its input
+    #[cfg(not(feature = "spawn"))]
+    fn spawn_p4_with_input(
+        &self,
+        _args: &[&str],
+        _write_input: impl FnOnce(&mut dyn io::Write) -> io::Result<()> + Send + 'static,
+    ) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        Err(P4Error::Spawn(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Built without the spawn feature, only backends can run commands with input",
+        )))
+    }
+
+    #[cfg(feature = "spawn")]
+    fn spawn_p4_with_input(
+        &self,
+        args: &[&str],
+        write_input: impl FnOnce(&mut dyn io::Write) -> io::Result<()> + Send + 'static,
+    ) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        let mut child = self
+            .command(args.to_vec())
+            .stdin(process::Stdio::piped())
+            .spawn()
+            .map_err(P4Error::Spawn)?;
+        self.metrics.add_process();
+
+        // Written from another thread, as with several forms p4 answers the first before reading the rest and
+        // could block on a full stdout pipe. Dropping stdin closes it, so p4 doesn't wait for more. The thread is
+        // joined at the end of the output, which then fails if writing did.
+        let written = child
+            .stdin
+            .take()
+            .ok_or(P4Error::InvalidRecord("Failed to get stdin of p4 command"))
+            .map(|mut stdin| std::thread::spawn(move || write_input(&mut stdin)));
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(P4Error::InvalidRecord("Failed to get stdout of p4 command"));
+        match written.and_then(|writer| Ok((writer, stdout?))) {
+            Ok((writer, stdout)) => {
+                let mut output = P4Output::new(stdout, self.output_limits());
+                output.set_input_writer(writer);
+                Ok((Some(child), output))
+            }
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(e)
+            }
+        }
+    }
+
+    fn can_login(&self) -> bool {
+        self.credential_provider.is_some() || self.sso_handler.is_some()
+    }
+
+    fn should_login(&self, message: &P4ServerMessage) -> bool {
+        self.can_login() && is_login_required(message)
+    }
+
+    // Credentials from the provider win over an SSO login
+    fn login(&self) -> Result<(), P4Error> {
+        #[cfg(feature = "tracing")]
+        tracing::info!("session expired, logging in again");
+        if let Some(provider) = &self.credential_provider {
+            return login_with_credential(self, &provider.credential()?);
+        }
+        match &self.sso_handler {
+            Some(handler) => login_sso_from_context(self, handler.as_ref()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "spawn"))]
+    fn spawn_p4_with_retries(
+        &self,
+        _args: &[&str],
+    ) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        Err(P4Error::Spawn(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Built without the spawn feature, only backends can run commands",
+        )))
+    }
+
+    #[cfg(feature = "spawn")]
+    fn spawn_p4_with_retries(
+        &self,
+        args: &[&str],
+    ) -> Result<(Option<process::Child>, P4Output), P4Error> {
+        let mut attempt = 0;
+        loop {
+            match self.try_spawn(args) {
+                Ok(result) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(attempt, pid = result.0.id(), "spawned p4");
+                    return Ok((Some(result.0), result.1));
+                }
+                Err(e) if self.retry_policy.should_retry(attempt, &e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempt, error = %e, "retrying p4 command");
+                    self.metrics.add_retry();
+                    std::thread::sleep(self.retry_policy.backoff(attempt));
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn output_limits(&self) -> OutputLimits {
+        OutputLimits {
+            idle_timeout: self.timeout,
+            total_timeout: self.total_timeout,
+            cancellation: self.cancellation.clone(),
+            high_water_mark: self.output_high_water_mark,
+        }
+    }
+
+    fn log_command(&self, args: &[&str]) {
+        self.log_command_line(&P4_OUTPUT_ARGS, args);
+    }
+
+    fn log_command_line(&self, output_args: &[&str], args: &[&str]) {
+        #[cfg(not(feature = "tracing"))]
+        if self.command_log.is_none() {
+            return;
+        }
+
+        let command_line = format_args_line(
+            self.p4_path(),
+            output_args.iter().chain(&self.global_args()).chain(args),
+        );
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(command_line = %command_line, "running p4 command");
+
+        if let Some(command_log) = &self.command_log {
+            command_log.record(command_line);
+        }
+    }
+
+    #[cfg(feature = "spawn")]
+    fn try_spawn(&self, args: &[&str]) -> Result<(process::Child, P4Output), P4Error> {
+        let mut child = self
+            .command(args.to_vec())
+            .spawn()
+            .map_err(P4Error::Spawn)?;
+        self.metrics.add_process();
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(P4Error::InvalidRecord("Failed to get stdout of p4 command"))?;
+        let mut output = P4Output::new(stdout, self.output_limits());
+
+        let (peeked, first_error) = match peek_first_error(&mut output) {
+            Ok(result) => result,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+        output.set_peeked(peeked);
+
+        if let Some(message) = first_error
+            .filter(|message| is_transient_message(message) || self.should_login(message))
+        {
+            let _ = child.wait();
+            return Err(P4Error::Server(message));
+        }
+
+        Ok((child, output))
+    }
+}
+
+// The client version reported by `p4 -V`, from the line like "Rev. P4/LINUX26X86_64/2023.2/2513900 (2024/01/31)."
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4ClientVersion {
+    pub platform: String,
+    // e.g. "2023.2"
+    pub release: String,
+    pub change: u32,
+}
+
+impl P4ClientVersion {
+    #[cfg(any(feature = "spawn", test))]
+    fn parse(output: &str) -> Option<Self> {
+        let rev = output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Rev. "))?;
+        let mut parts = rev.split_whitespace().next()?.split('/');
+        let (Some("P4"), Some(platform), Some(release), Some(change)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+
+        Some(P4ClientVersion {
+            platform: platform.to_string(),
+            release: release.to_string(),
+            change: change.parse().ok()?,
+        })
+    }
+}
+
+// Records everything read through it, so it can be replayed to the real consumer
+struct RecordingReader<'a, ReadT: io::Read> {
+    inner: &'a mut ReadT,
+    recorded: Vec<u8>,
+}
+
+impl<ReadT: io::Read> io::Read for RecordingReader<'_, ReadT> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.recorded.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+// Reads the first record if it is an error, or just its first key otherwise, returning the consumed bytes
+fn peek_first_error<ReadT: io::Read>(
+    reader: &mut ReadT,
+) -> Result<(Vec<u8>, Option<P4ServerMessage>), P4Error> {
+    let mut recorder = RecordingReader {
+        inner: reader,
+        recorded: Vec::new(),
+    };
+
+    let mut message = None;
+    {
+        let mut parser = P4PyDictParser::new(&mut recorder);
+        if let Some(kvp) = parser.get_next_kvp()?
+            && kvp.key == "code"
+            && kvp.value == "error"
+        {
+            let mut error = P4ServerMessage::default();
+            while let Some(kvp) = parser.get_next_kvp()? {
+                if kvp.dict_index != 0 {
+                    break;
+                }
+                error.populate_field(kvp.key, kvp.value);
+            }
+            message = Some(error);
+        }
+    }
+
+    Ok((recorder.recorded, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_global_args() {
+        let context = MockP4::new()
+            .with_records("changes", [[("code", "stat"), ("change", "1")]])
+            .into_context()
+            .with_charset("utf8")
+            .with_user("david")
+            .with_extended_errors()
+            .with_global_args(["-v", "net=3"])
+            .with_global_args(["-r", "2"])
+            .with_command_log();
+        context.spawn(vec!["changes", "-m", "1"]).unwrap();
+
+        assert_eq!(
+            context.command_log().unwrap().entries(),
+            ["p4 -ztag -G -u david -C utf8 -e -v net=3 -r 2 changes -m 1"]
+        );
+    }
+
+    #[test]
+    fn test_debug_redacts_ticket() {
+        let context = P4Context::new();
+        context.set_ticket("0123456789ABCDEF");
+        let debug = format!("{:?}", context);
+        assert!(debug.contains("ticket: Some(<redacted>)"));
+        assert!(!debug.contains("0123456789ABCDEF"));
+    }
+
+    #[test]
+    fn test_with_p4config() {
+        let config = P4Config {
+            port: Some("ssl:perforce:1666".to_string()),
+            user: Some("david".to_string()),
+            client: Some("david_ws".to_string()),
+            ..Default::default()
+        };
+        let context = P4Context::new().with_user("alice").with_p4config(&config);
+        assert_eq!(context.user(), Some("alice"));
+        assert_eq!(context.client(), Some("david_ws"));
+        assert_eq!(context.port(), Some("ssl:perforce:1666"));
+
+        let config = P4Config {
+            port: Some("perforce:1666:extra".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(P4Context::new().with_p4config(&config).port(), None);
+        assert!(P4Context::new().try_with_port("perforce:0").is_err());
+    }
+
+    #[test]
+    fn test_run_raw() {
+        let context = MockP4::new()
+            .with_records(
+                "monitor show -al",
+                [
+                    [("code", "stat"), ("id", "12"), ("command", "sync")],
+                    [("code", "stat"), ("id", "13"), ("command", "fstat")],
+                ],
+            )
+            .with_error("nosuchcommand", E_FAILED, EV_USAGE, "Unknown command.\n")
+            .into_context();
+
+        let records = context
+            .run_raw(["monitor", "show", "-al"])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].get("command"), Some("fstat"));
+
+        let error = context
+            .run_raw(vec!["nosuchcommand".to_string()])
+            .and_then(|mut records| records.next().transpose());
+        assert!(matches!(error, Err(P4Error::Server(_))));
+    }
+
+    #[test]
+    fn test_client_version() {
+        let output = "Perforce - The Fast Software Configuration Management System.\n\
+                      Copyright 1995-2024 Perforce Software.  All rights reserved.\n\
+                      Rev. P4/LINUX26X86_64/2023.2/2513900 (2024/01/31).\n";
+        let version = P4ClientVersion::parse(output).unwrap();
+        assert_eq!(version.platform, "LINUX26X86_64");
+        assert_eq!(version.release, "2023.2");
+        assert_eq!(version.change, 2513900);
+        assert_eq!(P4ClientVersion::parse("p4: command not found"), None);
+
+        #[cfg(feature = "spawn")]
+        {
+            let context = P4Context::new().with_p4_path("/nonexistent/p4");
+            assert!(matches!(context.verify_binary(), Err(P4Error::Spawn(_))));
+        }
+    }
+
+    #[cfg(not(feature = "spawn"))]
+    #[test]
+    fn test_input_without_spawn() {
+        let result = P4Context::new().spawn_with_input(vec!["client", "-i"], b"Client: ws\n");
+        match result {
+            Err(P4Error::Spawn(error)) => assert_eq!(error.kind(), io::ErrorKind::Unsupported),
+            _ => panic!("Expected a spawn error"),
+        }
+    }
+
+    #[cfg(feature = "spawn")]
+    #[test]
+    fn test_env() {
+        let context = P4Context::new()
+            .with_p4_path("/nonexistent/p4")
+            .with_env("P4EDITOR", "/bin/true");
+        let command = context.command(vec!["info"]);
+        assert_eq!(command.get_program(), "/nonexistent/p4");
+        assert!(
+            command
+                .get_envs()
+                .any(|(key, value)| key == "P4EDITOR" && value == Some("/bin/true".as_ref()))
+        );
+    }
+
+    #[cfg(feature = "spawn")]
+    #[test]
+    fn test_peek_first_error() {
+        use crate::parsers::py_dict::to_py_dict_bytes;
+        use std::io::Read;
+
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "error"),
+                ("data", "TCP connect to perforce:1666 failed.\n"),
+                ("severity", "4"),
+                ("generic", "38"),
+            ],
+            &[("code", "stat"), ("change", "1")],
+        ]);
+
+        let mut reader = &data[..];
+        let (peeked, message) = peek_first_error(&mut reader).unwrap();
+        let message = message.unwrap();
+        assert_eq!(message.severity, E_FATAL);
+        assert_eq!(message.generic, EV_COMM);
+        assert!(is_transient_message(&message));
+
+        // Nothing read while peeking may be lost
+        let mut replayed = peeked;
+        reader.read_to_end(&mut replayed).unwrap();
+        assert_eq!(replayed, data);
+
+        // Non-error output is passed through untouched
+        let data = to_py_dict_bytes(&[&[("code", "stat"), ("change", "1")]]);
+        let mut reader = &data[..];
+        let (peeked, message) = peek_first_error(&mut reader).unwrap();
+        assert!(message.is_none());
+        let mut replayed = peeked;
+        reader.read_to_end(&mut replayed).unwrap();
+        assert_eq!(replayed, data);
+    }
+}
@@ -0,0 +1,110 @@
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+
+// A depot as listed by `p4 depots`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4Depot {
+    pub name: String,
+    // e.g. local, stream, remote, spec, archive or unload
+    pub depot_type: String,
+    // Where the depot's archive files live on the server, e.g. depot/...
+    pub map: String,
+}
+
+impl P4Depot {
+    // The filespec for everything in the depot, e.g. //depot/...
+    pub fn filespec(&self) -> String {
+        format!("//{}/...", self.name)
+    }
+}
+
+// The arguments of `p4 depots`, which takes no filespec
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct P4DepotsQuery;
+
+impl P4Command for P4DepotsQuery {
+    type Item = P4Depot;
+    type Fields = InterimP4Depot;
+
+    fn args(&self) -> Vec<String> {
+        vec!["depots".to_string()]
+    }
+}
+
+pub type P4DepotsIterator<ReadT> = P4CommandIterator<P4DepotsQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4DepotsIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new() -> Result<P4DepotsIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default())
+    }
+
+    pub fn new_from_context(context: &P4Context) -> Result<P4DepotsIterator<P4Output>, P4Error> {
+        Self::spawn(context, P4DepotsQuery)
+    }
+}
+
+// The parsing state of a P4Depot
+#[derive(Debug, Default)]
+pub struct InterimP4Depot {
+    name: Option<String>,
+    depot_type: Option<String>,
+    map: Option<String>,
+}
+
+impl P4RecordFields for InterimP4Depot {
+    type Output = P4Depot;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "name" => self.name = Some(value.to_string()),
+            "type" => self.depot_type = Some(value.to_string()),
+            "map" => self.map = Some(value.to_string()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4Depot, P4Error> {
+        Ok(P4Depot {
+            name: self.name.ok_or(P4Error::InvalidRecord("Missing name"))?,
+            depot_type: self
+                .depot_type
+                .ok_or(P4Error::InvalidRecord("Missing depot type"))?,
+            map: self.map.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_depots() {
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("name", "depot"),
+                ("type", "local"),
+                ("map", "depot/..."),
+            ],
+            &[("code", "stat"), ("name", "spec"), ("type", "spec")],
+        ]);
+
+        let depots = P4DepotsIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(depots.len(), 2);
+        assert_eq!(depots[0].filespec(), "//depot/...");
+        assert_eq!(depots[1].depot_type, "spec");
+        assert_eq!(depots[1].map, "");
+    }
+}
@@ -1,176 +1,1006 @@
 // == Std crates
-use std::{io, process};
+#[cfg(feature = "process")]
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "process")]
+use std::iter::Peekable;
+use std::sync::Arc;
 
 // == Internal crates
+use crate::annotations::*;
+use crate::cancel::*;
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+pub use crate::diff::{P4DiffOptions, P4DiffWhitespace};
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::filelog::*;
+use crate::metrics::*;
+#[cfg(feature = "process")]
+use crate::output::*;
 use crate::parsers::py_dict::P4PyDictParser;
+#[cfg(feature = "process")]
+use crate::patch::*;
+#[cfg(feature = "process")]
+use crate::print::*;
 use crate::*;
 
+// A unified diff of a submitted changelist that `git apply` and `patch -p1` accept, with the hunks of edited files
+// from `p4 describe -du` and the content of added and deleted ones printed. Paths are the depot paths without the
+// leading //.
+#[cfg(feature = "spawn")]
+pub fn to_patch(changelist: u32) -> Result<Vec<u8>, P4Error> {
+    to_patch_from_context(&P4Context::default(), changelist)
+}
+
+#[cfg(feature = "process")]
+pub fn to_patch_from_context(context: &P4Context, changelist: u32) -> Result<Vec<u8>, P4Error> {
+    to_patch_with_options(context, changelist, &P4DiffOptions::default())
+}
+
+// Same as to_patch_from_context, with the hunks of edited files from `p4 describe -du` with the same options
+#[cfg(feature = "process")]
+pub fn to_patch_with_options(
+    context: &P4Context,
+    changelist: u32,
+    options: &P4DiffOptions,
+) -> Result<Vec<u8>, P4Error> {
+    let query = P4DescribeQuery::new(changelist)
+        .with_diff_format(P4DiffFormat::Unified)
+        .with_diff_options(*options);
+    let mut describe = P4DescribeIterator::new_from_context(context, query)?;
+    let files = describe.by_ref().collect::<Result<Vec<_>, _>>()?;
+    let hunks = diff_hunks(describe.diff());
+
+    // The revision before is the one filelog lists next, which doesn't exist for a file branched, moved or added
+    // again after a delete
+    let filespecs = files
+        .iter()
+        .filter_map(|file| Some(format!("{}#{}", file.depot_path, file.revision?)))
+        .collect::<Vec<_>>();
+    let filelogs = filelog_revisions(context, &filespecs)?;
+    let sides = files
+        .iter()
+        .map(|file| {
+            let revisions = filelogs
+                .get(&file.depot_path)
+                .map_or(&[][..], Vec::as_slice);
+            let old = revisions
+                .get(1)
+                .filter(|revision| !is_deleted_action(&revision.action));
+            let new = revisions.first().filter(|_| !file.is_deleted());
+            (file, old, new)
+        })
+        .collect::<Vec<_>>();
+
+    // Added and deleted files are printed with one command and written as they're read, in the same order
+    let whole = sides
+        .iter()
+        .filter_map(|(_, old, new)| match (old, new) {
+            (None, Some(side)) | (Some(side), None) => {
+                Some(format!("{}#{}", side.depot_path, side.revision))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let whole = whole.iter().map(String::as_str).collect::<Vec<_>>();
+    let printed = match whole.is_empty() {
+        true => None,
+        false => Some(P4PrintIterator::new_from_context(context, &whole)?),
+    };
+    let mut printed = printed.into_iter().flatten().peekable();
+
+    let mut patch = Vec::new();
+    for (file, old, new) in sides {
+        let printed_file;
+        let content = match (old, new) {
+            (Some(_), Some(_)) => hunks
+                .get(file.depot_path.as_str())
+                .map_or(P4PatchContent::Unknown, |hunks| {
+                    P4PatchContent::Hunks(hunks)
+                }),
+            (None, None) => continue,
+            _ => {
+                printed_file = next_printed(&mut printed, &file.depot_path)?;
+                printed_file
+                    .as_ref()
+                    .map_or(P4PatchContent::Unknown, |printed| {
+                        P4PatchContent::Whole(&printed.content)
+                    })
+            }
+        };
+        write_file_patch(
+            &mut patch,
+            &file.depot_path,
+            old.map(|revision| revision.file_type.as_str()),
+            new.map(|revision| revision.file_type.as_str()),
+            content,
+        )?;
+    }
+    Ok(patch)
+}
+
+// The newest two revisions of each file up to the given one, by depot path
+#[cfg(feature = "process")]
+fn filelog_revisions(
+    context: &P4Context,
+    filespecs: &[String],
+) -> Result<HashMap<String, Vec<P4FileRevision>>, P4Error> {
+    if filespecs.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let query = filespecs
+        .iter()
+        .fold(P4FilelogQuery::default(), |query, filespec| {
+            query.with_filespec(filespec)
+        })
+        .with_max_revisions(2);
+    P4FilelogIterator::new_from_context(context, query)?
+        .map(|filelog| filelog.map(|filelog| (filelog.depot_path, filelog.revisions)))
+        .collect()
+}
+
+// The next printed file when it's `depot_path`, p4 leaves out the revisions it can't print, e.g. purged ones
+#[cfg(feature = "process")]
+fn next_printed(
+    printed: &mut Peekable<impl Iterator<Item = Result<P4PrintedFile, P4Error>>>,
+    depot_path: &str,
+) -> Result<Option<P4PrintedFile>, P4Error> {
+    printed
+        .next_if(|result| {
+            result
+                .as_ref()
+                .map_or(true, |printed| printed.depot_path == depot_path)
+        })
+        .transpose()
+}
+
+// The options of a `p4 describe` command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4DescribeQuery {
+    changelists: Vec<u32>,
+    shelved: bool,
+    diff_format: P4DiffFormat,
+    diff_options: P4DiffOptions,
+    max_files: Option<u32>,
+    original_numbering: bool,
+}
+
+// The -d<flags> diff options, the iterator only yields the files so None (-s) is the cheapest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P4DiffFormat {
+    #[default]
+    None,
+    Unified,
+    Context,
+    Summary,
+    Rcs,
+}
+
+impl P4DescribeQuery {
+    pub fn new(changelist: u32) -> Self {
+        P4DescribeQuery {
+            changelists: vec![changelist],
+            ..Default::default()
+        }
+    }
+
+    // Describe several changelists with one command, their files are yielded in order
+    pub fn with_changelist(mut self, changelist: u32) -> Self {
+        self.changelists.push(changelist);
+        self
+    }
+
+    // -S, the shelved files of a pending changelist
+    pub fn with_shelved(mut self) -> Self {
+        self.shelved = true;
+        self
+    }
+
+    pub fn with_diff_format(mut self, diff_format: P4DiffFormat) -> Self {
+        self.diff_format = diff_format;
+        self
+    }
+
+    // Whitespace, line ending and context options for the diff format, ignored with P4DiffFormat::None
+    pub fn with_diff_options(mut self, diff_options: P4DiffOptions) -> Self {
+        self.diff_options = diff_options;
+        self
+    }
+
+    // -m, list at most this many files of each changelist
+    pub fn with_max_files(mut self, max_files: u32) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    // -O, the changelist numbers are the original ones of changelists renumbered on submit
+    pub fn with_original_numbering(mut self) -> Self {
+        self.original_numbering = true;
+        self
+    }
+}
+
+impl From<u32> for P4DescribeQuery {
+    fn from(changelist: u32) -> Self {
+        P4DescribeQuery::new(changelist)
+    }
+}
+
+impl P4Command for P4DescribeQuery {
+    type Item = P4File;
+    type Fields = InterimP4Describe;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["describe".to_string()];
+        let format = match self.diff_format {
+            P4DiffFormat::None => None,
+            P4DiffFormat::Unified => Some('u'),
+            P4DiffFormat::Context => Some('c'),
+            P4DiffFormat::Summary => Some('s'),
+            P4DiffFormat::Rcs => Some('n'),
+        };
+        args.push(match format {
+            None => "-s".to_string(),
+            Some(format) => self.diff_options.arg(format),
+        });
+        if self.shelved {
+            args.push("-S".to_string());
+        }
+        if self.original_numbering {
+            args.push("-O".to_string());
+        }
+        if let Some(max_files) = self.max_files {
+            args.extend(["-m".to_string(), max_files.to_string()]);
+        }
+        args.extend(self.changelists.iter().map(u32::to_string));
+        args
+    }
+}
+
+// Yields the files one at a time, holding only the changelist header and the file being read, so memory
+// doesn't grow with the number of files. Collecting them, e.g. for P4ChangelistHandle::files, is up to the caller.
 pub struct P4DescribeIterator<ReadT: io::Read> {
-    p4_process: Option<process::Child>,
-    parser: P4PyDictParser<ReadT>,
-    changelist: P4Changelist,
-    // Storage for various state variables
-    current_file_index: Option<u32>,
-    current_file: InterimP4File,
+    files: P4CommandIterator<P4DescribeQuery, ReadT>,
+    // Read along with the first header, which the constructors read so the changelist is there from the start
+    first_file: Option<Result<P4File, P4Error>>,
+}
+
+#[cfg(feature = "process")]
+impl P4DescribeIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(query: impl Into<P4DescribeQuery>) -> Result<P4DescribeIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), query)
+    }
+
+    // `query` is a P4DescribeQuery, or just the changelist number
+    pub fn new_from_context(
+        context: &P4Context,
+        query: impl Into<P4DescribeQuery>,
+    ) -> Result<P4DescribeIterator<P4Output>, P4Error> {
+        let mut files = P4CommandIterator::spawn(context, query.into())?;
+        files.fields_mut().description_scanner = context.description_scanner();
+        Self::read_header(files)
+    }
 }
 
 impl<ReadT: io::Read> P4DescribeIterator<ReadT> {
-    pub fn new(changelist: u32) -> Result<P4DescribeIterator<process::ChildStdout>, &'static str> {
-        let mut p4_process = get_p4_cmd(vec!["describe", "-s", &format!("{}", changelist)])
-            .spawn()
-            .expect("Failed to start p4 command");
+    pub fn new_from_reader(reader: ReadT) -> Result<Self, P4Error> {
+        Self::new_from_parser(P4PyDictParser::new(reader))
+    }
 
-        let reader = p4_process
-            .stdout
-            .take()
-            .expect("Failed to get stdout of p4 command");
+    // For a parser with non-default settings, e.g. a string decoding
+    pub fn new_from_parser(parser: P4PyDictParser<ReadT>) -> Result<Self, P4Error> {
+        Self::read_header(P4CommandIterator::new_from_parser(parser))
+    }
 
-        let mut result = P4DescribeIterator::<process::ChildStdout>::new_from_reader(reader)?;
-        result.p4_process = Some(p4_process);
+    // An error in place of the header, e.g. for a changelist that doesn't exist, is returned here. Dropping
+    // `files` on one kills the process. One in the first file is left for next().
+    fn read_header(mut files: P4CommandIterator<P4DescribeQuery, ReadT>) -> Result<Self, P4Error> {
+        let first_file = files.next();
+        if !files.fields().described {
+            first_file.transpose()?;
+            return Err(P4Error::InvalidRecord("Missing changelist"));
+        }
+        Ok(P4DescribeIterator { files, first_file })
+    }
 
-        Ok(result)
+    // Fills in the annotations of the changelist from its description
+    pub fn with_description_scanner(mut self, scanner: DescriptionScanner) -> Self {
+        // The first header has already been read by the time the scanner is set
+        let fields = self.files.fields_mut();
+        scanner.annotate(&mut fields.changelist);
+        fields.description_scanner = Some(Arc::new(scanner));
+        self
     }
 
-    pub fn new_from_reader(reader: ReadT) -> Result<Self, &'static str> {
-        let mut parser = P4PyDictParser::new(reader);
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.files = self.files.with_cancellation_token(cancellation);
+        self
+    }
 
-        let mut current_file_index = None;
-        let mut current_change = InterimP4Changelist::default();
-        let mut current_file = InterimP4File::default();
+    pub fn records_yielded(&self) -> u64 {
+        self.files.records_yielded()
+    }
 
-        // Read the first parts to get the CL information
-        while let Some(kvp) = parser.get_next_kvp().unwrap() {
-            match kvp.key {
-                "change" => {
-                    current_change.change = Some(kvp.value.parse().unwrap());
-                }
-                "time" => {
-                    current_change.time = Some(kvp.value.parse().unwrap());
-                }
-                "user" => {
-                    current_change.user = Some(kvp.value.to_string());
-                }
-                "desc" => {
-                    current_change.description = Some(kvp.value.to_string());
-                }
-                key => {
-                    if let Some((key, index)) = split_indexed_key(key) {
-                        Self::populate_field(&mut current_file, key, kvp.value);
-                        current_file_index = Some(index);
-                        break;
-                    }
-                }
-            }
+    // Totals for the command, None until the iterator is exhausted
+    pub fn summary(&self) -> Option<P4IteratorSummary> {
+        self.files.summary()
+    }
+
+    // The changelist of the files yielded so far, when several were described
+    pub fn get_changelist(&self) -> &P4Changelist {
+        &self.files.fields().changelist
+    }
+
+    // The diffs of a query with a diff format, as p4 prints them, e.g. "==== //depot/a.txt#2 (text) ====" and
+    // the hunks for each edited file. They come after the files of their changelist, so this is complete once
+    // the files have all been read.
+    pub fn diff(&self) -> &str {
+        &self.files.fields().diff
+    }
+
+    // Same as next(), reading into `file` instead of a new value. Its strings keep their allocations, so
+    // describing a huge changelist one file at a time doesn't allocate per file.
+    pub fn next_into(&mut self, file: &mut P4File) -> Option<Result<(), P4Error>> {
+        if let Some(first_file) = self.first_file.take() {
+            return Some(first_file.map(|first_file| *file = first_file));
         }
+        self.files.next_into(file)
+    }
+}
 
-        let changelist = current_change.try_into()?;
+impl<ReadT: io::Read> Iterator for P4DescribeIterator<ReadT> {
+    type Item = Result<P4File, P4Error>;
 
-        // We are done with the header, so we can store it
-        Ok(P4DescribeIterator {
-            p4_process: None,
-            parser,
-            changelist,
-            current_file_index,
-            current_file,
-        })
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first_file) = self.first_file.take() {
+            return Some(first_file);
+        }
+        self.files.next()
     }
+}
 
-    pub fn get_changelist(&self) -> &P4Changelist {
-        &self.changelist
+// The parsing state of `p4 describe`. A record is a changelist with its files as depotFile0, depotFile1 and so
+// on, each yielded once the next starts, or -d output. The changelist and diff carry over from one record to
+// the next.
+#[derive(Default)]
+pub struct InterimP4Describe {
+    // The changelist of the files read so far
+    changelist: P4Changelist,
+    // Whether a header has been read into it
+    described: bool,
+    // Whether the record has had a field yet, the first says what kind of record it is
+    started: bool,
+    // The header of the record's changelist while it is being read
+    header: Option<InterimP4Changelist>,
+    file_index: Option<u32>,
+    file: InterimP4File,
+    description_scanner: Option<Arc<DescriptionScanner>>,
+    // Whether the record is -d output rather than a changelist
+    in_diff: bool,
+    diff: String,
+}
+
+impl InterimP4Describe {
+    // Returns false for the indexed keys of the files, which end the header
+    fn populate_header(
+        change: &mut InterimP4Changelist,
+        key: &str,
+        value: &str,
+    ) -> Result<bool, P4Error> {
+        match key {
+            "change" => {
+                change.change = Some(parse_field(value, "Invalid changelist")?);
+            }
+            "time" => {
+                change.time = Some(parse_field(value, "Invalid time")?);
+            }
+            "user" => {
+                change.user.set(value);
+            }
+            "desc" => {
+                change.description.set(value);
+            }
+            "status" => {
+                change.status = Some(parse_field(value, "Invalid changelist status")?);
+            }
+            key => return Ok(split_indexed_key(key)?.is_none()),
+        }
+
+        Ok(true)
     }
 
-    fn populate_field(file: &mut InterimP4File, key: &str, value: &str) {
+    fn populate_file(file: &mut InterimP4File, key: &str, value: &str) -> Result<(), P4Error> {
         match key {
             "depotFile" => {
-                file.depot_path = Some(value.to_string());
+                file.depot_path.set(value);
             }
             "action" => {
-                file.action = Some(value.to_string());
+                file.action.set(value);
             }
             "rev" => {
-                file.revision = Some(value.parse().unwrap());
+                file.revision = Some(parse_field(value, "Invalid revision")?);
             }
             "fileSize" => {
-                file.file_size = Some(value.parse().unwrap());
+                file.file_size = Some(parse_field(value, "Invalid file size")?);
+            }
+            "digest" => {
+                file.digest = Some(
+                    const_hex::decode_to_array(value)
+                        .map_err(|_| P4Error::InvalidRecord("Invalid digest"))?,
+                )
             }
-            "digest" => file.digest = Some(const_hex::decode_to_array(value).unwrap()),
             _ => {} // No op
         }
+
+        Ok(())
     }
-}
 
-impl<ReadT: io::Read> Iterator for P4DescribeIterator<ReadT> {
-    type Item = P4File;
+    // The files that follow belong to the header read so far
+    fn finish_header(&mut self) -> Result<(), P4Error> {
+        if let Some(mut header) = self.header.take() {
+            header.finish_into(&mut self.changelist)?;
+            if let Some(scanner) = &self.description_scanner {
+                scanner.annotate(&mut self.changelist);
+            }
+            self.described = true;
+        }
+        Ok(())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // Read the next file from the p4 process
-        while let Some(kvp) = self.parser.get_next_kvp().unwrap() {
-            if let Some((key, index)) = split_indexed_key(kvp.key) {
-                if Some(index) != self.current_file_index {
-                    self.current_file_index = Some(index);
+    fn finish_file_into(&mut self, output: &mut Option<P4File>) -> Result<bool, P4Error> {
+        if self.file_index.take().is_none() {
+            return Ok(false);
+        }
+        let file = output.get_or_insert_with(P4File::default);
+        self.file.finish_into(file, self.changelist.status)?;
 
-                    // We are done with the current record, so we can yield it
-                    let file = std::mem::take(&mut self.current_file).try_into().unwrap();
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            changelist = self.changelist.changelist,
+            depot_path = %file.depot_path,
+            "parsed file"
+        );
 
-                    // We still need to process this pair for the next file
-                    Self::populate_field(&mut self.current_file, key, kvp.value);
+        Ok(true)
+    }
+}
 
-                    return Some(file);
-                }
+impl P4RecordFields for InterimP4Describe {
+    type Output = P4File;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        if !self.started {
+            self.started = true;
+            // -d output comes as text records after the files of its changelist
+            self.in_diff = key == "data";
+            if !self.in_diff {
+                self.header = Some(InterimP4Changelist::default());
+            }
+        }
+
+        if self.in_diff {
+            if key == "data" {
+                self.diff.push_str(value);
+            }
+            return Ok(());
+        }
 
-                Self::populate_field(&mut self.current_file, key, kvp.value);
-            } else {
-                // This shouldn't happen, so warn?
-                panic!("Unexpected key format: {}", kvp.key);
+        if let Some(header) = self.header.as_mut() {
+            if Self::populate_header(header, key, value)? {
+                return Ok(());
             }
+            self.finish_header()?;
         }
 
-        // Yield the last file
-        if self.current_file_index.is_some() {
-            let file = std::mem::take(&mut self.current_file).try_into().unwrap();
-            self.current_file_index = None;
-            return Some(file);
+        match split_indexed_key(key)? {
+            Some((key, index, None)) => {
+                self.file_index = Some(index);
+                Self::populate_file(&mut self.file, key, value)
+            }
+            _ => Err(P4Error::InvalidRecord("Unexpected key format")),
         }
+    }
+
+    fn finish(mut self) -> Result<P4File, P4Error> {
+        let mut file = None;
+        self.finish_into(&mut file)?;
+        file.ok_or(P4Error::InvalidRecord("Missing depot path"))
+    }
 
-        if let Some(mut p4_process) = self.p4_process.take() {
-            // Wait for the p4 process to finish
-            p4_process.wait().unwrap();
+    // The last file of the changelist, if it had any
+    fn finish_into(&mut self, output: &mut Option<P4File>) -> Result<bool, P4Error> {
+        self.finish_header()?;
+        self.finish_file_into(output)
+    }
+
+    fn finish_item_into(
+        &mut self,
+        key: &str,
+        output: &mut Option<P4File>,
+    ) -> Result<bool, P4Error> {
+        let Some(index) = self.file_index else {
+            return Ok(false);
+        };
+        match split_indexed_key(key)? {
+            Some((_, next_index, None)) if next_index != index => self.finish_file_into(output),
+            _ => Ok(false),
         }
+    }
 
-        None
+    fn next_fields(&mut self) -> Option<Self> {
+        Some(InterimP4Describe {
+            changelist: std::mem::take(&mut self.changelist),
+            described: self.described,
+            file: std::mem::take(&mut self.file),
+            description_scanner: self.description_scanner.clone(),
+            diff: std::mem::take(&mut self.diff),
+            ..Default::default()
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parsers::py_dict::write_py_dict;
     use std::fs;
 
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_to_patch() {
+        use crate::testing::*;
+
+        let printed = |path: &'static str, rev: &'static str, content: &'static str| {
+            [
+                [
+                    ("code", "stat"),
+                    ("depotFile", path),
+                    ("rev", rev),
+                    ("change", "12"),
+                    ("action", "edit"),
+                    ("type", "text"),
+                    ("time", "1743724741"),
+                ]
+                .to_vec(),
+                [("code", "text"), ("data", content)].to_vec(),
+            ]
+        };
+        let filelog = |path: &str, revisions: [(&str, &str, &str); 2]| {
+            let mut record = vec![
+                ("code".to_string(), "stat".to_string()),
+                ("depotFile".to_string(), path.to_string()),
+            ];
+            for (index, (rev, action, file_type)) in revisions.into_iter().enumerate() {
+                for (name, value) in [
+                    ("rev", rev),
+                    ("change", "10"),
+                    ("action", action),
+                    ("type", file_type),
+                ] {
+                    record.push((format!("{}{}", name, index), value.to_string()));
+                }
+            }
+            record
+        };
+
+        let digest = "00000000000000000000000000000000";
+        let describe = |hunks: &'static str| {
+            [
+                [
+                    ("code", "stat"),
+                    ("change", "12"),
+                    ("user", "alice"),
+                    ("time", "1743724741"),
+                    ("desc", "Fix the build\n"),
+                    ("depotFile0", "//depot/a.txt"),
+                    ("action0", "edit"),
+                    ("rev0", "2"),
+                    ("fileSize0", "4"),
+                    ("digest0", digest),
+                    ("depotFile1", "//depot/b.txt"),
+                    ("action1", "add"),
+                    ("rev1", "3"),
+                    ("fileSize1", "2"),
+                    ("digest1", digest),
+                    ("depotFile2", "//depot/c.txt"),
+                    ("action2", "delete"),
+                    ("rev2", "3"),
+                    ("fileSize2", "0"),
+                    ("digest2", digest),
+                ]
+                .to_vec(),
+                [
+                    ("code", "text"),
+                    ("data", "\n==== //depot/a.txt#2 (text+x) ====\n\n"),
+                ]
+                .to_vec(),
+                [("code", "text"), ("data", hunks)].to_vec(),
+                [
+                    ("code", "text"),
+                    ("data", "\n==== //depot/b.txt#3 (text) ====\n\n"),
+                ]
+                .to_vec(),
+            ]
+        };
+        let mock = |describe_args: &str, hunks: &'static str| {
+            MockP4::new()
+                .with_records(describe_args, describe(hunks))
+                .with_records(
+                    "filelog -m 2 //depot/a.txt#2 //depot/b.txt#3 //depot/c.txt#3",
+                    [
+                        filelog(
+                            "//depot/a.txt",
+                            [("2", "edit", "text+x"), ("1", "add", "text")],
+                        ),
+                        // Added again after a delete, so there's no revision before it
+                        filelog(
+                            "//depot/b.txt",
+                            [("3", "add", "text"), ("2", "delete", "text")],
+                        ),
+                        filelog(
+                            "//depot/c.txt",
+                            [("3", "delete", "text"), ("2", "edit", "text")],
+                        ),
+                    ],
+                )
+                .with_records(
+                    "print //depot/b.txt#3 //depot/c.txt#2",
+                    [
+                        printed("//depot/b.txt", "3", "b\n"),
+                        printed("//depot/c.txt", "2", "c"),
+                    ]
+                    .concat(),
+                )
+        };
+
+        let hunks = "@@ -1,2 +1,2 @@\n a\n-b \n+b\n";
+        let patch =
+            to_patch_from_context(&mock("describe -du 12", hunks).into_context(), 12).unwrap();
+        assert_eq!(
+            String::from_utf8(patch).unwrap(),
+            "diff --git a/depot/a.txt b/depot/a.txt\n\
+             old mode 100644\n\
+             new mode 100755\n\
+             --- a/depot/a.txt\n\
+             +++ b/depot/a.txt\n\
+             @@ -1,2 +1,2 @@\n a\n-b \n+b\n\
+             diff --git a/depot/b.txt b/depot/b.txt\n\
+             new file mode 100644\n\
+             --- /dev/null\n\
+             +++ b/depot/b.txt\n\
+             @@ -0,0 +1,1 @@\n+b\n\
+             diff --git a/depot/c.txt b/depot/c.txt\n\
+             deleted file mode 100644\n\
+             --- a/depot/c.txt\n\
+             +++ /dev/null\n\
+             @@ -1,1 +0,0 @@\n-c\n\\ No newline at end of file\n"
+        );
+
+        // The options go to the server, which leaves out the hunks of a.txt
+        let options = P4DiffOptions::new().with_whitespace(P4DiffWhitespace::IgnoreChanges);
+        let context = mock("describe -dbu 12", "").into_context();
+        let patch = to_patch_with_options(&context, 12, &options).unwrap();
+        assert!(String::from_utf8(patch).unwrap().starts_with(
+            "diff --git a/depot/a.txt b/depot/a.txt\nold mode 100644\nnew mode 100755\ndiff --git a/depot/b.txt"
+        ));
+    }
+
+    #[test]
+    fn test_describe_query() {
+        assert_eq!(P4DescribeQuery::from(12).args(), ["describe", "-s", "12"]);
+        assert_eq!(
+            P4DescribeQuery::new(12)
+                .with_changelist(14)
+                .with_shelved()
+                .with_diff_format(P4DiffFormat::Unified)
+                .with_max_files(100)
+                .with_original_numbering()
+                .args(),
+            ["describe", "-du", "-S", "-O", "-m", "100", "12", "14"]
+        );
+        let diff_options = P4DiffOptions::new()
+            .with_whitespace(P4DiffWhitespace::IgnoreChanges)
+            .with_ignore_line_endings()
+            .with_context_lines(5);
+        assert_eq!(
+            P4DescribeQuery::new(12)
+                .with_diff_format(P4DiffFormat::Unified)
+                .with_diff_options(diff_options)
+                .args(),
+            ["describe", "-dblu5", "12"]
+        );
+        assert_eq!(
+            P4DescribeQuery::new(12)
+                .with_diff_format(P4DiffFormat::Summary)
+                .with_diff_options(diff_options)
+                .args(),
+            ["describe", "-dbls", "12"]
+        );
+
+        // Several changelists are one record each, the files of the second one follow the first
+        let change = |change: &'static str, path: &'static str| {
+            [
+                ("code", "stat"),
+                ("change", change),
+                ("user", "alice"),
+                ("time", "1743724741"),
+                ("desc", "Fix the build\n"),
+                ("depotFile0", path),
+                ("action0", "edit"),
+                ("rev0", "2"),
+                ("fileSize0", "4"),
+                ("digest0", "00000000000000000000000000000000"),
+            ]
+        };
+        let mut data = Vec::new();
+        write_py_dict(&mut data, change("12", "//depot/a.txt")).unwrap();
+        write_py_dict(&mut data, change("14", "//depot/b.txt")).unwrap();
+
+        let mut describe_iter = P4DescribeIterator::new_from_reader(&data[..]).unwrap();
+        assert_eq!(describe_iter.get_changelist().changelist, 12);
+        let file = describe_iter.next().unwrap().unwrap();
+        assert_eq!(file.depot_path, "//depot/a.txt");
+        assert_eq!(describe_iter.get_changelist().changelist, 12);
+        let file = describe_iter.next().unwrap().unwrap();
+        assert_eq!(file.depot_path, "//depot/b.txt");
+        assert_eq!(describe_iter.get_changelist().changelist, 14);
+        assert!(describe_iter.next().is_none());
+    }
+
+    #[test]
+    fn test_describe_pending() {
+        let mut data = Vec::new();
+        write_py_dict(
+            &mut data,
+            [
+                ("code", "stat"),
+                ("change", "15"),
+                ("user", "alice"),
+                ("time", "1743724741"),
+                ("desc", "Work in progress\n"),
+                ("status", "pending"),
+                ("depotFile0", "//depot/a.txt"),
+                ("action0", "edit"),
+                ("rev0", "3"),
+                ("depotFile1", "//depot/new.txt"),
+                ("action1", "add"),
+            ],
+        )
+        .unwrap();
+
+        let mut describe_iter = P4DescribeIterator::new_from_reader(&data[..]).unwrap();
+        assert_eq!(
+            describe_iter.get_changelist().status,
+            P4ChangeStatus::Pending
+        );
+        let files = describe_iter
+            .by_ref()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files[0].revision, Some(3));
+        assert_eq!(
+            files[1],
+            P4File {
+                depot_path: "//depot/new.txt".into(),
+                action: "add".into(),
+                ..Default::default()
+            }
+        );
+
+        // Submitted files must have them
+        let mut data = Vec::new();
+        write_py_dict(
+            &mut data,
+            [
+                ("code", "stat"),
+                ("change", "12"),
+                ("user", "alice"),
+                ("time", "1743724741"),
+                ("desc", "Fix the build\n"),
+                ("status", "submitted"),
+                ("depotFile0", "//depot/a.txt"),
+                ("action0", "edit"),
+            ],
+        )
+        .unwrap();
+        let mut describe_iter = P4DescribeIterator::new_from_reader(&data[..]).unwrap();
+        assert!(matches!(
+            describe_iter.next(),
+            Some(Err(P4Error::InvalidRecord("Missing revision")))
+        ));
+    }
+
     #[test]
     fn test_describe() {
         let input_file = fs::File::open("./test_data/describe.pyc").unwrap();
         let mut describe_iter = P4DescribeIterator::new_from_reader(input_file).unwrap();
 
         let expected = vec![
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/.editorconfig".into(), action: "add".into(), revision: 1, file_size: 1015, digest: [169, 233, 51, 32, 225, 252, 70, 146, 40, 215, 7, 201, 18, 76, 135, 140] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/.gitattributes".into(), action: "add".into(), revision: 1, file_size: 522, digest: [172, 90, 50, 21, 100, 157, 221, 71, 43, 74, 89, 74, 35, 198, 32, 190] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/.gitignore".into(), action: "add".into(), revision: 1, file_size: 8396, digest: [158, 21, 68, 189, 138, 27, 249, 134, 129, 107, 108, 15, 200, 3, 246, 82] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/Default.uprojectdirs".into(), action: "add".into(), revision: 1, file_size: 285, digest: [137, 95, 100, 35, 141, 109, 217, 189, 117, 209, 91, 18, 71, 141, 157, 232] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CSVCollate.deps.json".into(), action: "add".into(), revision: 1, file_size: 1611, digest: [62, 23, 231, 202, 158, 32, 102, 86, 126, 10, 108, 96, 20, 235, 16, 69] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CSVCollate.dll.config".into(), action: "add".into(), revision: 1, file_size: 178, digest: [105, 168, 101, 152, 92, 186, 230, 239, 44, 201, 60, 26, 137, 45, 57, 117] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CSVCollate.runtimeconfig.json".into(), action: "add".into(), revision: 1, file_size: 242, digest: [43, 242, 132, 218, 100, 17, 155, 106, 223, 229, 123, 3, 64, 7, 15, 97] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CsvConvert.deps.json".into(), action: "add".into(), revision: 1, file_size: 1611, digest: [89, 227, 34, 142, 6, 213, 177, 28, 182, 195, 18, 181, 218, 41, 183, 222] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CsvConvert.dll.config".into(), action: "add".into(), revision: 1, file_size: 178, digest: [105, 168, 101, 152, 92, 186, 230, 239, 44, 201, 60, 26, 137, 45, 57, 117] },
-            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CsvConvert.runtimeconfig.json".into(), action: "add".into(), revision: 1, file_size: 242, digest: [43, 242, 132, 218, 100, 17, 155, 106, 223, 229, 123, 3, 64, 7, 15, 97] },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/.editorconfig".into(), action: "add".into(), revision: Some(1), file_size: Some(1015), digest: Some([169, 233, 51, 32, 225, 252, 70, 146, 40, 215, 7, 201, 18, 76, 135, 140]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/.gitattributes".into(), action: "add".into(), revision: Some(1), file_size: Some(522), digest: Some([172, 90, 50, 21, 100, 157, 221, 71, 43, 74, 89, 74, 35, 198, 32, 190]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/.gitignore".into(), action: "add".into(), revision: Some(1), file_size: Some(8396), digest: Some([158, 21, 68, 189, 138, 27, 249, 134, 129, 107, 108, 15, 200, 3, 246, 82]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/Default.uprojectdirs".into(), action: "add".into(), revision: Some(1), file_size: Some(285), digest: Some([137, 95, 100, 35, 141, 109, 217, 189, 117, 209, 91, 18, 71, 141, 157, 232]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CSVCollate.deps.json".into(), action: "add".into(), revision: Some(1), file_size: Some(1611), digest: Some([62, 23, 231, 202, 158, 32, 102, 86, 126, 10, 108, 96, 20, 235, 16, 69]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CSVCollate.dll.config".into(), action: "add".into(), revision: Some(1), file_size: Some(178), digest: Some([105, 168, 101, 152, 92, 186, 230, 239, 44, 201, 60, 26, 137, 45, 57, 117]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CSVCollate.runtimeconfig.json".into(), action: "add".into(), revision: Some(1), file_size: Some(242), digest: Some([43, 242, 132, 218, 100, 17, 155, 106, 223, 229, 123, 3, 64, 7, 15, 97]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CsvConvert.deps.json".into(), action: "add".into(), revision: Some(1), file_size: Some(1611), digest: Some([89, 227, 34, 142, 6, 213, 177, 28, 182, 195, 18, 181, 218, 41, 183, 222]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CsvConvert.dll.config".into(), action: "add".into(), revision: Some(1), file_size: Some(178), digest: Some([105, 168, 101, 152, 92, 186, 230, 239, 44, 201, 60, 26, 137, 45, 57, 117]) },
+            P4File { depot_path: "//depot/main3/UE5.5_github_src/Engine/Binaries/DotNET/CsvTools/CsvConvert.runtimeconfig.json".into(), action: "add".into(), revision: Some(1), file_size: Some(242), digest: Some([43, 242, 132, 218, 100, 17, 155, 106, 223, 229, 123, 3, 64, 7, 15, 97]) },
         ];
 
-        let mut expected_iter = expected.into_iter();
-
-        while let Some(expected) = expected_iter.next() {
-            assert_eq!(describe_iter.next(), Some(expected));
+        for file in expected {
+            assert_eq!(describe_iter.next().unwrap().unwrap(), file);
         }
 
         // Make sure there are no more records
-        assert_eq!(describe_iter.next(), None, "Expected no more files");
+        assert!(describe_iter.next().is_none(), "Expected no more files");
+    }
+
+    #[test]
+    fn test_describe_unified_diff() {
+        // describe -du 12, the diffs are text records after the changelist
+        let input_file = fs::File::open("./test_data/describe_du.pyc").unwrap();
+        let mut describe_iter = P4DescribeIterator::new_from_reader(input_file).unwrap();
+        let files = describe_iter
+            .by_ref()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].depot_path, "//depot/main/notes.txt");
+        assert_eq!(describe_iter.get_changelist().changelist, 12);
+        assert!(
+            describe_iter
+                .diff()
+                .starts_with("\n==== //depot/main/build.sh#3 (text+x) ====\n")
+        );
+        assert!(describe_iter.diff().contains("-make all\n+make -j8 all\n"));
+        assert!(
+            describe_iter
+                .diff()
+                .ends_with("==== //depot/main/notes.txt#1 (text) ====\n\n")
+        );
+    }
+
+    #[test]
+    fn test_describe_next_into() {
+        let input_file = fs::File::open("./test_data/describe.pyc").unwrap();
+        let mut describe_iter = P4DescribeIterator::new_from_reader(input_file).unwrap();
+
+        // The buffers are swapped between the iterator and the output, and only reallocated to fit a longer path
+        let mut file = P4File::default();
+        let mut buffers = std::collections::HashSet::new();
+        let mut files = 0;
+        while let Some(result) = describe_iter.next_into(&mut file) {
+            result.unwrap();
+            assert!(
+                file.depot_path
+                    .starts_with("//depot/main3/UE5.5_github_src/")
+            );
+            buffers.insert(file.depot_path.as_ptr());
+            files += 1;
+        }
+        assert_eq!(files, 10);
+        assert!(
+            buffers.len() < files,
+            "{} buffers for {} files",
+            buffers.len(),
+            files
+        );
+        assert_eq!(file.action, "add");
+        assert_eq!(describe_iter.records_yielded(), 10);
+    }
+
+    // Generates the output of describing a changelist with `files` files, a chunk at a time, so the fixture
+    // itself doesn't take memory proportional to the number of files
+    struct GeneratedDescribe {
+        files: usize,
+        // 0 is the header, then one per file, then the end of the dict
+        next_chunk: usize,
+        buffer: Vec<u8>,
+        position: usize,
+    }
+
+    impl GeneratedDescribe {
+        fn new(files: usize) -> Self {
+            GeneratedDescribe {
+                files,
+                next_chunk: 0,
+                buffer: Vec::new(),
+                position: 0,
+            }
+        }
+
+        fn write_pair(&mut self, key: &str, value: &str) {
+            for s in [key, value] {
+                self.buffer.push(b's');
+                self.buffer
+                    .extend_from_slice(&(s.len() as u32).to_le_bytes());
+                self.buffer.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+
+    impl io::Read for GeneratedDescribe {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if self.position == self.buffer.len() {
+                self.buffer.clear();
+                self.position = 0;
+                match self.next_chunk {
+                    0 => {
+                        self.buffer.push(b'{');
+                        for (key, value) in [
+                            ("code", "stat"),
+                            ("change", "1"),
+                            ("time", "1743724741"),
+                            ("user", "importer"),
+                            ("desc", "Initial import\n"),
+                        ] {
+                            self.write_pair(key, value);
+                        }
+                    }
+                    chunk if chunk <= self.files => {
+                        let index = chunk - 1;
+                        let depot_path =
+                            format!("//depot/import/{}/file{}.txt", index / 1000, index);
+                        self.write_pair(&format!("depotFile{}", index), &depot_path);
+                        self.write_pair(&format!("action{}", index), "add");
+                        self.write_pair(&format!("rev{}", index), "1");
+                        self.write_pair(&format!("fileSize{}", index), "42");
+                        self.write_pair(
+                            &format!("digest{}", index),
+                            "A9E93320E1FC469228D707C9124C878C",
+                        );
+                    }
+                    chunk if chunk == self.files + 1 => self.buffer.push(b'0'),
+                    _ => return Ok(0),
+                }
+                self.next_chunk += 1;
+            }
+
+            let len = out.len().min(self.buffer.len() - self.position);
+            out[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+            self.position += len;
+            Ok(len)
+        }
+    }
+
+    fn assert_describe_streams(files: usize) {
+        // Every key and value the parser holds is checked against the budget, so a parser that kept more
+        // than the current pair around would fail here
+        let budget = Arc::new(crate::budget::MemoryBudget::new().with_max_buffered_bytes(1024));
+        let parser = P4PyDictParser::new(io::BufReader::new(GeneratedDescribe::new(files)))
+            .with_memory_budget(budget.clone());
+        let mut describe_iter = P4DescribeIterator::new_from_parser(parser).unwrap();
+
+        let mut file = P4File::default();
+        let mut files_read = 0;
+        while let Some(result) = describe_iter.next_into(&mut file) {
+            result.unwrap();
+            files_read += 1;
+        }
+        assert_eq!(files_read, files);
+        assert!(
+            file.depot_path
+                .ends_with(&format!("/file{}.txt", files - 1))
+        );
+        assert!(file.depot_path.capacity() < 1024);
+        assert!(describe_iter.get_changelist().files.is_empty());
+        assert!(budget.buffered_bytes() <= 1024);
+    }
+
+    #[test]
+    fn test_describe_constant_memory() {
+        assert_describe_streams(100_000);
+    }
+
+    // The size of a large initial import
+    #[test]
+    #[ignore = "takes around 20s in debug builds, run with --ignored"]
+    fn test_describe_constant_memory_2m_files() {
+        assert_describe_streams(2_000_000);
     }
 }
@@ -0,0 +1,435 @@
+// == Std crates
+use std::time::Duration;
+#[cfg(feature = "process")]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// == Internal crates
+#[cfg(feature = "process")]
+use crate::changes::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+use crate::error::*;
+
+// The options of `p4 ping`, the defaults are p4's: 30 messages without a payload, one iteration
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4PingOptions {
+    // -c, messages per iteration
+    pub count: Option<u32>,
+    // -i, how many times the count is sent, each timed on its own
+    pub iterations: Option<u32>,
+    // -s and -r, the payload of each message to and from the server
+    pub send_bytes: Option<u32>,
+    pub receive_bytes: Option<u32>,
+}
+
+impl P4PingOptions {
+    #[cfg(feature = "process")]
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["ping".to_string()];
+        let options = [
+            ("-c", self.count),
+            ("-i", self.iterations),
+            ("-s", self.send_bytes),
+            ("-r", self.receive_bytes),
+        ];
+        for (flag, value) in options {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value.to_string()]);
+            }
+        }
+        args
+    }
+}
+
+// The timing of one iteration of `p4 ping`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct P4PingResult {
+    pub count: u32,
+    pub elapsed: Duration,
+    pub send_bytes: u32,
+    pub receive_bytes: u32,
+}
+
+impl P4PingResult {
+    // The round trip time of one message
+    pub fn per_message(&self) -> Duration {
+        self.elapsed / self.count.max(1)
+    }
+
+    // From the line p4 prints per iteration, e.g.
+    // "Count 30 in 0.004s (0.00013 per), sent 100 bytes, received 10 bytes each"
+    // Wording differs between server versions, so only the count, the time in seconds and the sizes after
+    // send/sent and receive/received are read.
+    pub fn parse(line: &str) -> Result<Self, P4Error> {
+        let invalid = || P4Error::InvalidRecord("Invalid ping result");
+        let words = line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '(' || c == ')')
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        let number_after = |index: usize| words.get(index + 1).and_then(|word| word.parse().ok());
+
+        let mut result = P4PingResult::default();
+        let mut count = None;
+        let mut seconds = None;
+        for (index, word) in words.iter().enumerate() {
+            let lower = word.to_ascii_lowercase();
+            if count.is_none() && lower == "count" {
+                count = number_after(index);
+            } else if count.is_none()
+                && let Ok(number) = word.parse::<u32>()
+            {
+                count = Some(number);
+            } else if seconds.is_none()
+                && let Some(number) = lower
+                    .strip_suffix("secs")
+                    .or_else(|| lower.strip_suffix('s'))
+                    .and_then(|number| number.parse::<f64>().ok())
+            {
+                seconds = Some(number);
+            } else if seconds.is_none()
+                && lower.starts_with("sec")
+                && let Some(number) = index
+                    .checked_sub(1)
+                    .and_then(|previous| words[previous].parse::<f64>().ok())
+            {
+                seconds = Some(number);
+            } else if lower.starts_with("sen") {
+                result.send_bytes = number_after(index).unwrap_or(result.send_bytes);
+            } else if lower.starts_with("rec") {
+                result.receive_bytes = number_after(index).unwrap_or(result.receive_bytes);
+            }
+        }
+
+        result.count = count.ok_or_else(invalid)?;
+        result.elapsed = seconds
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(invalid)?;
+        Ok(result)
+    }
+}
+
+// Times round trips to the server, e.g. before choosing parallel sync settings. One result per iteration.
+#[cfg(feature = "spawn")]
+pub fn ping(options: &P4PingOptions) -> Result<Vec<P4PingResult>, P4Error> {
+    ping_from_context(&P4Context::default(), options)
+}
+
+#[cfg(feature = "process")]
+pub fn ping_from_context(
+    context: &P4Context,
+    options: &P4PingOptions,
+) -> Result<Vec<P4PingResult>, P4Error> {
+    let args = options.args();
+    let mut results = Vec::new();
+    for record in
+        P4DictIterator::new_from_context(context, args.iter().map(String::as_str).collect())?
+    {
+        if let Some(line) = record?.get("data") {
+            results.push(P4PingResult::parse(line)?);
+        }
+    }
+    Ok(results)
+}
+
+// What a readiness probe needs to know about the server, from `p4 info`, `p4 changes -m 1` and optionally
+// `p4 monitor show`, and on replicas `p4 pull -lj`. Each check only runs if the one before it succeeded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4HealthReport {
+    // `p4 info` succeeded
+    pub reachable: bool,
+    // `p4 changes -m 1` succeeded, so the user is logged in and can read something
+    pub authenticated: bool,
+    // How long `p4 info` took
+    pub response_time: Option<Duration>,
+    pub server_address: Option<String>,
+    pub server_version: Option<String>,
+    pub server_id: Option<String>,
+    // e.g. "standard", "replica", "forwarding-replica" or "edge-server"
+    pub server_services: Option<String>,
+    pub latest_change: Option<u32>,
+    // How long ago the latest changelist was submitted, which on a quiet depot says nothing about replication
+    pub latest_change_age: Option<Duration>,
+    // How far a replica's journal is behind its master's, None on other servers or without super access
+    pub journal_lag: Option<P4JournalLag>,
+    // The commands running on the server and the longest running one, None if monitoring wasn't asked for or
+    // isn't enabled
+    pub running_commands: Option<usize>,
+    pub longest_running: Option<Duration>,
+    // Why the last check failed
+    pub error: Option<P4ErrorKind>,
+}
+
+impl P4HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.reachable && self.authenticated
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.server_services
+            .as_deref()
+            .is_some_and(|services| services.contains("replica") || services.starts_with("edge"))
+    }
+}
+
+// The journal positions `p4 pull -lj` reports on a replica
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct P4JournalLag {
+    pub replica_journal: u32,
+    pub replica_sequence: u64,
+    pub master_journal: u32,
+    pub master_sequence: u64,
+}
+
+impl P4JournalLag {
+    // The journal bytes still to be pulled, None if the master has rotated its journal since, as sequences in
+    // different journals can't be compared
+    pub fn bytes_behind(&self) -> Option<u64> {
+        (self.replica_journal == self.master_journal)
+            .then(|| self.master_sequence.saturating_sub(self.replica_sequence))
+    }
+
+    pub fn is_caught_up(&self) -> bool {
+        self.bytes_behind() == Some(0)
+    }
+}
+
+// Never fails, problems are recorded in the report
+#[cfg(feature = "spawn")]
+pub fn health(include_monitor: bool) -> P4HealthReport {
+    health_from_context(&P4Context::default(), include_monitor)
+}
+
+#[cfg(feature = "process")]
+pub fn health_from_context(context: &P4Context, include_monitor: bool) -> P4HealthReport {
+    let mut report = P4HealthReport::default();
+
+    let start = Instant::now();
+    let info = match all_records(context, &["info"]) {
+        Ok(records) => records,
+        Err(error) => {
+            report.error = Some(error.kind());
+            return report;
+        }
+    };
+    report.reachable = true;
+    report.response_time = Some(start.elapsed());
+    if let Some(info) = info.first() {
+        let field = |key: &str| info.get(key).map(str::to_string);
+        report.server_address = field("serverAddress");
+        report.server_version = field("serverVersion");
+        report.server_id = field("ServerID").or_else(|| field("serverID"));
+        report.server_services = field("serverServices");
+    }
+
+    match first_change(
+        context,
+        P4ChangesQuery::new().with_description_mode(P4DescriptionMode::Short),
+    ) {
+        Ok(change) => {
+            report.authenticated = true;
+            if let Some(change) = change {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                report.latest_change = Some(change.changelist);
+                report.latest_change_age =
+                    Some(now.saturating_sub(Duration::from_secs(change.time.into())));
+            }
+        }
+        Err(error) => {
+            report.error = Some(error.kind());
+            return report;
+        }
+    }
+
+    // pull needs super access, so failing is no more than a missing hint
+    if report.is_replica()
+        && let Ok(records) = all_records(context, &["pull", "-lj"])
+    {
+        report.journal_lag = records.first().and_then(parse_journal_lag);
+    }
+
+    // Monitoring is off on many servers, which isn't a health problem
+    if include_monitor && let Ok(commands) = all_records(context, &["monitor", "show", "-a"]) {
+        report.running_commands = Some(commands.len());
+        report.longest_running = commands
+            .iter()
+            .filter_map(|command| parse_monitor_time(command.get("time")?))
+            .max();
+    }
+
+    report
+}
+
+#[cfg(feature = "process")]
+fn all_records(context: &P4Context, args: &[&str]) -> Result<Vec<P4Dict>, P4Error> {
+    context.run_raw(args)?.collect()
+}
+
+#[cfg(feature = "process")]
+fn parse_journal_lag(record: &P4Dict) -> Option<P4JournalLag> {
+    Some(P4JournalLag {
+        replica_journal: record.get("replicaJournalNumber")?.parse().ok()?,
+        replica_sequence: record.get("replicaJournalSequence")?.parse().ok()?,
+        master_journal: record.get("masterJournalNumber")?.parse().ok()?,
+        master_sequence: record.get("masterJournalSequence")?.parse().ok()?,
+    })
+}
+
+// The time a command has been running in `p4 monitor show`, as HH:MM:SS
+#[cfg(feature = "process")]
+fn parse_monitor_time(time: &str) -> Option<Duration> {
+    let mut seconds = 0;
+    for part in time.split(':') {
+        seconds = seconds * 60 + part.trim().parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ping() {
+        let result = P4PingResult::parse(
+            "Count 30 in 0.006s (0.0002 per), sent 100 bytes, received 10 bytes each",
+        )
+        .unwrap();
+        assert_eq!(result.count, 30);
+        assert_eq!(result.elapsed, Duration::from_millis(6));
+        assert_eq!(result.per_message(), Duration::from_micros(200));
+        assert_eq!((result.send_bytes, result.receive_bytes), (100, 10));
+
+        let result = P4PingResult::parse("10 messages in 0.5 seconds").unwrap();
+        assert_eq!(
+            (result.count, result.elapsed),
+            (10, Duration::from_millis(500))
+        );
+
+        assert!(P4PingResult::parse("Perforce server info:").is_err());
+        assert!(P4PingResult::parse("Count 30 in infs").is_err());
+        assert!(P4PingResult::parse("Count 30 in 1e300s").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_ping() {
+        use crate::testing::*;
+
+        let options = P4PingOptions {
+            count: Some(10),
+            iterations: Some(2),
+            send_bytes: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.args(),
+            ["ping", "-c", "10", "-i", "2", "-s", "1000"]
+        );
+
+        let context = MockP4::new()
+            .with_records(
+                "ping -c 10 -i 2 -s 1000",
+                [
+                    [
+                        ("code", "info"),
+                        ("data", "Count 10 in 0.010s, sent 1000 bytes"),
+                    ],
+                    [
+                        ("code", "info"),
+                        ("data", "Count 10 in 0.030s, sent 1000 bytes"),
+                    ],
+                ],
+            )
+            .into_context();
+        let results = ping_from_context(&context, &options).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].per_message(), Duration::from_millis(3));
+        assert_eq!(results[0].send_bytes, 1000);
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_health() {
+        use crate::testing::*;
+
+        let context = MockP4::new()
+            .with_records(
+                "info",
+                [[
+                    ("code", "stat"),
+                    ("serverAddress", "perforce:1666"),
+                    (
+                        "serverVersion",
+                        "P4D/LINUX26X86_64/2023.2/2519561 (2024/01/31)",
+                    ),
+                    ("serverServices", "forwarding-replica"),
+                ]],
+            )
+            .with_records(
+                "changes -s submitted -m 1",
+                [[
+                    ("code", "stat"),
+                    ("change", "42"),
+                    ("time", "1743724741"),
+                    ("user", "alice"),
+                    ("client", "alice-ws"),
+                    ("status", "submitted"),
+                    ("desc", "Fix the build\n"),
+                ]],
+            )
+            .with_records(
+                "pull -lj",
+                [[
+                    ("code", "stat"),
+                    ("replicaJournalNumber", "7"),
+                    ("replicaJournalSequence", "1000"),
+                    ("masterJournalNumber", "7"),
+                    ("masterJournalSequence", "4096"),
+                ]],
+            )
+            .with_records(
+                "monitor show -a",
+                [
+                    [("code", "stat"), ("id", "12"), ("time", "00:00:03")],
+                    [("code", "stat"), ("id", "13"), ("time", "01:02:03")],
+                ],
+            )
+            .into_context();
+        let report = health_from_context(&context, true);
+        assert!(report.is_healthy() && report.is_replica());
+        assert_eq!(report.server_address.as_deref(), Some("perforce:1666"));
+        assert_eq!(report.latest_change, Some(42));
+        assert!(report.latest_change_age.is_some());
+        let lag = report.journal_lag.unwrap();
+        assert_eq!(lag.bytes_behind(), Some(3096));
+        assert!(!lag.is_caught_up());
+        assert_eq!(report.running_commands, Some(2));
+        assert_eq!(report.longest_running, Some(Duration::from_secs(3723)));
+
+        let context = MockP4::new()
+            .with_records("info", [[("code", "stat"), ("serverServices", "standard")]])
+            .with_error(
+                "changes -s submitted -m 1",
+                E_FAILED,
+                0,
+                "Perforce password (P4PASSWD) invalid or unset.\n",
+            )
+            .into_context();
+        let report = health_from_context(&context, false);
+        assert!(report.reachable && !report.authenticated);
+        assert_eq!(report.error, Some(P4ErrorKind::LoginRequired));
+        assert_eq!(report.running_commands, None);
+        assert_eq!(report.journal_lag, None);
+
+        let context = MockP4::new()
+            .with_error("info", E_FAILED, EV_COMM, "Connect to server failed.\n")
+            .into_context();
+        let report = health_from_context(&context, false);
+        assert!(!report.reachable);
+        assert_eq!(report.error, Some(P4ErrorKind::ConnectRefused));
+    }
+}
@@ -0,0 +1,359 @@
+// == Std crates
+use std::{
+    collections::{BTreeMap, HashSet},
+    io,
+    sync::Arc,
+};
+
+// == Internal crates
+use crate::cancel::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+use crate::metrics::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::parsers::py_dict::*;
+use crate::process_state::*;
+use crate::records::*;
+
+// Fields by their indices and name, see P4Dict::indexed_fields
+pub type P4IndexedFields<'a> = BTreeMap<Vec<usize>, BTreeMap<&'a str, &'a str>>;
+
+// One -G dict as an owned record, for commands without a typed wrapper. Keys are shared between records.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4Dict {
+    fields: Vec<(Arc<str>, String)>,
+}
+
+impl P4Dict {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field_key, _)| &**field_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // In the order p4 sent them
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (&**key, value.as_str()))
+    }
+
+    // The keys are interned, cloning them doesn't allocate
+    pub fn fields(&self) -> &[(Arc<str>, String)] {
+        &self.fields
+    }
+
+    // The fields grouped by the indices on their names, for indexed output without a typed iterator, e.g.
+    // `p4 resolve -n` or `p4 filelog`. Fields without an index are under the empty index and how0,1 is how
+    // under [0, 1].
+    pub fn indexed_fields(&self) -> Result<P4IndexedFields<'_>, P4Error> {
+        let mut groups = P4IndexedFields::new();
+        for (key, value) in self.iter() {
+            let key = P4IndexedKey::parse(key)?;
+            groups
+                .entry(key.indices)
+                .or_default()
+                .insert(key.name, value);
+        }
+        Ok(groups)
+    }
+
+    pub(crate) fn push(&mut self, key: Arc<str>, value: String) {
+        self.fields.push((key, value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+// Hands out one Arc<str> per distinct key, so a scan allocates `depotFile` once rather than once per record
+#[derive(Debug, Default)]
+pub(crate) struct KeyInterner {
+    keys: HashSet<Arc<str>>,
+}
+
+impl KeyInterner {
+    // Indexed keys (depotFile0, depotFile1, ...) can make the set of keys unbounded, past this many keys new
+    // ones are allocated per record instead of cached
+    const MAX_KEYS: usize = 4096;
+
+    pub(crate) fn intern(&mut self, key: &str) -> Arc<str> {
+        if let Some(interned) = self.keys.get(key) {
+            return interned.clone();
+        }
+        let interned = Arc::<str>::from(key);
+        if self.keys.len() < Self::MAX_KEYS {
+            self.keys.insert(interned.clone());
+        }
+        interned
+    }
+}
+
+pub struct P4DictIterator<ReadT: io::Read> {
+    process_state: P4ProcessState,
+    parser: P4PyDictParser<ReadT>,
+    keys: KeyInterner,
+    previous_dict_index: Option<u32>,
+    current: P4Dict,
+    error: Option<P4ServerMessage>,
+    records_skipped: u64,
+}
+
+#[cfg(feature = "process")]
+impl P4DictIterator<P4Output> {
+    // `args` is the command and its arguments, e.g. ["counters"]
+    #[cfg(feature = "spawn")]
+    pub fn new(args: Vec<&str>) -> Result<P4DictIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), args)
+    }
+
+    pub fn new_from_context(
+        context: &P4Context,
+        args: Vec<&str>,
+    ) -> Result<P4DictIterator<P4Output>, P4Error> {
+        let (p4_process, reader) = context.spawn(args.clone())?;
+
+        let parser = context.parser(reader);
+        let mut result = P4DictIterator::new_from_parser(parser);
+        result.process_state.attach(p4_process, context, &args);
+
+        Ok(result)
+    }
+
+    // The same, for a command that reads `input` from stdin
+    pub(crate) fn new_from_context_with_input(
+        context: &P4Context,
+        args: Vec<&str>,
+        input: &[u8],
+    ) -> Result<P4DictIterator<P4Output>, P4Error> {
+        let (p4_process, reader) = context.spawn_with_input(args.clone(), input)?;
+
+        let parser = context.parser(reader);
+        let mut result = P4DictIterator::new_from_parser(parser);
+        result.process_state.attach(p4_process, context, &args);
+
+        Ok(result)
+    }
+}
+
+// Runs a command that reads forms from stdin, e.g. ["job", "-i"] or ["client", "-i"], sending each record in
+// the -G marshal format so several forms go through one process. The results are the command's records, e.g.
+// one "Job job000123 saved." per form.
+#[cfg(feature = "spawn")]
+pub fn run_with_input<KeyT, ValueT>(
+    args: Vec<&str>,
+    records: impl IntoIterator<Item = impl IntoIterator<Item = (KeyT, ValueT)>>,
+) -> Result<P4DictIterator<P4Output>, P4Error>
+where
+    KeyT: AsRef<[u8]>,
+    ValueT: AsRef<[u8]>,
+{
+    run_with_input_from_context(&P4Context::default(), args, records)
+}
+
+#[cfg(feature = "process")]
+pub fn run_with_input_from_context<KeyT, ValueT>(
+    context: &P4Context,
+    args: Vec<&str>,
+    records: impl IntoIterator<Item = impl IntoIterator<Item = (KeyT, ValueT)>>,
+) -> Result<P4DictIterator<P4Output>, P4Error>
+where
+    KeyT: AsRef<[u8]>,
+    ValueT: AsRef<[u8]>,
+{
+    let mut input = Vec::new();
+    for record in records {
+        write_py_dict(&mut input, record)?;
+    }
+    P4DictIterator::new_from_context_with_input(context, args, &input)
+}
+
+impl<ReadT: io::Read> P4DictIterator<ReadT> {
+    pub fn new_from_reader(reader: ReadT) -> P4DictIterator<ReadT> {
+        Self::new_from_parser(P4PyDictParser::new(reader))
+    }
+
+    pub fn new_from_parser(parser: P4PyDictParser<ReadT>) -> P4DictIterator<ReadT> {
+        P4DictIterator {
+            process_state: P4ProcessState::default(),
+            parser,
+            keys: KeyInterner::default(),
+            previous_dict_index: None,
+            current: P4Dict::default(),
+            error: None,
+            records_skipped: 0,
+        }
+    }
+
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.process_state.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn records_yielded(&self) -> u64 {
+        self.process_state.records_yielded()
+    }
+
+    // Totals for the command, None until the iterator is exhausted
+    pub fn summary(&self) -> Option<P4IteratorSummary> {
+        self.process_state.summary(self.records_skipped)
+    }
+
+    fn next_dict(&mut self) -> Result<Option<P4Dict>, P4Error> {
+        while let Some(kvp) = self.parser.get_next_kvp()? {
+            let completed = if self.previous_dict_index.is_some()
+                && Some(kvp.dict_index) != self.previous_dict_index
+            {
+                Some((std::mem::take(&mut self.current), self.error.take()))
+            } else {
+                None
+            };
+            self.previous_dict_index = Some(kvp.dict_index);
+
+            if let Some(error) = self.error.as_mut() {
+                error.populate_field(kvp.key, kvp.value);
+            } else if kvp.key == "code" && kvp.value == "error" {
+                self.error = Some(P4ServerMessage::default());
+            } else {
+                let key = self.keys.intern(kvp.key);
+                self.current.fields.push((key, kvp.value.to_string()));
+            }
+
+            if let Some((dict, error)) = completed
+                && let Some(dict) = finish_dict(dict, error, &mut self.records_skipped)?
+            {
+                return Ok(Some(dict));
+            }
+        }
+
+        // The final dict
+        if self.previous_dict_index.take().is_some() {
+            let dict = std::mem::take(&mut self.current);
+            return finish_dict(dict, self.error.take(), &mut self.records_skipped);
+        }
+
+        Ok(None)
+    }
+}
+
+// Warnings such as "file(s) not on client." are skipped (None), anything worse is an error
+fn finish_dict(
+    dict: P4Dict,
+    error: Option<P4ServerMessage>,
+    records_skipped: &mut u64,
+) -> Result<Option<P4Dict>, P4Error> {
+    match error {
+        Some(message) if message.severity <= E_WARN => {
+            *records_skipped += 1;
+            Ok(None)
+        }
+        Some(message) => Err(P4Error::Server(message)),
+        None => Ok(Some(dict)),
+    }
+}
+
+impl<ReadT: io::Read> Iterator for P4DictIterator<ReadT> {
+    type Item = Result<P4Dict, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(result) = self.process_state.before_next() {
+            return result;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = self.process_state.span().entered();
+
+        let result = self.next_dict();
+        let bytes_read = self.parser.bytes_read();
+        self.process_state.after_next(result, bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_dict_iterator() {
+        let data = to_py_dict_bytes(&[
+            &[("code", "stat"), ("counter", "change"), ("value", "12")],
+            &[
+                ("code", "error"),
+                ("data", "no such counter\n"),
+                ("severity", "2"),
+            ],
+            &[("code", "stat"), ("counter", "journal"), ("value", "3")],
+        ]);
+
+        let mut iterator = P4DictIterator::new_from_reader(&data[..]);
+        let dicts = iterator.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(dicts.len(), 2);
+        assert_eq!(dicts[0].get("counter"), Some("change"));
+        assert_eq!(dicts[1].get("value"), Some("3"));
+        assert_eq!(dicts[1].iter().next(), Some(("code", "stat")));
+        assert_eq!(iterator.summary().unwrap().records_skipped, 1);
+
+        // Each key is allocated once for the whole stream
+        assert!(Arc::ptr_eq(
+            &dicts[0].fields()[1].0,
+            &dicts[1].fields()[1].0
+        ));
+
+        // Double-indexed integration fields, as filelog prints them
+        let data = to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("depotFile", "//depot/rel/a.txt"),
+            ("rev0", "2"),
+            ("how0,0", "copy from"),
+            ("file0,0", "//depot/main/a.txt"),
+            ("how0,1", "merge from"),
+            ("file0,1", "//depot/dev/a.txt"),
+        ]]);
+        let dict = P4DictIterator::new_from_reader(&data[..])
+            .next()
+            .unwrap()
+            .unwrap();
+        let fields = dict.indexed_fields().unwrap();
+        assert_eq!(fields[&vec![]]["depotFile"], "//depot/rel/a.txt");
+        assert_eq!(fields[&vec![0]]["rev"], "2");
+        assert_eq!(fields[&vec![0, 1]]["how"], "merge from");
+        assert_eq!(fields[&vec![0, 1]]["file"], "//depot/dev/a.txt");
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_run_with_input() {
+        use crate::testing::*;
+
+        let mock = Arc::new(MockP4::new().with_records(
+            "job -i",
+            [
+                [("code", "info"), ("data", "Job job000001 saved.")],
+                [("code", "info"), ("data", "Job job000002 saved.")],
+            ],
+        ));
+        let context = P4Context::new().with_backend(mock.clone());
+        let jobs = [
+            [("Job", "new"), ("Description", "First\n")],
+            [("Job", "new"), ("Description", "Second\n")],
+        ];
+        let saved = run_with_input_from_context(&context, vec!["job", "-i"], jobs)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(saved.len(), 2);
+        let sent = P4DictIterator::new_from_reader(&mock.inputs()[0][..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(sent[1].get("Description"), Some("Second\n"));
+    }
+}
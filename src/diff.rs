@@ -0,0 +1,86 @@
+// How whitespace is compared, the -db and -dw diff flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P4DiffWhitespace {
+    #[default]
+    Exact,
+    // -db, runs of whitespace compare equal to a single space and trailing whitespace is ignored
+    IgnoreChanges,
+    // -dw
+    IgnoreAll,
+}
+
+// The modifiers of the -d<flags> diff options of `p4 describe` and `p4 diff2`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct P4DiffOptions {
+    context_lines: Option<u32>,
+    whitespace: P4DiffWhitespace,
+    ignore_line_endings: bool,
+}
+
+impl P4DiffOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // e.g. -du5, 3 lines if not set
+    pub fn with_context_lines(mut self, context_lines: u32) -> Self {
+        self.context_lines = Some(context_lines);
+        self
+    }
+
+    pub fn with_whitespace(mut self, whitespace: P4DiffWhitespace) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    // -dl, lines that only differ by CRLF vs LF compare equal
+    pub fn with_ignore_line_endings(mut self) -> Self {
+        self.ignore_line_endings = true;
+        self
+    }
+
+    // The flags that go before the diff format letter, e.g. "bl"
+    fn flags(&self) -> String {
+        let mut flags = String::new();
+        match self.whitespace {
+            P4DiffWhitespace::Exact => {}
+            P4DiffWhitespace::IgnoreChanges => flags.push('b'),
+            P4DiffWhitespace::IgnoreAll => flags.push('w'),
+        }
+        if self.ignore_line_endings {
+            flags.push('l');
+        }
+        flags
+    }
+
+    // e.g. -dbu5 for the unified format, only the unified and context formats take a number of lines
+    pub(crate) fn arg(&self, format: char) -> String {
+        let lines = match (format, self.context_lines) {
+            ('u' | 'c', Some(lines)) => lines.to_string(),
+            _ => String::new(),
+        };
+        format!("-d{}{}{}", self.flags(), format, lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_options() {
+        assert_eq!(P4DiffOptions::new().arg('u'), "-du");
+        assert_eq!(
+            P4DiffOptions::new()
+                .with_whitespace(P4DiffWhitespace::IgnoreAll)
+                .with_ignore_line_endings()
+                .arg('s'),
+            "-dwls"
+        );
+        let options = P4DiffOptions::new()
+            .with_whitespace(P4DiffWhitespace::IgnoreChanges)
+            .with_context_lines(5);
+        assert_eq!(options.arg('u'), "-dbu5");
+        assert_eq!(options.arg('n'), "-dbn");
+    }
+}
@@ -0,0 +1,369 @@
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+#[cfg(feature = "process")]
+use crate::diff::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+#[cfg(feature = "process")]
+use crate::patch::*;
+use crate::*;
+
+// How a file compares between the two sides of `p4 diff2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum P4Diff2Status {
+    // The content differs
+    Content,
+    // Only the file type differs
+    Types,
+    Identical,
+    // Only on the left, e.g. deleted or not yet added at the right
+    LeftOnly,
+    RightOnly,
+}
+
+impl P4Diff2Status {
+    const NAMES: [(P4Diff2Status, &'static str); 5] = [
+        (P4Diff2Status::Content, "content"),
+        (P4Diff2Status::Types, "types"),
+        (P4Diff2Status::Identical, "identical"),
+        (P4Diff2Status::LeftOnly, "left only"),
+        (P4Diff2Status::RightOnly, "right only"),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(status, _)| status == self)
+            .map_or("", |(_, name)| name)
+    }
+
+    fn from_name(name: &str) -> Result<Self, P4Error> {
+        Self::NAMES
+            .iter()
+            .find(|(_, status_name)| *status_name == name)
+            .map(|(status, _)| *status)
+            .ok_or(P4Error::InvalidRecord("Invalid diff2 status"))
+    }
+}
+
+// A file revision on one side of the comparison
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4Diff2Revision {
+    pub depot_path: String,
+    pub revision: u32,
+    pub file_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4FileDifference {
+    pub status: P4Diff2Status,
+    // None for right only files
+    pub left: Option<P4Diff2Revision>,
+    // None for left only files
+    pub right: Option<P4Diff2Revision>,
+}
+
+// The files that differ under `path` between two changelists, e.g. what changed between the builds at 100
+// and 120. `path` is a directory, a single file or a filespec with wildcards, e.g. //depot/main or //depot/main/....
+#[cfg(feature = "spawn")]
+pub fn path_between(path: &str, cl_a: u32, cl_b: u32) -> Result<Vec<P4FileDifference>, P4Error> {
+    path_between_from_context(&P4Context::default(), path, cl_a, cl_b)
+}
+
+#[cfg(feature = "process")]
+pub fn path_between_from_context(
+    context: &P4Context,
+    path: &str,
+    cl_a: u32,
+    cl_b: u32,
+) -> Result<Vec<P4FileDifference>, P4Error> {
+    let filespec = if is_directory_from_context(context, path)? {
+        format!("{}/...", path.trim_end_matches('/'))
+    } else {
+        path.to_string()
+    };
+    let left = format!("{}@{}", filespec, cl_a);
+    let right = format!("{}@{}", filespec, cl_b);
+    P4Diff2Iterator::new_from_context(context, &left, &right)?.collect()
+}
+
+// Wildcards are taken as they are, a trailing slash marks a directory, and otherwise `p4 dirs` tells a
+// directory from a single file
+#[cfg(feature = "process")]
+fn is_directory_from_context(context: &P4Context, path: &str) -> Result<bool, P4Error> {
+    if path.contains("...") || path.contains('*') {
+        return Ok(false);
+    }
+    if path.ends_with('/') {
+        return Ok(true);
+    }
+    match crate::dirs::P4DirsIterator::new_from_context(context, path)?.next() {
+        Some(Ok(_)) => Ok(true),
+        Some(Err(error)) if error.kind() == P4ErrorKind::NoSuchFile => Ok(false),
+        Some(Err(error)) => Err(error),
+        None => Ok(false),
+    }
+}
+
+// `p4 diff2 -du` between two revisions of a file, with the hunks as p4 prints them. There are none when only the
+// types differ, or for files the server doesn't diff, e.g. binary ones.
+#[cfg(feature = "process")]
+pub(crate) fn unified_diff_from_context(
+    context: &P4Context,
+    left: &str,
+    right: &str,
+    options: &P4DiffOptions,
+) -> Result<(P4Diff2Status, String), P4Error> {
+    let arg = options.arg('u');
+    let mut status = P4Diff2Status::Identical;
+    let mut diff = String::new();
+    for record in P4DictIterator::new_from_context(context, vec!["diff2", &arg, left, right])? {
+        let record = record?;
+        if record.get("code") == Some("text") {
+            diff.push_str(record.get("data").unwrap_or_default());
+        } else if let Some(value) = record.get("status") {
+            status = P4Diff2Status::from_name(value)?;
+        }
+    }
+    Ok((status, section_hunks(&diff).to_string()))
+}
+
+// The files of `p4 diff2 -q`, which leaves out identical ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4Diff2Query {
+    left: String,
+    right: String,
+}
+
+impl P4Diff2Query {
+    // `left` and `right` are filespecs with the same wildcards, e.g. //depot/main/...@100 and //depot/main/...@120
+    pub fn new(left: &str, right: &str) -> Self {
+        P4Diff2Query {
+            left: left.to_string(),
+            right: right.to_string(),
+        }
+    }
+}
+
+impl P4Command for P4Diff2Query {
+    type Item = P4FileDifference;
+    type Fields = InterimP4FileDifference;
+
+    fn args(&self) -> Vec<String> {
+        vec![
+            "diff2".to_string(),
+            "-q".to_string(),
+            self.left.clone(),
+            self.right.clone(),
+        ]
+    }
+}
+
+pub type P4Diff2Iterator<ReadT> = P4CommandIterator<P4Diff2Query, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4Diff2Iterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(left: &str, right: &str) -> Result<P4Diff2Iterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), left, right)
+    }
+
+    pub fn new_from_context(
+        context: &P4Context,
+        left: &str,
+        right: &str,
+    ) -> Result<P4Diff2Iterator<P4Output>, P4Error> {
+        Self::spawn(context, P4Diff2Query::new(left, right))
+    }
+}
+
+#[derive(Debug, Default)]
+struct InterimP4Diff2Revision {
+    depot_path: Option<String>,
+    revision: Option<u32>,
+    file_type: Option<String>,
+}
+
+impl InterimP4Diff2Revision {
+    fn finish(self) -> Result<Option<P4Diff2Revision>, P4Error> {
+        let Some(depot_path) = self.depot_path else {
+            return Ok(None);
+        };
+        Ok(Some(P4Diff2Revision {
+            depot_path,
+            revision: self
+                .revision
+                .ok_or(P4Error::InvalidRecord("Missing revision"))?,
+            file_type: self.file_type.unwrap_or_default(),
+        }))
+    }
+}
+
+// The parsing state of a P4FileDifference
+#[derive(Debug, Default)]
+pub struct InterimP4FileDifference {
+    status: Option<P4Diff2Status>,
+    left: InterimP4Diff2Revision,
+    right: InterimP4Diff2Revision,
+}
+
+impl P4RecordFields for InterimP4FileDifference {
+    type Output = P4FileDifference;
+
+    // The left side's fields are depotFile, rev and type, the right side's depotFile2, rev2 and type2
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        let (side, key) = match key.strip_suffix('2') {
+            Some(key) => (&mut self.right, key),
+            None => (&mut self.left, key),
+        };
+        match key {
+            "status" => self.status = Some(P4Diff2Status::from_name(value)?),
+            "depotFile" => side.depot_path = Some(value.to_string()),
+            "rev" => side.revision = Some(parse_field(value, "Invalid revision")?),
+            "type" => side.file_type = Some(value.to_string()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4FileDifference, P4Error> {
+        Ok(P4FileDifference {
+            status: self
+                .status
+                .ok_or(P4Error::InvalidRecord("Missing status"))?,
+            left: self.left.finish()?,
+            right: self.right.finish()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_diff2() {
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("status", "content"),
+                ("depotFile", "//depot/main/a.rs"),
+                ("rev", "3"),
+                ("type", "text"),
+                ("depotFile2", "//depot/main/a.rs"),
+                ("rev2", "5"),
+                ("type2", "text"),
+            ],
+            &[
+                ("code", "stat"),
+                ("status", "types"),
+                ("depotFile", "//depot/main/run.sh"),
+                ("rev", "1"),
+                ("type", "text"),
+                ("depotFile2", "//depot/main/run.sh"),
+                ("rev2", "2"),
+                ("type2", "text+x"),
+            ],
+            &[
+                ("code", "stat"),
+                ("status", "right only"),
+                ("depotFile2", "//depot/main/new.rs"),
+                ("rev2", "1"),
+                ("type2", "text"),
+            ],
+            &[
+                ("code", "stat"),
+                ("status", "left only"),
+                ("depotFile", "//depot/main/old.rs"),
+                ("rev", "4"),
+                ("type", "text"),
+            ],
+        ]);
+
+        let differences = P4Diff2Iterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(differences.len(), 4);
+        assert_eq!(differences[0].status, P4Diff2Status::Content);
+        assert_eq!(differences[0].left.as_ref().unwrap().revision, 3);
+        assert_eq!(differences[0].right.as_ref().unwrap().revision, 5);
+        assert_eq!(differences[1].right.as_ref().unwrap().file_type, "text+x");
+        assert_eq!(differences[2].status, P4Diff2Status::RightOnly);
+        assert_eq!(differences[2].left, None);
+        assert_eq!(differences[3].status.as_str(), "left only");
+        assert_eq!(differences[3].right, None);
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_path_between() {
+        use crate::testing::*;
+
+        let context = MockP4::new()
+            .with_records(
+                "diff2 -q //depot/main/...@100 //depot/main/...@120",
+                [[
+                    ("code", "stat"),
+                    ("status", "right only"),
+                    ("depotFile2", "//depot/main/new.rs"),
+                    ("rev2", "1"),
+                    ("type2", "text"),
+                ]],
+            )
+            .into_context();
+        let differences = path_between_from_context(&context, "//depot/main/", 100, 120).unwrap();
+        assert_eq!(
+            differences[0].right.as_ref().unwrap().depot_path,
+            "//depot/main/new.rs"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_path_between_file() {
+        use crate::testing::*;
+
+        let context = MockP4::new()
+            .with_error(
+                "dirs //depot/main/build.rs",
+                2,
+                17,
+                "//depot/main/build.rs - no such file(s).",
+            )
+            .with_records(
+                "diff2 -q //depot/main/build.rs@100 //depot/main/build.rs@120",
+                [[
+                    ("code", "stat"),
+                    ("status", "content"),
+                    ("depotFile", "//depot/main/build.rs"),
+                    ("rev", "3"),
+                    ("type", "text"),
+                    ("depotFile2", "//depot/main/build.rs"),
+                    ("rev2", "4"),
+                    ("type2", "text"),
+                ]],
+            )
+            .into_context();
+        let differences =
+            path_between_from_context(&context, "//depot/main/build.rs", 100, 120).unwrap();
+        assert_eq!(differences.len(), 1);
+
+        let context = MockP4::new()
+            .with_records(
+                "dirs //depot/main",
+                [[("code", "stat"), ("dir", "//depot/main")]],
+            )
+            .with_records(
+                "diff2 -q //depot/main/...@100 //depot/main/...@120",
+                [[("code", "stat"), ("status", "identical")]],
+            )
+            .into_context();
+        assert!(path_between_from_context(&context, "//depot/main", 100, 120).is_ok());
+    }
+}
@@ -0,0 +1,96 @@
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+
+// Yields the depot paths of the directories matching a `p4 dirs` filespec, e.g. //depot/main/*
+// The filespecs of a `p4 dirs` command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4DirsQuery {
+    filespecs: Vec<String>,
+}
+
+impl P4DirsQuery {
+    pub fn new(filespec: &str) -> Self {
+        P4DirsQuery {
+            filespecs: vec![filespec.to_string()],
+        }
+    }
+
+    pub fn with_filespec(mut self, filespec: &str) -> Self {
+        self.filespecs.push(filespec.to_string());
+        self
+    }
+}
+
+impl P4Command for P4DirsQuery {
+    type Item = String;
+    type Fields = InterimP4Dir;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["dirs".to_string()];
+        args.extend(self.filespecs.iter().cloned());
+        args
+    }
+}
+
+// Yields the depot paths of the directories matching a `p4 dirs` filespec, e.g. //depot/main/*
+pub type P4DirsIterator<ReadT> = P4CommandIterator<P4DirsQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4DirsIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(filespec: &str) -> Result<P4DirsIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), filespec)
+    }
+
+    pub fn new_from_context(
+        context: &P4Context,
+        filespec: &str,
+    ) -> Result<P4DirsIterator<P4Output>, P4Error> {
+        Self::spawn(context, P4DirsQuery::new(filespec))
+    }
+}
+
+// The parsing state of a `p4 dirs` record
+#[derive(Debug, Default)]
+pub struct InterimP4Dir {
+    dir: Option<String>,
+}
+
+impl P4RecordFields for InterimP4Dir {
+    type Output = String;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        if key == "dir" {
+            self.dir = Some(value.to_string());
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<String, P4Error> {
+        self.dir.ok_or(P4Error::InvalidRecord("Missing dir"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_dirs() {
+        let data = to_py_dict_bytes(&[
+            &[("code", "stat"), ("dir", "//depot/main")],
+            &[("code", "stat"), ("dir", "//depot/rel")],
+        ]);
+
+        let dirs = P4DirsIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(dirs, ["//depot/main", "//depot/rel"]);
+    }
+}
@@ -0,0 +1,439 @@
+// == Std crates
+use std::{collections::BTreeMap, fmt, io};
+
+// == Internal crates
+use crate::budget::P4BudgetExceeded;
+#[cfg(feature = "process")]
+use crate::output::is_cancelled_io_error;
+use crate::parsers::py_dict::P4PyDictParseError;
+
+// == External crates
+use thiserror::Error;
+
+// Generic codes reported by the server in the `generic` field of error records. Unlike the text they don't
+// depend on the server's language or release, so branch on these rather than on `data`.
+pub const EV_NONE: u32 = 0x00;
+pub const EV_USAGE: u32 = 0x01;
+pub const EV_UNKNOWN: u32 = 0x02;
+pub const EV_CONTEXT: u32 = 0x03;
+pub const EV_ILLEGAL: u32 = 0x04;
+pub const EV_NOTYET: u32 = 0x05;
+pub const EV_PROTECT: u32 = 0x06;
+pub const EV_EMPTY: u32 = 0x11;
+pub const EV_FAULT: u32 = 0x21;
+pub const EV_CLIENT: u32 = 0x22;
+pub const EV_ADMIN: u32 = 0x23;
+pub const EV_CONFIG: u32 = 0x24;
+pub const EV_UPGRADE: u32 = 0x25;
+pub const EV_COMM: u32 = 0x26;
+pub const EV_TOOBIG: u32 = 0x27;
+
+// The subsystem of a message id for the server's own messages, as opposed to e.g. the database or depot ones
+pub const ES_SERVER: u32 = 7;
+
+// Severity codes reported by the server in the `severity` field of error records
+pub const E_INFO: u32 = 1;
+pub const E_WARN: u32 = 2;
+pub const E_FAILED: u32 = 3;
+pub const E_FATAL: u32 = 4;
+
+// An error record emitted by p4 in -G mode, i.e. { code: error, data, severity, generic }. With the -e global
+// option (see P4Context::with_extended_errors) it also carries the message's unique code, its format string
+// and the values substituted into it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4ServerMessage {
+    pub severity: u32,
+    pub generic: u32,
+    pub data: String,
+    // 0 without -e
+    pub code: u32,
+    // e.g. "%depotFile% - no such file(s)."
+    pub format: String,
+    // e.g. depotFile: //depot/missing
+    pub args: BTreeMap<String, String>,
+}
+
+impl P4ServerMessage {
+    pub(crate) fn populate_field(&mut self, key: &str, value: &str) {
+        match key {
+            "severity" => self.severity = value.parse().unwrap_or_default(),
+            "generic" => self.generic = value.parse().unwrap_or_default(),
+            "data" => self.data = value.to_string(),
+            "code0" => self.code = value.parse().unwrap_or_default(),
+            "fmt0" => self.format = value.to_string(),
+            "argc" => {}
+            // Only the first message of a multi-message error is kept, like `data`
+            _ if key.starts_with("code") || key.starts_with("fmt") => {}
+            _ => {
+                self.args.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    // Whether the server sent the structured fields, i.e. the command ran with -e
+    pub fn is_structured(&self) -> bool {
+        self.code != 0
+    }
+
+    // Identifies the message whatever language the server speaks, None without -e
+    pub fn id(&self) -> Option<P4MessageId> {
+        self.is_structured()
+            .then(|| P4MessageId::from_code(self.code))
+    }
+
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args.get(name).map(String::as_str)
+    }
+
+    // What went wrong. With -e this goes by the unique code and the message's arguments, so it doesn't depend
+    // on the server's language. The text is only used for messages without a code: those from servers run
+    // without -e, for what has no generic code of its own such as an expired session, and those from the p4
+    // client itself.
+    pub fn kind(&self) -> P4ErrorKind {
+        if let Some(id) = self.id() {
+            let (severity, generic) = (self.code >> 28, (self.code >> 16) & 0xff);
+            return match generic {
+                // The password and session messages are the server's configuration errors
+                EV_CONFIG if id.subsystem == ES_SERVER && severity >= E_FAILED => {
+                    P4ErrorKind::LoginRequired
+                }
+                // e.g. "Change %change% unknown."
+                EV_UNKNOWN if self.arg("change").is_some() => P4ErrorKind::NoSuchChangelist,
+                generic => Self::generic_kind(generic),
+            };
+        }
+
+        const LOGIN_FRAGMENTS: [&str; 3] = [
+            "your session has expired",
+            "your session was logged out",
+            "perforce password (p4passwd) invalid or unset",
+        ];
+        const CONNECT_FRAGMENTS: [&str; 2] = ["connect to server failed", "tcp connect to"];
+
+        let data = self.data.to_ascii_lowercase();
+        let contains_any = |fragments: &[&str]| fragments.iter().any(|f| data.contains(f));
+        if self.severity >= E_FAILED && contains_any(&LOGIN_FRAGMENTS) {
+            return P4ErrorKind::LoginRequired;
+        }
+        match self.generic {
+            // e.g. "Change 12 unknown."
+            EV_UNKNOWN if data.starts_with("change ") && data.contains(" unknown") => {
+                P4ErrorKind::NoSuchChangelist
+            }
+            EV_NONE if contains_any(&CONNECT_FRAGMENTS) => P4ErrorKind::ConnectRefused,
+            generic => Self::generic_kind(generic),
+        }
+    }
+
+    fn generic_kind(generic: u32) -> P4ErrorKind {
+        match generic {
+            EV_COMM => P4ErrorKind::ConnectRefused,
+            EV_PROTECT => P4ErrorKind::ProtectedPath,
+            EV_EMPTY => P4ErrorKind::NoSuchFile,
+            EV_UNKNOWN => P4ErrorKind::NotFound,
+            EV_USAGE => P4ErrorKind::Usage,
+            EV_TOOBIG => P4ErrorKind::TooBig,
+            _ => P4ErrorKind::Other,
+        }
+    }
+
+    // Takes the severity and generic code from the unique code when there is one, and trims the text
+    pub fn normalized(mut self) -> Self {
+        if self.is_structured() {
+            self.severity = self.code >> 28;
+            self.generic = (self.code >> 16) & 0xff;
+        }
+        self.data.truncate(self.data.trim_end().len());
+        self
+    }
+}
+
+// A message's subsystem and its code within the subsystem, stable across server languages and releases
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct P4MessageId {
+    pub subsystem: u32,
+    pub subcode: u32,
+}
+
+impl P4MessageId {
+    // Unique codes are laid out as severity:4 argc:4 generic:8 subsystem:6 subcode:10
+    pub fn from_code(code: u32) -> Self {
+        P4MessageId {
+            subsystem: (code >> 10) & 0x3f,
+            subcode: code & 0x3ff,
+        }
+    }
+}
+
+impl fmt::Display for P4MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.subsystem, self.subcode)
+    }
+}
+
+// A stable classification of P4Error for calling code to branch on rather than matching message text. Kinds
+// may be added in minor releases, but an error keeps its kind once it has one.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum P4ErrorKind {
+    // The server couldn't be reached or the connection dropped
+    ConnectRefused,
+    // No ticket, or it has expired
+    LoginRequired,
+    NoSuchChangelist,
+    // No such file(s), or none in the client view
+    NoSuchFile,
+    // Other unknown objects, e.g. labels, branches and users
+    NotFound,
+    // The protections table doesn't allow it
+    ProtectedPath,
+    // Bad arguments, e.g. an unknown command or flag
+    Usage,
+    // Over a server limit such as MaxResults or MaxScanRows
+    TooBig,
+    Timeout,
+    Cancelled,
+    // Over the context's memory budget
+    BudgetExceeded,
+    // The p4 executable couldn't be started
+    Spawn,
+    Io,
+    // Output that couldn't be parsed
+    Parse,
+    Other,
+}
+
+// Returned by the FromStr implementations of the value types, e.g. DepotPath and RevSpec
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum P4ValueParseError {
+    #[error("Invalid depot path: {0}")]
+    InvalidDepotPath(String),
+    #[error("Invalid revision specifier: {0}")]
+    InvalidRevSpec(String),
+    #[error("Unknown file action: {0}")]
+    UnknownFileAction(String),
+    #[error("Unknown changelist status: {0}")]
+    UnknownChangeStatus(String),
+    #[error("Unknown client type: {0}")]
+    UnknownClientType(String),
+    #[error("Unknown line ending: {0}")]
+    UnknownLineEnd(String),
+    #[error("Invalid scan checkpoint: {0}")]
+    InvalidCheckpoint(String),
+    #[error("Invalid P4PORT '{0}': {1}")]
+    InvalidPort(String, &'static str),
+    #[error("Invalid view line '{0}': {1}")]
+    InvalidViewLine(String, &'static str),
+    #[error("Invalid pattern '{0}': {1}")]
+    InvalidPattern(String, &'static str),
+}
+
+#[derive(Debug, Error)]
+pub enum P4Error {
+    #[error("Failed to spawn p4: {0}")]
+    Spawn(io::Error),
+    #[error("I/O error: {0}")]
+    Io(io::Error),
+    #[error("Failed to parse p4 output: {0:?}")]
+    Parse(P4PyDictParseError),
+    #[error("p4 reported an error: {}", .0.data.trim_end())]
+    Server(P4ServerMessage),
+    #[error("Invalid record: {0}")]
+    InvalidRecord(&'static str),
+    #[error("No such label: {0}")]
+    NoSuchLabel(String),
+    #[error("No such branch: {0}")]
+    NoSuchBranch(String),
+    #[error("No mapper registered for command: {0}")]
+    UnregisteredCommand(String),
+    #[error("Failed to get credentials: {0}")]
+    Credentials(String),
+    #[error("Timed out waiting for p4")]
+    Timeout,
+    #[error("Cancelled after {records_yielded} records")]
+    Cancelled { records_yielded: u64 },
+    #[error("{0}")]
+    BudgetExceeded(P4BudgetExceeded),
+}
+
+impl P4Error {
+    pub fn kind(&self) -> P4ErrorKind {
+        match self {
+            P4Error::Spawn(_) => P4ErrorKind::Spawn,
+            P4Error::Io(_) => P4ErrorKind::Io,
+            P4Error::Parse(_) | P4Error::InvalidRecord(_) => P4ErrorKind::Parse,
+            P4Error::Server(message) => message.kind(),
+            P4Error::NoSuchLabel(_) | P4Error::NoSuchBranch(_) => P4ErrorKind::NotFound,
+            P4Error::UnregisteredCommand(_) => P4ErrorKind::Usage,
+            P4Error::Credentials(_) => P4ErrorKind::LoginRequired,
+            P4Error::Timeout => P4ErrorKind::Timeout,
+            P4Error::Cancelled { .. } => P4ErrorKind::Cancelled,
+            P4Error::BudgetExceeded(_) => P4ErrorKind::BudgetExceeded,
+        }
+    }
+}
+
+impl From<io::Error> for P4Error {
+    fn from(error: io::Error) -> Self {
+        // e.g. from a backend that reads ahead of the parser
+        if let Some(exceeded) = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<P4BudgetExceeded>())
+        {
+            return P4Error::BudgetExceeded(*exceeded);
+        }
+        match error.kind() {
+            io::ErrorKind::TimedOut => P4Error::Timeout,
+            #[cfg(feature = "process")]
+            _ if is_cancelled_io_error(&error) => P4Error::Cancelled { records_yielded: 0 },
+            _ => P4Error::Io(error),
+        }
+    }
+}
+
+impl From<P4PyDictParseError> for P4Error {
+    fn from(error: P4PyDictParseError) -> Self {
+        match error {
+            P4PyDictParseError::Io(e) => e.into(),
+            P4PyDictParseError::BudgetExceeded(e) => P4Error::BudgetExceeded(e),
+            error => P4Error::Parse(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_structured_message() {
+        // The same message from a server set to P4LANGUAGE=ja
+        let code = (E_FAILED << 28) | (1 << 24) | (EV_EMPTY << 16) | (6 << 10) | 17;
+        let mut message = P4ServerMessage::default();
+        for (key, value) in [
+            ("data", "//depot/missing - ファイルがありません。\n"),
+            ("code0", &code.to_string()),
+            ("fmt0", "%depotFile% - ファイルがありません。"),
+            ("argc", "1"),
+            ("depotFile", "//depot/missing"),
+        ] {
+            message.populate_field(key, value);
+        }
+
+        let message = message.normalized();
+        assert_eq!((message.severity, message.generic), (E_FAILED, EV_EMPTY));
+        assert_eq!(
+            message.id(),
+            Some(P4MessageId {
+                subsystem: 6,
+                subcode: 17
+            })
+        );
+        assert_eq!(message.arg("depotFile"), Some("//depot/missing"));
+        assert_eq!(message.args.len(), 1);
+        assert!(!message.data.ends_with('\n'));
+        assert_eq!(P4ServerMessage::default().id(), None);
+        assert_eq!(message.kind(), P4ErrorKind::NoSuchFile);
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let message = |severity, generic, data: &str| P4ServerMessage {
+            severity,
+            generic,
+            data: data.to_string(),
+            ..Default::default()
+        };
+        let kind =
+            |severity, generic, data| P4Error::Server(message(severity, generic, data)).kind();
+
+        assert_eq!(
+            kind(E_FATAL, EV_COMM, "TCP receive failed.\n"),
+            P4ErrorKind::ConnectRefused
+        );
+        assert_eq!(
+            kind(
+                E_FATAL,
+                EV_NONE,
+                "Connect to server failed; check $P4PORT.\n"
+            ),
+            P4ErrorKind::ConnectRefused
+        );
+        assert_eq!(
+            kind(
+                E_FAILED,
+                EV_CONFIG,
+                "Your session has expired, please login again.\n"
+            ),
+            P4ErrorKind::LoginRequired
+        );
+        assert_eq!(
+            kind(E_FAILED, EV_UNKNOWN, "Change 12 unknown.\n"),
+            P4ErrorKind::NoSuchChangelist
+        );
+        assert_eq!(
+            kind(E_FAILED, EV_UNKNOWN, "Label 'rel-1' unknown.\n"),
+            P4ErrorKind::NotFound
+        );
+        assert_eq!(
+            kind(
+                E_FAILED,
+                EV_PROTECT,
+                "You don't have permission for this operation.\n"
+            ),
+            P4ErrorKind::ProtectedPath
+        );
+        assert_eq!(kind(E_FAILED, EV_FAULT, "Oops.\n"), P4ErrorKind::Other);
+
+        // With -e the text isn't looked at, e.g. from a server set to P4LANGUAGE=ja
+        let structured =
+            |generic: u32, subsystem: u32, data: &str, args: &[(&str, &str)]| P4ServerMessage {
+                code: (E_FAILED << 28) | (generic << 16) | (subsystem << 10) | 1,
+                data: data.to_string(),
+                args: args
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Default::default()
+            };
+        assert_eq!(
+            structured(
+                EV_CONFIG,
+                ES_SERVER,
+                "セッションの有効期限が切れました。",
+                &[]
+            )
+            .kind(),
+            P4ErrorKind::LoginRequired
+        );
+        assert_eq!(
+            structured(
+                EV_UNKNOWN,
+                6,
+                "チェンジ 12 は不明です。",
+                &[("change", "12")]
+            )
+            .kind(),
+            P4ErrorKind::NoSuchChangelist
+        );
+        assert_eq!(
+            structured(EV_UNKNOWN, 6, "Change 12 unknown.", &[("label", "12")]).kind(),
+            P4ErrorKind::NotFound
+        );
+        assert_eq!(
+            structured(
+                EV_NONE,
+                6,
+                "Your session has expired, please login again.",
+                &[]
+            )
+            .kind(),
+            P4ErrorKind::Other
+        );
+        assert_eq!(P4Error::Timeout.kind(), P4ErrorKind::Timeout);
+        assert_eq!(
+            P4Error::NoSuchLabel("rel-1".into()).kind(),
+            P4ErrorKind::NotFound
+        );
+    }
+}
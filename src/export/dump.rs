@@ -0,0 +1,363 @@
+// == Std crates
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+// == Internal crates
+use crate::context::*;
+use crate::depots::*;
+use crate::error::*;
+use crate::history::*;
+use crate::progress::*;
+use crate::*;
+
+pub type ShardEncoder = Box<dyn Fn(fs::File) -> io::Result<Box<dyn Write>> + Send + Sync>;
+
+// Settings for `dump_all`
+pub struct DumpOptions {
+    output_dir: PathBuf,
+    depot_types: Vec<String>,
+    shard_size: u64,
+    window_size: Option<u32>,
+    extension: String,
+    encoder: Option<ShardEncoder>,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+impl DumpOptions {
+    const DEFAULT_DEPOT_TYPES: [&str; 2] = ["local", "stream"];
+    const DEFAULT_SHARD_SIZE: u64 = 10_000;
+
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        DumpOptions {
+            output_dir: output_dir.into(),
+            depot_types: Vec::new(),
+            shard_size: Self::DEFAULT_SHARD_SIZE,
+            window_size: None,
+            extension: "ndjson".to_string(),
+            encoder: None,
+            progress: None,
+        }
+    }
+
+    // Dump the depots of these types, local and stream ones if none are given
+    pub fn with_depot_type(mut self, depot_type: &str) -> Self {
+        self.depot_types.push(depot_type.to_string());
+        self
+    }
+
+    // Changelists per shard
+    pub fn with_shard_size(mut self, shard_size: u64) -> Self {
+        self.shard_size = shard_size.max(1);
+        self
+    }
+
+    // Changelist numbers covered by each `p4 changes`, see P4HistoryScan::with_window_size
+    pub fn with_window_size(mut self, window_size: u32) -> Self {
+        self.window_size = Some(window_size);
+        self
+    }
+
+    // Shards are plain NDJSON unless an encoder is given, e.g. a gzip writer with the extension
+    // "ndjson.gz". The encoder is flushed and dropped once its shard is full.
+    pub fn with_encoder(
+        mut self,
+        extension: &str,
+        encoder: impl Fn(fs::File) -> io::Result<Box<dyn Write>> + Send + Sync + 'static,
+    ) -> Self {
+        self.extension = extension.to_string();
+        self.encoder = Some(Box::new(encoder));
+        self
+    }
+
+    // Reports each changelist as it is written
+    pub fn with_progress(mut self, sink: impl ProgressSink + 'static) -> Self {
+        self.progress = Some(Arc::new(sink));
+        self
+    }
+
+    fn keeps_depot(&self, depot: &P4Depot) -> bool {
+        if self.depot_types.is_empty() {
+            Self::DEFAULT_DEPOT_TYPES.contains(&depot.depot_type.as_str())
+        } else {
+            self.depot_types.contains(&depot.depot_type)
+        }
+    }
+
+    fn open_shard(&self, index: usize) -> io::Result<(String, Box<dyn Write>)> {
+        let file_name = format!("changes-{:05}.{}", index, self.extension);
+        let file = fs::File::create(self.output_dir.join(&file_name))?;
+        let writer = match &self.encoder {
+            Some(encoder) => encoder(file)?,
+            None => Box::new(io::BufWriter::new(file)),
+        };
+        Ok((file_name, writer))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DumpShard {
+    pub file_name: String,
+    pub first_changelist: u32,
+    pub last_changelist: u32,
+    pub changelists: u64,
+    pub files: u64,
+}
+
+// Written to manifest.json next to the shards once the dump is complete
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DumpManifest {
+    pub depots: Vec<String>,
+    pub shards: Vec<DumpShard>,
+    // Where the history scan got to, for dumping the changelists submitted since
+    pub checkpoint: ScanCheckpoint,
+}
+
+impl DumpManifest {
+    fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        let depots = self
+            .depots
+            .iter()
+            .map(|depot| json_string(depot))
+            .collect::<Vec<_>>();
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"depots\": [{}],", depots.join(", "))?;
+        writeln!(
+            writer,
+            "  \"checkpoint\": {},",
+            json_string(&self.checkpoint.to_string())
+        )?;
+        writeln!(writer, "  \"shards\": [")?;
+        for (index, shard) in self.shards.iter().enumerate() {
+            writeln!(
+                writer,
+                "    {{\"file\": {}, \"first_changelist\": {}, \"last_changelist\": {}, \"changelists\": {}, \"files\": {}}}{}",
+                json_string(&shard.file_name),
+                shard.first_changelist,
+                shard.last_changelist,
+                shard.changelists,
+                shard.files,
+                if index + 1 < self.shards.len() {
+                    ","
+                } else {
+                    ""
+                }
+            )?;
+        }
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")
+    }
+}
+
+// Dumps every submitted changelist in the depots, with its files, to NDJSON shards of one changelist
+// per line, oldest first, followed by a manifest.json describing the shards
+pub fn dump_all(context: &P4Context, options: &DumpOptions) -> Result<DumpManifest, P4Error> {
+    fs::create_dir_all(&options.output_dir)?;
+
+    let mut depots = Vec::new();
+    for depot in P4DepotsIterator::new_from_context(context)? {
+        let depot = depot?;
+        if options.keeps_depot(&depot) {
+            depots.push(depot);
+        }
+    }
+    let mut manifest = DumpManifest {
+        depots: depots.iter().map(|depot| depot.name.clone()).collect(),
+        ..Default::default()
+    };
+
+    // Without any paths the scan would cover every depot, including the ones left out
+    if !depots.is_empty() {
+        let mut scan = depots
+            .iter()
+            .fold(P4HistoryScan::new(context, 1), |scan, depot| {
+                scan.with_path(&depot.filespec())
+            });
+        if let Some(window_size) = options.window_size {
+            scan = scan.with_window_size(window_size);
+        }
+
+        let mut tracker = ProgressTracker::new(options.progress.clone());
+        tracker.start_phase("dump", None, None);
+        let mut current: Option<(DumpShard, Box<dyn Write>)> = None;
+        for changelist in scan.by_ref() {
+            let changelist = changelist?;
+            let (shard, writer) = match current.as_mut() {
+                Some(current) => current,
+                None => {
+                    let (file_name, writer) = options.open_shard(manifest.shards.len())?;
+                    let shard = DumpShard {
+                        file_name,
+                        first_changelist: changelist.changelist,
+                        ..Default::default()
+                    };
+                    current.insert((shard, writer))
+                }
+            };
+
+            write_changelist_json(writer, &changelist)?;
+            shard.last_changelist = changelist.changelist;
+            shard.changelists += 1;
+            shard.files += changelist.files.len() as u64;
+            tracker.record(&changelist.changelist.to_string(), 0);
+
+            if shard.changelists >= options.shard_size
+                && let Some((shard, mut writer)) = current.take()
+            {
+                writer.flush()?;
+                manifest.shards.push(shard);
+            }
+        }
+        if let Some((shard, mut writer)) = current.take() {
+            writer.flush()?;
+            manifest.shards.push(shard);
+        }
+        manifest.checkpoint = scan.checkpoint();
+    }
+
+    let mut writer =
+        io::BufWriter::new(fs::File::create(options.output_dir.join("manifest.json"))?);
+    manifest.write_json(&mut writer)?;
+    writer.flush()?;
+
+    Ok(manifest)
+}
+
+fn write_changelist_json(writer: &mut impl Write, changelist: &P4Changelist) -> io::Result<()> {
+    write!(
+        writer,
+        "{{\"change\":{},\"time\":{},\"user\":{},\"description\":{},\"files\":[",
+        changelist.changelist,
+        changelist.time,
+        json_string(&changelist.user),
+        json_string(&changelist.description)
+    )?;
+    for (index, file) in changelist.files.iter().enumerate() {
+        write!(
+            writer,
+            "{}{{\"depot_path\":{},\"action\":{},\"revision\":{},\"file_size\":{},\"digest\":{}}}",
+            if index > 0 { "," } else { "" },
+            json_string(&file.depot_path),
+            json_string(&file.action),
+            json_number(file.revision),
+            json_number(file.file_size),
+            file.digest.map_or("null".to_string(), |digest| format!(
+                "\"{}\"",
+                const_hex::encode_upper(digest)
+            ))
+        )?;
+    }
+    writeln!(writer, "]}}")
+}
+
+// null for what p4 left out, e.g. the revision of a file opened for add
+fn json_number(value: Option<impl fmt::Display>) -> String {
+    value.map_or("null".to_string(), |value| value.to_string())
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_dump_all() {
+        let change = |change: &'static str| {
+            [
+                ("code", "stat"),
+                ("change", change),
+                ("time", "1743724741"),
+                ("user", "alice"),
+                ("desc", "Fix \"the\" build\n"),
+            ]
+            .to_vec()
+        };
+        let describe = |number: &'static str| {
+            let mut record = change(number);
+            record.extend([
+                ("depotFile0", "//depot/a.txt"),
+                ("action0", "edit"),
+                ("rev0", number),
+                ("fileSize0", "4"),
+                ("digest0", "00000000000000000000000000000000"),
+            ]);
+            [record]
+        };
+        let mock = MockP4::new()
+            .with_records(
+                "depots",
+                [
+                    [("code", "stat"), ("name", "depot"), ("type", "local")],
+                    [("code", "stat"), ("name", "spec"), ("type", "spec")],
+                ],
+            )
+            .with_records("changes -s submitted -l -m 1 //depot/...", [change("3")])
+            .with_records(
+                "changes -s submitted -l //depot/...@1,3",
+                [change("3"), change("2"), change("1")],
+            )
+            .with_records("describe -s 1", describe("1"))
+            .with_records("describe -s 2", describe("2"))
+            .with_records("describe -s 3", describe("3"));
+
+        let dir = std::env::temp_dir().join(format!("p4_helper_dump_{}", std::process::id()));
+        let progress = ProgressLog::new();
+        let manifest = dump_all(
+            &mock.into_context(),
+            &DumpOptions::new(&dir)
+                .with_shard_size(2)
+                .with_progress(progress.clone()),
+        )
+        .unwrap();
+        assert_eq!(manifest.depots, ["depot"]);
+        assert_eq!(
+            progress.updates(),
+            [
+                ("dump", 0, 0, None),
+                ("dump", 1, 0, Some("1".to_string())),
+                ("dump", 2, 0, Some("2".to_string())),
+                ("dump", 3, 0, Some("3".to_string()))
+            ]
+        );
+        assert_eq!(
+            manifest
+                .shards
+                .iter()
+                .map(|shard| (shard.first_changelist, shard.last_changelist, shard.files))
+                .collect::<Vec<_>>(),
+            [(1, 2, 2), (3, 3, 1)]
+        );
+        assert_eq!(manifest.checkpoint.last_changelist, 3);
+
+        let shard = fs::read_to_string(dir.join("changes-00000.ndjson")).unwrap();
+        assert_eq!(shard.lines().count(), 2);
+        assert!(shard.starts_with(
+            "{\"change\":1,\"time\":1743724741,\"user\":\"alice\",\"description\":\"Fix \\\"the\\\" build\\n\",\"files\":[{\"depot_path\":\"//depot/a.txt\""
+        ));
+        let manifest_json = fs::read_to_string(dir.join("manifest.json")).unwrap();
+        assert!(manifest_json.contains("\"file\": \"changes-00001.ndjson\""));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+// == Std crates
+use std::{collections::HashMap, io, sync::Arc};
+
+// == Internal crates
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::describe::*;
+#[cfg(feature = "process")]
+use crate::error::*;
+use crate::patch::git_mode;
+use crate::print::*;
+use crate::progress::*;
+use crate::*;
+
+// Writes changelists as a `git fast-import` stream, for one-way mirroring of a depot path into a git
+// branch. Changelists must be written oldest first; each becomes one commit with mark :<changelist>.
+pub struct GitExporter<WriteT: io::Write> {
+    writer: WriteT,
+    branch: String,
+    depot_root: String,
+    email_domain: String,
+    authors: HashMap<String, (String, String)>,
+    commits_written: u32,
+    progress: ProgressTracker,
+}
+
+impl<WriteT: io::Write> GitExporter<WriteT> {
+    pub fn new(writer: WriteT) -> Self {
+        GitExporter {
+            writer,
+            branch: "refs/heads/main".to_string(),
+            depot_root: "//".to_string(),
+            email_domain: "localhost".to_string(),
+            authors: HashMap::new(),
+            commits_written: 0,
+            progress: ProgressTracker::default(),
+        }
+    }
+
+    pub fn with_branch(mut self, branch: &str) -> Self {
+        self.branch = branch.to_string();
+        self
+    }
+
+    // Only files under the root are exported, with the root stripped to give the path in git, e.g.
+    // //depot/main/src/a.c with a root of //depot/main becomes src/a.c
+    pub fn with_depot_root(mut self, depot_root: &str) -> Self {
+        self.depot_root = format!("{}/", depot_root.trim_end_matches('/'));
+        self
+    }
+
+    // Users without an author entry are written as <user>@<domain>
+    pub fn with_email_domain(mut self, email_domain: &str) -> Self {
+        self.email_domain = email_domain.to_string();
+        self
+    }
+
+    pub fn with_author(mut self, user: &str, name: &str, email: &str) -> Self {
+        self.authors
+            .insert(user.to_string(), (name.to_string(), email.to_string()));
+        self
+    }
+
+    // Reports each changelist written, with the bytes of its content
+    pub fn with_progress(mut self, sink: impl ProgressSink + 'static) -> Self {
+        self.progress = ProgressTracker::new(Some(Arc::new(sink)));
+        self
+    }
+
+    pub fn commits_written(&self) -> u32 {
+        self.commits_written
+    }
+
+    // `changelist.files` gives the adds, edits and deletes, `contents` the new content of the files that
+    // still exist. Files outside the depot root are ignored.
+    pub fn write_changelist(
+        &mut self,
+        changelist: &P4Changelist,
+        contents: &[P4PrintedFile],
+    ) -> io::Result<()> {
+        let contents = contents
+            .iter()
+            .map(|content| (content.depot_path.as_str(), content))
+            .collect::<HashMap<_, _>>();
+
+        self.write_commit(changelist)?;
+        let mut bytes = 0;
+        for file in &changelist.files {
+            if file.is_deleted() {
+                self.write_delete(&file.depot_path)?;
+                continue;
+            }
+            if self.git_path(&file.depot_path).is_none() {
+                continue;
+            }
+
+            let content = contents.get(file.depot_path.as_str()).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("No content for {}", file))
+            })?;
+            bytes += content.content.len() as u64;
+            self.write_content(content)?;
+        }
+        self.end_commit(changelist.changelist, bytes)
+    }
+
+    // Describes and prints each changelist, e.g. from a changes iterator reversed to oldest first. The content
+    // is written as it's printed, so only one file is held at a time. Returns the number of commits written.
+    #[cfg(feature = "process")]
+    pub fn export_from_context(
+        &mut self,
+        context: &P4Context,
+        changelists: impl IntoIterator<Item = Result<P4Changelist, P4Error>>,
+    ) -> Result<u32, P4Error> {
+        let mut written = 0;
+        self.progress.ensure_phase("export");
+        for changelist in changelists {
+            let mut changelist = changelist?;
+            let describe = P4DescribeIterator::new_from_context(context, changelist.changelist)?;
+            changelist.files = describe.collect::<Result<_, _>>()?;
+
+            self.write_commit(&changelist)?;
+            // Only the files submitted in the changelist, deleted revisions print nothing
+            let filespec = format!("{}...@={}", self.depot_root, changelist.changelist);
+            let mut bytes = 0;
+            for content in P4PrintIterator::new_from_context(context, &[&filespec])? {
+                let content = content?;
+                bytes += content.content.len() as u64;
+                self.write_content(&content)?;
+            }
+            for file in changelist.files.iter().filter(|file| file.is_deleted()) {
+                self.write_delete(&file.depot_path)?;
+            }
+            self.end_commit(changelist.changelist, bytes)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    fn write_commit(&mut self, changelist: &P4Changelist) -> io::Result<()> {
+        let (name, email) = match self.authors.get(&changelist.user) {
+            Some((name, email)) => (name.clone(), email.clone()),
+            None => (
+                changelist.user.clone(),
+                format!("{}@{}", changelist.user, self.email_domain),
+            ),
+        };
+        let message = format!(
+            "{}\n\nP4-Change: {}\n",
+            changelist.description.trim_end(),
+            changelist.changelist
+        );
+
+        writeln!(self.writer, "commit {}", self.branch)?;
+        writeln!(self.writer, "mark :{}", changelist.changelist)?;
+        writeln!(
+            self.writer,
+            "committer {} <{}> {} +0000",
+            name, email, changelist.time
+        )?;
+        self.write_data(message.as_bytes())
+    }
+
+    fn write_content(&mut self, content: &P4PrintedFile) -> io::Result<()> {
+        let Some(path) = self.git_path(&content.depot_path) else {
+            return Ok(());
+        };
+        writeln!(
+            self.writer,
+            "M {} inline {}",
+            git_mode(&content.file_type),
+            path
+        )?;
+        self.write_data(&content.content)
+    }
+
+    fn write_delete(&mut self, depot_path: &str) -> io::Result<()> {
+        match self.git_path(depot_path) {
+            Some(path) => writeln!(self.writer, "D {}", path),
+            None => Ok(()),
+        }
+    }
+
+    fn end_commit(&mut self, changelist: u32, bytes: u64) -> io::Result<()> {
+        writeln!(self.writer)?;
+        self.commits_written += 1;
+        self.progress.ensure_phase("export");
+        self.progress.record(&changelist.to_string(), bytes);
+        Ok(())
+    }
+
+    // Ends the stream, so fast-import knows it wasn't cut short
+    pub fn finish(mut self) -> io::Result<WriteT> {
+        writeln!(self.writer, "done")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        writeln!(self.writer, "data {}", data.len())?;
+        self.writer.write_all(data)?;
+        writeln!(self.writer)
+    }
+
+    fn git_path(&self, depot_path: &str) -> Option<String> {
+        let path = depot_path.strip_prefix(&self.depot_root)?;
+        Some(quote_path(path))
+    }
+}
+
+// fast-import takes paths with quotes or control characters as C-style quoted strings
+fn quote_path(path: &str) -> String {
+    if !path.contains(|c: char| c == '"' || c == '\\' || c.is_control()) {
+        return path.to_string();
+    }
+
+    let mut result = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\{:03o}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_exporter() {
+        let file = |depot_path: &str, action: &str| P4File {
+            depot_path: depot_path.to_string(),
+            action: action.to_string(),
+            revision: Some(2),
+            file_size: Some(0),
+            digest: Some([0; 16]),
+        };
+        let content = |depot_path: &str, file_type: &str, content: &[u8]| P4PrintedFile {
+            depot_path: depot_path.to_string(),
+            revision: 2,
+            change: 12,
+            action: "edit".to_string(),
+            file_type: file_type.to_string(),
+            time: 1743724741,
+            content: content.to_vec(),
+        };
+
+        let changelist = P4Changelist {
+            changelist: 12,
+            time: 1743724741,
+            user: "alice".to_string(),
+            description: "Fix the build\n".to_string(),
+            files: vec![
+                file("//depot/main/build.sh", "edit"),
+                file("//depot/main/old.txt", "delete"),
+                file("//depot/rel/other.txt", "edit"),
+            ],
+            ..Default::default()
+        };
+        let contents = [content("//depot/main/build.sh", "xtext", b"make\n")];
+
+        let progress = ProgressLog::new();
+        let mut exporter = GitExporter::new(Vec::new())
+            .with_depot_root("//depot/main")
+            .with_author("alice", "Alice", "alice@example.com")
+            .with_progress(progress.clone());
+        exporter.write_changelist(&changelist, &contents).unwrap();
+        assert_eq!(exporter.commits_written(), 1);
+        assert_eq!(
+            progress.updates(),
+            [
+                ("export", 0, 0, None),
+                ("export", 1, 5, Some("12".to_string()))
+            ]
+        );
+
+        let stream = String::from_utf8(exporter.finish().unwrap()).unwrap();
+        assert_eq!(
+            stream,
+            "commit refs/heads/main\n\
+             mark :12\n\
+             committer Alice <alice@example.com> 1743724741 +0000\n\
+             data 29\n\
+             Fix the build\n\nP4-Change: 12\n\n\
+             M 100755 inline build.sh\n\
+             data 5\n\
+             make\n\n\
+             D old.txt\n\
+             \n\
+             done\n"
+        );
+
+        assert_eq!(quote_path("a \"b\".txt"), "\"a \\\"b\\\".txt\"");
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_export_from_context() {
+        use crate::testing::*;
+
+        let printed = |path: &'static str, file_type: &'static str, content: &'static str| {
+            [
+                [
+                    ("code", "stat"),
+                    ("depotFile", path),
+                    ("rev", "2"),
+                    ("change", "12"),
+                    ("action", "edit"),
+                    ("type", file_type),
+                    ("time", "1743724741"),
+                ]
+                .to_vec(),
+                [("code", "text"), ("data", content)].to_vec(),
+            ]
+        };
+        let digest = "00000000000000000000000000000000";
+        let context = MockP4::new()
+            .with_records(
+                "describe -s 12",
+                [[
+                    ("code", "stat"),
+                    ("change", "12"),
+                    ("user", "alice"),
+                    ("time", "1743724741"),
+                    ("desc", "Fix the build\n"),
+                    ("depotFile0", "//depot/main/build.sh"),
+                    ("action0", "edit"),
+                    ("rev0", "2"),
+                    ("fileSize0", "5"),
+                    ("digest0", digest),
+                    ("depotFile1", "//depot/main/old.txt"),
+                    ("action1", "delete"),
+                    ("rev1", "3"),
+                    ("fileSize1", "0"),
+                    ("digest1", digest),
+                    ("depotFile2", "//depot/main/run.sh"),
+                    ("action2", "add"),
+                    ("rev2", "1"),
+                    ("fileSize2", "3"),
+                    ("digest2", digest),
+                ]],
+            )
+            .with_records(
+                "print //depot/main/...@=12",
+                [
+                    printed("//depot/main/build.sh", "text", "make\n"),
+                    printed("//depot/main/run.sh", "text+x", "sh\n"),
+                ]
+                .concat(),
+            )
+            .into_context();
+        let changelist = P4Changelist {
+            changelist: 12,
+            time: 1743724741,
+            user: "alice".to_string(),
+            description: "Fix the build\n".to_string(),
+            ..Default::default()
+        };
+
+        let progress = ProgressLog::new();
+        let mut exporter = GitExporter::new(Vec::new())
+            .with_depot_root("//depot/main")
+            .with_progress(progress.clone());
+        let written = exporter
+            .export_from_context(&context, [Ok(changelist)])
+            .unwrap();
+        assert_eq!(written, 1);
+        assert_eq!(
+            progress.updates(),
+            [
+                ("export", 0, 0, None),
+                ("export", 1, 8, Some("12".to_string()))
+            ]
+        );
+        let stream = String::from_utf8(exporter.finish().unwrap()).unwrap();
+        assert_eq!(
+            stream,
+            "commit refs/heads/main\n\
+             mark :12\n\
+             committer alice <alice@localhost> 1743724741 +0000\n\
+             data 29\n\
+             Fix the build\n\nP4-Change: 12\n\n\
+             M 100644 inline build.sh\n\
+             data 5\n\
+             make\n\n\
+             M 100755 inline run.sh\n\
+             data 3\n\
+             sh\n\n\
+             D old.txt\n\
+             \n\
+             done\n"
+        );
+    }
+}
@@ -0,0 +1,6 @@
+#[cfg(feature = "process")]
+pub mod dump;
+pub mod git;
+
+#[cfg(feature = "process")]
+pub use dump::dump_all;
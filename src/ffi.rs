@@ -0,0 +1,315 @@
+// C interface for the changes and describe iterators, see include/p4_helper.h
+//
+// Strings handed out in records stay valid until the next call on the same iterator (or until it is freed).
+// Functions returning int32_t return 1 when a record was written, 0 at the end and -1 on error, in which
+// case p4h_last_error() describes the problem.
+
+// == Std crates
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char},
+    fs, io, ptr,
+};
+
+// == Internal crates
+use crate::changes::*;
+use crate::describe::*;
+use crate::error::*;
+use crate::*;
+
+#[repr(C)]
+pub struct P4hChangelist {
+    pub changelist: u32,
+    pub time: u32,
+    pub user: *const c_char,
+    pub description: *const c_char,
+}
+
+#[repr(C)]
+// The revision, size and digest are 0 for files p4 left them out for, see P4File
+pub struct P4hFile {
+    pub depot_path: *const c_char,
+    pub action: *const c_char,
+    pub revision: u32,
+    pub file_size: u64,
+    pub digest: [u8; 16],
+}
+
+pub struct P4hChangesIterator {
+    inner: Box<dyn Iterator<Item = Result<P4Changelist, P4Error>>>,
+    strings: Vec<CString>,
+}
+
+type BoxedFileIterator = Box<dyn Iterator<Item = Result<P4File, P4Error>>>;
+
+pub struct P4hDescribeIterator {
+    inner: BoxedFileIterator,
+    changelist: P4Changelist,
+    strings: Vec<CString>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: impl ToString) {
+    let message = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+// Keeps the string alive in `strings` and returns a pointer to it
+fn store_string(strings: &mut Vec<CString>, value: &str) -> *const c_char {
+    strings.push(CString::new(value.replace('\0', " ")).unwrap_or_default());
+    strings.last().unwrap().as_ptr()
+}
+
+unsafe fn path_from_c<'a>(path: *const c_char) -> Option<&'a str> {
+    if path.is_null() {
+        set_last_error("Path is null");
+        return None;
+    }
+
+    match unsafe { CStr::from_ptr(path) }.to_str() {
+        Ok(path) => Some(path),
+        Err(e) => {
+            set_last_error(e);
+            None
+        }
+    }
+}
+
+// Message for the last error on this thread, or null. Valid until the next failing call on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn p4h_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+// == Changes
+
+#[cfg(feature = "spawn")]
+#[unsafe(no_mangle)]
+pub extern "C" fn p4h_changes_open(from: u32, to: u32) -> *mut P4hChangesIterator {
+    match P4ChangesIterator::new_from_p4_exe(Some(from..to)) {
+        Ok(iter) => Box::into_raw(Box::new(P4hChangesIterator {
+            inner: Box::new(iter),
+            strings: Vec::new(),
+        })),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `path` must be a valid, nul terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn p4h_changes_open_file(path: *const c_char) -> *mut P4hChangesIterator {
+    let Some(path) = (unsafe { path_from_c(path) }) else {
+        return ptr::null_mut();
+    };
+
+    match fs::File::open(path) {
+        Ok(file) => Box::into_raw(Box::new(P4hChangesIterator {
+            inner: Box::new(P4ChangesIterator::new_from_reader(io::BufReader::new(file))),
+            strings: Vec::new(),
+        })),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `iter` must come from one of the p4h_changes_open functions and `out` must point to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn p4h_changes_next(
+    iter: *mut P4hChangesIterator,
+    out: *mut P4hChangelist,
+) -> i32 {
+    let (Some(iter), Some(out)) = (unsafe { iter.as_mut() }, unsafe { out.as_mut() }) else {
+        set_last_error("Iterator or output is null");
+        return -1;
+    };
+
+    iter.strings.clear();
+    match iter.inner.next() {
+        Some(Ok(change)) => {
+            *out = P4hChangelist {
+                changelist: change.changelist,
+                time: change.time,
+                user: store_string(&mut iter.strings, &change.user),
+                description: store_string(&mut iter.strings, &change.description),
+            };
+            1
+        }
+        Some(Err(e)) => {
+            set_last_error(e);
+            -1
+        }
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `iter` must come from one of the p4h_changes_open functions (or be null) and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn p4h_changes_free(iter: *mut P4hChangesIterator) {
+    if !iter.is_null() {
+        drop(unsafe { Box::from_raw(iter) });
+    }
+}
+
+// == Describe
+
+fn new_describe_handle(
+    iter: Result<(P4Changelist, BoxedFileIterator), P4Error>,
+) -> *mut P4hDescribeIterator {
+    match iter {
+        Ok((changelist, inner)) => Box::into_raw(Box::new(P4hDescribeIterator {
+            inner,
+            changelist,
+            strings: Vec::new(),
+        })),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[cfg(feature = "spawn")]
+#[unsafe(no_mangle)]
+pub extern "C" fn p4h_describe_open(changelist: u32) -> *mut P4hDescribeIterator {
+    new_describe_handle(P4DescribeIterator::new(changelist).map(|iter| {
+        let changelist = iter.get_changelist().clone();
+        (changelist, Box::new(iter) as BoxedFileIterator)
+    }))
+}
+
+/// # Safety
+/// `path` must be a valid, nul terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn p4h_describe_open_file(path: *const c_char) -> *mut P4hDescribeIterator {
+    let Some(path) = (unsafe { path_from_c(path) }) else {
+        return ptr::null_mut();
+    };
+
+    new_describe_handle(
+        fs::File::open(path)
+            .map_err(P4Error::from)
+            .and_then(|file| P4DescribeIterator::new_from_reader(io::BufReader::new(file)))
+            .map(|iter| {
+                let changelist = iter.get_changelist().clone();
+                (changelist, Box::new(iter) as BoxedFileIterator)
+            }),
+    )
+}
+
+/// # Safety
+/// `iter` must come from one of the p4h_describe_open functions and `out` must point to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn p4h_describe_changelist(
+    iter: *mut P4hDescribeIterator,
+    out: *mut P4hChangelist,
+) -> i32 {
+    let (Some(iter), Some(out)) = (unsafe { iter.as_mut() }, unsafe { out.as_mut() }) else {
+        set_last_error("Iterator or output is null");
+        return -1;
+    };
+
+    iter.strings.clear();
+    *out = P4hChangelist {
+        changelist: iter.changelist.changelist,
+        time: iter.changelist.time,
+        user: store_string(&mut iter.strings, &iter.changelist.user),
+        description: store_string(&mut iter.strings, &iter.changelist.description),
+    };
+    1
+}
+
+/// # Safety
+/// `iter` must come from one of the p4h_describe_open functions and `out` must point to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn p4h_describe_next(
+    iter: *mut P4hDescribeIterator,
+    out: *mut P4hFile,
+) -> i32 {
+    let (Some(iter), Some(out)) = (unsafe { iter.as_mut() }, unsafe { out.as_mut() }) else {
+        set_last_error("Iterator or output is null");
+        return -1;
+    };
+
+    iter.strings.clear();
+    match iter.inner.next() {
+        Some(Ok(file)) => {
+            *out = P4hFile {
+                depot_path: store_string(&mut iter.strings, &file.depot_path),
+                action: store_string(&mut iter.strings, &file.action),
+                revision: file.revision.unwrap_or_default(),
+                file_size: file.file_size.unwrap_or_default(),
+                digest: file.digest.unwrap_or_default(),
+            };
+            1
+        }
+        Some(Err(e)) => {
+            set_last_error(e);
+            -1
+        }
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `iter` must come from one of the p4h_describe_open functions (or be null) and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn p4h_describe_free(iter: *mut P4hDescribeIterator) {
+    if !iter.is_null() {
+        drop(unsafe { Box::from_raw(iter) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_iterators() {
+        unsafe {
+            let iter = p4h_changes_open_file(c"./test_data/changes.pyc".as_ptr());
+            assert!(!iter.is_null());
+
+            let mut change = std::mem::zeroed::<P4hChangelist>();
+            let mut count = 0;
+            while p4h_changes_next(iter, &mut change) == 1 {
+                assert_eq!(CStr::from_ptr(change.user).to_str().unwrap(), "david");
+                count += 1;
+            }
+            assert_eq!(count, 8);
+            p4h_changes_free(iter);
+
+            let iter = p4h_describe_open_file(c"./test_data/describe.pyc".as_ptr());
+            assert!(!iter.is_null());
+            assert_eq!(p4h_describe_changelist(iter, &mut change), 1);
+            assert_eq!(change.changelist, 5);
+
+            let mut file = std::mem::zeroed::<P4hFile>();
+            assert_eq!(p4h_describe_next(iter, &mut file), 1);
+            assert_eq!(
+                CStr::from_ptr(file.depot_path).to_str().unwrap(),
+                "//depot/main3/UE5.5_github_src/.editorconfig"
+            );
+            assert_eq!(file.file_size, 1015);
+            p4h_describe_free(iter);
+
+            assert!(p4h_describe_open_file(c"./test_data/missing.pyc".as_ptr()).is_null());
+            assert!(!p4h_last_error().is_null());
+        }
+    }
+}
@@ -0,0 +1,692 @@
+// == Std crates
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+};
+
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::diff::*;
+#[cfg(feature = "process")]
+use crate::diff2::*;
+use crate::error::*;
+use crate::filespec::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+#[cfg(feature = "process")]
+use crate::patch::*;
+#[cfg(feature = "process")]
+use crate::print::*;
+use crate::records::*;
+use crate::*;
+
+// The history of one file as reported by `p4 filelog`, newest revision first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4Filelog {
+    pub depot_path: String,
+    pub revisions: Vec<P4FileRevision>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4FileRevision {
+    pub depot_path: String,
+    pub revision: u32,
+    pub changelist: u32,
+    pub action: String,
+    pub file_type: String,
+    pub time: u32,
+    pub user: String,
+    pub client: String,
+    pub description: String,
+    pub integrations: Vec<P4Integration>,
+}
+
+// From the howN,M, fileN,M, srevN,M and erevN,M fields
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4Integration {
+    // e.g. "branch from", "merge into" or "ignored"
+    pub how: String,
+    pub file: String,
+    // The first and last revisions of `file`, srev is exclusive so #none,#3 gives 1 and 3
+    pub start_revision: u32,
+    pub end_revision: u32,
+}
+
+impl P4Integration {
+    pub fn revisions(&self) -> RangeInclusive<u32> {
+        self.start_revision..=self.end_revision
+    }
+
+    // Whether `file` is the source, rather than the target, of the integration
+    pub fn is_from(&self) -> bool {
+        self.how.ends_with(" from") || matches!(self.how.as_str(), "ignored" | "undid")
+    }
+
+    pub fn kind(&self) -> P4EdgeKind {
+        match self.how.split(' ').next().unwrap_or_default() {
+            "branch" | "add" => P4EdgeKind::Branch,
+            "merge" | "edit" => P4EdgeKind::Merge,
+            "copy" => P4EdgeKind::Copy,
+            "moved" => P4EdgeKind::Move,
+            "delete" => P4EdgeKind::Delete,
+            "undid" | "undone" => P4EdgeKind::Undo,
+            _ => P4EdgeKind::Ignore,
+        }
+    }
+}
+
+// The options of a `p4 filelog` command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4FilelogQuery {
+    filespecs: Vec<String>,
+    branch_history: bool,
+    max_revisions: Option<u32>,
+}
+
+impl P4FilelogQuery {
+    pub fn new(filespec: &str) -> Self {
+        P4FilelogQuery {
+            filespecs: vec![filespec.to_string()],
+            ..Default::default()
+        }
+    }
+
+    pub fn with_filespec(mut self, filespec: &str) -> Self {
+        self.filespecs.push(filespec.to_string());
+        self
+    }
+
+    // -i, also the history of the files the files were branched from
+    pub fn with_branch_history(mut self) -> Self {
+        self.branch_history = true;
+        self
+    }
+
+    // -m, only the newest `max` revisions of each file
+    pub fn with_max_revisions(mut self, max: u32) -> Self {
+        self.max_revisions = Some(max);
+        self
+    }
+}
+
+impl From<&str> for P4FilelogQuery {
+    fn from(filespec: &str) -> Self {
+        P4FilelogQuery::new(filespec)
+    }
+}
+
+impl P4Command for P4FilelogQuery {
+    type Item = P4Filelog;
+    type Fields = InterimP4Filelog;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["filelog".to_string()];
+        if self.branch_history {
+            args.push("-i".to_string());
+        }
+        if let Some(max) = self.max_revisions {
+            args.extend(["-m".to_string(), max.to_string()]);
+        }
+        args.extend(self.filespecs.iter().cloned());
+        args
+    }
+}
+
+pub type P4FilelogIterator<ReadT> = P4CommandIterator<P4FilelogQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4FilelogIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(query: impl Into<P4FilelogQuery>) -> Result<P4FilelogIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), query)
+    }
+
+    // `query` is a P4FilelogQuery, or just a filespec
+    pub fn new_from_context(
+        context: &P4Context,
+        query: impl Into<P4FilelogQuery>,
+    ) -> Result<P4FilelogIterator<P4Output>, P4Error> {
+        Self::spawn(context, query.into())
+    }
+}
+
+// The parsing state of a P4Filelog
+#[derive(Debug, Default)]
+pub struct InterimP4Filelog(P4Filelog);
+
+// #3, or #none before the first revision
+fn parse_revision(value: &str) -> Result<u32, P4Error> {
+    match parse_field(value, "Invalid integration revision")? {
+        Revision::Number(number) => Ok(number),
+        Revision::None => Ok(0),
+        Revision::Head | Revision::Have => {
+            Err(P4Error::InvalidRecord("Invalid integration revision"))
+        }
+    }
+}
+
+impl P4RecordFields for InterimP4Filelog {
+    type Output = P4Filelog;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        if key == "depotFile" {
+            self.0.depot_path = value.to_string();
+            return Ok(());
+        }
+        let key = P4IndexedKey::parse(key)?;
+        let (name, index, integration_index) = match key.indices[..] {
+            [index] => (key.name, index, None),
+            [index, integration_index] => (key.name, index, Some(integration_index)),
+            _ => return Ok(()),
+        };
+
+        let revision = entry_at(&mut self.0.revisions, index)?;
+        if let Some(integration_index) = integration_index {
+            let integration = entry_at(&mut revision.integrations, integration_index)?;
+            match name {
+                "how" => integration.how = value.to_string(),
+                "file" => integration.file = value.to_string(),
+                "srev" => integration.start_revision = parse_revision(value)? + 1,
+                "erev" => integration.end_revision = parse_revision(value)?,
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match name {
+            "rev" => revision.revision = parse_field(value, "Invalid revision")?,
+            "change" => revision.changelist = parse_field(value, "Invalid changelist")?,
+            "action" => revision.action = value.to_string(),
+            "type" => revision.file_type = value.to_string(),
+            "time" => revision.time = parse_field(value, "Invalid time")?,
+            "user" => revision.user = value.to_string(),
+            "client" => revision.client = value.to_string(),
+            "desc" => revision.description = value.to_string(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<P4Filelog, P4Error> {
+        if self.0.depot_path.is_empty() {
+            return Err(P4Error::InvalidRecord("Missing depot path"));
+        }
+        for revision in &mut self.0.revisions {
+            revision.depot_path.clone_from(&self.0.depot_path);
+        }
+        Ok(self.0)
+    }
+}
+
+// How one revision leads to another in a P4RevisionGraph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum P4EdgeKind {
+    // The previous revision of the same file
+    Edit,
+    Branch,
+    Merge,
+    Copy,
+    Move,
+    Delete,
+    Undo,
+    Ignore,
+}
+
+impl P4EdgeKind {
+    // Whether the target starts a line of development from the source, rather than taking changes from it
+    pub fn starts_line(&self) -> bool {
+        matches!(
+            self,
+            P4EdgeKind::Branch | P4EdgeKind::Copy | P4EdgeKind::Move
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4RevisionEdge {
+    // Indexes into P4RevisionGraph::revisions
+    pub from: usize,
+    pub to: usize,
+    pub kind: P4EdgeKind,
+    // The revisions of the source file that were integrated, `from` is the last of them
+    pub source_revisions: RangeInclusive<u32>,
+}
+
+// The revisions of some files with the edits and integrations between them. Integrations with files outside
+// the filelog are left out, `p4 filelog -i` brings in the files that were branched from.
+#[derive(Debug, Default)]
+pub struct P4RevisionGraph {
+    revisions: Vec<P4FileRevision>,
+    edges: Vec<P4RevisionEdge>,
+    index: HashMap<(String, u32), usize>,
+}
+
+// Runs `p4 filelog -i` and builds the graph of the files' revisions
+#[cfg(feature = "spawn")]
+pub fn build_graph(filespec: &str) -> Result<P4RevisionGraph, P4Error> {
+    build_graph_from_context(&P4Context::default(), filespec)
+}
+
+#[cfg(feature = "process")]
+pub fn build_graph_from_context(
+    context: &P4Context,
+    filespec: &str,
+) -> Result<P4RevisionGraph, P4Error> {
+    let query = P4FilelogQuery::new(filespec).with_branch_history();
+    let filelogs =
+        P4FilelogIterator::new_from_context(context, query)?.collect::<Result<Vec<_>, _>>()?;
+    Ok(P4RevisionGraph::from_filelogs(filelogs))
+}
+
+// A revision of a file with the patch from the revision before it to this one
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4RevisionPatch {
+    pub revision: P4FileRevision,
+    // A `git apply` patch the same as describe::to_patch makes, empty when the content didn't change
+    pub patch: Vec<u8>,
+}
+
+// The patches that take a file from nothing to its head revision, oldest first, e.g. for moving a single
+// file to another system with its history
+#[cfg(feature = "spawn")]
+pub fn diff_chain(depot_path: &str) -> Result<P4DiffChain, P4Error> {
+    diff_chain_from_context(&P4Context::default(), depot_path, P4DiffOptions::default())
+}
+
+// Runs filelog up front, then `p4 diff2 -du` for each edit as the chain is read. Added and deleted revisions are
+// printed.
+#[cfg(feature = "process")]
+pub fn diff_chain_from_context(
+    context: &P4Context,
+    depot_path: &str,
+    options: P4DiffOptions,
+) -> Result<P4DiffChain, P4Error> {
+    let mut revisions = Vec::new();
+    for filelog in P4FilelogIterator::new_from_context(context, depot_path)? {
+        revisions.extend(filelog?.revisions);
+    }
+    revisions.sort_by_key(|revision| revision.revision);
+    Ok(P4DiffChain {
+        context: context.clone(),
+        revisions: revisions.into_iter(),
+        previous: None,
+        options,
+    })
+}
+
+#[cfg(feature = "process")]
+pub struct P4DiffChain {
+    context: P4Context,
+    revisions: std::vec::IntoIter<P4FileRevision>,
+    // The last revision, None when it was deleted
+    previous: Option<P4FileRevision>,
+    options: P4DiffOptions,
+}
+
+#[cfg(feature = "process")]
+impl P4DiffChain {
+    fn next_patch(&mut self, revision: P4FileRevision) -> Result<P4RevisionPatch, P4Error> {
+        let current = (!is_deleted_action(&revision.action)).then_some(&revision);
+        let filespec =
+            |revision: &P4FileRevision| format!("{}#{}", revision.depot_path, revision.revision);
+
+        let (hunks, printed);
+        let content = match (&self.previous, current) {
+            (Some(old), Some(new)) => {
+                let status;
+                (status, hunks) = unified_diff_from_context(
+                    &self.context,
+                    &filespec(old),
+                    &filespec(new),
+                    &self.options,
+                )?;
+                match status {
+                    P4Diff2Status::Content if hunks.is_empty() => P4PatchContent::Unknown,
+                    _ => P4PatchContent::Hunks(&hunks),
+                }
+            }
+            (Some(side), None) | (None, Some(side)) => {
+                printed = self.print(&filespec(side))?;
+                printed.as_ref().map_or(P4PatchContent::Unknown, |printed| {
+                    P4PatchContent::Whole(&printed.content)
+                })
+            }
+            (None, None) => P4PatchContent::Unknown,
+        };
+
+        let mut patch = Vec::new();
+        write_file_patch(
+            &mut patch,
+            &revision.depot_path,
+            self.previous.as_ref().map(|old| old.file_type.as_str()),
+            current.map(|new| new.file_type.as_str()),
+            content,
+        )?;
+        self.previous = current.cloned();
+        Ok(P4RevisionPatch { revision, patch })
+    }
+
+    fn print(&self, filespec: &str) -> Result<Option<P4PrintedFile>, P4Error> {
+        let mut printed = None;
+        for file in P4PrintIterator::new_from_context(&self.context, &[filespec])? {
+            printed = Some(file?);
+        }
+        Ok(printed)
+    }
+}
+
+#[cfg(feature = "process")]
+impl Iterator for P4DiffChain {
+    type Item = Result<P4RevisionPatch, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let revision = self.revisions.next()?;
+        Some(self.next_patch(revision))
+    }
+}
+
+impl P4RevisionGraph {
+    pub fn from_filelogs(filelogs: impl IntoIterator<Item = P4Filelog>) -> Self {
+        let mut graph = P4RevisionGraph::default();
+        for filelog in filelogs {
+            for revision in filelog.revisions {
+                let key = (revision.depot_path.clone(), revision.revision);
+                graph.index.insert(key, graph.revisions.len());
+                graph.revisions.push(revision);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut add_edge = |edges: &mut Vec<P4RevisionEdge>, edge: P4RevisionEdge| {
+            if seen.insert((edge.from, edge.to, edge.kind)) {
+                edges.push(edge);
+            }
+        };
+
+        let mut edges = Vec::new();
+        for (to, revision) in graph.revisions.iter().enumerate() {
+            let previous = revision.revision.saturating_sub(1);
+            if let Some(from) = graph.find(&revision.depot_path, previous) {
+                let kind = P4EdgeKind::Edit;
+                let source_revisions = previous..=previous;
+                add_edge(
+                    &mut edges,
+                    P4RevisionEdge {
+                        from,
+                        to,
+                        kind,
+                        source_revisions,
+                    },
+                );
+            }
+        }
+        // The "from" side of an integration knows the source range, so it goes before the "into" side
+        for sources in [true, false] {
+            for (this, revision) in graph.revisions.iter().enumerate() {
+                for integration in &revision.integrations {
+                    if integration.is_from() != sources {
+                        continue;
+                    }
+                    let Some(other) = graph.find(&integration.file, integration.end_revision)
+                    else {
+                        continue;
+                    };
+                    let (from, to, source_revisions) = if sources {
+                        (other, this, integration.revisions())
+                    } else {
+                        (this, other, revision.revision..=revision.revision)
+                    };
+                    let kind = integration.kind();
+                    add_edge(
+                        &mut edges,
+                        P4RevisionEdge {
+                            from,
+                            to,
+                            kind,
+                            source_revisions,
+                        },
+                    );
+                }
+            }
+        }
+        graph.edges = edges;
+        graph
+    }
+
+    pub fn revisions(&self) -> &[P4FileRevision] {
+        &self.revisions
+    }
+
+    pub fn edges(&self) -> &[P4RevisionEdge] {
+        &self.edges
+    }
+
+    // The index of a revision in revisions()
+    pub fn find(&self, depot_path: &str, revision: u32) -> Option<usize> {
+        self.index.get(&(depot_path.to_string(), revision)).copied()
+    }
+
+    // The edges leading to the revision
+    pub fn incoming(&self, index: usize) -> impl Iterator<Item = &P4RevisionEdge> {
+        self.edges.iter().filter(move |edge| edge.to == index)
+    }
+
+    // The edges leading from the revision
+    pub fn outgoing(&self, index: usize) -> impl Iterator<Item = &P4RevisionEdge> {
+        self.edges.iter().filter(move |edge| edge.from == index)
+    }
+
+    // The revisions the line of development came through, from the revision back to where it started. A
+    // branched revision steps to its source, anything else to its previous revision.
+    pub fn lineage(&self, depot_path: &str, revision: u32) -> Vec<&P4FileRevision> {
+        let mut lineage = Vec::new();
+        let mut current = self.find(depot_path, revision);
+        while let Some(index) = current
+            && lineage.len() < self.revisions.len()
+        {
+            lineage.push(&self.revisions[index]);
+            current = self.line_source(index).map(|edge| edge.from);
+        }
+        lineage
+    }
+
+    // The revision the file's line of development was branched, copied or moved from, None when it started
+    // as a new file
+    pub fn branched_from(&self, depot_path: &str, revision: u32) -> Option<&P4FileRevision> {
+        let mut index = self.find(depot_path, revision)?;
+        for _ in 0..self.revisions.len() {
+            let edge = self.line_source(index)?;
+            if edge.kind.starts_line() {
+                return Some(&self.revisions[edge.from]);
+            }
+            index = edge.from;
+        }
+        None
+    }
+
+    fn line_source(&self, index: usize) -> Option<&P4RevisionEdge> {
+        self.incoming(index)
+            .find(|edge| edge.kind.starts_line())
+            .or_else(|| {
+                self.incoming(index)
+                    .find(|edge| edge.kind == P4EdgeKind::Edit)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_revision_graph() {
+        assert_eq!(
+            P4FilelogQuery::new("//depot/rel/a.txt")
+                .with_branch_history()
+                .with_max_revisions(5)
+                .args(),
+            ["filelog", "-i", "-m", "5", "//depot/rel/a.txt"]
+        );
+
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/rel/a.txt"),
+                ("rev0", "2"),
+                ("change0", "12"),
+                ("action0", "integrate"),
+                ("how0,0", "merge from"),
+                ("file0,0", "//depot/main/a.txt"),
+                ("srev0,0", "#2"),
+                ("erev0,0", "#3"),
+                ("rev1", "1"),
+                ("change1", "10"),
+                ("action1", "branch"),
+                ("how1,0", "branch from"),
+                ("file1,0", "//depot/main/a.txt"),
+                ("srev1,0", "#none"),
+                ("erev1,0", "#2"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/main/a.txt"),
+                ("rev0", "3"),
+                ("change0", "11"),
+                ("action0", "edit"),
+                ("how0,0", "merge into"),
+                ("file0,0", "//depot/rel/a.txt"),
+                ("srev0,0", "#none"),
+                ("erev0,0", "#2"),
+                ("rev1", "2"),
+                ("change1", "5"),
+                ("action1", "edit"),
+                ("how1,0", "branch into"),
+                ("file1,0", "//depot/rel/a.txt"),
+                ("srev1,0", "#none"),
+                ("erev1,0", "#1"),
+                ("rev2", "1"),
+                ("change2", "1"),
+                ("action2", "add"),
+            ],
+        ]);
+        let filelogs = P4FilelogIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(filelogs.len(), 2);
+        let branch = &filelogs[0].revisions[1].integrations[0];
+        assert_eq!(branch.revisions(), 1..=2);
+        assert_eq!(branch.kind(), P4EdgeKind::Branch);
+
+        let graph = P4RevisionGraph::from_filelogs(filelogs);
+        assert_eq!(graph.revisions().len(), 5);
+        // Two edits on main, one on rel, the branch and the merge, which both files list but are only added once
+        assert_eq!(graph.edges().len(), 5);
+        let merge = graph
+            .edges()
+            .iter()
+            .find(|edge| edge.kind == P4EdgeKind::Merge)
+            .unwrap();
+        assert_eq!(merge.source_revisions, 3..=3);
+
+        let origin = graph.branched_from("//depot/rel/a.txt", 2).unwrap();
+        assert_eq!(
+            (origin.depot_path.as_str(), origin.revision),
+            ("//depot/main/a.txt", 2)
+        );
+        assert!(graph.branched_from("//depot/main/a.txt", 3).is_none());
+        let lineage = graph
+            .lineage("//depot/rel/a.txt", 2)
+            .iter()
+            .map(|revision| revision.changelist)
+            .collect::<Vec<_>>();
+        assert_eq!(lineage, [12, 10, 5, 1]);
+
+        let data = to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("depotFile", "//depot/main/a.txt"),
+            ("rev0", "1"),
+            ("how0,4000000000", "branch from"),
+        ]]);
+        assert!(matches!(
+            P4FilelogIterator::new_from_reader(&data[..]).next(),
+            Some(Err(P4Error::InvalidRecord(_)))
+        ));
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_diff_chain() {
+        use crate::testing::*;
+
+        let printed = |rev: &'static str, content: &'static str| {
+            [
+                [
+                    ("code", "stat"),
+                    ("depotFile", "//depot/a.txt"),
+                    ("rev", rev),
+                    ("change", "12"),
+                    ("action", "edit"),
+                    ("type", "text"),
+                    ("time", "1743724741"),
+                ]
+                .to_vec(),
+                [("code", "text"), ("data", content)].to_vec(),
+            ]
+        };
+        let context = MockP4::new()
+            .with_records(
+                "filelog //depot/a.txt",
+                [[
+                    ("code", "stat"),
+                    ("depotFile", "//depot/a.txt"),
+                    ("rev0", "3"),
+                    ("change0", "14"),
+                    ("action0", "delete"),
+                    ("rev1", "2"),
+                    ("change1", "12"),
+                    ("action1", "edit"),
+                    ("rev2", "1"),
+                    ("change2", "10"),
+                    ("action2", "add"),
+                ]],
+            )
+            .with_records("print //depot/a.txt#1", printed("1", "a\nb\n"))
+            .with_records(
+                "diff2 -du //depot/a.txt#1 //depot/a.txt#2",
+                [
+                    [
+                        ("code", "stat"),
+                        ("status", "content"),
+                        ("depotFile", "//depot/a.txt"),
+                        ("rev", "1"),
+                        ("type", "text"),
+                        ("depotFile2", "//depot/a.txt"),
+                        ("rev2", "2"),
+                        ("type2", "text"),
+                    ]
+                    .to_vec(),
+                    [("code", "text"), ("data", "@@ -1,2 +1,2 @@\n a\n-b\n+c\n")].to_vec(),
+                ],
+            )
+            .with_records("print //depot/a.txt#2", printed("2", "a\nc\n"))
+            .into_context();
+
+        let patches = diff_chain_from_context(&context, "//depot/a.txt", P4DiffOptions::default())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let changelists = patches
+            .iter()
+            .map(|patch| patch.revision.changelist)
+            .collect::<Vec<_>>();
+        assert_eq!(changelists, [10, 12, 14]);
+        let patch = |index: usize| String::from_utf8(patches[index].patch.clone()).unwrap();
+        assert!(patch(0).contains("new file mode 100644\n--- /dev/null\n+++ b/depot/a.txt\n"));
+        assert!(patch(1).ends_with("@@ -1,2 +1,2 @@\n a\n-b\n+c\n"));
+        assert!(patch(2).contains("deleted file mode 100644\n--- a/depot/a.txt\n+++ /dev/null\n"));
+    }
+}
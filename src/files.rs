@@ -0,0 +1,188 @@
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+use crate::filespec::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::*;
+
+// A file revision in the depot, as listed by `p4 files`
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4DepotFile {
+    pub depot_path: String,
+    pub revision: Revision,
+    pub change: u32,
+    // The action of this revision, e.g. add, edit, delete
+    pub action: String,
+    pub file_type: String,
+    pub time: u32,
+}
+
+impl P4DepotFile {
+    // Whether the file doesn't exist at this revision
+    pub fn is_deleted(&self) -> bool {
+        is_deleted_action(&self.action)
+    }
+}
+
+// The filespecs of a `p4 files` command, each of which may include a revision, e.g. //depot/...@1234
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4FilesQuery {
+    filespecs: Vec<String>,
+}
+
+impl P4FilesQuery {
+    pub fn new(filespec: &str) -> Self {
+        P4FilesQuery {
+            filespecs: vec![filespec.to_string()],
+        }
+    }
+
+    pub fn with_filespec(mut self, filespec: &str) -> Self {
+        self.filespecs.push(filespec.to_string());
+        self
+    }
+}
+
+impl P4Command for P4FilesQuery {
+    type Item = P4DepotFile;
+    type Fields = InterimP4DepotFile;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["files".to_string()];
+        args.extend(self.filespecs.iter().cloned());
+        args
+    }
+}
+
+pub type P4FilesIterator<ReadT> = P4CommandIterator<P4FilesQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4FilesIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(filespec: &str) -> Result<P4FilesIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), filespec)
+    }
+
+    pub fn new_from_context(
+        context: &P4Context,
+        filespec: &str,
+    ) -> Result<P4FilesIterator<P4Output>, P4Error> {
+        Self::spawn(context, P4FilesQuery::new(filespec))
+    }
+
+    #[cfg(feature = "spawn")]
+    pub fn new_with_paths(
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+    ) -> Result<P4FilesIterator<P4Output>, P4Error> {
+        Self::new_from_context_with_paths(&P4Context::default(), paths)
+    }
+
+    // Any number of filespecs, streamed to `p4 -x -` rather than put on the command line
+    pub fn new_from_context_with_paths(
+        context: &P4Context,
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+    ) -> Result<P4FilesIterator<P4Output>, P4Error> {
+        Self::spawn_with_paths(context, P4FilesQuery::default(), paths)
+    }
+}
+
+// The parsing state of a P4DepotFile
+#[derive(Debug, Default)]
+pub struct InterimP4DepotFile {
+    depot_path: Option<String>,
+    revision: Option<Revision>,
+    change: Option<u32>,
+    action: Option<String>,
+    file_type: Option<String>,
+    time: Option<u32>,
+}
+
+impl P4RecordFields for InterimP4DepotFile {
+    type Output = P4DepotFile;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "depotFile" => self.depot_path = Some(value.to_string()),
+            "rev" => self.revision = Some(parse_field(value, "Invalid revision")?),
+            "change" => self.change = Some(parse_field(value, "Invalid changelist")?),
+            "action" => self.action = Some(value.to_string()),
+            "type" => self.file_type = Some(value.to_string()),
+            "time" => self.time = Some(parse_field(value, "Invalid time")?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4DepotFile, P4Error> {
+        Ok(P4DepotFile {
+            depot_path: self
+                .depot_path
+                .ok_or(P4Error::InvalidRecord("Missing depot path"))?,
+            revision: self
+                .revision
+                .ok_or(P4Error::InvalidRecord("Missing revision"))?,
+            change: self
+                .change
+                .ok_or(P4Error::InvalidRecord("Missing changelist"))?,
+            action: self
+                .action
+                .ok_or(P4Error::InvalidRecord("Missing action"))?,
+            file_type: self
+                .file_type
+                .ok_or(P4Error::InvalidRecord("Missing file type"))?,
+            time: self.time.ok_or(P4Error::InvalidRecord("Missing time"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_files() {
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/a.txt"),
+                ("rev", "3"),
+                ("change", "12"),
+                ("action", "edit"),
+                ("type", "text"),
+                ("time", "1743724741"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/b.txt"),
+                ("rev", "2"),
+                ("change", "11"),
+                ("action", "delete"),
+                ("type", "binary"),
+                ("time", "1743723360"),
+            ],
+        ]);
+
+        let files = P4FilesIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].change, 12);
+        assert!(!files[0].is_deleted());
+        assert!(files[1].is_deleted());
+
+        // Errors end the iteration
+        let data = to_py_dict_bytes(&[&[
+            ("code", "error"),
+            ("data", "Invalid revision number '@x'.\n"),
+            ("severity", "3"),
+            ("generic", "1"),
+        ]]);
+        let mut files = P4FilesIterator::new_from_reader(&data[..]);
+        assert!(matches!(files.next(), Some(Err(P4Error::Server(_)))));
+        assert!(files.next().is_none());
+    }
+}
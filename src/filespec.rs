@@ -0,0 +1,214 @@
+// == Std crates
+use std::{fmt, str::FromStr};
+
+// == Internal crates
+use crate::error::*;
+
+// A depot path without a revision, e.g. //depot/main/... or //depot/a.txt
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DepotPath(String);
+
+impl DepotPath {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // The filespec for this path at a revision, e.g. //depot/a.txt#3
+    pub fn at(&self, revision: &RevSpec) -> String {
+        format!("{}{}", self.0, revision)
+    }
+}
+
+impl FromStr for DepotPath {
+    type Err = P4ValueParseError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let is_valid = path.len() > 2
+            && path.starts_with("//")
+            && !path.contains(['#', '@'])
+            && !path.contains(char::is_control);
+        if !is_valid {
+            return Err(P4ValueParseError::InvalidDepotPath(path.to_string()));
+        }
+        Ok(DepotPath(path.to_string()))
+    }
+}
+
+impl fmt::Display for DepotPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for DepotPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// A file revision as p4 reports it, e.g. haveRev is "none" for a file synced to #none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Revision {
+    Number(u32),
+    None,
+    Head,
+    Have,
+}
+
+impl Revision {
+    // The revision number, None for the symbolic revisions
+    pub fn number(&self) -> Option<u32> {
+        match self {
+            Revision::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+}
+
+impl From<u32> for Revision {
+    fn from(number: u32) -> Self {
+        Revision::Number(number)
+    }
+}
+
+// As in p4 output, e.g. 3 or none, with or without the leading #
+impl FromStr for Revision {
+    type Err = P4ValueParseError;
+
+    fn from_str(revision: &str) -> Result<Self, Self::Err> {
+        match revision.strip_prefix('#').unwrap_or(revision) {
+            "head" => Ok(Revision::Head),
+            "have" => Ok(Revision::Have),
+            "none" => Ok(Revision::None),
+            number => number
+                .parse()
+                .map(Revision::Number)
+                .map_err(|_| P4ValueParseError::InvalidRevSpec(revision.to_string())),
+        }
+    }
+}
+
+// Without the #, as p4 prints it in fields
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Revision::Number(number) => write!(f, "{}", number),
+            Revision::None => f.write_str("none"),
+            Revision::Head => f.write_str("head"),
+            Revision::Have => f.write_str("have"),
+        }
+    }
+}
+
+// The revision part of a filespec, see `p4 help revisions`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RevSpec {
+    // #N, #head, #have or #none
+    Revision(Revision),
+    // @N, the revisions as of a changelist
+    Change(u32),
+    // @=N, only the revisions submitted in a changelist
+    ChangeOnly(u32),
+    // @<label, client or date>, e.g. @release-1.0 or @2025/04/01
+    Label(String),
+}
+
+impl FromStr for RevSpec {
+    type Err = P4ValueParseError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || P4ValueParseError::InvalidRevSpec(spec.to_string());
+
+        if spec.starts_with('#') {
+            return spec.parse().map(RevSpec::Revision);
+        }
+
+        let change = spec.strip_prefix('@').ok_or_else(invalid)?;
+        if let Some(change) = change.strip_prefix('=') {
+            return change
+                .parse()
+                .map(RevSpec::ChangeOnly)
+                .map_err(|_| invalid());
+        }
+        if let Ok(change) = change.parse() {
+            return Ok(RevSpec::Change(change));
+        }
+        if change.is_empty() || change.contains(['#', '@']) {
+            return Err(invalid());
+        }
+        Ok(RevSpec::Label(change.to_string()))
+    }
+}
+
+impl fmt::Display for RevSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevSpec::Revision(revision) => write!(f, "#{}", revision),
+            RevSpec::Change(change) => write!(f, "@{}", change),
+            RevSpec::ChangeOnly(change) => write!(f, "@={}", change),
+            RevSpec::Label(label) => write!(f, "@{}", label),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_display_and_from_str() {
+        for spec in [
+            "#3",
+            "#head",
+            "#have",
+            "#none",
+            "@12",
+            "@=12",
+            "@release-1.0",
+        ] {
+            assert_eq!(spec.parse::<RevSpec>().unwrap().to_string(), spec);
+        }
+        assert_eq!("@=12".parse::<RevSpec>(), Ok(RevSpec::ChangeOnly(12)));
+        assert_eq!(
+            "#none".parse::<RevSpec>(),
+            Ok(RevSpec::Revision(Revision::None))
+        );
+        assert!("#x".parse::<RevSpec>().is_err());
+        assert_eq!("none".parse::<Revision>(), Ok(Revision::None));
+        assert_eq!("3".parse::<Revision>().unwrap().number(), Some(3));
+        assert_eq!(Revision::Have.number(), None);
+        assert!("12".parse::<RevSpec>().is_err());
+
+        let path = "//depot/main/...".parse::<DepotPath>().unwrap();
+        assert_eq!(path.at(&RevSpec::Change(12)), "//depot/main/...@12");
+        assert!("depot/a.txt".parse::<DepotPath>().is_err());
+        assert!("//depot/a.txt#3".parse::<DepotPath>().is_err());
+
+        assert_eq!("move/add".parse(), Ok(P4FileAction::MoveAdd));
+        assert_eq!(P4FileAction::MoveAdd.to_string(), "move/add");
+        assert!("nope".parse::<P4FileAction>().is_err());
+
+        let file = P4File {
+            depot_path: "//depot/a.txt".into(),
+            action: "edit".into(),
+            revision: Some(3),
+            file_size: Some(10),
+            digest: Some([0; 16]),
+        };
+        assert_eq!(file.to_string(), "//depot/a.txt#3 edit");
+
+        let changelist = P4Changelist {
+            changelist: 10,
+            time: 1743724741,
+            user: "david".into(),
+            description: "Long yeet\nMore details\n".into(),
+            files: vec![file],
+            ..Default::default()
+        };
+        assert_eq!(
+            changelist.to_string(),
+            "Change 10 on 2025/04/03 by david 'Long yeet'"
+        );
+    }
+}
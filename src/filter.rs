@@ -0,0 +1,165 @@
+// == Internal crates
+use crate::error::*;
+use crate::files::*;
+use crate::print::*;
+use crate::view::*;
+use crate::*;
+
+// Filters over the iterators, e.g. P4ChangesIterator::new_from_context(...)?.by_user("alice").since(1743724741).
+// Errors are always passed through, so they still end the iteration.
+pub trait P4ChangelistFilter: Iterator<Item = Result<P4Changelist, P4Error>> + Sized {
+    fn by_user(self, user: &str) -> impl Iterator<Item = Result<P4Changelist, P4Error>> {
+        let user = user.to_string();
+        self.filter(move |result| keep(result, |changelist| changelist.user == user))
+    }
+
+    // Changelists submitted at or after `time`, in seconds since the epoch
+    fn since(self, time: u32) -> impl Iterator<Item = Result<P4Changelist, P4Error>> {
+        self.filter(move |result| keep(result, |changelist| changelist.time >= time))
+    }
+
+    // Changelists with at least one file under the filespec, for iterators that fill in the files
+    fn matching_path(self, filespec: &str) -> impl Iterator<Item = Result<P4Changelist, P4Error>> {
+        let filespec = filespec.to_string();
+        self.filter(move |result| {
+            keep(result, |changelist| {
+                changelist
+                    .files
+                    .iter()
+                    .any(|file| matches_filespec(&filespec, &file.depot_path))
+            })
+        })
+    }
+}
+
+impl<IterT: Iterator<Item = Result<P4Changelist, P4Error>>> P4ChangelistFilter for IterT {}
+
+// The parts of a file record the file filters look at
+pub trait P4FileRecord {
+    fn depot_path(&self) -> &str;
+    fn action(&self) -> &str;
+}
+
+pub trait P4FileFilter<FileT: P4FileRecord>:
+    Iterator<Item = Result<FileT, P4Error>> + Sized
+{
+    // `filespec` may use the p4 wildcards, e.g. //depot/main/... or //depot/*.txt
+    fn matching_path(self, filespec: &str) -> impl Iterator<Item = Result<FileT, P4Error>> {
+        let filespec = filespec.to_string();
+        self.filter(move |result| {
+            keep(result, |file| {
+                matches_filespec(&filespec, file.depot_path())
+            })
+        })
+    }
+
+    fn with_action(self, action: P4FileAction) -> impl Iterator<Item = Result<FileT, P4Error>> {
+        self.filter(move |result| keep(result, |file| P4FileAction::from(file.action()) == action))
+    }
+}
+
+impl<FileT: P4FileRecord, IterT: Iterator<Item = Result<FileT, P4Error>>> P4FileFilter<FileT>
+    for IterT
+{
+}
+
+impl P4FileRecord for P4File {
+    fn depot_path(&self) -> &str {
+        &self.depot_path
+    }
+
+    fn action(&self) -> &str {
+        &self.action
+    }
+}
+
+impl P4FileRecord for P4DepotFile {
+    fn depot_path(&self) -> &str {
+        &self.depot_path
+    }
+
+    fn action(&self) -> &str {
+        &self.action
+    }
+}
+
+impl P4FileRecord for P4PrintedFile {
+    fn depot_path(&self) -> &str {
+        &self.depot_path
+    }
+
+    fn action(&self) -> &str {
+        &self.action
+    }
+}
+
+// Matches a depot path against a filespec, where ... matches anything and * anything but a /
+pub fn matches_filespec(filespec: &str, depot_path: &str) -> bool {
+    matches_pattern(filespec, depot_path)
+}
+
+fn keep<T>(result: &Result<T, P4Error>, predicate: impl FnOnce(&T) -> bool) -> bool {
+    match result {
+        Ok(item) => predicate(item),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::*;
+    use crate::describe::*;
+    use std::fs;
+
+    #[test]
+    fn test_filters() {
+        let input_file = fs::File::open("./test_data/changes.pyc").unwrap();
+        let all = P4ChangesIterator::new_from_reader(input_file)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let user = all[0].user.clone();
+        let time = all[all.len() / 2].time;
+
+        let filtered = all
+            .clone()
+            .into_iter()
+            .map(Ok)
+            .by_user(&user)
+            .since(time)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(!filtered.is_empty());
+        assert!(
+            filtered
+                .iter()
+                .all(|changelist| changelist.user == user && changelist.time >= time)
+        );
+
+        // `p4 changes` doesn't list files, so there is nothing to match
+        assert_eq!(all.into_iter().map(Ok).matching_path("//...").count(), 0);
+
+        let input_file = fs::File::open("./test_data/describe.pyc").unwrap();
+        let files = P4DescribeIterator::new_from_reader(input_file)
+            .unwrap()
+            .matching_path("//depot/main3/UE5.5_github_src/Engine/.../*.json")
+            .with_action(P4FileAction::Add)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files.len(), 4);
+
+        assert!(matches_filespec("//depot/*.txt", "//depot/a.txt"));
+        assert!(!matches_filespec("//depot/*.txt", "//depot/sub/a.txt"));
+        assert!(matches_filespec("//depot/.../a.txt", "//depot/sub/a.txt"));
+        // Many wildcards that can't match don't take exponential time
+        let path = format!("//depot/{}", "a".repeat(200));
+        assert!(!matches_filespec(
+            &format!("//depot/{}b", "...a".repeat(30)),
+            &path
+        ));
+        assert!(matches_filespec(
+            &format!("//depot/{}", "*a".repeat(30)),
+            &path
+        ));
+    }
+}
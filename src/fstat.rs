@@ -0,0 +1,456 @@
+// == Std crates
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+use crate::filespec::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::records::*;
+use crate::*;
+
+// A file as reported by `p4 fstat`. Which fields are set depends on the file's state and the -O options.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4FstatEntry {
+    pub depot_path: String,
+    // In client syntax, e.g. //my-client/a.txt
+    pub client_path: Option<String>,
+    // On the local filesystem, with -Op (or for mapped files without it)
+    pub local_path: Option<String>,
+    pub head_action: Option<String>,
+    pub head_type: Option<String>,
+    pub head_time: Option<u32>,
+    pub head_rev: Option<u32>,
+    pub head_change: Option<u32>,
+    pub head_mod_time: Option<u32>,
+    // Revision::None when the file was synced to #none
+    pub have_rev: Option<Revision>,
+    // Set when the file is opened in this client
+    pub action: Option<String>,
+    // The changelist the file is opened in, "default" or a number
+    pub change: Option<String>,
+    pub file_type: Option<String>,
+    // With -Ol
+    pub digest: Option<[u8; 16]>,
+    pub file_size: Option<u64>,
+    // Opened in other clients
+    pub other_opens: Vec<P4OtherOpen>,
+    // With -Or
+    pub pending_integrations: Vec<P4PendingIntegration>,
+    // With -Oa, from the attr-<name> and attrProp-<name> fields. Decoded from hex with -Oae.
+    pub attributes: HashMap<String, Vec<u8>>,
+    // The names of the attributes that propagate with the file, from attrProp-<name>
+    pub propagating_attributes: BTreeSet<String>,
+    // Every other field, e.g. isMapped or ourLock, keyed as p4 names them
+    pub other_fields: BTreeMap<String, String>,
+}
+
+// From the otherOpenN, otherActionN and otherChangeN fields
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4OtherOpen {
+    // user@client
+    pub user_client: String,
+    pub action: String,
+    pub change: String,
+}
+
+// From the resolve*N fields of `fstat -Or`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4PendingIntegration {
+    pub action: String,
+    pub from_file: String,
+    pub start_from_rev: Option<u32>,
+    pub end_from_rev: Option<u32>,
+    pub base_file: Option<String>,
+    pub base_rev: Option<u32>,
+}
+
+// The options of a `p4 fstat` command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4FstatQuery {
+    filespecs: Vec<String>,
+    file_details: bool,
+    local_paths: bool,
+    pending_integrations: bool,
+    attributes: bool,
+    hex_attributes: bool,
+}
+
+impl P4FstatQuery {
+    pub fn new(filespec: &str) -> Self {
+        P4FstatQuery {
+            filespecs: vec![filespec.to_string()],
+            ..Default::default()
+        }
+    }
+
+    pub fn with_filespec(mut self, filespec: &str) -> Self {
+        self.filespecs.push(filespec.to_string());
+        self
+    }
+
+    // -Ol, the digest and size of the head revision
+    pub fn with_file_details(mut self) -> Self {
+        self.file_details = true;
+        self
+    }
+
+    // -Op, the local path of each file
+    pub fn with_local_paths(mut self) -> Self {
+        self.local_paths = true;
+        self
+    }
+
+    // -Or, the integrations still to be resolved
+    pub fn with_pending_integrations(mut self) -> Self {
+        self.pending_integrations = true;
+        self
+    }
+
+    // -Oa, the attributes set with `p4 attribute`
+    pub fn with_attributes(mut self) -> Self {
+        self.attributes = true;
+        self
+    }
+
+    // -Oae, the attributes with their values hex encoded, for binary values that aren't valid text
+    pub fn with_hex_attributes(mut self) -> Self {
+        self.attributes = true;
+        self.hex_attributes = true;
+        self
+    }
+}
+
+impl From<&str> for P4FstatQuery {
+    fn from(filespec: &str) -> Self {
+        P4FstatQuery::new(filespec)
+    }
+}
+
+impl P4Command for P4FstatQuery {
+    type Item = P4FstatEntry;
+    type Fields = InterimP4FstatEntry;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["fstat".to_string()];
+        let options = [
+            (self.file_details, 'l'),
+            (self.local_paths, 'p'),
+            (self.pending_integrations, 'r'),
+            (self.attributes, 'a'),
+            (self.hex_attributes, 'e'),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, option)| *option)
+        .collect::<String>();
+        if !options.is_empty() {
+            args.push(format!("-O{}", options));
+        }
+        args.extend(self.filespecs.iter().cloned());
+        args
+    }
+
+    fn new_fields(&self) -> Option<P4FieldsFactory<InterimP4FstatEntry>> {
+        if !self.hex_attributes {
+            return None;
+        }
+        Some(Box::new(|| InterimP4FstatEntry {
+            hex_attributes: true,
+            ..Default::default()
+        }))
+    }
+}
+
+pub type P4FstatIterator<ReadT> = P4CommandIterator<P4FstatQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4FstatIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(query: impl Into<P4FstatQuery>) -> Result<P4FstatIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), query)
+    }
+
+    // `query` is a P4FstatQuery, or just a filespec
+    pub fn new_from_context(
+        context: &P4Context,
+        query: impl Into<P4FstatQuery>,
+    ) -> Result<P4FstatIterator<P4Output>, P4Error> {
+        Self::spawn(context, query.into())
+    }
+
+    #[cfg(feature = "spawn")]
+    pub fn new_with_paths(
+        query: P4FstatQuery,
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+    ) -> Result<P4FstatIterator<P4Output>, P4Error> {
+        Self::new_from_context_with_paths(&P4Context::default(), query, paths)
+    }
+
+    // Any number of paths, streamed to `p4 -x -` rather than put on the command line, e.g. every file of a
+    // depot for a digest audit. They are added to the query's own filespecs, so the query can be
+    // P4FstatQuery::default() with its options.
+    pub fn new_from_context_with_paths(
+        context: &P4Context,
+        query: P4FstatQuery,
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+    ) -> Result<P4FstatIterator<P4Output>, P4Error> {
+        Self::spawn_with_paths(context, query, paths)
+    }
+}
+
+// The parsing state of a P4FstatEntry
+#[derive(Debug, Default)]
+pub struct InterimP4FstatEntry {
+    entry: P4FstatEntry,
+    hex_attributes: bool,
+}
+
+impl InterimP4FstatEntry {
+    fn attribute_value(&self, value: &str) -> Result<Vec<u8>, P4Error> {
+        if !self.hex_attributes {
+            return Ok(value.as_bytes().to_vec());
+        }
+        const_hex::decode(value).map_err(|_| P4Error::InvalidRecord("Invalid attribute value"))
+    }
+}
+
+// The N of e.g. otherOpenN, None for other keys including the bare otherOpen count
+fn field_index(key: &str, name: &str) -> Option<usize> {
+    key.strip_prefix(name)?.parse().ok()
+}
+
+impl P4RecordFields for InterimP4FstatEntry {
+    type Output = P4FstatEntry;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        if let Some(name) = key.strip_prefix("attr-") {
+            let value = self.attribute_value(value)?;
+            self.entry.attributes.insert(name.to_string(), value);
+            return Ok(());
+        }
+        if let Some(name) = key.strip_prefix("attrProp-") {
+            let value = self.attribute_value(value)?;
+            self.entry.attributes.insert(name.to_string(), value);
+            self.entry.propagating_attributes.insert(name.to_string());
+            return Ok(());
+        }
+
+        let entry = &mut self.entry;
+        match key {
+            "depotFile" => entry.depot_path = value.to_string(),
+            "clientFile" => entry.client_path = Some(value.to_string()),
+            "path" => entry.local_path = Some(value.to_string()),
+            "headAction" => entry.head_action = Some(value.to_string()),
+            "headType" => entry.head_type = Some(value.to_string()),
+            "headTime" => entry.head_time = Some(parse_field(value, "Invalid head time")?),
+            "headRev" => entry.head_rev = Some(parse_field(value, "Invalid head revision")?),
+            "headChange" => {
+                entry.head_change = Some(parse_field(value, "Invalid head changelist")?)
+            }
+            "headModTime" => {
+                entry.head_mod_time = Some(parse_field(value, "Invalid head mod time")?)
+            }
+            "haveRev" => entry.have_rev = Some(parse_field(value, "Invalid have revision")?),
+            "action" => entry.action = Some(value.to_string()),
+            "change" => entry.change = Some(value.to_string()),
+            "type" => entry.file_type = Some(value.to_string()),
+            "digest" => {
+                entry.digest = Some(
+                    const_hex::decode_to_array(value)
+                        .map_err(|_| P4Error::InvalidRecord("Invalid digest"))?,
+                )
+            }
+            "fileSize" => entry.file_size = Some(parse_field(value, "Invalid file size")?),
+            key => {
+                let other_opens = &mut entry.other_opens;
+                let integrations = &mut entry.pending_integrations;
+                if let Some(index) = field_index(key, "otherOpen") {
+                    entry_at(other_opens, index)?.user_client = value.to_string();
+                } else if let Some(index) = field_index(key, "otherAction") {
+                    entry_at(other_opens, index)?.action = value.to_string();
+                } else if let Some(index) = field_index(key, "otherChange") {
+                    entry_at(other_opens, index)?.change = value.to_string();
+                } else if let Some(index) = field_index(key, "resolveAction") {
+                    entry_at(integrations, index)?.action = value.to_string();
+                } else if let Some(index) = field_index(key, "resolveFromFile") {
+                    entry_at(integrations, index)?.from_file = value.to_string();
+                } else if let Some(index) = field_index(key, "resolveStartFromRev") {
+                    entry_at(integrations, index)?.start_from_rev =
+                        Some(parse_field(value, "Invalid resolve revision")?);
+                } else if let Some(index) = field_index(key, "resolveEndFromRev") {
+                    entry_at(integrations, index)?.end_from_rev =
+                        Some(parse_field(value, "Invalid resolve revision")?);
+                } else if let Some(index) = field_index(key, "resolveBaseFile") {
+                    entry_at(integrations, index)?.base_file = Some(value.to_string());
+                } else if let Some(index) = field_index(key, "resolveBaseRev") {
+                    entry_at(integrations, index)?.base_rev =
+                        Some(parse_field(value, "Invalid resolve revision")?);
+                } else {
+                    entry
+                        .other_fields
+                        .insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4FstatEntry, P4Error> {
+        if self.entry.depot_path.is_empty() {
+            return Err(P4Error::InvalidRecord("Missing depot path"));
+        }
+        Ok(self.entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_fstat() {
+        assert_eq!(
+            P4FstatQuery::from("//depot/...").args(),
+            ["fstat", "//depot/..."]
+        );
+        assert_eq!(
+            P4FstatQuery::new("//depot/a.txt")
+                .with_file_details()
+                .with_local_paths()
+                .with_pending_integrations()
+                .args(),
+            ["fstat", "-Olpr", "//depot/a.txt"]
+        );
+
+        let data = to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("depotFile", "//depot/a.txt"),
+            ("clientFile", "//my-client/a.txt"),
+            ("path", "/work/a.txt"),
+            ("isMapped", ""),
+            ("headAction", "edit"),
+            ("headType", "text"),
+            ("headTime", "1743724741"),
+            ("headRev", "3"),
+            ("headChange", "12"),
+            ("headModTime", "1743724700"),
+            ("haveRev", "2"),
+            ("action", "integrate"),
+            ("change", "default"),
+            ("type", "text"),
+            ("digest", "A9E93320E1FC469228D707C9124C878C"),
+            ("fileSize", "1015"),
+            ("otherOpen0", "bob@bob-ws"),
+            ("otherAction0", "edit"),
+            ("otherChange0", "15"),
+            ("otherOpen", "1"),
+            ("resolveAction0", "content"),
+            ("resolveFromFile0", "//depot/rel/a.txt"),
+            ("resolveStartFromRev0", "1"),
+            ("resolveEndFromRev0", "4"),
+            ("resolveBaseFile0", "//depot/rel/a.txt"),
+            ("resolveBaseRev0", "1"),
+        ]]);
+
+        let entries = P4FstatIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.local_path.as_deref(), Some("/work/a.txt"));
+        assert_eq!(
+            (entry.head_rev, entry.have_rev),
+            (Some(3), Some(Revision::Number(2)))
+        );
+        assert_eq!(entry.digest.unwrap()[0], 0xA9);
+        assert_eq!(entry.file_size, Some(1015));
+        assert_eq!(
+            entry.other_opens,
+            [P4OtherOpen {
+                user_client: "bob@bob-ws".into(),
+                action: "edit".into(),
+                change: "15".into(),
+            }]
+        );
+        assert_eq!(entry.pending_integrations.len(), 1);
+        assert_eq!(entry.pending_integrations[0].end_from_rev, Some(4));
+        assert_eq!(entry.other_fields["otherOpen"], "1");
+        assert!(entry.other_fields.contains_key("isMapped"));
+
+        // Synced to #none
+        let data = to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("depotFile", "//depot/a.txt"),
+            ("headRev", "3"),
+            ("haveRev", "none"),
+        ]]);
+        let entry = P4FstatIterator::new_from_reader(&data[..])
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.have_rev, Some(Revision::None));
+
+        // Attributes, hex encoded with -Oae
+        let query = P4FstatQuery::new("//depot/a.txt").with_hex_attributes();
+        assert_eq!(query.args(), ["fstat", "-Oae", "//depot/a.txt"]);
+        let data = to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("depotFile", "//depot/a.txt"),
+            ("attr-owner", "616C696365"),
+            ("attrProp-thumbnail", "FF00"),
+        ]]);
+        let entry = P4FstatIterator::new_from_reader(&data[..])
+            .for_command(&query)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.attributes["owner"], b"alice");
+        assert_eq!(entry.attributes["thumbnail"], [0xFF, 0x00]);
+        assert!(entry.propagating_attributes.contains("thumbnail"));
+        assert!(entry.other_fields.is_empty());
+
+        // An index the server can't have meant
+        let data = to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("depotFile", "//depot/a.txt"),
+            ("otherOpen18446744073709551615", "bob@bob-ws"),
+        ]]);
+        assert!(matches!(
+            P4FstatIterator::new_from_reader(&data[..]).next(),
+            Some(Err(P4Error::InvalidRecord(_)))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_fstat_with_paths() {
+        use crate::testing::*;
+        use std::sync::Arc;
+
+        let mock = Arc::new(
+            MockP4::new().with_records(
+                "-x - fstat -Ol",
+                ["//depot/a.txt", "//depot/b c.txt"]
+                    .map(|path| [("code", "stat"), ("depotFile", path)]),
+            ),
+        );
+        let context = P4Context::new().with_backend(mock.clone());
+
+        let paths = ["//depot/a.txt", "//depot/b c.txt"].map(String::from);
+        let query = P4FstatQuery::default().with_file_details();
+        let entries = P4FstatIterator::new_from_context_with_paths(&context, query, paths)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries[1].depot_path, "//depot/b c.txt");
+        assert_eq!(
+            mock.inputs(),
+            [b"//depot/a.txt\n//depot/b c.txt\n".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+use crate::filespec::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::*;
+
+// A file revision synced to the workspace
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4HaveFile {
+    pub depot_path: String,
+    // Local path
+    pub client_path: String,
+    // Revision::None for a file synced to #none, which is still in the have list
+    pub revision: Revision,
+}
+
+// The filespecs of a `p4 have` command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4HaveQuery {
+    filespecs: Vec<String>,
+}
+
+impl P4HaveQuery {
+    pub fn new(filespec: &str) -> Self {
+        P4HaveQuery {
+            filespecs: vec![filespec.to_string()],
+        }
+    }
+
+    pub fn with_filespec(mut self, filespec: &str) -> Self {
+        self.filespecs.push(filespec.to_string());
+        self
+    }
+}
+
+impl P4Command for P4HaveQuery {
+    type Item = P4HaveFile;
+    type Fields = InterimP4HaveFile;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["have".to_string()];
+        args.extend(self.filespecs.iter().cloned());
+        args
+    }
+}
+
+pub type P4HaveIterator<ReadT> = P4CommandIterator<P4HaveQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4HaveIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(filespec: &str) -> Result<P4HaveIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), filespec)
+    }
+
+    pub fn new_from_context(
+        context: &P4Context,
+        filespec: &str,
+    ) -> Result<P4HaveIterator<P4Output>, P4Error> {
+        Self::spawn(context, P4HaveQuery::new(filespec))
+    }
+}
+
+// The parsing state of a P4HaveFile
+#[derive(Debug, Default)]
+pub struct InterimP4HaveFile {
+    depot_path: Option<String>,
+    client_path: Option<String>,
+    revision: Option<Revision>,
+}
+
+impl P4RecordFields for InterimP4HaveFile {
+    type Output = P4HaveFile;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "depotFile" => self.depot_path = Some(value.to_string()),
+            "path" => self.client_path = Some(value.to_string()),
+            "haveRev" => self.revision = Some(parse_field(value, "Invalid revision")?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4HaveFile, P4Error> {
+        Ok(P4HaveFile {
+            depot_path: self
+                .depot_path
+                .ok_or(P4Error::InvalidRecord("Missing depot path"))?,
+            client_path: self
+                .client_path
+                .ok_or(P4Error::InvalidRecord("Missing client path"))?,
+            revision: self
+                .revision
+                .ok_or(P4Error::InvalidRecord("Missing revision"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_have() {
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/a.txt"),
+                ("clientFile", "//ws/a.txt"),
+                ("path", "/ws/a.txt"),
+                ("haveRev", "3"),
+            ],
+            &[
+                ("code", "error"),
+                ("data", "//depot/nope/... - file(s) not on client.\n"),
+                ("severity", "2"),
+                ("generic", "17"),
+            ],
+        ]);
+
+        let mut have = P4HaveIterator::new_from_reader(&data[..]);
+        assert!(have.summary().is_none());
+        let files = have.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            files,
+            [P4HaveFile {
+                depot_path: "//depot/a.txt".into(),
+                client_path: "/ws/a.txt".into(),
+                revision: 3.into(),
+            }]
+        );
+
+        let summary = have.summary().unwrap();
+        assert_eq!(summary.records_yielded, 1);
+        assert_eq!(summary.records_skipped, 1);
+        assert_eq!(summary.bytes_read, data.len() as u64);
+        assert_eq!(summary.exit_code, None);
+    }
+
+    #[test]
+    fn test_have_none() {
+        let data = to_py_dict_bytes(&[&[
+            ("code", "stat"),
+            ("depotFile", "//depot/a.txt"),
+            ("clientFile", "//ws/a.txt"),
+            ("path", "/ws/a.txt"),
+            ("haveRev", "none"),
+        ]]);
+
+        let file = P4HaveIterator::new_from_reader(&data[..])
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.revision, Revision::None);
+    }
+}
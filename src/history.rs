@@ -0,0 +1,281 @@
+// == Std crates
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+// == Internal crates
+use crate::changes::*;
+use crate::context::*;
+use crate::describe::*;
+use crate::error::*;
+use crate::*;
+
+pub type CheckpointCallback = Box<dyn FnMut(&ScanCheckpoint) + Send>;
+
+// Where a history scan got to, saved by the caller so a scan that dies can be resumed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanCheckpoint {
+    // Every changelist up to and including this one has been yielded
+    pub last_changelist: u32,
+}
+
+// Saved as "<last changelist>"
+impl fmt::Display for ScanCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.last_changelist)
+    }
+}
+
+impl FromStr for ScanCheckpoint {
+    type Err = P4ValueParseError;
+
+    fn from_str(checkpoint: &str) -> Result<Self, Self::Err> {
+        let invalid = || P4ValueParseError::InvalidCheckpoint(checkpoint.to_string());
+        // Older checkpoints were "<last changelist>:<bytes parsed>"
+        let (last_changelist, _) = checkpoint.split_once(':').unwrap_or((checkpoint, ""));
+        Ok(ScanCheckpoint {
+            last_changelist: last_changelist.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// Yields the submitted changelists from oldest to newest with their files, listing them one window of
+// `p4 changes` at a time and running `p4 describe` for each
+pub struct P4HistoryScan {
+    context: P4Context,
+    paths: Vec<String>,
+    next_start: u32,
+    // Inclusive, the newest changelist when the scan starts if not set
+    end: Option<u32>,
+    window_size: u32,
+    window: VecDeque<P4Changelist>,
+    checkpoint: ScanCheckpoint,
+    checkpoint_interval: u32,
+    since_checkpoint: u32,
+    checkpoint_callback: Option<CheckpointCallback>,
+    failed: bool,
+}
+
+impl P4HistoryScan {
+    const DEFAULT_WINDOW_SIZE: u32 = 1000;
+
+    pub fn new(context: &P4Context, start: u32) -> Self {
+        P4HistoryScan {
+            context: context.clone(),
+            paths: Vec::new(),
+            next_start: start,
+            end: None,
+            window_size: Self::DEFAULT_WINDOW_SIZE,
+            window: VecDeque::new(),
+            checkpoint: ScanCheckpoint::default(),
+            checkpoint_interval: 0,
+            since_checkpoint: 0,
+            checkpoint_callback: None,
+            failed: false,
+        }
+    }
+
+    // Carries on after the last changelist the checkpoint recorded
+    pub fn resume(context: &P4Context, checkpoint: &ScanCheckpoint) -> Self {
+        let mut scan = Self::new(context, checkpoint.last_changelist.saturating_add(1));
+        scan.checkpoint = *checkpoint;
+        scan
+    }
+
+    pub fn with_end(mut self, end: u32) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    // Only changelists with files under one of the paths, e.g. //depot/main/...
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.paths.push(path.to_string());
+        self
+    }
+
+    // Changelist numbers covered by each `p4 changes`
+    pub fn with_window_size(mut self, window_size: u32) -> Self {
+        self.window_size = window_size.max(1);
+        self
+    }
+
+    // `callback` is called after every `interval` changelists, and once more when the scan is done
+    pub fn with_checkpoints(
+        mut self,
+        interval: u32,
+        callback: impl FnMut(&ScanCheckpoint) + Send + 'static,
+    ) -> Self {
+        self.checkpoint_interval = interval.max(1);
+        self.checkpoint_callback = Some(Box::new(callback));
+        self
+    }
+
+    // As of the last changelist yielded
+    pub fn checkpoint(&self) -> ScanCheckpoint {
+        self.checkpoint
+    }
+
+    fn changes_query(&self) -> P4ChangesQuery {
+        self.paths
+            .iter()
+            .fold(P4ChangesQuery::new(), |query, path| query.with_path(path))
+    }
+
+    fn head_changelist(&mut self) -> Result<u32, P4Error> {
+        let mut changes =
+            P4ChangesIterator::new_from_context(&self.context, self.changes_query().with_max(1))?;
+        let head = changes.next().transpose()?;
+        changes.by_ref().for_each(drop);
+        Ok(head.map_or(0, |changelist| changelist.changelist))
+    }
+
+    fn list_next_window(&mut self) -> Result<(), P4Error> {
+        let end = match self.end {
+            Some(end) => end,
+            None => {
+                let head = self.head_changelist()?;
+                *self.end.insert(head)
+            }
+        };
+
+        while self.window.is_empty() && self.next_start <= end {
+            let window_end = self
+                .next_start
+                .saturating_add(self.window_size - 1)
+                .min(end);
+            let query = self.changes_query().with_range(self.next_start..window_end);
+            let changes = P4ChangesIterator::new_from_context(&self.context, query)?;
+            let headers = changes.collect::<Result<Vec<_>, _>>()?;
+
+            // p4 lists the newest first
+            self.window.extend(headers.into_iter().rev());
+            self.next_start = window_end.saturating_add(1);
+        }
+
+        Ok(())
+    }
+
+    fn next_changelist(&mut self) -> Result<Option<P4Changelist>, P4Error> {
+        if self.window.is_empty() {
+            self.list_next_window()?;
+        }
+        let Some(mut changelist) = self.window.pop_front() else {
+            if self.since_checkpoint > 0 {
+                self.save_checkpoint();
+            }
+            return Ok(None);
+        };
+
+        let describe = P4DescribeIterator::new_from_context(&self.context, changelist.changelist)?;
+        changelist.files = describe.collect::<Result<_, _>>()?;
+
+        self.checkpoint.last_changelist = changelist.changelist;
+        self.since_checkpoint += 1;
+        if self.since_checkpoint >= self.checkpoint_interval {
+            self.save_checkpoint();
+        }
+
+        Ok(Some(changelist))
+    }
+
+    fn save_checkpoint(&mut self) {
+        if let Some(callback) = self.checkpoint_callback.as_mut() {
+            callback(&self.checkpoint);
+        }
+        self.since_checkpoint = 0;
+    }
+}
+
+impl Iterator for P4HistoryScan {
+    type Item = Result<P4Changelist, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        // The checkpoint isn't moved past a changelist that failed, so resuming retries it
+        let result = self.next_changelist().transpose();
+        self.failed = matches!(result, Some(Err(_)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_history_scan() {
+        let change = |change: &'static str| {
+            [
+                ("code", "stat"),
+                ("change", change),
+                ("time", "1743724741"),
+                ("user", "alice"),
+                ("desc", "Fix the build\n"),
+            ]
+            .to_vec()
+        };
+        let describe = |number: &'static str, path: &'static str| {
+            let mut record = change(number);
+            record.extend([
+                ("depotFile0", path),
+                ("action0", "edit"),
+                ("rev0", "2"),
+                ("fileSize0", "4"),
+                ("digest0", "00000000000000000000000000000000"),
+            ]);
+            [record]
+        };
+        let mock = MockP4::new()
+            .with_records("changes -s submitted -l -m 1", [change("3")])
+            .with_records("changes -s submitted -l @1,2", [change("2"), change("1")])
+            .with_records("changes -s submitted -l @3,3", [change("3")])
+            .with_records("describe -s 1", describe("1", "//depot/a.txt"))
+            .with_records("describe -s 2", describe("2", "//depot/b.txt"))
+            .with_records("describe -s 3", describe("3", "//depot/c.txt"));
+        let context = mock.into_context();
+
+        let checkpoints = Arc::new(Mutex::new(Vec::new()));
+        let checkpoints_clone = checkpoints.clone();
+        let changelists = P4HistoryScan::new(&context, 1)
+            .with_window_size(2)
+            .with_checkpoints(2, move |checkpoint| {
+                checkpoints_clone.lock().unwrap().push(*checkpoint)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            changelists
+                .iter()
+                .map(|changelist| (changelist.changelist, changelist.files.len()))
+                .collect::<Vec<_>>(),
+            [(1, 1), (2, 1), (3, 1)]
+        );
+
+        let checkpoints = checkpoints.lock().unwrap().clone();
+        assert_eq!(
+            checkpoints
+                .iter()
+                .map(|checkpoint| checkpoint.last_changelist)
+                .collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert_eq!("2:1234".parse::<ScanCheckpoint>().unwrap(), checkpoints[0]);
+
+        // Resuming from the first checkpoint only yields what came after it
+        let checkpoint = checkpoints[0]
+            .to_string()
+            .parse::<ScanCheckpoint>()
+            .unwrap();
+        assert_eq!(checkpoint, checkpoints[0]);
+        let resumed = P4HistoryScan::resume(&context, &checkpoint)
+            .with_end(3)
+            .with_window_size(2)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(resumed.len(), 1);
+        assert_eq!(resumed[0].changelist, 3);
+    }
+}
@@ -0,0 +1,135 @@
+// == Std crates
+use std::collections::BTreeMap;
+
+// == Internal crates
+#[cfg(feature = "process")]
+use crate::changes::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::error::*;
+use crate::files::*;
+
+// How a file differs between two labels
+#[derive(Debug, Clone, PartialEq)]
+pub enum P4LabelDifference {
+    // Only in the second label
+    Added(P4DepotFile),
+    // Only in the first label
+    Removed(P4DepotFile),
+    // In both at different revisions
+    Changed { from: P4DepotFile, to: P4DepotFile },
+}
+
+impl P4LabelDifference {
+    pub fn depot_path(&self) -> &str {
+        match self {
+            P4LabelDifference::Added(file) | P4LabelDifference::Removed(file) => &file.depot_path,
+            P4LabelDifference::Changed { to, .. } => &to.depot_path,
+        }
+    }
+}
+
+// The files added, removed and changed going from one label to another, e.g. two releases, by depot path
+#[cfg(feature = "spawn")]
+pub fn diff(from_label: &str, to_label: &str) -> Result<Vec<P4LabelDifference>, P4Error> {
+    diff_from_context(&P4Context::default(), from_label, to_label)
+}
+
+// Lists the files of both labels with `p4 files`. Both labels are checked to exist first, as a misspelt one
+// would list no files and make everything look added or removed.
+#[cfg(feature = "process")]
+pub fn diff_from_context(
+    context: &P4Context,
+    from_label: &str,
+    to_label: &str,
+) -> Result<Vec<P4LabelDifference>, P4Error> {
+    check_label_exists(context, from_label)?;
+    check_label_exists(context, to_label)?;
+    let files = |label: &str| {
+        P4FilesIterator::new_from_context(context, &format!("//...@{}", label))?
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok(diff_files(files(from_label)?, files(to_label)?))
+}
+
+// Deleted revisions count as the file not being in the label
+pub fn diff_files(
+    from: impl IntoIterator<Item = P4DepotFile>,
+    to: impl IntoIterator<Item = P4DepotFile>,
+) -> Vec<P4LabelDifference> {
+    let by_path = |files: &mut dyn Iterator<Item = P4DepotFile>| {
+        files
+            .filter(|file| !file.is_deleted())
+            .map(|file| (file.depot_path.clone(), file))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let mut from = by_path(&mut from.into_iter());
+    let to = by_path(&mut to.into_iter());
+
+    let mut differences = Vec::new();
+    for (depot_path, to_file) in to {
+        match from.remove(&depot_path) {
+            None => differences.push(P4LabelDifference::Added(to_file)),
+            Some(from_file) if from_file.revision != to_file.revision => {
+                differences.push(P4LabelDifference::Changed {
+                    from: from_file,
+                    to: to_file,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    differences.extend(from.into_values().map(P4LabelDifference::Removed));
+    differences.sort_by(|a, b| a.depot_path().cmp(b.depot_path()));
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_files() {
+        let file = |path: &str, revision: u32, action: &str| P4DepotFile {
+            depot_path: path.to_string(),
+            revision: revision.into(),
+            change: revision * 10,
+            action: action.to_string(),
+            file_type: "text".to_string(),
+            time: 1743724741,
+        };
+        let from = vec![
+            file("//depot/a.txt", 1, "add"),
+            file("//depot/b.txt", 2, "edit"),
+            file("//depot/c.txt", 1, "add"),
+            file("//depot/d.txt", 3, "delete"),
+        ];
+        let to = vec![
+            file("//depot/a.txt", 1, "add"),
+            file("//depot/b.txt", 3, "edit"),
+            file("//depot/d.txt", 4, "add"),
+            file("//depot/e.txt", 1, "add"),
+        ];
+
+        let differences = diff_files(from, to)
+            .into_iter()
+            .map(|difference| match difference {
+                P4LabelDifference::Added(file) => format!("+{}", file.depot_path),
+                P4LabelDifference::Removed(file) => format!("-{}", file.depot_path),
+                P4LabelDifference::Changed { from, to } => {
+                    format!("{}#{}->#{}", to.depot_path, from.revision, to.revision)
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            differences,
+            [
+                "//depot/b.txt#2->#3",
+                "-//depot/c.txt",
+                "+//depot/d.txt",
+                "+//depot/e.txt"
+            ]
+        );
+    }
+}
@@ -1,78 +1,363 @@
+pub mod annotate;
+pub mod annotations;
+#[cfg(feature = "process")]
+pub mod auth;
+pub mod backend;
+pub mod branch;
+pub mod budget;
+pub mod cancel;
+#[cfg(feature = "process")]
+pub mod capture;
 pub mod changes;
+pub mod client;
+pub mod command;
+#[cfg(feature = "process")]
+pub mod command_log;
+pub mod config;
+#[cfg(feature = "process")]
+pub mod context;
+pub mod depots;
 pub mod describe;
+pub mod diagnostics;
+pub mod dict;
+mod diff;
+pub mod diff2;
+pub mod dirs;
+pub mod error;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filelog;
+pub mod files;
+pub mod filespec;
+pub mod filter;
+pub mod fstat;
+pub mod have;
+#[cfg(feature = "process")]
+pub mod history;
+pub mod labels;
+pub mod metrics;
+#[cfg(feature = "process")]
+pub mod output;
 pub mod parsers;
+mod patch;
+pub mod port;
+pub mod prefetch;
+pub mod print;
+mod process_state;
+pub mod progress;
+pub mod property;
+#[cfg(feature = "python")]
+pub mod python;
+mod records;
+pub mod reports;
+pub mod retry;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "process")]
+pub mod shelve;
+pub mod sizes;
+pub mod stats;
+pub mod stream;
+pub mod sync;
+#[cfg(feature = "process")]
+pub mod testing;
+pub mod tickets;
+pub mod triggers;
+pub mod validate;
+#[cfg(feature = "process")]
+pub mod verify;
+pub mod view;
+#[cfg(feature = "process")]
+pub mod walk;
+pub mod watch;
 
 // == Std crates
+use std::fmt;
+#[cfg(feature = "spawn")]
 use std::process;
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+// == Internal crates
+use crate::annotations::*;
+use crate::changes::*;
+use crate::error::*;
+use crate::records::*;
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct P4Changelist {
     pub changelist: u32,
     pub time: u32,
     pub user: String,
     pub description: String,
+    // Whether p4 may have cut the description short, see P4Changelist::full_description
+    pub description_truncated: bool,
+    // Submitted unless p4 said otherwise
+    pub status: P4ChangeStatus,
     pub files: Vec<P4File>,
+    // Filled in from the description when the iterator has a DescriptionScanner, empty otherwise
+    pub annotations: P4Annotations,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct P4File {
     pub depot_path: String,
     pub action: String,
-    pub revision: u32,
-    pub file_size: u64,
-    pub digest: [u8; 16],
+    // The revision, size and digest are always there for submitted changelists, but p4 may leave them out for
+    // files open in a pending one
+    pub revision: Option<u32>,
+    pub file_size: Option<u64>,
+    pub digest: Option<[u8; 16]>,
+}
+
+impl P4File {
+    // Whether the file doesn't exist at this revision
+    pub fn is_deleted(&self) -> bool {
+        is_deleted_action(&self.action)
+    }
+}
+
+// The action of a file revision, see `p4 help fileactions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum P4FileAction {
+    Add,
+    Edit,
+    Delete,
+    Branch,
+    Integrate,
+    MoveAdd,
+    MoveDelete,
+    Import,
+    Purge,
+    Archive,
+    Other,
+}
+
+impl P4FileAction {
+    const NAMES: [(P4FileAction, &'static str); 10] = [
+        (P4FileAction::Add, "add"),
+        (P4FileAction::Edit, "edit"),
+        (P4FileAction::Delete, "delete"),
+        (P4FileAction::Branch, "branch"),
+        (P4FileAction::Integrate, "integrate"),
+        (P4FileAction::MoveAdd, "move/add"),
+        (P4FileAction::MoveDelete, "move/delete"),
+        (P4FileAction::Import, "import"),
+        (P4FileAction::Purge, "purge"),
+        (P4FileAction::Archive, "archive"),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(action, _)| action == self)
+            .map_or("other", |(_, name)| name)
+    }
+}
+
+// Unknown actions become Other, use parse() to reject them instead
+impl From<&str> for P4FileAction {
+    fn from(action: &str) -> Self {
+        action.parse().unwrap_or(P4FileAction::Other)
+    }
+}
+
+impl FromStr for P4FileAction {
+    type Err = P4ValueParseError;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| *name == action)
+            .map(|(action, _)| *action)
+            .ok_or_else(|| P4ValueParseError::UnknownFileAction(action.to_string()))
+    }
+}
+
+impl fmt::Display for P4FileAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// How a file's content is to be written out, from the base of its type, see `p4 help filetypes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum P4FileKind {
+    #[default]
+    Regular,
+    // The content is the link target, not a file body
+    Symlink,
+    // A Mac file, the content holds its data and resource forks together
+    Apple,
+    // A Mac resource fork on its own
+    Resource,
+}
+
+impl P4FileKind {
+    // e.g. symlink, apple+C or text+x
+    pub fn from_file_type(file_type: &str) -> Self {
+        match file_type.split('+').next().unwrap_or_default() {
+            "symlink" => P4FileKind::Symlink,
+            "apple" => P4FileKind::Apple,
+            "resource" => P4FileKind::Resource,
+            _ => P4FileKind::Regular,
+        }
+    }
+}
+
+// The same line `p4 changes` prints, with the date in UTC
+impl fmt::Display for P4Changelist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_date(self.time);
+        write!(
+            f,
+            "Change {} on {:04}/{:02}/{:02} by {} '{}'",
+            self.changelist,
+            year,
+            month,
+            day,
+            self.user,
+            self.description.lines().next().unwrap_or_default()
+        )
+    }
+}
+
+// The same line `p4 describe` prints for each file
+impl fmt::Display for P4File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.revision {
+            Some(revision) => write!(f, "{}#{} {}", self.depot_path, revision, self.action),
+            None => write!(f, "{} {}", self.depot_path, self.action),
+        }
+    }
+}
+
+// A string field that keeps its buffer from one record to the next. Finishing a record swaps the buffer with
+// the output's, so reading into the same output over and over stops allocating once the buffers are big enough.
+#[derive(Debug, Default)]
+struct ReusableString {
+    buffer: String,
+    is_set: bool,
+}
+
+impl ReusableString {
+    fn set(&mut self, value: &str) {
+        self.buffer.clear();
+        self.buffer.push_str(value);
+        self.is_set = true;
+    }
+
+    fn swap_into(&mut self, out: &mut String, missing: &'static str) -> Result<(), P4Error> {
+        if !std::mem::take(&mut self.is_set) {
+            return Err(P4Error::InvalidRecord(missing));
+        }
+        std::mem::swap(&mut self.buffer, out);
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default)]
 struct InterimP4Changelist {
     change: Option<u32>,
     time: Option<u32>,
-    user: Option<String>,
-    description: Option<String>,
+    user: ReusableString,
+    description: ReusableString,
+    status: Option<P4ChangeStatus>,
     files: Vec<P4File>,
 }
 
+impl InterimP4Changelist {
+    // Moves the record into `changelist` and resets this one for the next record
+    fn finish_into(&mut self, changelist: &mut P4Changelist) -> Result<(), P4Error> {
+        changelist.changelist = self
+            .change
+            .take()
+            .ok_or(P4Error::InvalidRecord("Missing changelist"))?;
+        changelist.time = self
+            .time
+            .take()
+            .ok_or(P4Error::InvalidRecord("Missing time"))?;
+        self.user.swap_into(&mut changelist.user, "Missing user")?;
+        self.description
+            .swap_into(&mut changelist.description, "Missing description")?;
+        changelist.status = self.status.take().unwrap_or_default();
+        std::mem::swap(&mut self.files, &mut changelist.files);
+        self.files.clear();
+        changelist.description_truncated = false;
+        changelist.annotations.clear();
+        Ok(())
+    }
+}
+
 impl TryInto<P4Changelist> for InterimP4Changelist {
-    type Error = &'static str;
+    type Error = P4Error;
 
-    fn try_into(self) -> Result<P4Changelist, Self::Error> {
-        Ok(P4Changelist {
-            changelist: self.change.ok_or("Missing changelist")?,
-            time: self.time.ok_or("Missing time")?,
-            user: self.user.ok_or("Missing user")?,
-            description: self.description.ok_or("Missing description")?,
-            files: self.files,
-        })
+    fn try_into(mut self) -> Result<P4Changelist, Self::Error> {
+        let mut changelist = P4Changelist::default();
+        self.finish_into(&mut changelist)?;
+        Ok(changelist)
     }
 }
 
 #[derive(Debug, Default)]
 struct InterimP4File {
-    depot_path: Option<String>,
-    action: Option<String>,
+    depot_path: ReusableString,
+    action: ReusableString,
     revision: Option<u32>,
     file_size: Option<u64>,
     digest: Option<[u8; 16]>,
 }
 
+impl InterimP4File {
+    // Moves the record into `file` and resets this one for the next record. The files of pending and shelved
+    // changelists may be missing the revision, size and digest.
+    fn finish_into(&mut self, file: &mut P4File, status: P4ChangeStatus) -> Result<(), P4Error> {
+        self.depot_path
+            .swap_into(&mut file.depot_path, "Missing depot path")?;
+        self.action.swap_into(&mut file.action, "Missing action")?;
+        file.revision = self.revision.take();
+        file.file_size = self.file_size.take();
+        file.digest = self.digest.take();
+        if status != P4ChangeStatus::Submitted {
+            return Ok(());
+        }
+
+        file.revision
+            .ok_or(P4Error::InvalidRecord("Missing revision"))?;
+        file.file_size
+            .ok_or(P4Error::InvalidRecord("Missing file size"))?;
+        file.digest
+            .ok_or(P4Error::InvalidRecord("Missing digest"))?;
+        Ok(())
+    }
+}
+
 impl TryInto<P4File> for InterimP4File {
-    type Error = &'static str;
+    type Error = P4Error;
 
-    fn try_into(self) -> Result<P4File, Self::Error> {
-        Ok(P4File {
-            depot_path: self.depot_path.ok_or("Missing depot path")?,
-            action: self.action.ok_or("Missing action")?,
-            revision: self.revision.ok_or("Missing revision")?,
-            file_size: self.file_size.ok_or("Missing file size")?,
-            digest: self.digest.ok_or("Missing digest")?,
-        })
+    fn try_into(mut self) -> Result<P4File, Self::Error> {
+        let mut file = P4File::default();
+        self.finish_into(&mut file, P4ChangeStatus::Submitted)?;
+        Ok(file)
     }
 }
 
 // == Utility functions
+#[cfg(feature = "process")]
+pub(crate) const P4_EXE: &str = "p4";
+#[cfg(feature = "process")]
+pub(crate) const P4_OUTPUT_ARGS: [&str; 2] = ["-ztag", "-G"];
+
+#[cfg(feature = "spawn")]
 pub fn get_p4_cmd(args: Vec<&str>) -> process::Command {
-    let mut cmd = process::Command::new("p4");
-    cmd.args(["-ztag", "-G"])
+    get_p4_cmd_at(P4_EXE, args)
+}
+
+// Same as get_p4_cmd, for a p4 executable that isn't the one on PATH
+#[cfg(feature = "spawn")]
+pub fn get_p4_cmd_at(p4_exe: impl AsRef<std::ffi::OsStr>, args: Vec<&str>) -> process::Command {
+    let mut cmd = process::Command::new(p4_exe);
+    cmd.args(P4_OUTPUT_ARGS)
         .args(args)
         .stdout(process::Stdio::piped())
         .stderr(process::Stdio::piped())
@@ -80,13 +365,139 @@ pub fn get_p4_cmd(args: Vec<&str>) -> process::Command {
     cmd
 }
 
-fn split_indexed_key(key: &str) -> Option<(&str, u32)> {
-    if let Some(first_num_index) = key.find(char::is_numeric) {
-        Some((
-            &key[..first_num_index],
-            key[first_num_index..].parse::<u32>().unwrap(),
-        ))
+pub(crate) fn is_deleted_action(action: &str) -> bool {
+    matches!(action, "delete" | "move/delete" | "purge" | "archive")
+}
+
+// Whether the server's digest for a file type is of the bytes print and sync produce. Symlinks, utf16 and +k
+// files are stored differently from how they come out.
+#[cfg(feature = "process")]
+pub(crate) fn digest_matches_content(file_type: &str) -> bool {
+    let (base_type, modifiers) = file_type.split_once('+').unwrap_or((file_type, ""));
+    !(base_type == "symlink" || base_type == "utf16" || modifiers.contains('k'))
+}
+
+// The UTC date of a p4 timestamp, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_date(time: u32) -> (u32, u32, u32) {
+    let days = time / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
     } else {
-        None
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u32;
+    (year, month, day)
+}
+
+// The p4 timestamp of midnight UTC on the date, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn civil_time(year: u32, month: u32, day: u32) -> u32 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146097 + day_of_era - 719468) * 86400
+}
+
+fn parse_field<T: FromStr>(value: &str, error: &'static str) -> Result<T, P4Error> {
+    value.parse().map_err(|_| P4Error::InvalidRecord(error))
+}
+
+// The name of an indexed key with its index, and the second index of a double-indexed key
+type IndexedKey<'a> = (&'a str, u32, Option<u32>);
+
+// Splits the index off the end of a key, e.g. depotFile3 into ("depotFile", 3, None), and the double index of
+// resolve and integration fields, e.g. how0,1 into ("how", 0, Some(1)). Digits elsewhere in the key are part of
+// its name, e.g. path2Root. None for keys without an index.
+fn split_indexed_key(key: &str) -> Result<Option<IndexedKey<'_>>, P4Error> {
+    let key = P4IndexedKey::parse(key)?;
+    let index = |index: usize| {
+        u32::try_from(index).map_err(|_| P4Error::InvalidRecord("Invalid index in field name"))
+    };
+    match key.indices[..] {
+        [] => Ok(None),
+        [first] => Ok(Some((key.name, index(first)?, None))),
+        [first, second] => Ok(Some((key.name, index(first)?, Some(index(second)?)))),
+        _ => Err(P4Error::InvalidRecord("Invalid index in field name")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn assert_send<T: Send>() {}
+
+    // So iterators can be moved into worker threads, e.g. one describe per thread
+    #[test]
+    fn test_iterators_are_send() {
+        use crate::{
+            annotate::*, changes::*, depots::*, describe::*, dict::*, dirs::*, filelog::*,
+            files::*, fstat::*, have::*, print::*, property::*, sizes::*, sync::*,
+        };
+
+        assert_send::<P4AnnotateIterator<&[u8]>>();
+        assert_send::<P4ChangesIterator<&[u8]>>();
+        assert_send::<P4DepotsIterator<&[u8]>>();
+        assert_send::<P4DescribeIterator<&[u8]>>();
+        assert_send::<P4DictIterator<&[u8]>>();
+        assert_send::<P4DirsIterator<&[u8]>>();
+        assert_send::<P4FilelogIterator<&[u8]>>();
+        assert_send::<P4FilesIterator<&[u8]>>();
+        assert_send::<P4FstatIterator<&[u8]>>();
+        assert_send::<P4HaveIterator<&[u8]>>();
+        assert_send::<P4PrintIterator<&[u8]>>();
+        assert_send::<P4PropertyIterator<&[u8]>>();
+        assert_send::<P4SizesIterator<&[u8]>>();
+        assert_send::<P4SyncIterator<&[u8]>>();
+
+        #[cfg(feature = "process")]
+        {
+            use crate::{history::*, output::*, walk::*};
+
+            assert_send::<P4AnnotateIterator<P4Output>>();
+            assert_send::<P4ChangesIterator<P4Output>>();
+            assert_send::<P4DepotsIterator<P4Output>>();
+            assert_send::<P4DescribeIterator<P4Output>>();
+            assert_send::<P4DictIterator<P4Output>>();
+            assert_send::<P4DirsIterator<P4Output>>();
+            assert_send::<P4FilelogIterator<P4Output>>();
+            assert_send::<P4FilesIterator<P4Output>>();
+            assert_send::<P4FstatIterator<P4Output>>();
+            assert_send::<P4HaveIterator<P4Output>>();
+            assert_send::<P4PrintIterator<P4Output>>();
+            assert_send::<P4SizesIterator<P4Output>>();
+            assert_send::<P4SyncIterator<P4Output>>();
+            assert_send::<P4ChangelistHandle>();
+            assert_send::<P4DescribeEach<P4Output>>();
+            assert_send::<P4DiffChain>();
+            assert_send::<DepotWalker>();
+            assert_send::<P4HistoryScan>();
+        }
+    }
+
+    #[test]
+    fn test_split_indexed_key() {
+        use super::*;
+
+        assert_eq!(
+            split_indexed_key("depotFile12").unwrap(),
+            Some(("depotFile", 12, None))
+        );
+        assert_eq!(
+            split_indexed_key("how0,1").unwrap(),
+            Some(("how", 0, Some(1)))
+        );
+        assert_eq!(split_indexed_key("path2Root").unwrap(), None);
+        assert_eq!(split_indexed_key("change").unwrap(), None);
+        assert_eq!(split_indexed_key("123").unwrap(), None);
+        assert!(split_indexed_key("rev99999999999").is_err());
+        assert!(split_indexed_key("how,1").is_err());
     }
 }
@@ -0,0 +1,146 @@
+// == Std crates
+use std::{
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+pub type MetricsCallback = Box<dyn Fn(&MetricsSnapshot) + Send + Sync>;
+type SharedMetricsCallback = Arc<dyn Fn(&MetricsSnapshot) + Send + Sync>;
+
+// Counters shared by every command run through a P4Context (and its clones)
+#[derive(Default)]
+pub struct Metrics {
+    commands_run: AtomicU64,
+    processes_spawned: AtomicU64,
+    records_parsed: AtomicU64,
+    bytes_read: AtomicU64,
+    parse_errors: AtomicU64,
+    retries: AtomicU64,
+    // Called with a fresh snapshot every time a command finishes
+    callback: Mutex<Option<SharedMetricsCallback>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub commands_run: u64,
+    pub processes_spawned: u64,
+    pub records_parsed: u64,
+    pub bytes_read: u64,
+    pub parse_errors: u64,
+    pub retries: u64,
+}
+
+// How a single command went, available from its iterator once it is exhausted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct P4IteratorSummary {
+    pub records_yielded: u64,
+    // Warnings that were skipped, e.g. "no such file(s)."
+    pub records_skipped: u64,
+    pub bytes_read: u64,
+    // Only known for commands run through a P4Context
+    pub elapsed: Option<Duration>,
+    // Only known when a p4 process was spawned, and None if it was killed
+    pub exit_code: Option<i32>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Metrics").field(&self.snapshot()).finish()
+    }
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            commands_run: self.commands_run.load(Ordering::Relaxed),
+            processes_spawned: self.processes_spawned.load(Ordering::Relaxed),
+            records_parsed: self.records_parsed.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn set_callback(&self, callback: Option<MetricsCallback>) {
+        *self.callback.lock().unwrap() = callback.map(Arc::from);
+    }
+
+    #[cfg(any(feature = "process", test))]
+    pub(crate) fn add_command(&self) {
+        self.commands_run.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(any(feature = "spawn", test))]
+    pub(crate) fn add_process(&self) {
+        self.processes_spawned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_record(&self) {
+        self.records_parsed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "spawn")]
+    pub(crate) fn add_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn command_finished(&self) {
+        // Called without the lock held, so the callback can run commands or replace itself
+        let callback = self.callback.lock().unwrap().clone();
+        if let Some(callback) = callback {
+            callback(&self.snapshot());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_callback() {
+        let metrics = Metrics::default();
+        let exported = Arc::new(Mutex::new(Vec::new()));
+
+        let exported_clone = exported.clone();
+        metrics.set_callback(Some(Box::new(move |snapshot| {
+            exported_clone.lock().unwrap().push(*snapshot)
+        })));
+
+        metrics.add_command();
+        metrics.add_process();
+        metrics.add_record();
+        metrics.add_record();
+        metrics.add_bytes(100);
+        metrics.command_finished();
+
+        let expected = MetricsSnapshot {
+            commands_run: 1,
+            processes_spawned: 1,
+            records_parsed: 2,
+            bytes_read: 100,
+            ..Default::default()
+        };
+        assert_eq!(metrics.snapshot(), expected);
+        assert_eq!(*exported.lock().unwrap(), vec![expected]);
+
+        // A callback that replaces itself doesn't deadlock
+        let metrics = Arc::new(Metrics::default());
+        let metrics_clone = metrics.clone();
+        metrics.set_callback(Some(Box::new(move |_| metrics_clone.set_callback(None))));
+        metrics.command_finished();
+        assert!(metrics.callback.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,359 @@
+// == Std crates
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// == Internal crates
+use crate::cancel::*;
+
+// Conditions under which we stop waiting for output from the p4 process
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OutputLimits {
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) total_timeout: Option<Duration>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    // Bytes the reader thread may get ahead of the parser before it stops reading from p4
+    pub(crate) high_water_mark: Option<usize>,
+}
+
+// Marker carried inside the io::Error returned when reading is abandoned due to cancellation
+#[derive(Debug, thiserror::Error)]
+#[error("Cancelled while waiting for p4 output")]
+struct OutputCancelled;
+
+pub(crate) fn is_cancelled_io_error(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<OutputCancelled>())
+}
+
+// The stdout of a running p4 process, including any bytes we had to read up front to check for errors
+#[derive(Debug)]
+pub struct P4Output {
+    peeked: io::Cursor<Vec<u8>>,
+    source: OutputSource,
+    // Everything read is also written here when capturing
+    capture: Option<fs::File>,
+    // The thread writing the command's stdin, joined at the end of the output
+    input_writer: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+enum OutputSource {
+    Direct(Box<dyn io::Read + Send>),
+    Watched(WatchedOutput),
+}
+
+impl fmt::Debug for OutputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSource::Direct(_) => f.write_str("Direct"),
+            OutputSource::Watched(watched) => f.debug_tuple("Watched").field(watched).finish(),
+        }
+    }
+}
+
+// Output read on a background thread so we can stop waiting for it when a limit is hit
+#[derive(Debug)]
+struct WatchedOutput {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    current: io::Cursor<Vec<u8>>,
+    idle_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl P4Output {
+    const CHUNK_SIZE: usize = 64 * 1024;
+    pub(crate) const DEFAULT_HIGH_WATER_MARK: usize = 16 * 1024 * 1024;
+
+    pub(crate) fn new<ReadT>(reader: ReadT, limits: OutputLimits) -> Self
+    where
+        ReadT: io::Read + Send + 'static,
+    {
+        let source = if limits.idle_timeout.is_none()
+            && limits.total_timeout.is_none()
+            && limits.cancellation.is_none()
+        {
+            OutputSource::Direct(Box::new(reader))
+        } else {
+            OutputSource::Watched(WatchedOutput {
+                receiver: Self::spawn_reader_thread(
+                    reader,
+                    limits
+                        .high_water_mark
+                        .unwrap_or(Self::DEFAULT_HIGH_WATER_MARK),
+                ),
+                current: io::Cursor::default(),
+                idle_timeout: limits.idle_timeout,
+                deadline: limits.total_timeout.map(|timeout| Instant::now() + timeout),
+                cancellation: limits.cancellation,
+            })
+        };
+
+        P4Output {
+            peeked: io::Cursor::default(),
+            source,
+            capture: None,
+            input_writer: None,
+        }
+    }
+
+    pub(crate) fn set_capture(&mut self, capture: fs::File) {
+        self.capture = Some(capture);
+    }
+
+    #[cfg(any(feature = "spawn", test))]
+    pub(crate) fn set_input_writer(&mut self, input_writer: thread::JoinHandle<io::Result<()>>) {
+        self.input_writer = Some(input_writer);
+    }
+
+    // A write that failed because p4 exited early isn't reported, p4's own error is in the output
+    fn join_input_writer(&mut self) -> io::Result<()> {
+        let Some(input_writer) = self.input_writer.take() else {
+            return Ok(());
+        };
+        match input_writer.join() {
+            Ok(Err(e)) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+            Ok(_) => Ok(()),
+            Err(_) => Err(io::Error::other("Writing the input of p4 panicked")),
+        }
+    }
+
+    // Bytes that were already consumed by the caller but should be read again first
+    pub(crate) fn set_peeked(&mut self, peeked: Vec<u8>) {
+        self.peeked = io::Cursor::new(peeked);
+    }
+
+    // The channel is bounded so a slow consumer blocks the thread, and p4 behind it, instead of
+    // buffering the whole output in memory
+    fn spawn_reader_thread<ReadT>(
+        mut reader: ReadT,
+        high_water_mark: usize,
+    ) -> mpsc::Receiver<io::Result<Vec<u8>>>
+    where
+        ReadT: io::Read + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel((high_water_mark / Self::CHUNK_SIZE).max(1));
+        thread::spawn(move || {
+            loop {
+                let mut chunk = vec![0u8; Self::CHUNK_SIZE];
+                match reader.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        if sender.send(Ok(chunk)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
+                        break;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+}
+
+impl io::Read for P4Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = if (self.peeked.position() as usize) < self.peeked.get_ref().len() {
+            self.peeked.read(buf)?
+        } else {
+            match &mut self.source {
+                OutputSource::Direct(reader) => reader.read(buf)?,
+                OutputSource::Watched(watched) => watched.read(buf)?,
+            }
+        };
+        if read == 0 && !buf.is_empty() {
+            self.join_input_writer()?;
+        }
+
+        if let Some(capture) = self.capture.as_mut() {
+            capture.write_all(&buf[..read])?;
+        }
+
+        Ok(read)
+    }
+}
+
+impl io::Read for WatchedOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let idle_deadline = self.idle_timeout.map(|timeout| Instant::now() + timeout);
+
+        while (self.current.position() as usize) >= self.current.get_ref().len() {
+            let wait = idle_deadline
+                .into_iter()
+                .chain(self.deadline)
+                .min()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+            // Wake up regularly to notice cancellation while the server is quiet
+            let wait = match self.cancellation {
+                Some(_) => Some(wait.map_or(Self::CANCELLATION_POLL, |wait| {
+                    wait.min(Self::CANCELLATION_POLL)
+                })),
+                None => wait,
+            };
+
+            let received = match wait {
+                Some(wait) => match self.receiver.recv_timeout(wait) {
+                    Ok(received) => received,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if self.is_cancelled() {
+                            return Err(io::Error::other(OutputCancelled));
+                        }
+                        let now = Instant::now();
+                        if idle_deadline
+                            .into_iter()
+                            .chain(self.deadline)
+                            .any(|deadline| now >= deadline)
+                        {
+                            return Err(Self::timed_out());
+                        }
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+                },
+                None => match self.receiver.recv() {
+                    Ok(received) => received,
+                    Err(_) => return Ok(0),
+                },
+            };
+
+            self.current = io::Cursor::new(received?);
+        }
+
+        // Don't let a command that keeps on producing output run past its deadline either
+        if self.is_past_deadline() {
+            return Err(Self::timed_out());
+        }
+        if self.is_cancelled() {
+            return Err(io::Error::other(OutputCancelled));
+        }
+
+        self.current.read(buf)
+    }
+}
+
+impl WatchedOutput {
+    const CANCELLATION_POLL: Duration = Duration::from_millis(50);
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn is_past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn timed_out() -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, "Timed out waiting for p4 output")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Read,
+        process,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    #[test]
+    fn test_input_writer_errors() {
+        let read_all = |result: io::Result<()>| {
+            let mut output = P4Output::new(io::Cursor::new(b"output".to_vec()), Default::default());
+            output.set_input_writer(thread::spawn(move || result));
+            let mut read = Vec::new();
+            output.read_to_end(&mut read).map(|_| read)
+        };
+
+        assert_eq!(read_all(Ok(())).unwrap(), b"output");
+        // p4 stopped reading, its own error is in the output
+        let broken_pipe = io::Error::from(io::ErrorKind::BrokenPipe);
+        assert_eq!(read_all(Err(broken_pipe)).unwrap(), b"output");
+        let error = read_all(Err(io::Error::other("Disk failed"))).unwrap_err();
+        assert_eq!(error.to_string(), "Disk failed");
+    }
+
+    #[test]
+    fn test_output_timeouts() {
+        // Output that arrives in time is passed through untouched
+        let data = b"some p4 output".to_vec();
+        let mut output = P4Output::new(
+            io::Cursor::new(data.clone()),
+            OutputLimits {
+                idle_timeout: Some(Duration::from_secs(5)),
+                ..Default::default()
+            },
+        );
+        output.set_peeked(b"peeked ".to_vec());
+        let mut result = Vec::new();
+        output.read_to_end(&mut result).unwrap();
+        assert_eq!(result, b"peeked some p4 output");
+
+        // A process that never writes anything trips the idle timeout
+        let mut child = process::Command::new("sleep")
+            .arg("10")
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut output = P4Output::new(
+            stdout,
+            OutputLimits {
+                idle_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        );
+
+        let start = Instant::now();
+        let error = output.read(&mut [0u8; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_output_backpressure() {
+        struct CountingReader(Arc<AtomicUsize>);
+
+        impl io::Read for CountingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.fetch_add(buf.len(), Ordering::Relaxed);
+                Ok(buf.len())
+            }
+        }
+
+        // Endless output that nobody reads only fills the channel and the chunk being sent
+        let read = Arc::new(AtomicUsize::new(0));
+        let mut output = P4Output::new(
+            CountingReader(read.clone()),
+            OutputLimits {
+                idle_timeout: Some(Duration::from_secs(5)),
+                high_water_mark: Some(2 * P4Output::CHUNK_SIZE),
+                ..Default::default()
+            },
+        );
+        thread::sleep(Duration::from_millis(100));
+        assert!(read.load(Ordering::Relaxed) <= 3 * P4Output::CHUNK_SIZE);
+
+        output.read_exact(&mut [0u8; 16]).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+// == Std crates
+use std::str::Utf8Error;
+
+// == External crates
+#[cfg(feature = "encoding")]
+use encoding_rs::Encoding;
+
+// How values that aren't valid UTF-8 are turned into text, e.g. descriptions submitted from CP1252 or
+// Shift-JIS machines on a non-unicode server. Valid UTF-8 is always passed through untouched.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct P4StringDecoding {
+    #[cfg(feature = "encoding")]
+    encoding: Option<&'static Encoding>,
+    // Replace anything that still can't be decoded with U+FFFD instead of failing
+    lossy: bool,
+}
+
+impl P4StringDecoding {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `label` is a WHATWG encoding label such as "windows-1252" or "shift_jis", returns None if it is unknown
+    #[cfg(feature = "encoding")]
+    pub fn with_encoding(mut self, label: &str) -> Option<Self> {
+        self.encoding = Some(Encoding::for_label(label.as_bytes())?);
+        Some(self)
+    }
+
+    pub fn with_lossy_fallback(mut self) -> Self {
+        self.lossy = true;
+        self
+    }
+
+    // Returns the text of `bytes`, using `buffer` as storage if it had to be converted
+    pub(crate) fn decode<'a>(
+        &self,
+        bytes: &'a [u8],
+        buffer: &'a mut String,
+    ) -> Result<&'a str, Utf8Error> {
+        let error = match std::str::from_utf8(bytes) {
+            Ok(text) => return Ok(text),
+            Err(error) => error,
+        };
+
+        #[cfg(feature = "encoding")]
+        if let Some(encoding) = self.encoding {
+            let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+            if !had_errors || self.lossy {
+                buffer.clear();
+                buffer.push_str(&text);
+                return Ok(buffer);
+            }
+        }
+
+        if self.lossy {
+            buffer.clear();
+            buffer.push_str(&String::from_utf8_lossy(bytes));
+            return Ok(buffer);
+        }
+
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_decoding() {
+        let mut buffer = String::new();
+
+        let strict = P4StringDecoding::new();
+        assert_eq!(strict.decode("café".as_bytes(), &mut buffer), Ok("café"));
+        assert!(strict.decode(b"caf\xe9", &mut buffer).is_err());
+
+        let lossy = P4StringDecoding::new().with_lossy_fallback();
+        assert_eq!(lossy.decode(b"caf\xe9", &mut buffer), Ok("caf\u{fffd}"));
+
+        #[cfg(feature = "encoding")]
+        {
+            let cp1252 = P4StringDecoding::new()
+                .with_encoding("windows-1252")
+                .unwrap();
+            assert_eq!(cp1252.decode(b"caf\xe9", &mut buffer), Ok("café"));
+
+            let shift_jis = P4StringDecoding::new().with_encoding("shift_jis").unwrap();
+            assert_eq!(
+                shift_jis.decode(b"\x93\xfa\x96\x7b", &mut buffer),
+                Ok("日本")
+            );
+            assert!(P4StringDecoding::new().with_encoding("klingon").is_none());
+        }
+    }
+}
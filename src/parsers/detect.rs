@@ -0,0 +1,248 @@
+// == Std crates
+use std::{io, io::Read, marker::PhantomData};
+
+// == Internal crates
+use super::{json::*, py_dict::*, scripting::*, text::*, ztag::*, *};
+use crate::error::*;
+use crate::*;
+
+// == External crates
+
+// How a capture of p4 output was produced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum P4OutputFormat {
+    // -G
+    Marshal,
+    // -ztag
+    Ztag,
+    // -Mj -ztag
+    Json,
+    // -s
+    Scripting,
+    // No options, read as `p4 changes` or `p4 describe` output
+    Text,
+}
+
+// How many bytes are sniffed, enough for the first line of any format
+const SNIFF_LEN: u64 = 512;
+
+// Decides the format from the first bytes of a capture. Empty input is taken to be -G, which reads as no records.
+pub fn detect_format(prefix: &[u8]) -> P4OutputFormat {
+    match prefix {
+        [] => P4OutputFormat::Marshal,
+        // A marshalled dict is followed by a type tag, a JSON object by a key or whitespace
+        [b'{', next, ..] if next.is_ascii_whitespace() || matches!(next, b'"' | b'}') => {
+            P4OutputFormat::Json
+        }
+        [b'{', ..] => P4OutputFormat::Marshal,
+        _ if prefix.starts_with(b"... ") => P4OutputFormat::Ztag,
+        _ if is_scripting_line(prefix) => P4OutputFormat::Scripting,
+        _ => P4OutputFormat::Text,
+    }
+}
+
+fn is_scripting_line(prefix: &[u8]) -> bool {
+    let Some(colon) = prefix.iter().position(|&byte| byte == b':') else {
+        return false;
+    };
+    match &prefix[..colon] {
+        b"info" | b"text" | b"warning" | b"error" | b"exit" => true,
+        level => level
+            .strip_prefix(b"info")
+            .is_some_and(|digits| !digits.is_empty() && digits.iter().all(u8::is_ascii_digit)),
+    }
+}
+
+// Sniffs the start of the capture and returns a key-value stream for its format, so a capture can be read
+// without knowing how it was made. -s and text records are given the keys -G would use.
+pub fn detect<'a, ReadT: io::Read + 'a>(
+    mut reader: ReadT,
+) -> Result<(P4OutputFormat, Box<dyn P4KvpStream<P4Error> + 'a>), io::Error> {
+    let mut prefix = Vec::new();
+    reader.by_ref().take(SNIFF_LEN).read_to_end(&mut prefix)?;
+    let format = detect_format(&prefix);
+    let first_key = first_ztag_key(&prefix);
+    let reader = io::Cursor::new(prefix).chain(reader);
+
+    let stream: Box<dyn P4KvpStream<P4Error> + 'a> = match format {
+        P4OutputFormat::Marshal => Box::new(IntoP4Error::new(P4PyDictParser::new(reader))),
+        // A ztag record starts again when its first key comes round again
+        P4OutputFormat::Ztag => Box::new(IntoP4Error::new(P4ZtagParser::new(
+            reader,
+            first_key.as_deref(),
+        ))),
+        P4OutputFormat::Json => Box::new(IntoP4Error::new(P4JsonParser::new(reader))),
+        P4OutputFormat::Scripting => Box::new(RecordStream::new(
+            P4ScriptingParser::new(reader).map(|record| Ok(scripting_fields(record?))),
+        )),
+        P4OutputFormat::Text => Box::new(RecordStream::new(
+            P4TextChangelistParser::new(reader)
+                .map(|changelist| Ok(changelist_fields(changelist?))),
+        )),
+    };
+    Ok((format, stream))
+}
+
+fn first_ztag_key(prefix: &[u8]) -> Option<String> {
+    let line = prefix.strip_prefix(b"... ")?;
+    let key_end = line.iter().position(|&byte| byte == b' ')?;
+    String::from_utf8(line[..key_end].to_vec()).ok()
+}
+
+// The same shape as the info and error dicts of -G
+fn scripting_fields(record: P4ScriptingRecord) -> Vec<(String, String)> {
+    let fields = match record {
+        P4ScriptingRecord::Message {
+            severity,
+            level,
+            text,
+        } => {
+            let code = if severity >= E_WARN { "error" } else { "info" };
+            vec![
+                ("code", code.to_string()),
+                ("data", text),
+                ("severity", severity.to_string()),
+                ("level", level.to_string()),
+            ]
+        }
+        P4ScriptingRecord::Text(text) => vec![("code", "text".to_string()), ("data", text)],
+        P4ScriptingRecord::Exit(exit_code) => {
+            vec![
+                ("code", "exit".to_string()),
+                ("data", exit_code.to_string()),
+            ]
+        }
+    };
+    fields
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+}
+
+// The keys of `p4 -G describe`
+fn changelist_fields(changelist: P4Changelist) -> Vec<(String, String)> {
+    let mut fields = vec![
+        ("code".to_string(), "stat".to_string()),
+        ("change".to_string(), changelist.changelist.to_string()),
+        ("time".to_string(), changelist.time.to_string()),
+        ("user".to_string(), changelist.user),
+        ("desc".to_string(), changelist.description),
+    ];
+    for (index, file) in changelist.files.into_iter().enumerate() {
+        fields.extend([
+            (format!("depotFile{}", index), file.depot_path),
+            (format!("action{}", index), file.action),
+        ]);
+        if let Some(revision) = file.revision {
+            fields.push((format!("rev{}", index), revision.to_string()));
+        }
+    }
+    fields
+}
+
+struct IntoP4Error<StreamT, ErrorT> {
+    stream: StreamT,
+    error: PhantomData<ErrorT>,
+}
+
+impl<StreamT, ErrorT> IntoP4Error<StreamT, ErrorT> {
+    fn new(stream: StreamT) -> Self {
+        IntoP4Error {
+            stream,
+            error: PhantomData,
+        }
+    }
+}
+
+impl<StreamT, ErrorT> P4KvpStream<P4Error> for IntoP4Error<StreamT, ErrorT>
+where
+    StreamT: P4KvpStream<ErrorT>,
+    ErrorT: std::error::Error + Into<P4Error>,
+{
+    fn get_next_kvp<'b>(&'b mut self) -> Result<Option<P4KeyValuePair<'b>>, P4Error> {
+        self.stream.get_next_kvp().map_err(Into::into)
+    }
+}
+
+struct RecordStream<RecordsT> {
+    records: RecordsT,
+    cursor: OwnedRecordCursor,
+}
+
+impl<RecordsT> RecordStream<RecordsT> {
+    fn new(records: RecordsT) -> Self {
+        RecordStream {
+            records,
+            cursor: OwnedRecordCursor::default(),
+        }
+    }
+}
+
+impl<RecordsT> P4KvpStream<P4Error> for RecordStream<RecordsT>
+where
+    RecordsT: Iterator<Item = Result<Vec<(String, String)>, P4Error>>,
+{
+    fn get_next_kvp<'b>(&'b mut self) -> Result<Option<P4KeyValuePair<'b>>, P4Error> {
+        while self.cursor.is_exhausted() {
+            match self.records.next().transpose()? {
+                Some(fields) => self.cursor.start_record(fields),
+                None => return Ok(None),
+            }
+        }
+        Ok(self.cursor.next_kvp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_detect() {
+        let marshal = to_py_dict_bytes(&[&[("code", "stat"), ("change", "12")]]);
+        let captures: [(&[u8], P4OutputFormat, &str); 6] = [
+            (&marshal, P4OutputFormat::Marshal, "code=stat change=12"),
+            (
+                b"... change 12\n... user alice\n\n... change 11\n",
+                P4OutputFormat::Ztag,
+                "change=12 user=alice | change=11",
+            ),
+            (
+                b"{\"change\":\"12\"}\n{\"change\":\"11\"}\n",
+                P4OutputFormat::Json,
+                "change=12 | change=11",
+            ),
+            (
+                b"info: //depot/a.txt#1 - added\nexit: 0\n",
+                P4OutputFormat::Scripting,
+                "code=info data=//depot/a.txt#1 - added severity=1 level=0 | code=exit data=0",
+            ),
+            (
+                b"Change 12 on 2024/01/31 by alice@ws 'Fix'\n",
+                P4OutputFormat::Text,
+                "code=stat change=12 time=1706659200 user=alice desc=Fix",
+            ),
+            (b"", P4OutputFormat::Marshal, ""),
+        ];
+
+        for (capture, expected_format, expected_records) in captures {
+            let (format, mut stream) = detect(capture).unwrap();
+            assert_eq!(format, expected_format);
+
+            let mut records = String::new();
+            let mut previous_dict_index = None;
+            while let Some(kvp) = stream.get_next_kvp().unwrap() {
+                if previous_dict_index.is_some_and(|index| index != kvp.dict_index) {
+                    records.push_str(" |");
+                }
+                if !records.is_empty() {
+                    records.push(' ');
+                }
+                records.push_str(&format!("{}={}", kvp.key, kvp.value));
+                previous_dict_index = Some(kvp.dict_index);
+            }
+            assert_eq!(records, expected_records, "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+// == Std crates
+use std::{io, io::BufRead};
+
+// == Internal crates
+use super::*;
+
+// == External crates
+
+// Reads the output of `p4 -Mj -ztag`, one flat JSON object per line. Values are kept as the text p4 sent, so
+// numbers, true, false and null come through as they are written.
+#[derive(Debug)]
+pub struct P4JsonParser<ReadT: io::Read> {
+    buffered_reader: io::BufReader<ReadT>,
+    line_buffer: String,
+    cursor: OwnedRecordCursor,
+}
+
+impl<ReadT: io::Read> P4KvpStream<io::Error> for P4JsonParser<ReadT> {
+    fn get_next_kvp<'b>(&'b mut self) -> Result<Option<P4KeyValuePair<'b>>, io::Error> {
+        self.get_next_kvp()
+    }
+}
+
+impl<ReadT: io::Read> P4JsonParser<ReadT> {
+    pub fn new(reader: ReadT) -> Self {
+        P4JsonParser {
+            buffered_reader: io::BufReader::new(reader),
+            line_buffer: String::default(),
+            cursor: OwnedRecordCursor::default(),
+        }
+    }
+
+    pub fn get_next_kvp<'b>(&'b mut self) -> Result<Option<P4KeyValuePair<'b>>, io::Error> {
+        while self.cursor.is_exhausted() {
+            self.line_buffer.clear();
+            if self.buffered_reader.read_line(&mut self.line_buffer)? == 0 {
+                return Ok(None);
+            }
+            if self.line_buffer.trim().is_empty() {
+                continue;
+            }
+            let fields = parse_object(&self.line_buffer)?;
+            self.cursor.start_record(fields);
+        }
+        Ok(self.cursor.next_kvp())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse_object(line: &str) -> Result<Vec<(String, String)>, io::Error> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = Vec::new();
+    if chars.next() != Some('{') {
+        return Err(invalid("Expected a JSON object"));
+    }
+
+    loop {
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some('}') if fields.is_empty() => break,
+            Some('"') => {}
+            _ => return Err(invalid("Expected a JSON key")),
+        }
+        let key = parse_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(invalid("Expected ':' after a JSON key"));
+        }
+        skip_whitespace(&mut chars);
+        let value = match chars.peek() {
+            Some('"') => {
+                chars.next();
+                parse_string(&mut chars)?
+            }
+            Some('{' | '[') => return Err(invalid("Nested JSON values aren't supported")),
+            _ => {
+                let mut literal = String::new();
+                while let Some(c) = chars.next_if(|c| !matches!(c, ',' | '}') && !c.is_whitespace())
+                {
+                    literal.push(c);
+                }
+                literal
+            }
+        };
+        fields.push((key, value));
+
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => {}
+            Some('}') => break,
+            _ => return Err(invalid("Expected ',' or '}' in a JSON object")),
+        }
+    }
+
+    if chars.next().is_some() {
+        return Err(invalid("Trailing characters after a JSON object"));
+    }
+    Ok(fields)
+}
+
+fn skip_whitespace(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+// Reads up to and including the closing quote, the opening one has been consumed
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, io::Error> {
+    let mut result = String::new();
+    loop {
+        match chars
+            .next()
+            .ok_or_else(|| invalid("Unterminated JSON string"))?
+        {
+            '"' => return Ok(result),
+            '\\' => match chars.next() {
+                Some('"') => result.push('"'),
+                Some('\\') => result.push('\\'),
+                Some('/') => result.push('/'),
+                Some('b') => result.push('\u{8}'),
+                Some('f') => result.push('\u{c}'),
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('u') => {
+                    let high = parse_hex4(chars)?;
+                    let code = if (0xd800..0xdc00).contains(&high) {
+                        // A surrogate pair
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err(invalid("Unpaired surrogate in a JSON string"));
+                        }
+                        let low = parse_hex4(chars)?;
+                        if !(0xdc00..0xe000).contains(&low) {
+                            return Err(invalid("Unpaired surrogate in a JSON string"));
+                        }
+                        0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                    } else {
+                        high
+                    };
+                    result.push(
+                        char::from_u32(code)
+                            .ok_or_else(|| invalid("Invalid escape in a JSON string"))?,
+                    );
+                }
+                _ => return Err(invalid("Invalid escape in a JSON string")),
+            },
+            c => result.push(c),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<u32, io::Error> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let digit = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or_else(|| invalid("Invalid escape in a JSON string"))?;
+        code = code * 16 + digit;
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_parsing() {
+        let data = "\
+            {\"change\":\"12\",\"desc\":\"Fix \\\"it\\\"\\n\\u00e9\\ud83d\\ude00\",\"time\":1706659200}\n\
+            \n\
+            { \"change\" : \"11\" }\n";
+
+        let mut parser = P4JsonParser::new(data.as_bytes());
+        let mut pairs = Vec::new();
+        while let Some(kvp) = parser.get_next_kvp().unwrap() {
+            pairs.push((kvp.dict_index, kvp.key.to_string(), kvp.value.to_string()));
+        }
+        assert_eq!(
+            pairs,
+            [
+                (0, "change".into(), "12".into()),
+                (0, "desc".into(), "Fix \"it\"\né😀".into()),
+                (0, "time".into(), "1706659200".into()),
+                (1, "change".into(), "11".into()),
+            ]
+        );
+
+        let mut parser = P4JsonParser::new("{\"files\":[\"a\"]}\n".as_bytes());
+        assert!(parser.get_next_kvp().is_err());
+
+        // A high surrogate followed by anything but a low one, and a low one on its own
+        for escapes in ["\\ud83d\\u0041", "\\ud83d\\ud83d", "\\ude00"] {
+            let data = format!("{{\"desc\":\"{}\"}}\n", escapes);
+            let mut parser = P4JsonParser::new(data.as_bytes());
+            assert!(parser.get_next_kvp().is_err());
+        }
+    }
+}
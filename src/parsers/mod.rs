@@ -1,6 +1,15 @@
+pub mod decode;
+mod detect;
+pub mod json;
 pub mod py_dict;
+pub mod scripting;
+pub mod serverlog;
+pub mod spec;
+pub mod text;
 pub mod ztag;
 
+pub use detect::{P4OutputFormat, detect, detect_format};
+
 #[derive(Debug, PartialEq)]
 pub struct P4KeyValuePair<'a> {
     pub dict_index: u32,
@@ -8,10 +17,48 @@ pub struct P4KeyValuePair<'a> {
     pub value: &'a str,
 }
 
+// A key-value pair whose value hasn't been decoded, e.g. file content from `p4 print`
+#[derive(Debug, PartialEq)]
+pub struct P4RawKeyValuePair<'a> {
+    pub dict_index: u32,
+    pub key: &'a str,
+    pub value: &'a [u8],
+}
+
 pub trait P4KvpStream<ErrorT: std::error::Error> {
     fn get_next_kvp<'b>(&'b mut self) -> Result<Option<P4KeyValuePair<'b>>, ErrorT>;
 }
 
+// Hands out the pairs of whole records one at a time, for formats that are decoded a record at a time
+#[derive(Debug, Default)]
+pub(crate) struct OwnedRecordCursor {
+    fields: Vec<(String, String)>,
+    position: usize,
+    dict_index: Option<u32>,
+}
+
+impl OwnedRecordCursor {
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.position >= self.fields.len()
+    }
+
+    pub(crate) fn start_record(&mut self, fields: Vec<(String, String)>) {
+        self.fields = fields;
+        self.position = 0;
+        self.dict_index = Some(self.dict_index.map_or(0, |index| index + 1));
+    }
+
+    pub(crate) fn next_kvp(&mut self) -> Option<P4KeyValuePair<'_>> {
+        let (key, value) = self.fields.get(self.position)?;
+        self.position += 1;
+        Some(P4KeyValuePair {
+            dict_index: self.dict_index.unwrap_or(0),
+            key,
+            value,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{py_dict::*, ztag::*};
@@ -1,8 +1,9 @@
 // == Std crates
-use std::io;
+use std::{collections::HashSet, io, sync::Arc};
 
 // == Internal crates
-use super::*;
+use super::{decode::*, *};
+use crate::budget::*;
 
 // == External crates
 use thiserror::Error;
@@ -44,9 +45,30 @@ impl PyDictTag {
 pub enum P4PyDictParseError {
     UnexpectedEof,
     InvalidTag { tag: u8 },
+    // A key or value that isn't valid UTF-8, e.g. from a unicode server queried without a charset
+    InvalidUtf8(std::str::Utf8Error),
+    // A key or value that would take the context past its memory budget
+    BudgetExceeded(P4BudgetExceeded),
+    // The same key twice in one dict, with P4DuplicateKeys::Error
+    DuplicateKey { dict_index: u32, key: String },
     Io(io::Error),
 }
 
+// What to do when a dict has the same key twice, which p4 never sends but corrupt captures and
+// buggy triggers can. Anything but KeepLast remembers every key of the current dict, which for a
+// describe of a huge changelist means memory proportional to its files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P4DuplicateKeys {
+    // Yield both, so the record layer ends up with the last value
+    #[default]
+    KeepLast,
+    // Skip the repeats
+    KeepFirst,
+    // Same as KeepLast, logging each repeat with the tracing feature
+    Warn,
+    Error,
+}
+
 pub struct P4PyDictParser<ReadT: io::Read> {
     reader: ReadT,
     state: PyDictParseState,
@@ -54,6 +76,17 @@ pub struct P4PyDictParser<ReadT: io::Read> {
     // Owned buffers we can re-use so we can just return references to kvps as they stream in
     current_key_buffer: Vec<u8>,
     current_value_buffer: Vec<u8>,
+    decoding: P4StringDecoding,
+    // Holds the value if it had to be converted to UTF-8
+    decoded_value_buffer: String,
+    // What the key and value buffers hold of the memory budget, if there is one
+    key_reservation: BudgetReservation,
+    value_reservation: BudgetReservation,
+    bytes_read: u64,
+    duplicate_keys: P4DuplicateKeys,
+    // The keys of the current dict, only tracked when duplicates aren't simply passed on
+    seen_keys: HashSet<Vec<u8>>,
+    duplicate_keys_seen: u64,
 }
 
 impl<ReadT: io::Read> P4KvpStream<P4PyDictParseError> for P4PyDictParser<ReadT> {
@@ -70,20 +103,80 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
             current_dict_index: None,
             current_key_buffer: Vec::with_capacity(1024),
             current_value_buffer: Vec::with_capacity(1024),
+            decoding: P4StringDecoding::default(),
+            decoded_value_buffer: String::new(),
+            key_reservation: BudgetReservation::default(),
+            value_reservation: BudgetReservation::default(),
+            bytes_read: 0,
+            duplicate_keys: P4DuplicateKeys::default(),
+            seen_keys: HashSet::new(),
+            duplicate_keys_seen: 0,
         }
     }
 
+    pub fn with_decoding(mut self, decoding: P4StringDecoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
+
+    // Keys and values are checked against the budget before they are read into memory
+    pub fn with_memory_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        self.key_reservation =
+            BudgetReservation::new(Some(budget.clone()), P4BudgetResource::BufferedBytes);
+        self.value_reservation =
+            BudgetReservation::new(Some(budget), P4BudgetResource::BufferedBytes);
+        self
+    }
+
+    pub fn with_duplicate_keys(mut self, duplicate_keys: P4DuplicateKeys) -> Self {
+        self.duplicate_keys = duplicate_keys;
+        self
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    // Repeated keys found so far, always 0 with P4DuplicateKeys::KeepLast as they aren't looked for
+    pub fn duplicate_keys_seen(&self) -> u64 {
+        self.duplicate_keys_seen
+    }
+
     pub fn get_next_kvp<'b>(
         &'b mut self,
     ) -> Result<Option<P4KeyValuePair<'b>>, P4PyDictParseError> {
         // Loop until we find a key-value pair
         while self.state != PyDictParseState::Eof {
-            if self.advance()? {
+            if self.advance()? && self.check_duplicate_key()? {
                 // We have a kvp, yield it
                 let kvp = P4KeyValuePair {
                     dict_index: self.current_dict_index.unwrap(),
-                    key: std::str::from_utf8(&self.current_key_buffer).unwrap(),
-                    value: std::str::from_utf8(&self.current_value_buffer).unwrap(),
+                    key: std::str::from_utf8(&self.current_key_buffer)
+                        .map_err(P4PyDictParseError::InvalidUtf8)?,
+                    value: self
+                        .decoding
+                        .decode(&self.current_value_buffer, &mut self.decoded_value_buffer)
+                        .map_err(P4PyDictParseError::InvalidUtf8)?,
+                };
+
+                return Ok(Some(kvp));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Same as get_next_kvp, without decoding the value
+    pub fn get_next_raw_kvp<'b>(
+        &'b mut self,
+    ) -> Result<Option<P4RawKeyValuePair<'b>>, P4PyDictParseError> {
+        while self.state != PyDictParseState::Eof {
+            if self.advance()? && self.check_duplicate_key()? {
+                let kvp = P4RawKeyValuePair {
+                    dict_index: self.current_dict_index.unwrap(),
+                    key: std::str::from_utf8(&self.current_key_buffer)
+                        .map_err(P4PyDictParseError::InvalidUtf8)?,
+                    value: &self.current_value_buffer,
                 };
 
                 return Ok(Some(kvp));
@@ -93,6 +186,34 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
         Ok(None)
     }
 
+    // Returns false if the key-value pair just read should be skipped
+    fn check_duplicate_key(&mut self) -> Result<bool, P4PyDictParseError> {
+        if self.duplicate_keys == P4DuplicateKeys::KeepLast
+            || !self.seen_keys.contains(&self.current_key_buffer)
+        {
+            if self.duplicate_keys != P4DuplicateKeys::KeepLast {
+                self.seen_keys.insert(self.current_key_buffer.clone());
+            }
+            return Ok(true);
+        }
+
+        self.duplicate_keys_seen += 1;
+        let dict_index = self.current_dict_index.unwrap();
+        let key = String::from_utf8_lossy(&self.current_key_buffer);
+        match self.duplicate_keys {
+            P4DuplicateKeys::KeepFirst => Ok(false),
+            P4DuplicateKeys::Error => Err(P4PyDictParseError::DuplicateKey {
+                dict_index,
+                key: key.into_owned(),
+            }),
+            _ => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(dict_index, key = %key, "duplicate key in p4 output");
+                Ok(true)
+            }
+        }
+    }
+
     fn advance(&mut self) -> Result<bool, P4PyDictParseError> {
         let mut should_yield = false;
         self.state = match self.state {
@@ -100,11 +221,9 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
                 // We can have a dict or nothing in the root state
                 match self.expect_tags(&[PyDictTag::Dict, PyDictTag::Eof])? {
                     PyDictTag::Dict => {
-                        if self.current_dict_index.is_none() {
-                            self.current_dict_index = Some(0);
-                        } else {
-                            *self.current_dict_index.as_mut().unwrap() += 1;
-                        }
+                        self.current_dict_index =
+                            Some(self.current_dict_index.map_or(0, |index| index + 1));
+                        self.seen_keys.clear();
 
                         PyDictParseState::Dict
                     }
@@ -125,7 +244,11 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
             }
             PyDictParseState::Key => {
                 // Extract the string
-                Self::read_string(&mut self.reader, &mut self.current_key_buffer)?;
+                self.bytes_read += Self::read_string(
+                    &mut self.reader,
+                    &mut self.current_key_buffer,
+                    &mut self.key_reservation,
+                )?;
 
                 // Single variant, no need to check, the ? operator will bubble up a bad tag
                 self.expect_tags(&[PyDictTag::String])?;
@@ -133,7 +256,11 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
             }
             PyDictParseState::Value => {
                 // Extract the string
-                Self::read_string(&mut self.reader, &mut self.current_value_buffer)?;
+                self.bytes_read += Self::read_string(
+                    &mut self.reader,
+                    &mut self.current_value_buffer,
+                    &mut self.value_reservation,
+                )?;
 
                 // Yield the KVP
                 should_yield = true;
@@ -156,13 +283,14 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
         let mut type_buffer = [0u8; 1];
         match self.reader.read_exact(&mut type_buffer) {
             Ok(_) => {
+                self.bytes_read += 1;
                 let found_tag = PyDictTag::from_byte(type_buffer[0]);
-                if tags.iter().any(|&tag| tag == found_tag) {
-                    return Ok(found_tag);
+                if tags.contains(&found_tag) {
+                    Ok(found_tag)
                 } else {
-                    return Err(P4PyDictParseError::InvalidTag {
+                    Err(P4PyDictParseError::InvalidTag {
                         tag: type_buffer[0],
-                    });
+                    })
                 }
             }
             Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(PyDictTag::Eof),
@@ -171,7 +299,12 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
     }
 
     // We receive the string with the reader already past the 's' tag at the beginning, so are expecting '<LEN:u32_le>[u8;LEN]'
-    fn read_string(reader: &mut ReadT, buffer: &mut Vec<u8>) -> Result<(), P4PyDictParseError> {
+    // Returns the number of bytes consumed
+    fn read_string(
+        reader: &mut ReadT,
+        buffer: &mut Vec<u8>,
+        reservation: &mut BudgetReservation,
+    ) -> Result<u64, P4PyDictParseError> {
         buffer.clear();
 
         let mut len_buffer = [0u8; 4];
@@ -185,10 +318,13 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
         }?;
 
         // Read the string
+        reservation
+            .resize(len as u64)
+            .map_err(P4PyDictParseError::BudgetExceeded)?;
         buffer.resize(len as usize, 0);
 
         match reader.read_exact(&mut buffer[..]) {
-            Ok(_) => Ok(()),
+            Ok(_) => Ok(4 + len as u64),
             Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
                 Err(P4PyDictParseError::UnexpectedEof)
             }
@@ -197,6 +333,39 @@ impl<ReadT: io::Read> P4PyDictParser<ReadT> {
     }
 }
 
+// Writes a record in the marshal format p4 -G produces (and accepts on stdin with -i)
+pub fn write_py_dict<WriteT, KeyT, ValueT>(
+    writer: &mut WriteT,
+    record: impl IntoIterator<Item = (KeyT, ValueT)>,
+) -> io::Result<()>
+where
+    WriteT: io::Write,
+    KeyT: AsRef<[u8]>,
+    ValueT: AsRef<[u8]>,
+{
+    writer.write_all(b"{")?;
+    for (key, value) in record {
+        for s in [key.as_ref(), value.as_ref()] {
+            let len = u32::try_from(s.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "String too long"))?;
+            writer.write_all(b"s")?;
+            writer.write_all(&len.to_le_bytes())?;
+            writer.write_all(s)?;
+        }
+    }
+    writer.write_all(b"0")
+}
+
+// Encodes records the same way p4 -G does, for building test inputs inline
+#[cfg(test)]
+pub(crate) fn to_py_dict_bytes(records: &[&[(&str, &str)]]) -> Vec<u8> {
+    let mut result = Vec::new();
+    for record in records {
+        write_py_dict(&mut result, record.iter().copied()).unwrap();
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -219,5 +388,58 @@ mod tests {
         }
 
         assert_eq!(num_records, 8);
+
+        // Undecodable strings are reported rather than panicking
+        let mut data = Vec::new();
+        write_py_dict(&mut data, [(&b"desc"[..], &b"caf\xe9"[..])]).unwrap();
+        let mut parser = P4PyDictParser::new(&data[..]);
+        assert!(matches!(
+            parser.get_next_kvp(),
+            Err(P4PyDictParseError::InvalidUtf8(_))
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let data = to_py_dict_bytes(&[
+            &[("change", "1"), ("user", "alice"), ("change", "2")],
+            &[("change", "3")],
+        ]);
+        let values = |duplicate_keys| {
+            let mut parser = P4PyDictParser::new(&data[..]).with_duplicate_keys(duplicate_keys);
+            let mut values = Vec::new();
+            while let Some(kvp) = parser.get_next_kvp()? {
+                values.push(format!("{}:{}={}", kvp.dict_index, kvp.key, kvp.value));
+            }
+            Ok::<_, P4PyDictParseError>((values, parser.duplicate_keys_seen()))
+        };
+
+        let keep_last = (
+            vec!["0:change=1", "0:user=alice", "0:change=2", "1:change=3"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+            0,
+        );
+        assert_eq!(values(P4DuplicateKeys::KeepLast).unwrap(), keep_last);
+        assert_eq!(
+            values(P4DuplicateKeys::Warn).unwrap(),
+            (keep_last.0.clone(), 1)
+        );
+        assert_eq!(
+            values(P4DuplicateKeys::KeepFirst).unwrap(),
+            (
+                vec![
+                    "0:change=1".into(),
+                    "0:user=alice".into(),
+                    "1:change=3".into()
+                ],
+                1
+            )
+        );
+        assert!(matches!(
+            values(P4DuplicateKeys::Error),
+            Err(P4PyDictParseError::DuplicateKey { dict_index: 0, ref key }) if key == "change"
+        ));
     }
 }
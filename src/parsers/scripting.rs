@@ -0,0 +1,147 @@
+// == Std crates
+use std::{io, io::BufRead};
+
+// == Internal crates
+use crate::error::{E_FAILED, E_INFO, E_WARN};
+
+// == External crates
+
+// One line of `p4 -s` output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum P4ScriptingRecord {
+    // info:, info1:, warning: and error: lines. `level` is the indent of infoN lines, 0 otherwise.
+    Message {
+        severity: u32,
+        level: u32,
+        text: String,
+    },
+    // text: lines, e.g. file content from `p4 -s print`
+    Text(String),
+    // The exit: line p4 ends with
+    Exit(i32),
+}
+
+impl P4ScriptingRecord {
+    pub fn severity(&self) -> u32 {
+        match self {
+            P4ScriptingRecord::Message { severity, .. } => *severity,
+            P4ScriptingRecord::Text(_) => E_INFO,
+            P4ScriptingRecord::Exit(0) => E_INFO,
+            P4ScriptingRecord::Exit(_) => E_FAILED,
+        }
+    }
+}
+
+// Reads the prefixed text of `p4 -s`, for output captured by tools that don't use -G. Each line becomes one
+// record, so a multi-line error is several error records in a row.
+#[derive(Debug)]
+pub struct P4ScriptingParser<ReadT: io::Read> {
+    buffered_reader: io::BufReader<ReadT>,
+    line_buffer: String,
+    exit_code: Option<i32>,
+}
+
+impl<ReadT: io::Read> P4ScriptingParser<ReadT> {
+    pub fn new(reader: ReadT) -> Self {
+        P4ScriptingParser {
+            buffered_reader: io::BufReader::new(reader),
+            line_buffer: String::default(),
+            exit_code: None,
+        }
+    }
+
+    // None until the exit: line has been read, output cut short never has one
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn get_next_record(&mut self) -> Result<Option<P4ScriptingRecord>, io::Error> {
+        self.line_buffer.clear();
+        if self.buffered_reader.read_line(&mut self.line_buffer)? == 0 {
+            return Ok(None);
+        }
+
+        let line = self
+            .line_buffer
+            .strip_suffix('\n')
+            .unwrap_or(&self.line_buffer);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (prefix, rest) = line.split_once(':').ok_or_else(|| invalid_line(line))?;
+        // p4 separates the prefix from the text with a single space, anything after that is part of the text
+        let text = rest.strip_prefix(' ').unwrap_or(rest).to_string();
+
+        let message = |severity, level| P4ScriptingRecord::Message {
+            severity,
+            level,
+            text: text.clone(),
+        };
+        let record = match prefix {
+            "info" => message(E_INFO, 0),
+            "warning" => message(E_WARN, 0),
+            "error" => message(E_FAILED, 0),
+            "text" => P4ScriptingRecord::Text(text),
+            "exit" => {
+                let code = text.trim().parse().map_err(|_| invalid_line(line))?;
+                self.exit_code = Some(code);
+                P4ScriptingRecord::Exit(code)
+            }
+            _ => match prefix.strip_prefix("info").map(str::parse) {
+                Some(Ok(level)) => message(E_INFO, level),
+                _ => return Err(invalid_line(line)),
+            },
+        };
+        Ok(Some(record))
+    }
+}
+
+impl<ReadT: io::Read> Iterator for P4ScriptingParser<ReadT> {
+    type Item = Result<P4ScriptingRecord, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.get_next_record().transpose()
+    }
+}
+
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Not a p4 -s line: {}", line),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripting_parsing() {
+        let data = "\
+            info: //depot/a.txt#3 - edit change 12 (text)\n\
+            info1: a.txt: 3 lines\n\
+            text: some: content\r\n\
+            warning: //depot/b.txt - file(s) up-to-date.\n\
+            error: //depot/c.txt - no such file(s).\n\
+            exit: 1\n";
+
+        let mut parser = P4ScriptingParser::new(data.as_bytes());
+        let records = parser.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 6);
+        assert_eq!(
+            records[1],
+            P4ScriptingRecord::Message {
+                severity: E_INFO,
+                level: 1,
+                text: "a.txt: 3 lines".to_string()
+            }
+        );
+        assert_eq!(records[2], P4ScriptingRecord::Text("some: content".into()));
+        assert_eq!(records[3].severity(), E_WARN);
+        assert_eq!(records[4].severity(), E_FAILED);
+        assert_eq!(records[5], P4ScriptingRecord::Exit(1));
+        assert_eq!(parser.exit_code(), Some(1));
+
+        let mut parser = P4ScriptingParser::new("... change 12\n".as_bytes());
+        assert!(parser.next().unwrap().is_err());
+        assert_eq!(parser.exit_code(), None);
+    }
+}
@@ -0,0 +1,252 @@
+// == Std crates
+use std::{io, io::BufRead};
+
+// == Internal crates
+use crate::error::{E_FAILED, E_FATAL};
+
+// The event type, the first field of each line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum P4ServerLogEventKind {
+    CommandStart,
+    // The command is done with the db and is sending results
+    ComputeEnd,
+    CommandEnd,
+    // In errors.csv, for E_FAILED and E_FATAL errors
+    Error,
+    Other(u32),
+}
+
+impl From<u32> for P4ServerLogEventKind {
+    fn from(event_type: u32) -> Self {
+        match event_type {
+            0 => P4ServerLogEventKind::CommandStart,
+            1 => P4ServerLogEventKind::ComputeEnd,
+            2 => P4ServerLogEventKind::CommandEnd,
+            3 | 4 => P4ServerLogEventKind::Error,
+            other => P4ServerLogEventKind::Other(other),
+        }
+    }
+}
+
+// The fields of an error event after the common ones
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4ServerLogError {
+    pub severity: u32,
+    pub subsystem: u32,
+    pub subcode: u32,
+    pub text: String,
+}
+
+// One line of a structured server log such as commands.csv or errors.csv. Every event starts with the same
+// fields, which identify the command it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4ServerLogEvent {
+    pub kind: P4ServerLogEventKind,
+    // Seconds since the epoch
+    pub timestamp: u32,
+    // The fraction of the second, in nanoseconds
+    pub timestamp_nanos: u32,
+    // e.g. 2025/04/07 12:00:00 523000000, in the server's time zone
+    pub date: String,
+    pub pid: u32,
+    // Ties the events of one command together, with pid and command_number
+    pub command_id: String,
+    pub server_id: String,
+    pub command_number: u64,
+    pub user: String,
+    pub client: String,
+    // e.g. user-sync
+    pub function: String,
+    pub host: String,
+    // The client program, e.g. p4/2024.1/LINUX26X86_64/2596294
+    pub program: String,
+    pub version: String,
+    pub args: String,
+    // Only for error events
+    pub error: Option<P4ServerLogError>,
+    // The fields after the common ones that aren't parsed, e.g. the usage figures of a command end event
+    pub extra: Vec<String>,
+}
+
+impl P4ServerLogEvent {
+    // The p4 command, e.g. sync for user-sync, to match against P4Context metrics and command logs
+    pub fn command(&self) -> &str {
+        self.function
+            .strip_prefix("user-")
+            .unwrap_or(&self.function)
+    }
+
+    pub fn is_failure(&self) -> bool {
+        self.error
+            .as_ref()
+            .is_some_and(|error| error.severity >= E_FAILED && error.severity <= E_FATAL)
+    }
+}
+
+// Reads the csv lines of a structured log. Quoted fields use "" for a quote and may span lines.
+#[derive(Debug)]
+pub struct P4ServerLogParser<ReadT: io::Read> {
+    buffered_reader: io::BufReader<ReadT>,
+    line_buffer: String,
+}
+
+impl<ReadT: io::Read> P4ServerLogParser<ReadT> {
+    // The fields every event starts with
+    const COMMON_FIELDS: usize = 15;
+
+    pub fn new(reader: ReadT) -> Self {
+        P4ServerLogParser {
+            buffered_reader: io::BufReader::new(reader),
+            line_buffer: String::default(),
+        }
+    }
+
+    pub fn get_next_event(&mut self) -> Result<Option<P4ServerLogEvent>, io::Error> {
+        let Some(fields) = self.next_fields()? else {
+            return Ok(None);
+        };
+        if fields.len() < Self::COMMON_FIELDS {
+            return Err(invalid_line(&fields.join(",")));
+        }
+
+        let number = |index: usize| {
+            fields[index]
+                .parse::<u64>()
+                .map_err(|_| invalid_line(&fields.join(",")))
+        };
+        // Values too big for their field are as invalid as ones that aren't numbers
+        let number_u32 = |index: usize| {
+            u32::try_from(number(index)?).map_err(|_| invalid_line(&fields.join(",")))
+        };
+        let kind = P4ServerLogEventKind::from(number_u32(0)?);
+        let mut extra = fields[Self::COMMON_FIELDS..].to_vec();
+        let error = match kind {
+            P4ServerLogEventKind::Error if extra.len() >= 4 => {
+                let rest = extra.split_off(4);
+                let error = P4ServerLogError {
+                    severity: extra[0].parse().unwrap_or(E_FAILED),
+                    subsystem: extra[1].parse().unwrap_or_default(),
+                    subcode: extra[2].parse().unwrap_or_default(),
+                    text: extra[3].trim_end().to_string(),
+                };
+                extra = rest;
+                Some(error)
+            }
+            _ => None,
+        };
+
+        Ok(Some(P4ServerLogEvent {
+            kind,
+            timestamp: number_u32(1)?,
+            timestamp_nanos: number_u32(2)?,
+            date: fields[3].clone(),
+            pid: number_u32(4)?,
+            command_id: fields[5].clone(),
+            server_id: fields[6].clone(),
+            command_number: number(7)?,
+            user: fields[8].clone(),
+            client: fields[9].clone(),
+            function: fields[10].clone(),
+            host: fields[11].clone(),
+            program: fields[12].clone(),
+            version: fields[13].clone(),
+            args: fields[14].clone(),
+            error,
+            extra,
+        }))
+    }
+
+    // The fields of the next non-empty line, reading on while a quoted field is open
+    fn next_fields(&mut self) -> Result<Option<Vec<String>>, io::Error> {
+        self.line_buffer.clear();
+        loop {
+            if self.buffered_reader.read_line(&mut self.line_buffer)? == 0 {
+                if self.line_buffer.trim().is_empty() {
+                    return Ok(None);
+                }
+                return Err(invalid_line(&self.line_buffer));
+            }
+            if self.line_buffer.matches('"').count() % 2 == 1 {
+                continue;
+            }
+            if !self.line_buffer.trim().is_empty() {
+                break;
+            }
+            self.line_buffer.clear();
+        }
+
+        let line = self.line_buffer.trim_end_matches(['\n', '\r']);
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if in_quotes && chars.next_if_eq(&'"').is_some() => field.push('"'),
+                '"' => in_quotes = !in_quotes,
+                ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+                c => field.push(c),
+            }
+        }
+        fields.push(field);
+        Ok(Some(fields))
+    }
+}
+
+impl<ReadT: io::Read> Iterator for P4ServerLogParser<ReadT> {
+    type Item = Result<P4ServerLogEvent, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.get_next_event().transpose()
+    }
+}
+
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Not a structured log line: {}", line.trim_end()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serverlog_parsing() {
+        let data = "\
+            0,1743984000,523000000,2025/04/07 12:00:00 523000000,4242,a1b2,master,7,alice,ws,user-sync,10.0.0.5,p4/2024.1/LINUX26X86_64/2596294,2024.1,//depot/...\n\
+            \n\
+            2,1743984003,1000,2025/04/07 12:00:03 000001000,4242,a1b2,master,7,alice,ws,user-sync,10.0.0.5,p4/2024.1,2024.1,//depot/...,120,30\n\
+            3,1743984004,0,2025/04/07 12:00:04 000000000,4243,c3d4,master,8,bob,ws2,user-edit,10.0.0.6,p4v,2024.1,\"a,b.txt\",3,6,17,\"//depot/a,b.txt - no such file(s).\n\"\n";
+
+        let events = P4ServerLogParser::new(data.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].kind, P4ServerLogEventKind::CommandStart);
+        assert_eq!((events[0].pid, events[0].command_number), (4242, 7));
+        assert_eq!(events[0].command(), "sync");
+        assert_eq!(events[1].kind, P4ServerLogEventKind::CommandEnd);
+        assert_eq!(events[1].extra, ["120", "30"]);
+        assert_eq!(events[2].args, "a,b.txt");
+        assert_eq!(
+            events[2].error,
+            Some(P4ServerLogError {
+                severity: 3,
+                subsystem: 6,
+                subcode: 17,
+                text: "//depot/a,b.txt - no such file(s).".to_string(),
+            })
+        );
+        assert!(events[2].is_failure() && !events[0].is_failure());
+
+        let mut parser = P4ServerLogParser::new("0,abc\n".as_bytes());
+        assert!(parser.next().unwrap().is_err());
+
+        // A pid that doesn't fit in 32 bits isn't truncated
+        let line = "0,1743984000,0,2025/04/07 12:00:00,4294967296,a1b2,master,7,alice,ws,user-sync,host,p4,2024.1,\n";
+        let mut parser = P4ServerLogParser::new(line.as_bytes());
+        assert!(parser.next().unwrap().is_err());
+    }
+}
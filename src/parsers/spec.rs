@@ -0,0 +1,134 @@
+// == Std crates
+use std::fmt::Write;
+
+// Fields whose lines are one block of text rather than a list
+const TEXT_FIELDS: [&str; 2] = ["Description", "Text"];
+
+// Parses the text form of a spec, as `p4 change -o` prints it without -G and form triggers get it in
+// %formfile%, into the fields -G gives: a list field such as View becomes View0, View1... and a text field
+// such as Description keeps its lines. Comments, the lines starting with #, are dropped, as is the # comment
+// at the end of a list entry, e.g. the action after each of a change form's Files.
+pub fn parse_spec(text: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        if line.starts_with('#') || line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if !value.is_empty() {
+            fields.push((key.to_string(), value.to_string()));
+            continue;
+        }
+
+        // The value is on the indented lines that follow, up to the next field
+        let mut block = Vec::new();
+        while let Some(line) =
+            lines.next_if(|line| line.is_empty() || line.starts_with(char::is_whitespace))
+        {
+            block.push(line.strip_prefix('\t').unwrap_or(line.trim_start()));
+        }
+        while block.last().is_some_and(|line| line.is_empty()) {
+            block.pop();
+        }
+        if TEXT_FIELDS.contains(&key) {
+            let mut value = block.join("\n");
+            value.push('\n');
+            fields.push((key.to_string(), value));
+        } else {
+            let entries = block
+                .iter()
+                .map(|line| {
+                    line.split_once(" #")
+                        .or(line.split_once("\t#"))
+                        .map_or(*line, |(entry, _)| entry)
+                })
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty());
+            for (index, entry) in entries.enumerate() {
+                fields.push((format!("{}{}", key, index), entry.to_string()));
+            }
+        }
+    }
+    fields
+}
+
+// The text form of the fields, as `p4 change -i` and form-in triggers read it back. Indexed fields are
+// written as one list field, in the order they first appear.
+pub fn format_spec<'a>(fields: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    // (key, values, whether it's a list or text block)
+    let mut blocks: Vec<(&str, Vec<&str>, bool)> = Vec::new();
+    for (key, value) in fields {
+        let list_key = key.trim_end_matches(|c: char| c.is_ascii_digit());
+        if list_key.len() < key.len() && !list_key.is_empty() {
+            match blocks
+                .iter_mut()
+                .find(|(name, _, is_block)| *is_block && *name == list_key)
+            {
+                Some((_, values, _)) => values.push(value),
+                None => blocks.push((list_key, vec![value], true)),
+            }
+        } else {
+            blocks.push((key, vec![value], TEXT_FIELDS.contains(&key)));
+        }
+    }
+
+    let mut text = String::new();
+    for (key, values, is_block) in blocks {
+        if is_block {
+            let _ = writeln!(text, "{}:", key);
+            for line in values.iter().flat_map(|value| value.lines()) {
+                let _ = writeln!(text, "\t{}", line);
+            }
+        } else {
+            let _ = writeln!(text, "{}:\t{}", key, values[0]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let form = "# A Perforce Change Specification.\n\
+                    #\n\
+                    #  Change:      The change number.\n\
+                    \n\
+                    Change:\tnew\n\
+                    \n\
+                    Client:\tws\n\
+                    \n\
+                    Description:\n\
+                    \tFix the loader\n\
+                    \t\n\
+                    \tJIRA-17\n\
+                    \n\
+                    Files:\n\
+                    \t//depot/a.txt\t# edit\n\
+                    \t//depot/b c.txt\t# add\n\
+                    \n";
+        let fields = parse_spec(form);
+        let pairs = fields
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            [
+                ("Change", "new"),
+                ("Client", "ws"),
+                ("Description", "Fix the loader\n\nJIRA-17\n"),
+                ("Files0", "//depot/a.txt"),
+                ("Files1", "//depot/b c.txt"),
+            ]
+        );
+        assert_eq!(parse_spec(&format_spec(pairs.iter().copied())), fields);
+    }
+}
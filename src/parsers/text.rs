@@ -0,0 +1,234 @@
+// == Std crates
+use std::{io, io::BufRead};
+
+// == Internal crates
+use crate::error::*;
+use crate::*;
+
+// == External crates
+
+// Best effort parsing of the untagged text of `p4 changes [-l] [-t]` and `p4 describe [-s]`, for captures made
+// without -G or -ztag. The text has no file sizes or digests, so those are left at zero, and dates are read as
+// UTC since the text is in the server's time zone.
+#[derive(Debug)]
+pub struct P4TextChangelistParser<ReadT: io::Read> {
+    buffered_reader: io::BufReader<ReadT>,
+    line_buffer: String,
+    // The header of the next changelist, read while looking for the end of the current one
+    pending_header: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum TextSection {
+    Description,
+    Files,
+    // Jobs fixed and diffs, whose lines aren't part of the changelist
+    Other,
+}
+
+impl<ReadT: io::Read> P4TextChangelistParser<ReadT> {
+    const HEADER_PREFIX: &str = "Change ";
+    const FILE_PREFIX: &str = "... ";
+
+    pub fn new(reader: ReadT) -> Self {
+        P4TextChangelistParser {
+            buffered_reader: io::BufReader::new(reader),
+            line_buffer: String::default(),
+            pending_header: None,
+        }
+    }
+
+    fn read_line(&mut self) -> Result<Option<&str>, P4Error> {
+        self.line_buffer.clear();
+        if self.buffered_reader.read_line(&mut self.line_buffer)? == 0 {
+            return Ok(None);
+        }
+        let line = self.line_buffer.trim_end_matches(['\n', '\r']);
+        Ok(Some(line))
+    }
+
+    fn next_changelist(&mut self) -> Result<Option<P4Changelist>, P4Error> {
+        let header = match self.pending_header.take() {
+            Some(header) => header,
+            None => loop {
+                match self.read_line()? {
+                    None => return Ok(None),
+                    Some("") => continue,
+                    Some(line) if line.starts_with(Self::HEADER_PREFIX) => break line.to_string(),
+                    Some(_) => return Err(P4Error::InvalidRecord("Expected a Change line")),
+                }
+            },
+        };
+        let mut changelist = parse_header(&header)?;
+
+        let mut section = TextSection::Description;
+        let mut description = Vec::new();
+        while let Some(line) = self.read_line()? {
+            if line.starts_with(Self::HEADER_PREFIX) {
+                self.pending_header = Some(line.to_string());
+                break;
+            }
+            if let Some(description_line) = line.strip_prefix('\t') {
+                if section == TextSection::Description {
+                    description.push(description_line.to_string());
+                }
+            } else if let Some(file) = line.strip_prefix(Self::FILE_PREFIX) {
+                if section == TextSection::Files {
+                    changelist.files.push(parse_file(file)?);
+                }
+            } else if line.starts_with("Affected files") || line.starts_with("Shelved files") {
+                section = TextSection::Files;
+            } else if !line.is_empty() {
+                section = TextSection::Other;
+            }
+        }
+
+        // With -l the whole description is indented below the header, otherwise only its start is quoted on it
+        while description.last().is_some_and(|line| line.is_empty()) {
+            description.pop();
+        }
+        if !description.is_empty() {
+            changelist.description = description.join("\n") + "\n";
+        }
+        Ok(Some(changelist))
+    }
+}
+
+impl<ReadT: io::Read> Iterator for P4TextChangelistParser<ReadT> {
+    type Item = Result<P4Changelist, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_changelist().transpose()
+    }
+}
+
+// `Change 12 on 2024/01/31 by user@client 'Fix the build'` from changes, or
+// `Change 12 by user@client on 2024/01/31 10:20:30` from describe, either possibly with *pending*
+fn parse_header(line: &str) -> Result<P4Changelist, P4Error> {
+    let (fields, short_description) = match line.split_once('\'') {
+        Some((fields, quoted)) => (fields, quoted.strip_suffix('\'').unwrap_or(quoted)),
+        None => (line, ""),
+    };
+    let mut tokens = fields.split_whitespace().skip(1);
+    let mut changelist = P4Changelist {
+        changelist: parse_field(tokens.next().unwrap_or_default(), "Invalid change")?,
+        description: short_description.to_string(),
+        ..Default::default()
+    };
+
+    let mut user = None;
+    let mut date = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            "by" => user = tokens.next(),
+            "on" => date = tokens.next(),
+            // Only with -t on changes, always on describe
+            _ if token.len() == 8 && token.as_bytes()[2] == b':' => {
+                changelist.time += parse_clock(token)?;
+            }
+            _ => {}
+        }
+    }
+
+    let user = user.ok_or(P4Error::InvalidRecord("Missing user"))?;
+    changelist.user = user
+        .split_once('@')
+        .map_or(user, |(user, _)| user)
+        .to_string();
+    changelist.time += parse_date(date.ok_or(P4Error::InvalidRecord("Missing date"))?)?;
+    Ok(changelist)
+}
+
+// 2024/01/31
+fn parse_date(date: &str) -> Result<u32, P4Error> {
+    let mut parts = date.split('/');
+    let mut next = || parse_field::<u32>(parts.next().unwrap_or_default(), "Invalid date");
+    let (year, month, day) = (next()?, next()?, next()?);
+    if !(1970..=2105).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(P4Error::InvalidRecord("Invalid date"));
+    }
+    Ok(civil_time(year, month, day))
+}
+
+// 10:20:30
+fn parse_clock(clock: &str) -> Result<u32, P4Error> {
+    let mut parts = clock.split(':');
+    let mut next = || parse_field::<u32>(parts.next().unwrap_or_default(), "Invalid time");
+    Ok(next()? * 3600 + next()? * 60 + next()?)
+}
+
+// //depot/a.txt#3 edit
+fn parse_file(file: &str) -> Result<P4File, P4Error> {
+    let (depot_path, rest) = file
+        .rsplit_once('#')
+        .ok_or(P4Error::InvalidRecord("Missing revision"))?;
+    let (revision, action) = rest.split_once(' ').unwrap_or((rest, ""));
+    Ok(P4File {
+        depot_path: depot_path.to_string(),
+        action: action.trim().to_string(),
+        revision: Some(parse_field(revision, "Invalid revision")?),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_changelists() {
+        let changes = "\
+            Change 12 on 2024/01/31 by alice@alice-ws 'Fix the build '\n\
+            Change 11 on 2024/01/30 by bob@bob-ws *pending* 'Add it'\n";
+        let changelists = P4TextChangelistParser::new(changes.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(changelists.len(), 2);
+        assert_eq!(changelists[0].user, "alice");
+        assert_eq!(changelists[0].description, "Fix the build ");
+        assert_eq!(changelists[0].time, 1706659200);
+        assert_eq!(
+            changelists[1].to_string(),
+            "Change 11 on 2024/01/30 by bob 'Add it'"
+        );
+
+        let describe = "\
+            Change 12 by alice@alice-ws on 2024/01/31 10:20:30\n\
+            \n\
+            \tFix the build\n\
+            \t\n\
+            \tChange 11 broke it\n\
+            \n\
+            Jobs fixed ...\n\
+            \n\
+            job000001 on 2024/01/31 by alice *closed*\n\
+            \n\
+            \tThe build is broken\n\
+            \n\
+            Affected files ...\n\
+            \n\
+            ... //depot/main/build.rs#3 edit\n\
+            ... //depot/main/a #1.txt#1 move/add\n\
+            \n";
+        let changelists = P4TextChangelistParser::new(describe.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(changelists.len(), 1);
+        let changelist = &changelists[0];
+        assert_eq!(changelist.time, 1706659200 + 37230);
+        assert_eq!(
+            changelist.description,
+            "Fix the build\n\nChange 11 broke it\n"
+        );
+        assert_eq!(changelist.files.len(), 2);
+        assert_eq!(
+            changelist.files[0].to_string(),
+            "//depot/main/build.rs#3 edit"
+        );
+        assert_eq!(changelist.files[1].depot_path, "//depot/main/a #1.txt");
+        assert_eq!(changelist.files[1].action, "move/add");
+
+        let mut parser = P4TextChangelistParser::new("... change 12\n".as_bytes());
+        assert!(parser.next().unwrap().is_err());
+    }
+}
@@ -1,5 +1,5 @@
 // == Std crates
-use std::{io, io::BufRead};
+use std::{collections::HashSet, io, io::BufRead};
 
 // == Internal crates
 use super::*;
@@ -13,7 +13,9 @@ pub struct P4ZtagParser<ReadT: io::Read> {
     state: ZtagParseState,
     line_buffer: String,
     pending_line_buffer: Option<String>,
-    dict_delimiter_key: Option<&'static str>,
+    dict_delimiter_key: Option<String>,
+    // Strict mode, only these keys can start a new field inside a multiline value
+    known_keys: Option<HashSet<String>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -45,26 +47,57 @@ impl ZtagParseState {
     }
 }
 
-impl<ReadT: io::Read + std::fmt::Debug> P4KvpStream<io::Error> for P4ZtagParser<ReadT> {
+impl<ReadT: io::Read> P4KvpStream<io::Error> for P4ZtagParser<ReadT> {
     fn get_next_kvp<'b>(&'b mut self) -> Result<Option<P4KeyValuePair<'b>>, io::Error> {
         self.get_next_kvp()
     }
 }
 
-impl<ReadT: io::Read + std::fmt::Debug> P4ZtagParser<ReadT> {
+impl<ReadT: io::Read> P4ZtagParser<ReadT> {
     // These are the variables that can be multiline, and we need to handle them specially
     const MULTILINE_VAR_PREFIXES: [&str; 1] = ["... desc "];
     const PREFIX: &str = "... ";
     const PREFIX_LEN: usize = Self::PREFIX.len();
 
-    pub fn new(reader: ReadT, dict_delimiter_key: Option<&'static str>) -> Self {
+    pub fn new(reader: ReadT, dict_delimiter_key: Option<&str>) -> Self {
         P4ZtagParser {
             buffered_reader: io::BufReader::new(reader),
             current_dict_index: None,
             state: ZtagParseState::Root,
             line_buffer: String::default(),
             pending_line_buffer: None,
-            dict_delimiter_key,
+            dict_delimiter_key: dict_delimiter_key.map(str::to_string),
+            known_keys: None,
+        }
+    }
+
+    // By default a "... " line inside a multiline value starts a new field if it looks like one,
+    // i.e. "... " then letters and digits then a space. A description line such as "... and then"
+    // still passes that test, so with known keys only those keys (ignoring an index suffix, so
+    // depotFile matches depotFile0) end a multiline value.
+    pub fn with_known_keys<'a>(mut self, keys: impl IntoIterator<Item = &'a str>) -> Self {
+        self.known_keys = Some(keys.into_iter().map(str::to_string).collect());
+        self
+    }
+
+    fn is_field_line(&self, line: &str) -> bool {
+        let Some(rest) = line.strip_prefix(Self::PREFIX) else {
+            return false;
+        };
+        let Some((key, _)) = rest.split_once(' ') else {
+            return false;
+        };
+        if !key.starts_with(|c: char| c.is_ascii_alphabetic())
+            || !key.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return false;
+        }
+        match &self.known_keys {
+            Some(known_keys) => {
+                known_keys.contains(key)
+                    || known_keys.contains(key.trim_end_matches(|c: char| c.is_ascii_digit()))
+            }
+            None => true,
         }
     }
 
@@ -79,12 +112,9 @@ impl<ReadT: io::Read + std::fmt::Debug> P4ZtagParser<ReadT> {
                 let (key, value) = Self::get_kvp_refs(&self.line_buffer)?;
 
                 // For ztag, we increment the dict index BEFORE we yield, since we update on the first delimited key
-                if Some(key) == self.dict_delimiter_key {
-                    if self.current_dict_index.is_none() {
-                        self.current_dict_index = Some(0);
-                    } else {
-                        *self.current_dict_index.as_mut().unwrap() += 1;
-                    };
+                if Some(key) == self.dict_delimiter_key.as_deref() {
+                    self.current_dict_index =
+                        Some(self.current_dict_index.map_or(0, |index| index + 1));
                 }
 
                 let result = Ok(Some(P4KeyValuePair {
@@ -104,7 +134,7 @@ impl<ReadT: io::Read + std::fmt::Debug> P4ZtagParser<ReadT> {
         Ok(None)
     }
 
-    fn get_kvp_refs<'a>(line_buffer: &'a String) -> Result<(&'a str, &'a str), io::Error> {
+    fn get_kvp_refs(line_buffer: &str) -> Result<(&str, &str), io::Error> {
         // If we're here, we have a new line to process, it _should_ always start with '... '
         assert!(
             line_buffer.starts_with(Self::PREFIX),
@@ -135,8 +165,8 @@ impl<ReadT: io::Read + std::fmt::Debug> P4ZtagParser<ReadT> {
     // Returns true if we should yield the line, false if we should continue reading
     fn advance(&mut self) -> Result<ZtagParseState, io::Error> {
         // If we're in a multiline var, there are two possibilities
-        // 1. If the next line starts with a the ... prefix, then we're done and need to yield
-        // 2. If the line doesn't start with ... we need to just append
+        // 1. If the next line is a field line (see is_field_line), then we're done and need to yield
+        // 2. Otherwise, even if it starts with ..., we need to just append
 
         assert_ne!(
             self.state,
@@ -166,7 +196,7 @@ impl<ReadT: io::Read + std::fmt::Debug> P4ZtagParser<ReadT> {
             if self.buffered_reader.read_line(&mut next_line)? == 0 {
                 // End of file, but we need to yield the current record first, next round will return EOF
                 return Ok(ZtagParseState::MultiLineYield);
-            } else if next_line.starts_with(Self::PREFIX) {
+            } else if self.is_field_line(&next_line) {
                 // We have a new line, so we can yield the previous one, BUT we need to keep the next line so we can yield that next
                 self.pending_line_buffer = Some(next_line);
                 return Ok(ZtagParseState::MultiLineYield);
@@ -211,8 +241,8 @@ mod tests {
             ("changeType", "public", 0),
             ("change", "12345", 0),
             ("desc", "BLAHBLAH\nBLAHBLAH", 0),
-            ("zambo", "aaa", 1),
-            ("zoop", "bbb", 1),
+            ("zambo", "aaa", 0),
+            ("zoop", "bbb", 0),
             ("desc", "WOOWOO\nWOWWOW", 1),
             ("desc", "SNASNA", 2),
             ("desc", "SNASNA2", 3),
@@ -240,4 +270,49 @@ mod tests {
 
         assert_eq!(index, expected.len(), "Not all key-value pairs were read");
     }
+
+    #[test]
+    fn test_ztag_ambiguous_multiline() {
+        let data = "\
+            ... change 12\n\
+            ... desc Fix the build\n\
+            ... \n\
+            ... (continued)\n\
+            ... 1. first step\n\
+            ... and then some\n\
+            ... user alice\n";
+
+        let fields = |parser: &mut P4ZtagParser<&[u8]>| {
+            let mut fields = Vec::new();
+            while let Some(kvp) = parser.get_next_kvp().unwrap() {
+                fields.push((kvp.key.to_string(), kvp.value.to_string()));
+            }
+            fields
+        };
+
+        // The heuristic rejects lines that can't be fields, but not ones that look like one
+        let mut parser = P4ZtagParser::new(data.as_bytes(), Some("change"));
+        let heuristic = fields(&mut parser);
+        assert_eq!(heuristic.len(), 4);
+        assert_eq!(
+            heuristic[1].1,
+            "Fix the build\n... \n... (continued)\n... 1. first step"
+        );
+        assert_eq!(heuristic[2], ("and".to_string(), "then some".to_string()));
+
+        let mut parser = P4ZtagParser::new(data.as_bytes(), Some("change")).with_known_keys([
+            "change",
+            "desc",
+            "user",
+            "depotFile",
+        ]);
+        let strict = fields(&mut parser);
+        assert_eq!(strict.len(), 3);
+        assert_eq!(
+            strict[1].1,
+            "Fix the build\n... \n... (continued)\n... 1. first step\n... and then some"
+        );
+        assert_eq!(strict[2], ("user".to_string(), "alice".to_string()));
+        assert!(parser.is_field_line("... depotFile0 //depot/a.txt\n"));
+    }
 }
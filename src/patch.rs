@@ -0,0 +1,191 @@
+// == Std crates
+#[cfg(feature = "process")]
+use std::collections::HashMap;
+#[cfg(any(feature = "process", test))]
+use std::io::{self, Write};
+
+// What the patch of one file shows of its content
+#[cfg(any(feature = "process", test))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum P4PatchContent<'a> {
+    // The hunks of an edit as `p4 describe -du` and `p4 diff2 -du` print them, empty when nothing changed
+    Hunks(&'a str),
+    // The whole content of an added or deleted file
+    Whole(&'a [u8]),
+    // Revisions the server doesn't diff, e.g. binary ones
+    Unknown,
+}
+
+// The part of a `git apply` patch for one file, where a missing file type means the file doesn't exist on that
+// side. Paths are the depot path without the leading //.
+#[cfg(any(feature = "process", test))]
+pub(crate) fn write_file_patch(
+    writer: &mut impl Write,
+    depot_path: &str,
+    old_type: Option<&str>,
+    new_type: Option<&str>,
+    content: P4PatchContent,
+) -> io::Result<()> {
+    if old_type.is_none() && new_type.is_none() {
+        return Ok(());
+    }
+
+    let path = depot_path.trim_start_matches('/');
+    writeln!(writer, "diff --git a/{} b/{}", path, path)?;
+    match (old_type, new_type) {
+        (None, Some(new)) => writeln!(writer, "new file mode {}", git_mode(new))?,
+        (Some(old), None) => writeln!(writer, "deleted file mode {}", git_mode(old))?,
+        (Some(old), Some(new)) if git_mode(old) != git_mode(new) => {
+            writeln!(writer, "old mode {}", git_mode(old))?;
+            writeln!(writer, "new mode {}", git_mode(new))?;
+        }
+        _ => {}
+    }
+
+    let binary = old_type.into_iter().chain(new_type).any(is_binary_type)
+        || matches!(content, P4PatchContent::Whole(content) if content.contains(&0));
+    match content {
+        P4PatchContent::Hunks("") => return Ok(()),
+        P4PatchContent::Whole([]) => return Ok(()),
+        P4PatchContent::Unknown if !binary => return Ok(()),
+        _ => {}
+    }
+
+    let old_name = old_type.map_or("/dev/null".to_string(), |_| format!("a/{}", path));
+    let new_name = new_type.map_or("/dev/null".to_string(), |_| format!("b/{}", path));
+    if binary {
+        return writeln!(writer, "Binary files {} and {} differ", old_name, new_name);
+    }
+
+    writeln!(writer, "--- {}", old_name)?;
+    writeln!(writer, "+++ {}", new_name)?;
+    match content {
+        P4PatchContent::Hunks(hunks) => writeln!(writer, "{}", hunks),
+        P4PatchContent::Whole(content) => write_whole_file(writer, content, new_type.is_some()),
+        P4PatchContent::Unknown => Ok(()),
+    }
+}
+
+// One hunk with every line of the file, added or deleted
+#[cfg(any(feature = "process", test))]
+fn write_whole_file(writer: &mut impl Write, content: &[u8], added: bool) -> io::Result<()> {
+    let lines = content.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    let (prefix, header) = if added {
+        (b'+', format!("@@ -0,0 +1,{} @@", lines.len()))
+    } else {
+        (b'-', format!("@@ -1,{} +0,0 @@", lines.len()))
+    };
+    writeln!(writer, "{}", header)?;
+    for line in lines {
+        writer.write_all(&[prefix])?;
+        writer.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            writer.write_all(b"\n\\ No newline at end of file\n")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "process", test))]
+fn is_binary_type(file_type: &str) -> bool {
+    let base_type = file_type.split('+').next().unwrap_or_default();
+    base_type.contains("binary") || base_type == "apple"
+}
+
+// p4 file types are a base type with optional +modifiers, older names such as xtext mean text+x
+pub(crate) fn git_mode(file_type: &str) -> &'static str {
+    let (base, modifiers) = file_type.split_once('+').unwrap_or((file_type, ""));
+    if base == "symlink" {
+        "120000"
+    } else if modifiers.contains('x') || base.starts_with('x') {
+        "100755"
+    } else {
+        "100644"
+    }
+}
+
+// The hunks of each file in -du output by depot path, from the "==== //depot/a.txt#2 (text) ====" line before
+// them. Files without a diff, e.g. binary ones, have none.
+#[cfg(feature = "process")]
+pub(crate) fn diff_hunks(diff: &str) -> HashMap<&str, &str> {
+    let mut result = HashMap::new();
+    let mut current: Option<(&str, usize)> = None;
+    let mut offset = 0;
+    for line in diff.split_inclusive('\n') {
+        let header = line
+            .strip_prefix("==== ")
+            .and_then(|line| line.trim_end().strip_suffix(" ===="));
+        if let Some(header) = header {
+            if let Some((depot_path, start)) = current.take() {
+                result.insert(depot_path, section_hunks(&diff[start..offset]));
+            }
+            let filespec = header
+                .rsplit_once(" (")
+                .map_or(header, |(filespec, _)| filespec);
+            let depot_path = filespec.rsplit_once('#').map_or(filespec, |(path, _)| path);
+            current = Some((depot_path, offset + line.len()));
+        }
+        offset += line.len();
+    }
+    if let Some((depot_path, start)) = current {
+        result.insert(depot_path, section_hunks(&diff[start..]));
+    }
+    result
+}
+
+// From the first @@ line, without the blank line p4 puts before the next file
+#[cfg(feature = "process")]
+pub(crate) fn section_hunks(section: &str) -> &str {
+    let start = if section.starts_with("@@") {
+        Some(0)
+    } else {
+        section.find("\n@@").map(|index| index + 1)
+    };
+    start.map_or("", |start| section[start..].trim_end_matches('\n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_file_patch() {
+        let patch = |old_type, new_type, content| {
+            let mut result = Vec::new();
+            write_file_patch(&mut result, "//depot/a.sh", old_type, new_type, content).unwrap();
+            String::from_utf8(result).unwrap()
+        };
+
+        assert_eq!(
+            patch(None, Some("text+x"), P4PatchContent::Whole(b"a\nb")),
+            "diff --git a/depot/a.sh b/depot/a.sh\nnew file mode 100755\n--- /dev/null\n+++ b/depot/a.sh\n\
+             @@ -0,0 +1,2 @@\n+a\n+b\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            patch(Some("text"), None, P4PatchContent::Whole(b"a\n")),
+            "diff --git a/depot/a.sh b/depot/a.sh\ndeleted file mode 100644\n--- a/depot/a.sh\n+++ /dev/null\n\
+             @@ -1,1 +0,0 @@\n-a\n"
+        );
+        // Only the mode changed
+        assert_eq!(
+            patch(Some("text"), Some("text+x"), P4PatchContent::Hunks("")),
+            "diff --git a/depot/a.sh b/depot/a.sh\nold mode 100644\nnew mode 100755\n"
+        );
+        assert_eq!(
+            patch(Some("binary"), Some("binary"), P4PatchContent::Unknown),
+            "diff --git a/depot/a.sh b/depot/a.sh\nBinary files a/depot/a.sh and b/depot/a.sh differ\n"
+        );
+        assert_eq!(git_mode("binary+Fx"), "100755");
+        assert_eq!(git_mode("symlink"), "120000");
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_diff_hunks() {
+        let diff = "\n==== //depot/a.txt#2 (text) ====\n\n@@ -1 +1 @@\n-a\n+b\n\n\
+                    ==== //depot/b.bin#3 (binary) ====\n\n";
+        let hunks = diff_hunks(diff);
+        assert_eq!(hunks["//depot/a.txt"], "@@ -1 +1 @@\n-a\n+b");
+        assert_eq!(hunks["//depot/b.bin"], "");
+    }
+}
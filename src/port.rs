@@ -0,0 +1,220 @@
+// == Std crates
+use std::{fmt, str::FromStr};
+
+// == Internal crates
+use crate::error::*;
+
+// The protocol part of a P4PORT, see `p4 help environment`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum P4Transport {
+    #[default]
+    Tcp,
+    Tcp4,
+    Tcp6,
+    Tcp46,
+    Tcp64,
+    Ssl,
+    Ssl4,
+    Ssl6,
+    Ssl46,
+    Ssl64,
+}
+
+impl P4Transport {
+    const NAMES: [(P4Transport, &'static str); 10] = [
+        (P4Transport::Tcp, "tcp"),
+        (P4Transport::Tcp4, "tcp4"),
+        (P4Transport::Tcp6, "tcp6"),
+        (P4Transport::Tcp46, "tcp46"),
+        (P4Transport::Tcp64, "tcp64"),
+        (P4Transport::Ssl, "ssl"),
+        (P4Transport::Ssl4, "ssl4"),
+        (P4Transport::Ssl6, "ssl6"),
+        (P4Transport::Ssl46, "ssl46"),
+        (P4Transport::Ssl64, "ssl64"),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(transport, _)| transport == self)
+            .map(|(_, name)| *name)
+            .unwrap_or("tcp")
+    }
+
+    pub fn is_ssl(&self) -> bool {
+        matches!(
+            self,
+            P4Transport::Ssl
+                | P4Transport::Ssl4
+                | P4Transport::Ssl6
+                | P4Transport::Ssl46
+                | P4Transport::Ssl64
+        )
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, transport_name)| *transport_name == name)
+            .map(|(transport, _)| *transport)
+    }
+}
+
+impl fmt::Display for P4Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// A P4PORT value, e.g. ssl:perforce.example.com:1666, tcp64:[::1]:1666, perforce:1666 or just 1666
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct P4Port {
+    transport: P4Transport,
+    // Written with an explicit transport, so it is kept when the port is displayed
+    explicit_transport: bool,
+    // None when only the port number is given, which p4 takes to mean this machine
+    host: Option<String>,
+    port: u16,
+}
+
+impl P4Port {
+    pub fn transport(&self) -> P4Transport {
+        self.transport
+    }
+
+    // Without the brackets of an IPv6 address
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn is_ssl(&self) -> bool {
+        self.transport.is_ssl()
+    }
+
+    // host:port for connecting a socket, e.g. [::1]:1666
+    pub fn address(&self) -> String {
+        match self.host.as_deref() {
+            Some(host) if host.contains(':') => format!("[{}]:{}", host, self.port),
+            Some(host) => format!("{}:{}", host, self.port),
+            None => format!("localhost:{}", self.port),
+        }
+    }
+}
+
+impl FromStr for P4Port {
+    type Err = P4ValueParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| P4ValueParseError::InvalidPort(value.to_string(), reason);
+
+        let (transport, rest) = match value.split_once(':') {
+            Some(("rsh", _)) => return Err(invalid("rsh ports are not supported")),
+            Some((name, rest)) => match P4Transport::from_name(name) {
+                Some(transport) => (Some(transport), rest),
+                None => (None, value),
+            },
+            None => (None, value),
+        };
+
+        let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+            let (host, port) = bracketed
+                .split_once("]:")
+                .ok_or_else(|| invalid("expected [address]:port for an IPv6 address"))?;
+            (Some(host), port)
+        } else {
+            match rest.rsplit_once(':') {
+                Some((host, _)) if host.contains(':') => {
+                    return Err(invalid(
+                        "unknown transport, or an IPv6 address without brackets",
+                    ));
+                }
+                Some((host, port)) => (Some(host), port),
+                None => (None, rest),
+            }
+        };
+
+        if host.is_some_and(|host| host.is_empty() || host.contains(char::is_whitespace)) {
+            return Err(invalid("the host name is empty or has spaces in it"));
+        }
+        let port = match port.parse::<u16>() {
+            Ok(port) if port > 0 => port,
+            _ => return Err(invalid("the port must be a number from 1 to 65535")),
+        };
+
+        Ok(P4Port {
+            transport: transport.unwrap_or_default(),
+            explicit_transport: transport.is_some(),
+            host: host.map(str::to_string),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for P4Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.explicit_transport {
+            write!(f, "{}:", self.transport)?;
+        }
+        match self.host.as_deref() {
+            Some(_) => f.write_str(&self.address()),
+            None => write!(f, "{}", self.port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_parsing() {
+        let port: P4Port = "ssl:perforce.example.com:1666".parse().unwrap();
+        assert!(port.is_ssl());
+        assert_eq!(port.host(), Some("perforce.example.com"));
+        assert_eq!(port.port(), 1666);
+
+        let port: P4Port = "tcp64:[::1]:1667".parse().unwrap();
+        assert_eq!(port.transport(), P4Transport::Tcp64);
+        assert_eq!(port.host(), Some("::1"));
+        assert_eq!(port.address(), "[::1]:1667");
+
+        let port: P4Port = "1666".parse().unwrap();
+        assert_eq!(port.host(), None);
+        assert_eq!(port.address(), "localhost:1666");
+
+        for value in [
+            "perforce:1666",
+            "ssl:perforce:1666",
+            "tcp6:[::1]:1666",
+            "1666",
+        ] {
+            assert_eq!(value.parse::<P4Port>().unwrap().to_string(), value);
+        }
+
+        for value in [
+            "perforce",
+            "perforce:0",
+            "perforce:70000",
+            "ssl::1666",
+            "tcp6:::1:1666",
+        ] {
+            assert!(
+                matches!(
+                    value.parse::<P4Port>(),
+                    Err(P4ValueParseError::InvalidPort(..))
+                ),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(
+            "rsh:p4d -i".parse::<P4Port>().unwrap_err().to_string(),
+            "Invalid P4PORT 'rsh:p4d -i': rsh ports are not supported"
+        );
+    }
+}
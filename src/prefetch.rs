@@ -0,0 +1,129 @@
+// == Std crates
+use std::{
+    panic,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+// Runs an iterator on a background thread, e.g. P4FstatIterator::new_from_context(...)?.prefetch(256), so
+// parsing the next records overlaps with the consumer's work on the current one. Errors are passed through in
+// order like any other item.
+pub trait P4Prefetch: Iterator + Send + Sized + 'static
+where
+    Self::Item: Send + 'static,
+{
+    // Keeps up to `n` records buffered, the background thread blocks when the buffer is full
+    fn prefetch(self, n: usize) -> P4PrefetchIterator<Self::Item> {
+        P4PrefetchIterator::new(self, n)
+    }
+}
+
+impl<IterT> P4Prefetch for IterT
+where
+    IterT: Iterator + Send + 'static,
+    IterT::Item: Send + 'static,
+{
+}
+
+// Dropping it drops the receiver, so the background thread's next send fails and it stops, dropping the inner
+// iterator, which kills any p4 process still running. The thread isn't joined, it may be blocked in the inner
+// iterator reading from a quiet server.
+pub struct P4PrefetchIterator<ItemT> {
+    receiver: Option<mpsc::Receiver<ItemT>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<ItemT: Send + 'static> P4PrefetchIterator<ItemT> {
+    pub fn new<IterT>(iterator: IterT, n: usize) -> P4PrefetchIterator<ItemT>
+    where
+        IterT: Iterator<Item = ItemT> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(n.max(1));
+        let worker = thread::spawn(move || {
+            for item in iterator {
+                // The consumer was dropped
+                if sender.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+
+        P4PrefetchIterator {
+            receiver: Some(receiver),
+            worker: Some(worker),
+        }
+    }
+
+    // Waits for the background thread, passing on its panic if it had one
+    fn join(&mut self) {
+        if let Some(worker) = self.worker.take()
+            && let Err(payload) = worker.join()
+            && !thread::panicking()
+        {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl<ItemT: Send + 'static> Iterator for P4PrefetchIterator<ItemT> {
+    type Item = ItemT;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.receiver.as_ref()?.recv().ok();
+        if item.is_none() {
+            self.receiver = None;
+            self.join();
+        }
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::*;
+    use crate::fstat::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+    use std::io;
+
+    #[test]
+    fn test_prefetch() {
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/a.txt"),
+                ("headRev", "1"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/b.txt"),
+                ("headRev", "4"),
+            ],
+            &[
+                ("code", "error"),
+                ("data", "//depot/c.txt - no such file(s).\n"),
+                ("severity", "3"),
+                ("generic", "17"),
+            ],
+        ]);
+        let entries = P4FstatIterator::new_from_reader(io::Cursor::new(data))
+            .prefetch(1)
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].as_ref().unwrap().depot_path, "//depot/b.txt");
+        assert!(matches!(entries[2], Err(P4Error::Server(_))));
+
+        // Dropping it early stops the background thread rather than leaving it blocked on a full buffer
+        let mut numbers = (0..u32::MAX).prefetch(4);
+        assert_eq!(numbers.next(), Some(0));
+        drop(numbers);
+
+        // Nor does dropping it wait for an inner iterator blocked on a record that never comes
+        let (sender, receiver) = mpsc::channel::<u32>();
+        let mut quiet = std::iter::from_fn(move || receiver.recv().ok()).prefetch(4);
+        sender.send(1).unwrap();
+        assert_eq!(quiet.next(), Some(1));
+        drop(quiet);
+        drop(sender);
+    }
+}
@@ -0,0 +1,1130 @@
+// == Std crates
+use std::{borrow::Cow, io};
+#[cfg(feature = "process")]
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+// == Internal crates
+use crate::budget::*;
+use crate::cancel::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::fstat::*;
+use crate::metrics::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::parsers::py_dict::*;
+use crate::process_state::*;
+use crate::*;
+
+// == External crates
+#[cfg(feature = "encoding")]
+use encoding_rs::{Decoder, Encoding};
+#[cfg(feature = "process")]
+use md5::{Digest, Md5};
+
+// A file revision with its content, as returned by `p4 print`
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4PrintedFile {
+    pub depot_path: String,
+    pub revision: u32,
+    pub change: u32,
+    pub action: String,
+    pub file_type: String,
+    pub time: u32,
+    // Raw bytes unless decoded, see with_content_decoding. Text files use the server's line endings.
+    pub content: Vec<u8>,
+}
+
+impl P4PrintedFile {
+    pub fn kind(&self) -> P4FileKind {
+        P4FileKind::from_file_type(&self.file_type)
+    }
+
+    // Where a symlink points, None for other kinds. The newline p4 ends it with isn't part of it.
+    pub fn symlink_target(&self) -> Option<&str> {
+        if self.kind() != P4FileKind::Symlink {
+            return None;
+        }
+        let target = std::str::from_utf8(&self.content).ok()?;
+        Some(target.strip_suffix('\n').unwrap_or(target))
+    }
+}
+
+// How the content of utf16, unicode and utf8 files is handed over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P4ContentDecoding {
+    // The bytes as p4 sent them
+    #[default]
+    Raw,
+    // As UTF-8 without a BOM, invalid sequences replaced with U+FFFD. Other file types are left as they are.
+    Utf8,
+}
+
+#[cfg(feature = "process")]
+pub type PrintCallback =
+    Box<dyn Fn(&P4PrintedFile, &mut dyn io::Read) -> io::Result<()> + Send + Sync>;
+
+// Where fetch_many puts each file's content
+#[cfg(feature = "process")]
+pub enum P4PrintDestination {
+    // Under the directory by depot path, e.g. //depot/main/a.txt to <dir>/depot/main/a.txt
+    Directory(PathBuf),
+    // Called with each file and a reader over its content
+    Callback(PrintCallback),
+}
+
+// What fetch_many wrote
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4FetchReport {
+    pub files: u64,
+    pub bytes: u64,
+    // Written without a digest check, e.g. symlinks and +k files
+    pub unverified: u64,
+    // Revisions whose content didn't match the server's digest, as path#rev. These are not written.
+    pub corrupt: Vec<String>,
+    // The paths the server refused to print, with its message
+    pub failed: Vec<(String, String)>,
+}
+
+// Prints the revisions in `paths`, e.g. //depot/a.txt#3, to the destination without a workspace. The paths
+// are split over `concurrency` `p4 -x - print` commands and each file is staged on disk and checked against
+// its digest from `p4 fstat -Ol` before it reaches the destination.
+#[cfg(feature = "spawn")]
+pub fn fetch_many(
+    paths: impl IntoIterator<Item = String>,
+    concurrency: usize,
+    destination: &P4PrintDestination,
+) -> Result<P4FetchReport, P4Error> {
+    fetch_many_from_context(&P4Context::default(), paths, concurrency, destination)
+}
+
+#[cfg(feature = "process")]
+pub fn fetch_many_from_context(
+    context: &P4Context,
+    paths: impl IntoIterator<Item = String>,
+    concurrency: usize,
+    destination: &P4PrintDestination,
+) -> Result<P4FetchReport, P4Error> {
+    let paths = paths.into_iter().collect::<Vec<_>>();
+    let mut digests = HashMap::new();
+    let query = P4FstatQuery::default().with_file_details();
+    for entry in P4FstatIterator::new_from_context_with_paths(context, query, paths.clone())? {
+        let entry = entry?;
+        if let (Some(revision), Some(digest)) = (entry.head_rev, entry.digest) {
+            let file_type = entry.head_type.unwrap_or_default();
+            digests.insert((entry.depot_path, revision), (digest, file_type));
+        }
+    }
+
+    // Staged next to the destination so a finished file is moved into place rather than copied. Each call has
+    // its own directory, so concurrent calls don't remove each other's files.
+    let parent = match destination {
+        P4PrintDestination::Directory(dir) => dir.clone(),
+        P4PrintDestination::Callback(_) => std::env::temp_dir(),
+    };
+    fs::create_dir_all(&parent)?;
+    let staging = create_staging_dir(&parent)?;
+
+    let chunk_size = paths.len().div_ceil(concurrency.max(1)).max(1);
+    let results = thread::scope(|scope| {
+        let workers = paths
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let staged = staging.join(format!("{}.part", index));
+                let digests = &digests;
+                scope.spawn(move || {
+                    fetch_chunk(context, chunk.to_vec(), &staged, digests, destination)
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Print worker panicked"))
+            .collect::<Vec<_>>()
+    });
+    let _ = fs::remove_dir_all(&staging);
+
+    let mut report = P4FetchReport::default();
+    for result in results {
+        let chunk = result?;
+        report.files += chunk.files;
+        report.bytes += chunk.bytes;
+        report.unverified += chunk.unverified;
+        report.corrupt.extend(chunk.corrupt);
+        report.failed.extend(chunk.failed);
+    }
+    report.corrupt.sort();
+    report.failed.sort();
+    Ok(report)
+}
+
+#[cfg(feature = "process")]
+fn create_staging_dir(parent: &Path) -> io::Result<PathBuf> {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let call = CALLS.fetch_add(1, Ordering::Relaxed);
+        let name = format!(".p4_helper_print_{}_{}", std::process::id(), call);
+        let staging = parent.join(name);
+        match fs::create_dir(&staging) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|()| staging),
+        }
+    }
+}
+
+#[cfg(feature = "process")]
+fn fetch_chunk(
+    context: &P4Context,
+    paths: Vec<String>,
+    staged: &Path,
+    digests: &HashMap<(String, u32), ([u8; 16], String)>,
+    destination: &P4PrintDestination,
+) -> Result<P4FetchReport, P4Error> {
+    let mut report = P4FetchReport::default();
+    let mut printed = HashSet::new();
+    let prints = P4PrintIterator::new_from_context_with_paths(context, paths.clone())?;
+    match fetch_files(
+        prints,
+        staged,
+        digests,
+        destination,
+        &mut printed,
+        &mut report,
+    ) {
+        Err(P4Error::Server(_)) => {}
+        result => return result.map(|()| report),
+    }
+
+    // The server stops at the first path it refuses, so the chunk is printed again a path at a time to find
+    // which ones fail. Files that were already printed are skipped.
+    for path in paths {
+        let prints = P4PrintIterator::new_from_context(context, &[&path])?;
+        match fetch_files(
+            prints,
+            staged,
+            digests,
+            destination,
+            &mut printed,
+            &mut report,
+        ) {
+            Err(P4Error::Server(message)) => report.failed.push((path, message.data)),
+            result => result?,
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(feature = "process")]
+fn fetch_files(
+    mut prints: P4PrintIterator<P4Output>,
+    staged: &Path,
+    digests: &HashMap<(String, u32), ([u8; 16], String)>,
+    destination: &P4PrintDestination,
+    printed: &mut HashSet<(String, u32)>,
+    report: &mut P4FetchReport,
+) -> Result<(), P4Error> {
+    loop {
+        let mut writer = DigestWriter {
+            inner: io::BufWriter::new(fs::File::create(staged)?),
+            md5: Md5::new(),
+        };
+        let Some(file) = prints.next_to_writer(&mut writer) else {
+            break;
+        };
+        let file = file?;
+        io::Write::flush(&mut writer.inner)?;
+        drop(writer.inner);
+        if !printed.insert((file.depot_path.clone(), file.revision)) {
+            continue;
+        }
+
+        match digests.get(&(file.depot_path.clone(), file.revision)) {
+            Some((digest, file_type)) if digest_matches_content(file_type) => {
+                if writer.md5.finalize()[..] != digest[..] {
+                    report
+                        .corrupt
+                        .push(format!("{}#{}", file.depot_path, file.revision));
+                    continue;
+                }
+            }
+            _ => report.unverified += 1,
+        }
+
+        report.files += 1;
+        report.bytes += fs::metadata(staged)?.len();
+        match destination {
+            P4PrintDestination::Directory(dir) => {
+                let target = dir.join(file.depot_path.trim_start_matches('/'));
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if file.kind() == P4FileKind::Symlink {
+                    write_symlink(staged, &target, dir)?;
+                } else {
+                    fs::rename(staged, target)?;
+                }
+            }
+            P4PrintDestination::Callback(callback) => {
+                callback(&file, &mut io::BufReader::new(fs::File::open(staged)?))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Makes `target` a link to where the staged symlink content points. Where there are no symlinks, or the link
+// would point outside `root`, the target is written as the file's content, as p4 does on such clients.
+#[cfg(feature = "process")]
+fn write_symlink(staged: &Path, target: &Path, root: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let content = fs::read_to_string(staged)?;
+        let link = content.strip_suffix('\n').unwrap_or(&content);
+        if stays_within(root, target, link) {
+            match fs::remove_file(target) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            return std::os::unix::fs::symlink(link, target);
+        }
+    }
+    fs::rename(staged, target)
+}
+
+// Whether `link`, relative to the directory of `target`, resolves to somewhere under `root`. Absolute links
+// and links climbing out with .. don't, or a later file written through them could land anywhere.
+#[cfg(all(feature = "process", unix))]
+fn stays_within(root: &Path, target: &Path, link: &str) -> bool {
+    let Some(mut depth) = target
+        .parent()
+        .and_then(|parent| parent.strip_prefix(root).ok())
+        .map(|parent| parent.components().count())
+    else {
+        return false;
+    };
+    for component in Path::new(link).components() {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+    true
+}
+
+// Hashes what passes through, to check printed content without reading it back
+#[cfg(feature = "process")]
+struct DigestWriter<WriteT: io::Write> {
+    inner: WriteT,
+    md5: Md5,
+}
+
+#[cfg(feature = "process")]
+impl<WriteT: io::Write> io::Write for DigestWriter<WriteT> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.md5.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct P4PrintIterator<ReadT: io::Read> {
+    process_state: P4ProcessState,
+    parser: P4PyDictParser<ReadT>,
+    // Storage for various state variables
+    current_file: Option<InterimP4PrintedFile>,
+    in_content: bool,
+    error: Option<P4ServerMessage>,
+    records_skipped: u64,
+    // What the content of the current file holds of the memory budget
+    content_reservation: BudgetReservation,
+    content_decoder: ContentDecoder,
+}
+
+#[cfg(feature = "process")]
+impl P4PrintIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(filespecs: &[&str]) -> Result<P4PrintIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), filespecs)
+    }
+
+    // `filespecs` may include revisions, e.g. //depot/a.txt#3 or //depot/...@=1234
+    pub fn new_from_context(
+        context: &P4Context,
+        filespecs: &[&str],
+    ) -> Result<P4PrintIterator<P4Output>, P4Error> {
+        let mut args = vec!["print"];
+        args.extend(filespecs);
+        let (p4_process, reader) = context.spawn(args.clone())?;
+
+        let parser = context.parser(reader);
+        let mut result = P4PrintIterator::new_from_parser(parser);
+        result.content_reservation = context.budget_reservation(P4BudgetResource::BufferedBytes);
+        #[cfg(feature = "encoding")]
+        if let Some(charset) = context.charset() {
+            result = result.with_charset(charset);
+        }
+        result.process_state.attach(p4_process, context, &args);
+
+        Ok(result)
+    }
+
+    #[cfg(feature = "spawn")]
+    pub fn new_with_paths(
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+    ) -> Result<P4PrintIterator<P4Output>, P4Error> {
+        Self::new_from_context_with_paths(&P4Context::default(), paths)
+    }
+
+    // Any number of filespecs, streamed to `p4 -x -` rather than put on the command line
+    pub fn new_from_context_with_paths(
+        context: &P4Context,
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+    ) -> Result<P4PrintIterator<P4Output>, P4Error> {
+        let args = vec!["print"];
+        let (p4_process, reader) = context.spawn_with_filespecs(args.clone(), paths.into_iter())?;
+
+        let parser = context.parser(reader);
+        let mut result = P4PrintIterator::new_from_parser(parser);
+        result.content_reservation = context.budget_reservation(P4BudgetResource::BufferedBytes);
+        #[cfg(feature = "encoding")]
+        if let Some(charset) = context.charset() {
+            result = result.with_charset(charset);
+        }
+        result.process_state.attach(p4_process, context, &args);
+
+        Ok(result)
+    }
+}
+
+impl<ReadT: io::Read> P4PrintIterator<ReadT> {
+    pub fn new_from_reader(reader: ReadT) -> P4PrintIterator<ReadT> {
+        Self::new_from_parser(P4PyDictParser::new(reader))
+    }
+
+    pub fn new_from_parser(parser: P4PyDictParser<ReadT>) -> P4PrintIterator<ReadT> {
+        P4PrintIterator {
+            process_state: P4ProcessState::default(),
+            parser,
+            current_file: None,
+            in_content: false,
+            error: None,
+            records_skipped: 0,
+            content_reservation: BudgetReservation::default(),
+            content_decoder: ContentDecoder::default(),
+        }
+    }
+
+    // Converts the content of utf16 files, and unicode and utf8 files in another encoding, to UTF-8 going by
+    // each file's type, for tools that extract text
+    pub fn with_content_decoding(mut self, decoding: P4ContentDecoding) -> Self {
+        self.content_decoder.decoding = decoding;
+        self
+    }
+
+    // The P4CHARSET the content of unicode files is in, e.g. winansi or shiftjis, set from the context's charset
+    // when spawned. Unknown charsets leave the content as it is.
+    #[cfg(feature = "encoding")]
+    pub fn with_charset(mut self, charset: &str) -> Self {
+        self.content_decoder.charset = charset_encoding(charset);
+        self
+    }
+
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.process_state.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn records_yielded(&self) -> u64 {
+        self.process_state.records_yielded()
+    }
+
+    // Totals for the command, None until the iterator is exhausted
+    pub fn summary(&self) -> Option<P4IteratorSummary> {
+        self.process_state.summary(self.records_skipped)
+    }
+
+    // The next file with its content streamed to `writer` rather than held in memory, so content is empty.
+    // Mixing this with next() is fine, each call reads one whole file.
+    pub fn next_to_writer(
+        &mut self,
+        writer: &mut dyn io::Write,
+    ) -> Option<Result<P4PrintedFile, P4Error>> {
+        if let Some(result) = self.process_state.before_next() {
+            return result;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = self.process_state.span().entered();
+
+        let result = self.next_file(Some(writer));
+        let bytes_read = self.parser.bytes_read();
+        self.process_state.after_next(result, bytes_read)
+    }
+
+    // Each file is a stat record followed by any number of content records, e.g. { code: text, data }
+    // With a sink the content is written to it instead of being kept in the file
+    fn next_file(
+        &mut self,
+        mut sink: Option<&mut dyn io::Write>,
+    ) -> Result<Option<P4PrintedFile>, P4Error> {
+        while let Some(kvp) = self.parser.get_next_raw_kvp()? {
+            if kvp.key == "code" {
+                Self::finish_error(&mut self.error, &mut self.records_skipped)?;
+
+                match kvp.value {
+                    b"stat" => {
+                        self.in_content = false;
+                        let previous = self.current_file.replace(InterimP4PrintedFile::default());
+                        if let Some(mut previous) = previous {
+                            self.content_decoder
+                                .finish(&mut previous, sink.as_deref_mut())?;
+                            self.content_reservation.release();
+                            return previous.try_into().map(Some);
+                        }
+                    }
+                    b"error" => {
+                        self.in_content = false;
+                        self.error = Some(P4ServerMessage::default());
+                    }
+                    _ => self.in_content = true,
+                }
+                continue;
+            }
+
+            let value = || {
+                std::str::from_utf8(kvp.value)
+                    .map_err(|e| P4Error::Parse(P4PyDictParseError::InvalidUtf8(e)))
+            };
+
+            if let Some(error) = self.error.as_mut() {
+                error.populate_field(kvp.key, value()?);
+            } else if let Some(file) = self.current_file.as_mut() {
+                if self.in_content {
+                    if kvp.key == "data" {
+                        let data = self
+                            .content_decoder
+                            .decode(file.file_type.as_deref(), kvp.value);
+                        if let Some(sink) = sink.as_mut() {
+                            sink.write_all(&data)?;
+                        } else {
+                            self.content_reservation
+                                .resize((file.content.len() + data.len()) as u64)
+                                .map_err(P4Error::BudgetExceeded)?;
+                            file.content.extend_from_slice(&data);
+                        }
+                    }
+                } else {
+                    file.populate_field(kvp.key, value()?)?;
+                }
+            }
+        }
+
+        Self::finish_error(&mut self.error, &mut self.records_skipped)?;
+        if let Some(file) = self.current_file.as_mut() {
+            self.content_decoder.finish(file, sink)?;
+        }
+        self.content_reservation.release();
+        self.current_file.take().map(TryInto::try_into).transpose()
+    }
+
+    // Warnings such as "no such file(s)." are skipped, anything worse is an error
+    fn finish_error(
+        error: &mut Option<P4ServerMessage>,
+        records_skipped: &mut u64,
+    ) -> Result<(), P4Error> {
+        match error.take() {
+            Some(error) if error.severity > E_WARN => Err(P4Error::Server(error)),
+            Some(_) => {
+                *records_skipped += 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<ReadT: io::Read> Iterator for P4PrintIterator<ReadT> {
+    type Item = Result<P4PrintedFile, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(result) = self.process_state.before_next() {
+            return result;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = self.process_state.span().entered();
+
+        let result = self.next_file(None);
+        let bytes_read = self.parser.bytes_read();
+        self.process_state.after_next(result, bytes_read)
+    }
+}
+
+// Decodes the content of one file at a time as its content records arrive
+#[derive(Default)]
+struct ContentDecoder {
+    decoding: P4ContentDecoding,
+    // Whether the current file's first content has been seen, which decides the encoding
+    started: bool,
+    utf16: Option<Utf16Decoder>,
+    #[cfg(feature = "encoding")]
+    charset: Option<&'static Encoding>,
+    // Unicode files in a charset other than UTF-8, a character may be split between content records
+    #[cfg(feature = "encoding")]
+    charset_decoder: Option<Decoder>,
+}
+
+impl ContentDecoder {
+    fn decode<'a>(&mut self, file_type: Option<&str>, data: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.decoding == P4ContentDecoding::Raw {
+            return Cow::Borrowed(data);
+        }
+
+        let mut data = data;
+        if !self.started {
+            self.started = true;
+            let file_type = file_type.unwrap_or_default();
+            let base_type = file_type.split('+').next().unwrap_or_default();
+            let utf16_bom = data.starts_with(b"\xff\xfe") || data.starts_with(b"\xfe\xff");
+            match base_type {
+                "utf16" => self.utf16 = Some(Utf16Decoder::default()),
+                // Unicode files come in the client's charset, e.g. utf16 or utf8-bom
+                "unicode" | "utf8" if utf16_bom => self.utf16 = Some(Utf16Decoder::default()),
+                "unicode" | "utf8" if data.starts_with(b"\xef\xbb\xbf") => data = &data[3..],
+                #[cfg(feature = "encoding")]
+                "unicode" => {
+                    self.charset_decoder =
+                        self.charset.map(Encoding::new_decoder_without_bom_handling)
+                }
+                _ => {}
+            }
+        }
+
+        #[cfg(feature = "encoding")]
+        if let Some(decoder) = self.charset_decoder.as_mut() {
+            let mut decoded = Vec::new();
+            decode_charset(decoder, data, false, &mut decoded);
+            return Cow::Owned(decoded);
+        }
+
+        match self.utf16.as_mut() {
+            Some(utf16) => {
+                let mut decoded = Vec::with_capacity(data.len());
+                utf16.decode(data, &mut decoded);
+                Cow::Owned(decoded)
+            }
+            None => Cow::Borrowed(data),
+        }
+    }
+
+    // Writes what's left of the file, a code unit cut short, and resets for the next file
+    fn finish<WriteT: io::Write + ?Sized>(
+        &mut self,
+        file: &mut InterimP4PrintedFile,
+        sink: Option<&mut WriteT>,
+    ) -> Result<(), P4Error> {
+        self.started = false;
+        let mut rest = Vec::new();
+        if let Some(mut utf16) = self.utf16.take() {
+            utf16.finish(&mut rest);
+        }
+        #[cfg(feature = "encoding")]
+        if let Some(mut decoder) = self.charset_decoder.take() {
+            decode_charset(&mut decoder, &[], true, &mut rest);
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+        match sink {
+            Some(sink) => sink.write_all(&rest)?,
+            None => file.content.extend_from_slice(&rest),
+        }
+        Ok(())
+    }
+}
+
+// The encoding_rs encoding of a P4CHARSET, None for the UTF-8 and UTF-16 ones that are handled without it
+#[cfg(feature = "encoding")]
+fn charset_encoding(charset: &str) -> Option<&'static Encoding> {
+    let label = match charset {
+        "winansi" => "windows-1252".to_string(),
+        "cp866" => "ibm866".to_string(),
+        "cp936" => "gbk".to_string(),
+        "cp949" => "euc-kr".to_string(),
+        "cp950" => "big5".to_string(),
+        "shiftjis" => "shift_jis".to_string(),
+        "eucjp" => "euc-jp".to_string(),
+        "macosroman" => "macintosh".to_string(),
+        charset if charset.starts_with("utf") || charset == "none" => return None,
+        charset => match (charset.strip_prefix("cp"), charset.strip_prefix("iso8859-")) {
+            (Some(code_page), _) => format!("windows-{}", code_page),
+            (_, Some(part)) => format!("iso-8859-{}", part),
+            // koi8-r is the same in both
+            _ => charset.to_string(),
+        },
+    };
+    Encoding::for_label(label.as_bytes())
+}
+
+// Appends `data` as UTF-8, `last` flushes a character cut short as U+FFFD
+#[cfg(feature = "encoding")]
+fn decode_charset(decoder: &mut Decoder, data: &[u8], last: bool, output: &mut Vec<u8>) {
+    let capacity = decoder
+        .max_utf8_buffer_length(data.len())
+        .unwrap_or(data.len() * 3 + 16);
+    let start = output.len();
+    output.resize(start + capacity, 0);
+    let (_, _, written, _) = decoder.decode_to_utf8(data, &mut output[start..], last);
+    output.truncate(start + written);
+}
+
+// UTF-16 to UTF-8, in the byte order of the BOM or little endian without one
+#[derive(Debug, Default)]
+struct Utf16Decoder {
+    little_endian: Option<bool>,
+    // A code unit or surrogate pair split between content records
+    pending: Vec<u8>,
+}
+
+impl Utf16Decoder {
+    fn decode(&mut self, data: &[u8], output: &mut Vec<u8>) {
+        self.pending.extend_from_slice(data);
+        let little_endian = match self.little_endian {
+            Some(little_endian) => little_endian,
+            None if self.pending.len() < 2 => return,
+            None => {
+                let little_endian = self.pending[..2] != *b"\xfe\xff";
+                if matches!(self.pending[..2], [0xff, 0xfe] | [0xfe, 0xff]) {
+                    self.pending.drain(..2);
+                }
+                self.little_endian = Some(little_endian);
+                little_endian
+            }
+        };
+
+        let mut units = self
+            .pending
+            .chunks_exact(2)
+            .map(|pair| match little_endian {
+                true => u16::from_le_bytes([pair[0], pair[1]]),
+                false => u16::from_be_bytes([pair[0], pair[1]]),
+            })
+            .collect::<Vec<_>>();
+        // A high surrogate waits for the low one in the next record
+        if units
+            .last()
+            .is_some_and(|unit| (0xd800..0xdc00).contains(unit))
+        {
+            units.pop();
+        }
+        self.pending.drain(..units.len() * 2);
+
+        let mut buffer = [0; 4];
+        for c in char::decode_utf16(units) {
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+            output.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            let mut buffer = [0; 4];
+            output.extend_from_slice(
+                char::REPLACEMENT_CHARACTER
+                    .encode_utf8(&mut buffer)
+                    .as_bytes(),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct InterimP4PrintedFile {
+    depot_path: Option<String>,
+    revision: Option<u32>,
+    change: Option<u32>,
+    action: Option<String>,
+    file_type: Option<String>,
+    time: Option<u32>,
+    content: Vec<u8>,
+}
+
+impl InterimP4PrintedFile {
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "depotFile" => self.depot_path = Some(value.to_string()),
+            "rev" => self.revision = Some(parse_field(value, "Invalid revision")?),
+            "change" => self.change = Some(parse_field(value, "Invalid changelist")?),
+            "action" => self.action = Some(value.to_string()),
+            "type" => self.file_type = Some(value.to_string()),
+            "time" => self.time = Some(parse_field(value, "Invalid time")?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl TryInto<P4PrintedFile> for InterimP4PrintedFile {
+    type Error = P4Error;
+
+    fn try_into(self) -> Result<P4PrintedFile, Self::Error> {
+        Ok(P4PrintedFile {
+            depot_path: self
+                .depot_path
+                .ok_or(P4Error::InvalidRecord("Missing depot path"))?,
+            revision: self
+                .revision
+                .ok_or(P4Error::InvalidRecord("Missing revision"))?,
+            change: self
+                .change
+                .ok_or(P4Error::InvalidRecord("Missing changelist"))?,
+            action: self
+                .action
+                .ok_or(P4Error::InvalidRecord("Missing action"))?,
+            file_type: self
+                .file_type
+                .ok_or(P4Error::InvalidRecord("Missing file type"))?,
+            time: self.time.ok_or(P4Error::InvalidRecord("Missing time"))?,
+            content: self.content,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print() {
+        let stat = |path: &'static str| {
+            [
+                ("code", "stat"),
+                ("depotFile", path),
+                ("rev", "2"),
+                ("change", "12"),
+                ("action", "edit"),
+                ("type", "text"),
+                ("time", "1743724741"),
+                ("fileSize", "12"),
+            ]
+        };
+
+        let mut data = Vec::new();
+        write_py_dict(&mut data, stat("//depot/a.txt")).unwrap();
+        write_py_dict(&mut data, [("code", "text"), ("data", "hello ")]).unwrap();
+        write_py_dict(&mut data, [("code", "text"), ("data", "world\n")]).unwrap();
+        write_py_dict(&mut data, stat("//depot/b.bin")).unwrap();
+        write_py_dict(
+            &mut data,
+            [(&b"code"[..], &b"binary"[..]), (b"data", b"\xff\x00")],
+        )
+        .unwrap();
+
+        let files = P4PrintIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].depot_path, "//depot/a.txt");
+        assert_eq!(files[0].content, b"hello world\n");
+        assert_eq!(files[1].content, b"\xff\x00");
+
+        // Errors end the iteration
+        let mut data = Vec::new();
+        write_py_dict(
+            &mut data,
+            [
+                ("code", "error"),
+                ("data", "Invalid revision number '#x'.\n"),
+                ("severity", "3"),
+                ("generic", "1"),
+            ],
+        )
+        .unwrap();
+        let mut files = P4PrintIterator::new_from_reader(&data[..]);
+        assert!(matches!(files.next(), Some(Err(P4Error::Server(_)))));
+        assert!(files.next().is_none());
+
+        // utf16 content decoded to UTF-8, with the surrogate pair of the emoji split between records
+        let utf16_stat = |path: &'static str, file_type: &'static str| {
+            [
+                ("code", "stat"),
+                ("depotFile", path),
+                ("rev", "1"),
+                ("change", "3"),
+                ("action", "add"),
+                ("type", file_type),
+                ("time", "1743724741"),
+            ]
+        };
+        let encoded = "h\u{e9}\u{1f600}\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let mut data = Vec::new();
+        write_py_dict(&mut data, utf16_stat("//depot/a.txt", "utf16")).unwrap();
+        for chunk in [&b"\xff\xfe"[..], &encoded[..5], &encoded[5..]] {
+            write_py_dict(&mut data, [(&b"code"[..], &b"text"[..]), (b"data", chunk)]).unwrap();
+        }
+        write_py_dict(&mut data, utf16_stat("//depot/b.txt", "unicode")).unwrap();
+        write_py_dict(
+            &mut data,
+            [("code", "text"), ("data", "\u{feff}caf\u{e9}\n")],
+        )
+        .unwrap();
+
+        let files = P4PrintIterator::new_from_reader(&data[..])
+            .with_content_decoding(P4ContentDecoding::Utf8)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files[0].content, "h\u{e9}\u{1f600}\n".as_bytes());
+        assert_eq!(files[1].content, "caf\u{e9}\n".as_bytes());
+
+        let mut written = Vec::new();
+        let mut files = P4PrintIterator::new_from_reader(&data[..])
+            .with_content_decoding(P4ContentDecoding::Utf8);
+        files.next_to_writer(&mut written).unwrap().unwrap();
+        assert_eq!(written, "h\u{e9}\u{1f600}\n".as_bytes());
+
+        let raw = P4PrintIterator::new_from_reader(&data[..])
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(raw.content[..2], *b"\xff\xfe");
+        assert_eq!(raw.kind(), P4FileKind::Regular);
+        assert_eq!(raw.symlink_target(), None);
+
+        let link = P4PrintedFile {
+            file_type: "symlink".to_string(),
+            content: b"../lib/a.so\n".to_vec(),
+            ..raw
+        };
+        assert_eq!(link.symlink_target(), Some("../lib/a.so"));
+        assert_eq!(P4FileKind::from_file_type("apple+C"), P4FileKind::Apple);
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn test_charset_decoding() {
+        let stat = |file_type| {
+            [
+                ("code", "stat"),
+                ("depotFile", "//depot/a.txt"),
+                ("rev", "1"),
+                ("change", "3"),
+                ("action", "add"),
+                ("type", file_type),
+                ("time", "1743724741"),
+            ]
+        };
+        // "日本\n" in Shift-JIS, split within a character
+        let encoded = b"\x93\xfa\x96\x7b\n";
+        let mut data = Vec::new();
+        for file_type in ["unicode", "text"] {
+            write_py_dict(&mut data, stat(file_type)).unwrap();
+            for chunk in [&encoded[..3], &encoded[3..]] {
+                write_py_dict(&mut data, [(&b"code"[..], &b"text"[..]), (b"data", chunk)]).unwrap();
+            }
+        }
+
+        let files = P4PrintIterator::new_from_reader(&data[..])
+            .with_content_decoding(P4ContentDecoding::Utf8)
+            .with_charset("shiftjis")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files[0].content, "\u{65e5}\u{672c}\n".as_bytes());
+        // Only unicode files are in the client's charset
+        assert_eq!(files[1].content, encoded);
+
+        assert_eq!(charset_encoding("winansi"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(charset_encoding("cp1251"), Some(encoding_rs::WINDOWS_1251));
+        assert_eq!(
+            charset_encoding("iso8859-15"),
+            Some(encoding_rs::ISO_8859_15)
+        );
+        assert_eq!(charset_encoding("utf8"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_fetch_many() {
+        use crate::testing::*;
+
+        let fstat = |path: &'static str, rev: &'static str, digest: &'static str| {
+            vec![
+                ("code", "stat"),
+                ("depotFile", path),
+                ("headRev", rev),
+                ("headType", "text"),
+                ("digest", digest),
+            ]
+        };
+        let stat = |path: &'static str, rev: &'static str| {
+            vec![
+                ("code", "stat"),
+                ("depotFile", path),
+                ("rev", rev),
+                ("change", "12"),
+                ("action", "edit"),
+                ("type", "text"),
+                ("time", "1743724741"),
+            ]
+        };
+        let context = MockP4::new()
+            .with_records(
+                "-x - fstat -Ol",
+                [
+                    // The digest of "hello\n"
+                    fstat("//depot/a.txt", "2", "B1946AC92492D2347C6235B4D2611184"),
+                    fstat("//depot/b.txt", "1", "00000000000000000000000000000000"),
+                ],
+            )
+            .with_records(
+                "-x - print",
+                [
+                    stat("//depot/a.txt", "2"),
+                    vec![("code", "text"), ("data", "hello\n")],
+                    stat("//depot/b.txt", "1"),
+                    vec![("code", "text"), ("data", "truncat")],
+                    stat("//depot/c.txt", "4"),
+                    vec![("code", "text"), ("data", "unchecked\n")],
+                    vec![
+                        ("code", "stat"),
+                        ("depotFile", "//depot/d.lnk"),
+                        ("rev", "1"),
+                        ("change", "12"),
+                        ("action", "add"),
+                        ("type", "symlink"),
+                        ("time", "1743724741"),
+                    ],
+                    vec![("code", "text"), ("data", "c.txt\n")],
+                ],
+            )
+            .into_context();
+
+        let dir = std::env::temp_dir().join(format!("p4_helper_fetch_{}", std::process::id()));
+        let paths = [
+            "//depot/a.txt#2",
+            "//depot/b.txt#1",
+            "//depot/c.txt#4",
+            "//depot/d.lnk#1",
+        ]
+        .map(String::from);
+        let destination = P4PrintDestination::Directory(dir.clone());
+        let report = fetch_many_from_context(&context, paths, 1, &destination).unwrap();
+        assert_eq!(
+            report,
+            P4FetchReport {
+                files: 3,
+                bytes: 22,
+                unverified: 2,
+                corrupt: vec!["//depot/b.txt#1".to_string()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(fs::read(dir.join("depot/a.txt")).unwrap(), b"hello\n");
+        assert!(!dir.join("depot/b.txt").exists());
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(dir.join("depot/d.lnk")).unwrap(),
+            PathBuf::from("c.txt")
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_fetch_many_failed_path() {
+        use crate::testing::*;
+
+        let stat = |path: &'static str| {
+            vec![
+                ("code", "stat"),
+                ("depotFile", path),
+                ("rev", "1"),
+                ("change", "12"),
+                ("action", "add"),
+                ("type", "text"),
+                ("time", "1743724741"),
+            ]
+        };
+        let refused = vec![
+            ("code", "error"),
+            ("data", "//depot/secret.txt - no permission"),
+            ("severity", "3"),
+            ("generic", "7"),
+        ];
+        let context = MockP4::new()
+            .with_records("-x - fstat -Ol", Vec::<Vec<(&str, &str)>>::new())
+            .with_records(
+                "-x - print",
+                [
+                    stat("//depot/a.txt"),
+                    vec![("code", "text"), ("data", "a\n")],
+                    refused.clone(),
+                ],
+            )
+            .with_records(
+                "print //depot/a.txt#1",
+                [
+                    stat("//depot/a.txt"),
+                    vec![("code", "text"), ("data", "a\n")],
+                ],
+            )
+            .with_records("print //depot/secret.txt#1", [refused])
+            .with_records(
+                "print //depot/c.txt#1",
+                [
+                    stat("//depot/c.txt"),
+                    vec![("code", "text"), ("data", "c\n")],
+                ],
+            )
+            .into_context();
+
+        let dir =
+            std::env::temp_dir().join(format!("p4_helper_fetch_failed_{}", std::process::id()));
+        let paths =
+            ["//depot/a.txt#1", "//depot/secret.txt#1", "//depot/c.txt#1"].map(String::from);
+        let destination = P4PrintDestination::Directory(dir.clone());
+        let report = fetch_many_from_context(&context, paths, 1, &destination).unwrap();
+        assert_eq!((report.files, report.bytes), (2, 4));
+        assert_eq!(
+            report.failed,
+            [(
+                "//depot/secret.txt#1".to_string(),
+                "//depot/secret.txt - no permission".to_string()
+            )]
+        );
+        assert_eq!(fs::read(dir.join("depot/c.txt")).unwrap(), b"c\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "process", unix))]
+    fn test_symlink_stays_within() {
+        let root = Path::new("/work/out");
+        let target = root.join("depot/main/lib.so");
+        assert!(stays_within(root, &target, "lib.so.1"));
+        assert!(stays_within(root, &target, "./../../depot/other/lib.so"));
+        assert!(!stays_within(root, &target, "../../../etc"));
+        assert!(!stays_within(root, &target, "/etc/passwd"));
+        assert!(!stays_within(root, Path::new("/elsewhere/a"), "b"));
+    }
+}
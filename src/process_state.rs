@@ -0,0 +1,278 @@
+// == Std crates
+#[cfg(feature = "process")]
+use std::process;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// == Internal crates
+use crate::cancel::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+use crate::metrics::*;
+
+// Book-keeping shared by the iterators that drive a p4 process
+#[derive(Debug, Default)]
+pub(crate) struct P4ProcessState {
+    #[cfg(feature = "process")]
+    pub(crate) p4_process: Option<process::Child>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    records_yielded: u64,
+    bytes_read: u64,
+    finished: bool,
+    metrics: Option<Arc<Metrics>>,
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+    exit_code: Option<i32>,
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::Span>,
+}
+
+impl P4ProcessState {
+    #[cfg(feature = "process")]
+    // Hands the running process and the relevant context settings over to the iterator
+    pub(crate) fn attach(
+        &mut self,
+        p4_process: Option<process::Child>,
+        context: &P4Context,
+        #[allow(unused_variables)] args: &[&str],
+    ) {
+        self.p4_process = p4_process;
+        if let Some(cancellation) = context.cancellation_token() {
+            self.cancellation = Some(cancellation.clone());
+        }
+        self.metrics = Some(context.shared_metrics());
+        self.started = Some(Instant::now());
+        #[cfg(feature = "tracing")]
+        {
+            self.span = Some(tracing::debug_span!(
+                "p4_command",
+                command = args.first().copied().unwrap_or_default(),
+                args = ?args,
+            ));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn span(&self) -> tracing::Span {
+        self.span.clone().unwrap_or_else(tracing::Span::none)
+    }
+
+    // Checked before reading the next record, returns Some if the iterator should stop here
+    pub(crate) fn before_next<T>(&mut self) -> Option<Option<Result<T, P4Error>>> {
+        if self.finished {
+            return Some(None);
+        }
+
+        if self.is_cancelled() {
+            self.finish(true);
+            return Some(Some(Err(self.cancelled_error())));
+        }
+
+        None
+    }
+
+    // Turns the result of reading a record into the iterator result, cleaning up the process when done
+    pub(crate) fn after_next<T>(
+        &mut self,
+        result: Result<Option<T>, P4Error>,
+        bytes_read: u64,
+    ) -> Option<Result<T, P4Error>> {
+        if let Some(metrics) = &self.metrics {
+            metrics.add_bytes(bytes_read.saturating_sub(self.bytes_read));
+            match &result {
+                Ok(Some(_)) => metrics.add_record(),
+                Err(P4Error::Parse(_) | P4Error::InvalidRecord(_)) => metrics.add_parse_error(),
+                _ => {}
+            }
+        }
+        self.bytes_read = bytes_read;
+
+        match result {
+            Ok(Some(item)) => {
+                self.records_yielded += 1;
+                Some(Ok(item))
+            }
+            Ok(None) => {
+                self.finish(false);
+                None
+            }
+            Err(e) => {
+                // The stream can't be trusted past an error, so stop here
+                self.finish(true);
+                if self.is_cancelled() {
+                    Some(Err(self.cancelled_error()))
+                } else {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(parent: &self.span(), error = %e, "p4 command failed");
+                    Some(Err(e))
+                }
+            }
+        }
+    }
+
+    pub(crate) fn records_yielded(&self) -> u64 {
+        self.records_yielded
+    }
+
+    // None until the iterator has finished
+    pub(crate) fn summary(&self, records_skipped: u64) -> Option<P4IteratorSummary> {
+        self.finished.then_some(P4IteratorSummary {
+            records_yielded: self.records_yielded,
+            records_skipped,
+            bytes_read: self.bytes_read,
+            elapsed: self.elapsed,
+            exit_code: self.exit_code,
+        })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn cancelled_error(&self) -> P4Error {
+        P4Error::Cancelled {
+            records_yielded: self.records_yielded,
+        }
+    }
+
+    #[cfg_attr(not(feature = "process"), allow(unused_variables))]
+    fn finish(&mut self, kill: bool) {
+        self.finished = true;
+
+        #[cfg(feature = "process")]
+        let exit_code = self
+            .p4_process
+            .take()
+            .and_then(|mut p4_process| {
+                if kill {
+                    let _ = p4_process.kill();
+                }
+                p4_process.wait().ok()
+            })
+            .and_then(|status| status.code());
+        #[cfg(not(feature = "process"))]
+        let exit_code: Option<i32> = None;
+        self.exit_code = exit_code;
+        self.elapsed = self.started.map(|started| started.elapsed());
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            parent: &self.span(),
+            records = self.records_yielded,
+            bytes_parsed = self.bytes_read,
+            duration_ms = self.elapsed.map(|elapsed| elapsed.as_millis() as u64),
+            exit_code,
+            killed = kill,
+            "p4 command finished"
+        );
+
+        if let Some(metrics) = &self.metrics {
+            metrics.command_finished();
+        }
+    }
+}
+
+// An iterator dropped before the end, e.g. on a break, a take(n) or a `?`, still kills and reaps its p4
+impl Drop for P4ProcessState {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "process")]
+    use super::*;
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "spawn"))]
+    fn test_drop_reaps_process() {
+        use crate::{files::*, parsers::py_dict::to_py_dict_bytes};
+        use std::{env, fs, os::unix::fs::PermissionsExt, path::Path};
+
+        // Writes its pid, prints two files and then hangs like a slow server
+        let dir = env::temp_dir().join(format!("p4_helper_drop_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |depot_path| {
+            [
+                ("code", "stat"),
+                ("depotFile", depot_path),
+                ("rev", "1"),
+                ("change", "12"),
+                ("action", "add"),
+                ("type", "text"),
+                ("time", "1743724741"),
+            ]
+        };
+        let records = to_py_dict_bytes(&[&file("//depot/a.txt"), &file("//depot/b.txt")]);
+        fs::write(dir.join("records"), records).unwrap();
+        let script = dir.join("p4.sh");
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho $$ > {}\ncat {}\nexec sleep 30\n",
+                dir.join("pid").display(),
+                dir.join("records").display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // Run as p4 itself, /bin/sh would take the -ztag -G before the script for its own options
+        let context = P4Context::new().with_p4_path(&script);
+        let mut files = P4FilesIterator::new_from_context(&context, "//depot/...").unwrap();
+        assert_eq!(files.next().unwrap().unwrap().depot_path, "//depot/a.txt");
+        drop(files);
+
+        // A zombie would still be listed in /proc
+        let pid = fs::read_to_string(dir.join("pid")).unwrap();
+        let reaped = !Path::new(&format!("/proc/{}", pid.trim())).exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(reaped);
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_drop_finishes_metrics() {
+        use crate::{files::*, testing::*};
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let file = |depot_path| {
+            [
+                ("code", "stat"),
+                ("depotFile", depot_path),
+                ("rev", "1"),
+                ("change", "12"),
+                ("action", "add"),
+                ("type", "text"),
+                ("time", "1743724741"),
+            ]
+        };
+        let finished = Arc::new(AtomicU64::new(0));
+        let finished_clone = finished.clone();
+        let context = MockP4::new()
+            .with_records(
+                "files //depot/...",
+                [file("//depot/a.txt"), file("//depot/b.txt")],
+            )
+            .into_context()
+            .with_metrics_callback(move |_| {
+                finished_clone.fetch_add(1, Ordering::Relaxed);
+            });
+
+        // Stopping after the first file still counts as a finished command
+        let mut files = P4FilesIterator::new_from_context(&context, "//depot/...").unwrap();
+        files.next().unwrap().unwrap();
+        assert_eq!(finished.load(Ordering::Relaxed), 0);
+        drop(files);
+        assert_eq!(finished.load(Ordering::Relaxed), 1);
+        assert_eq!(context.metrics().snapshot().records_parsed, 1);
+    }
+}
@@ -0,0 +1,156 @@
+// == Std crates
+use std::sync::{Arc, Mutex};
+
+// How far a long operation has got. Counts start again at each phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgressUpdate<'a> {
+    // What the operation is doing, e.g. "preview" then "sync"
+    pub phase: &'static str,
+    pub records: u64,
+    pub records_total: Option<u64>,
+    pub bytes: u64,
+    pub bytes_total: Option<u64>,
+    // What was just handled, e.g. a depot path or changelist number. None at the start of a phase.
+    pub item: Option<&'a str>,
+}
+
+// Where sync, describe_each, the depot walker and the exporters report progress, given with their
+// with_progress. Called on the thread doing the work, so it should return quickly.
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, update: &ProgressUpdate<'_>);
+}
+
+impl<F: Fn(&ProgressUpdate<'_>) + Send + Sync> ProgressSink for F {
+    fn report(&self, update: &ProgressUpdate<'_>) {
+        self(update)
+    }
+}
+
+// One sink shared by several operations
+impl<SinkT: ProgressSink + ?Sized> ProgressSink for Arc<SinkT> {
+    fn report(&self, update: &ProgressUpdate<'_>) {
+        (**self).report(update)
+    }
+}
+
+// The running counts of one operation, reporting nothing without a sink
+#[derive(Clone, Default)]
+pub(crate) struct ProgressTracker {
+    sink: Option<Arc<dyn ProgressSink>>,
+    phase: &'static str,
+    records: u64,
+    records_total: Option<u64>,
+    bytes: u64,
+    bytes_total: Option<u64>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(sink: Option<Arc<dyn ProgressSink>>) -> Self {
+        ProgressTracker {
+            sink,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn start_phase(
+        &mut self,
+        phase: &'static str,
+        records_total: Option<u64>,
+        bytes_total: Option<u64>,
+    ) {
+        *self = ProgressTracker {
+            sink: self.sink.take(),
+            phase,
+            records_total,
+            bytes_total,
+            ..Default::default()
+        };
+        self.report(None);
+    }
+
+    // Starts `phase` unless one has been started, for iterators that report from their first item
+    pub(crate) fn ensure_phase(&mut self, phase: &'static str) {
+        if self.phase.is_empty() {
+            self.start_phase(phase, None, None);
+        }
+    }
+
+    pub(crate) fn record(&mut self, item: &str, bytes: u64) {
+        self.records += 1;
+        self.bytes += bytes;
+        self.report(Some(item));
+    }
+
+    fn report(&self, item: Option<&str>) {
+        if let Some(sink) = &self.sink {
+            sink.report(&ProgressUpdate {
+                phase: self.phase,
+                records: self.records,
+                records_total: self.records_total,
+                bytes: self.bytes,
+                bytes_total: self.bytes_total,
+                item,
+            });
+        }
+    }
+}
+
+// The phase, records, bytes and item of one update
+pub type LoggedProgress = (&'static str, u64, u64, Option<String>);
+
+// A progress sink that keeps every update, clones share them
+#[derive(Debug, Clone, Default)]
+pub struct ProgressLog {
+    updates: Arc<Mutex<Vec<LoggedProgress>>>,
+}
+
+impl ProgressLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Every update so far, in order
+    pub fn updates(&self) -> Vec<LoggedProgress> {
+        self.updates.lock().unwrap().clone()
+    }
+}
+
+impl ProgressSink for ProgressLog {
+    fn report(&self, update: &ProgressUpdate<'_>) {
+        self.updates.lock().unwrap().push((
+            update.phase,
+            update.records,
+            update.bytes,
+            update.item.map(str::to_string),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_tracker() {
+        let log = ProgressLog::new();
+        let mut tracker = ProgressTracker::new(Some(Arc::new(log.clone())));
+        tracker.start_phase("sync", Some(2), Some(30));
+        tracker.record("//depot/a.txt", 10);
+        tracker.record("//depot/b.txt", 20);
+        tracker.start_phase("verify", None, None);
+        assert_eq!(
+            log.updates(),
+            [
+                ("sync", 0, 0, None),
+                ("sync", 1, 10, Some("//depot/a.txt".to_string())),
+                ("sync", 2, 30, Some("//depot/b.txt".to_string())),
+                ("verify", 0, 0, None),
+            ]
+        );
+
+        // Without a sink it only counts
+        let mut tracker = ProgressTracker::default();
+        tracker.record("//depot/a.txt", 10);
+        assert_eq!(tracker.bytes, 10);
+    }
+}
@@ -0,0 +1,288 @@
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::*;
+
+// Where Swarm publishes its URL, for tools that link reviews
+pub const SWARM_URL: &str = "P4.Swarm.URL";
+
+// A server property as listed by `p4 property -l`. A property can be set several times with different
+// sequence numbers, and for a single user or group rather than everyone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4Property {
+    pub name: String,
+    pub value: String,
+    pub sequence: Option<u32>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+impl P4Property {
+    pub fn new(name: &str, value: &str) -> Self {
+        P4Property {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_sequence(mut self, sequence: u32) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    // A property is for a user or a group, not both, the user wins if both are set
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn with_group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    // `property -a` or `property -d` with the options that pick out this property
+    #[cfg(feature = "process")]
+    fn update_args(&self, operation: &str) -> Vec<String> {
+        let mut args = vec!["property".to_string(), operation.to_string()];
+        args.extend(["-n".to_string(), self.name.clone()]);
+        if let Some(sequence) = self.sequence {
+            args.extend(["-s".to_string(), sequence.to_string()]);
+        }
+        if let Some(user) = &self.user {
+            args.extend(["-u".to_string(), user.clone()]);
+        } else if let Some(group) = &self.group {
+            args.extend(["-g".to_string(), group.clone()]);
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4PropertyQuery {
+    name: Option<String>,
+    all_scopes: bool,
+}
+
+impl P4PropertyQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    // Also list the properties set for other users and groups, needs admin
+    pub fn with_all_scopes(mut self) -> Self {
+        self.all_scopes = true;
+        self
+    }
+}
+
+impl From<&str> for P4PropertyQuery {
+    fn from(name: &str) -> Self {
+        P4PropertyQuery::new().with_name(name)
+    }
+}
+
+// The value of a property that applies to the current user, None if it isn't set
+#[cfg(feature = "spawn")]
+pub fn get(name: &str) -> Result<Option<String>, P4Error> {
+    get_from_context(&P4Context::default(), name)
+}
+
+#[cfg(feature = "process")]
+pub fn get_from_context(context: &P4Context, name: &str) -> Result<Option<String>, P4Error> {
+    let properties = P4PropertyIterator::new_from_context(context, P4PropertyQuery::from(name))?
+        .collect::<Result<Vec<_>, _>>()?;
+    // Without -A the server only lists the value in effect for the current user
+    Ok(properties.into_iter().next().map(|property| property.value))
+}
+
+// Adds the property, or replaces its value, needs admin
+#[cfg(feature = "spawn")]
+pub fn set(property: &P4Property) -> Result<(), P4Error> {
+    set_from_context(&P4Context::default(), property)
+}
+
+#[cfg(feature = "process")]
+pub fn set_from_context(context: &P4Context, property: &P4Property) -> Result<(), P4Error> {
+    let mut args = property.update_args("-a");
+    args.extend(["-v".to_string(), property.value.clone()]);
+    run_to_end(context, &args)
+}
+
+// Deletes the property, the value is ignored, needs admin
+#[cfg(feature = "spawn")]
+pub fn delete(property: &P4Property) -> Result<(), P4Error> {
+    delete_from_context(&P4Context::default(), property)
+}
+
+#[cfg(feature = "process")]
+pub fn delete_from_context(context: &P4Context, property: &P4Property) -> Result<(), P4Error> {
+    run_to_end(context, &property.update_args("-d"))
+}
+
+#[cfg(feature = "process")]
+fn run_to_end(context: &P4Context, args: &[String]) -> Result<(), P4Error> {
+    let args = args.iter().map(String::as_str).collect();
+    P4DictIterator::new_from_context(context, args)?.try_for_each(|dict| dict.map(drop))
+}
+
+impl P4Command for P4PropertyQuery {
+    type Item = P4Property;
+    type Fields = InterimP4Property;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["property".to_string(), "-l".to_string()];
+        if self.all_scopes {
+            args.push("-A".to_string());
+        }
+        if let Some(name) = &self.name {
+            args.extend(["-n".to_string(), name.clone()]);
+        }
+        args
+    }
+}
+
+pub type P4PropertyIterator<ReadT> = P4CommandIterator<P4PropertyQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4PropertyIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(query: P4PropertyQuery) -> Result<P4PropertyIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), query)
+    }
+
+    pub fn new_from_context(
+        context: &P4Context,
+        query: P4PropertyQuery,
+    ) -> Result<P4PropertyIterator<P4Output>, P4Error> {
+        Self::spawn(context, query)
+    }
+}
+
+// The parsing state of a P4Property
+#[derive(Debug, Default)]
+pub struct InterimP4Property {
+    name: Option<String>,
+    value: Option<String>,
+    sequence: Option<u32>,
+    user: Option<String>,
+    group: Option<String>,
+}
+
+impl P4RecordFields for InterimP4Property {
+    type Output = P4Property;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "name" => self.name = Some(value.to_string()),
+            "value" => self.value = Some(value.to_string()),
+            "sequence" => self.sequence = Some(parse_field(value, "Invalid sequence")?),
+            "user" => self.user = Some(value.to_string()),
+            "group" => self.group = Some(value.to_string()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4Property, P4Error> {
+        Ok(P4Property {
+            name: self.name.ok_or(P4Error::InvalidRecord("Missing name"))?,
+            value: self.value.unwrap_or_default(),
+            sequence: self.sequence,
+            user: self.user,
+            group: self.group,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_property_records() {
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("name", "P4.Swarm.URL"),
+                ("sequence", "0"),
+                ("value", "https://swarm.example.com/"),
+            ],
+            &[
+                ("code", "stat"),
+                ("name", "P4.Swarm.URL"),
+                ("sequence", "1"),
+                ("group", "qa"),
+                ("value", "https://qa-swarm.example.com/"),
+            ],
+        ]);
+
+        let properties = P4PropertyIterator::new_from_reader(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            properties[1],
+            P4Property::new(SWARM_URL, "https://qa-swarm.example.com/")
+                .with_sequence(1)
+                .with_group("qa")
+        );
+        assert_eq!(properties[0].user, None);
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_property() {
+        use crate::testing::*;
+
+        let mock = MockP4::new()
+            .with_records(
+                "property -l -n P4.Swarm.URL",
+                [[
+                    ("code", "stat"),
+                    ("name", "P4.Swarm.URL"),
+                    ("sequence", "0"),
+                    ("value", "https://swarm.example.com/"),
+                ]],
+            )
+            .with_records("property -l -n P4.Missing", [] as [[(&str, &str); 0]; 0])
+            .with_records(
+                "property -a -n P4.Swarm.URL -s 1 -g qa -v https://qa-swarm.example.com/",
+                [[("code", "info"), ("data", "Property P4.Swarm.URL saved.")]],
+            )
+            .with_error(
+                "property -d -n P4.Swarm.URL",
+                3,
+                1,
+                "You don't have permission for this operation.",
+            );
+        let context = mock.into_context();
+
+        assert_eq!(
+            get_from_context(&context, SWARM_URL).unwrap().as_deref(),
+            Some("https://swarm.example.com/")
+        );
+        assert_eq!(get_from_context(&context, "P4.Missing").unwrap(), None);
+
+        let qa = P4Property::new(SWARM_URL, "https://qa-swarm.example.com/")
+            .with_sequence(1)
+            .with_group("qa");
+        set_from_context(&context, &qa).unwrap();
+        assert!(matches!(
+            delete_from_context(&context, &P4Property::new(SWARM_URL, "")),
+            Err(P4Error::Server(_))
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+// Python bindings, built with `cargo build --features python` (or maturin) as the `p4_helper` module
+
+// == Std crates
+use std::{fs, io};
+
+// == Internal crates
+use crate::changes::*;
+use crate::describe::*;
+use crate::error::*;
+use crate::parsers::{py_dict::*, ztag::*};
+use crate::*;
+
+// == External crates
+use pyo3::{
+    exceptions::{PyIOError, PyRuntimeError, PyTimeoutError, PyValueError},
+    prelude::*,
+};
+
+impl From<P4Error> for PyErr {
+    fn from(error: P4Error) -> Self {
+        match error {
+            P4Error::Io(e) | P4Error::Spawn(e) => PyIOError::new_err(e.to_string()),
+            P4Error::Timeout => PyTimeoutError::new_err(error.to_string()),
+            P4Error::Parse(_) | P4Error::InvalidRecord(_) => {
+                PyValueError::new_err(error.to_string())
+            }
+            _ => PyRuntimeError::new_err(error.to_string()),
+        }
+    }
+}
+
+type FileReader = io::BufReader<fs::File>;
+
+fn open_file(path: &str) -> PyResult<FileReader> {
+    let file = fs::File::open(path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(io::BufReader::new(file))
+}
+
+#[pyclass(name = "P4Changelist", get_all, frozen, skip_from_py_object)]
+#[derive(Clone)]
+pub struct PyP4Changelist {
+    changelist: u32,
+    time: u32,
+    user: String,
+    description: String,
+}
+
+impl From<&P4Changelist> for PyP4Changelist {
+    fn from(change: &P4Changelist) -> Self {
+        PyP4Changelist {
+            changelist: change.changelist,
+            time: change.time,
+            user: change.user.clone(),
+            description: change.description.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyP4Changelist {
+    fn __repr__(&self) -> String {
+        format!("P4Changelist({}, user={:?})", self.changelist, self.user)
+    }
+}
+
+#[pyclass(name = "P4File", get_all, frozen, skip_from_py_object)]
+#[derive(Clone)]
+pub struct PyP4File {
+    depot_path: String,
+    action: String,
+    revision: Option<u32>,
+    file_size: Option<u64>,
+    digest: Option<String>,
+}
+
+impl From<P4File> for PyP4File {
+    fn from(file: P4File) -> Self {
+        PyP4File {
+            digest: file.digest.map(const_hex::encode_upper),
+            depot_path: file.depot_path,
+            action: file.action,
+            revision: file.revision,
+            file_size: file.file_size,
+        }
+    }
+}
+
+#[pymethods]
+impl PyP4File {
+    fn __repr__(&self) -> String {
+        match self.revision {
+            Some(revision) => format!("P4File({}#{})", self.depot_path, revision),
+            None => format!("P4File({})", self.depot_path),
+        }
+    }
+}
+
+#[pyclass(name = "P4ChangesIterator", unsendable)]
+pub struct PyP4ChangesIterator {
+    inner: Box<dyn Iterator<Item = Result<P4Changelist, P4Error>>>,
+}
+
+#[pymethods]
+impl PyP4ChangesIterator {
+    // Runs `p4 changes` for the (optional) changelist range
+    #[cfg(feature = "spawn")]
+    #[new]
+    #[pyo3(signature = (start=None, end=None))]
+    fn new(start: Option<u32>, end: Option<u32>) -> PyResult<Self> {
+        let cl_range = match (start, end) {
+            (None, None) => None,
+            (start, end) => Some(start.unwrap_or(0)..end.unwrap_or(u32::MAX)),
+        };
+        Ok(PyP4ChangesIterator {
+            inner: Box::new(P4ChangesIterator::new_from_p4_exe(cl_range)?),
+        })
+    }
+
+    // Parses a file captured with `p4 -G changes`
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<Self> {
+        Ok(PyP4ChangesIterator {
+            inner: Box::new(P4ChangesIterator::new_from_reader(open_file(path)?)),
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<PyP4Changelist>> {
+        Ok(self.inner.next().transpose()?.as_ref().map(Into::into))
+    }
+}
+
+#[pyclass(name = "P4DescribeIterator", unsendable)]
+pub struct PyP4DescribeIterator {
+    changelist: PyP4Changelist,
+    inner: Box<dyn Iterator<Item = Result<P4File, P4Error>>>,
+}
+
+#[pymethods]
+impl PyP4DescribeIterator {
+    // Runs `p4 describe -s` for the changelist
+    #[cfg(feature = "spawn")]
+    #[new]
+    fn new(changelist: u32) -> PyResult<Self> {
+        let iter = P4DescribeIterator::new(changelist)?;
+        Ok(PyP4DescribeIterator {
+            changelist: iter.get_changelist().into(),
+            inner: Box::new(iter),
+        })
+    }
+
+    // Parses a file captured with `p4 -G describe -s`
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<Self> {
+        let iter = P4DescribeIterator::new_from_reader(open_file(path)?)?;
+        Ok(PyP4DescribeIterator {
+            changelist: iter.get_changelist().into(),
+            inner: Box::new(iter),
+        })
+    }
+
+    #[getter]
+    fn changelist(&self) -> PyP4Changelist {
+        self.changelist.clone()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<PyP4File>> {
+        Ok(self.inner.next().transpose()?.map(Into::into))
+    }
+}
+
+// Yields (dict_index, key, value) tuples from a -G capture
+#[pyclass(name = "P4PyDictParser", unsendable)]
+pub struct PyP4PyDictParser {
+    inner: P4PyDictParser<FileReader>,
+}
+
+#[pymethods]
+impl PyP4PyDictParser {
+    #[new]
+    fn new(path: &str) -> PyResult<Self> {
+        Ok(PyP4PyDictParser {
+            inner: P4PyDictParser::new(open_file(path)?),
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<(u32, String, String)>> {
+        let kvp = self.inner.get_next_kvp().map_err(P4Error::from)?;
+        Ok(kvp.map(|kvp| (kvp.dict_index, kvp.key.to_string(), kvp.value.to_string())))
+    }
+}
+
+// Yields (dict_index, key, value) tuples from a -ztag capture
+#[pyclass(name = "P4ZtagParser", unsendable)]
+pub struct PyP4ZtagParser {
+    inner: P4ZtagParser<FileReader>,
+}
+
+#[pymethods]
+impl PyP4ZtagParser {
+    #[new]
+    #[pyo3(signature = (path, dict_delimiter_key=None, known_keys=None))]
+    fn new(
+        path: &str,
+        dict_delimiter_key: Option<&str>,
+        known_keys: Option<Vec<String>>,
+    ) -> PyResult<Self> {
+        let mut inner = P4ZtagParser::new(open_file(path)?, dict_delimiter_key);
+        if let Some(known_keys) = known_keys {
+            inner = inner.with_known_keys(known_keys.iter().map(String::as_str));
+        }
+        Ok(PyP4ZtagParser { inner })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<(u32, String, String)>> {
+        let kvp = self.inner.get_next_kvp().map_err(P4Error::from)?;
+        Ok(kvp.map(|kvp| (kvp.dict_index, kvp.key.to_string(), kvp.value.to_string())))
+    }
+}
+
+#[pymodule]
+fn p4_helper(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyP4Changelist>()?;
+    module.add_class::<PyP4File>()?;
+    module.add_class::<PyP4ChangesIterator>()?;
+    module.add_class::<PyP4DescribeIterator>()?;
+    module.add_class::<PyP4PyDictParser>()?;
+    module.add_class::<PyP4ZtagParser>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python_iterators() {
+        let mut changes = PyP4ChangesIterator::from_file("./test_data/changes.pyc").unwrap();
+        let change = changes.__next__().unwrap().unwrap();
+        assert_eq!(change.user, "david");
+
+        let mut describe = PyP4DescribeIterator::from_file("./test_data/describe.pyc").unwrap();
+        assert_eq!(describe.changelist().changelist, 5);
+        let file = describe.__next__().unwrap().unwrap();
+        assert_eq!(
+            file.depot_path,
+            "//depot/main3/UE5.5_github_src/.editorconfig"
+        );
+
+        let mut parser = PyP4PyDictParser::new("./test_data/describe.pyc").unwrap();
+        assert!(parser.__next__().unwrap().is_some());
+    }
+}
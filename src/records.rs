@@ -0,0 +1,243 @@
+// == Std crates
+use std::io;
+
+// == Internal crates
+use crate::error::*;
+use crate::parsers::py_dict::*;
+
+// The fields of one record type, filled in one key-value pair at a time. Re-exported from the command module
+// for P4Command::Fields.
+pub trait P4RecordFields: Default {
+    type Output;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error>;
+    fn finish(self) -> Result<Self::Output, P4Error>;
+
+    // Same as finish, into `output`, so fields that keep their buffers, e.g. a changelist's strings, can swap
+    // them with the previous output's. Returns false for a record that has no item, or none left.
+    fn finish_into(&mut self, output: &mut Option<Self::Output>) -> Result<bool, P4Error> {
+        *output = Some(std::mem::take(self).finish()?);
+        Ok(true)
+    }
+
+    // For records holding several items, e.g. the files of a `p4 describe` as depotFile0, depotFile1 and so on:
+    // finishes the item read so far into `output` if `key` starts the next one. Called before `key` is populated.
+    fn finish_item_into(
+        &mut self,
+        _key: &str,
+        _output: &mut Option<Self::Output>,
+    ) -> Result<bool, P4Error> {
+        Ok(false)
+    }
+
+    // The fields the next record starts from, once this one is finished, for state carried from one record to
+    // the next. None starts it from the command's P4FieldsFactory.
+    fn next_fields(&mut self) -> Option<Self> {
+        None
+    }
+}
+
+// Makes the fields each record starts from, for commands whose parsing depends on their options
+pub type P4FieldsFactory<FieldsT> = Box<dyn Fn() -> FieldsT + Send>;
+
+// A field name with the indices p4 appends to it, e.g. depotFile3 is depotFile with [3] and the resolve and
+// integration field how0,1 is how with [0, 1]. Digits elsewhere in the name are part of it, e.g. path2Root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct P4IndexedKey<'a> {
+    pub(crate) name: &'a str,
+    // Empty for keys without an index
+    pub(crate) indices: Vec<usize>,
+}
+
+impl<'a> P4IndexedKey<'a> {
+    pub(crate) fn parse(key: &'a str) -> Result<Self, P4Error> {
+        let invalid = || P4Error::InvalidRecord("Invalid index in field name");
+
+        if !key.ends_with(|c: char| c.is_ascii_digit()) {
+            return Ok(P4IndexedKey {
+                name: key,
+                indices: Vec::new(),
+            });
+        }
+
+        let mut name = key;
+        let mut indices = Vec::new();
+        loop {
+            let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 {
+                // Only a comma ends up here, e.g. how,1
+                return Err(invalid());
+            }
+            let (rest, index) = name.split_at(name.len() - digits);
+            indices.push(index.parse().map_err(|_| invalid())?);
+            name = rest;
+            match name.strip_suffix(',') {
+                Some(rest) => name = rest,
+                None => break,
+            }
+        }
+
+        // A key that is all digits has no name to index
+        if name.is_empty() {
+            return Ok(P4IndexedKey {
+                name: key,
+                indices: Vec::new(),
+            });
+        }
+        indices.reverse();
+        Ok(P4IndexedKey { name, indices })
+    }
+}
+
+// The entry at `index`, adding it if it is the next one. p4 numbers indexed fields from 0 in order, so an
+// index further on is a broken record rather than a reason to allocate up to it.
+pub(crate) fn entry_at<T: Default>(entries: &mut Vec<T>, index: usize) -> Result<&mut T, P4Error> {
+    if index == entries.len() {
+        entries.push(T::default());
+    }
+    entries
+        .get_mut(index)
+        .ok_or(P4Error::InvalidRecord("Field index out of order"))
+}
+
+// Groups the key-value pairs of a -G stream into one record per dict
+pub(crate) struct P4RecordReader<ReadT: io::Read, FieldsT> {
+    parser: P4PyDictParser<ReadT>,
+    previous_dict_index: Option<u32>,
+    // Apart from the parser, as it holds the key-value pair being read
+    state: P4RecordState<FieldsT>,
+}
+
+struct P4RecordState<FieldsT> {
+    fields: FieldsT,
+    // Set when the record is a message from the server rather than the fields the command asked for
+    message: Option<P4ServerMessage>,
+    records_skipped: u64,
+    // None starts every record from FieldsT::default()
+    new_fields: Option<P4FieldsFactory<FieldsT>>,
+}
+
+impl<FieldsT: P4RecordFields> P4RecordState<FieldsT> {
+    // Returns true if the pair finished an item into `output`
+    fn populate_field(
+        &mut self,
+        key: &str,
+        value: &str,
+        output: &mut Option<FieldsT::Output>,
+    ) -> Result<bool, P4Error> {
+        if key == "code" {
+            if value == "error" {
+                self.message = Some(P4ServerMessage::default());
+            }
+            return Ok(false);
+        }
+
+        if let Some(message) = self.message.as_mut() {
+            message.populate_field(key, value);
+            return Ok(false);
+        }
+
+        let finished = self.fields.finish_item_into(key, output)?;
+        self.fields.populate_field(key, value)?;
+        Ok(finished)
+    }
+
+    // Warnings such as "file(s) not on client." are skipped, anything worse is an error. Returns true if the
+    // record finished an item into `output`.
+    fn finish_record(&mut self, output: &mut Option<FieldsT::Output>) -> Result<bool, P4Error> {
+        if let Some(message) = self.message.take() {
+            if message.severity > E_WARN {
+                return Err(P4Error::Server(message));
+            }
+            self.records_skipped += 1;
+            return Ok(false);
+        }
+
+        let finished = self.fields.finish_into(output)?;
+        self.fields = match self.fields.next_fields() {
+            Some(fields) => fields,
+            None => self
+                .new_fields
+                .as_ref()
+                .map_or_else(FieldsT::default, |new_fields| new_fields()),
+        };
+        Ok(finished)
+    }
+}
+
+impl<ReadT: io::Read, FieldsT: P4RecordFields> P4RecordReader<ReadT, FieldsT> {
+    pub(crate) fn new(parser: P4PyDictParser<ReadT>) -> Self {
+        P4RecordReader {
+            parser,
+            previous_dict_index: None,
+            state: P4RecordState {
+                fields: FieldsT::default(),
+                message: None,
+                records_skipped: 0,
+                new_fields: None,
+            },
+        }
+    }
+
+    pub(crate) fn with_new_fields(mut self, new_fields: P4FieldsFactory<FieldsT>) -> Self {
+        self.state.fields = new_fields();
+        self.state.new_fields = Some(new_fields);
+        self
+    }
+
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.parser.bytes_read()
+    }
+
+    pub(crate) fn records_skipped(&self) -> u64 {
+        self.state.records_skipped
+    }
+
+    // The fields of the record being read, for commands that keep state in them across records
+    pub(crate) fn fields(&self) -> &FieldsT {
+        &self.state.fields
+    }
+
+    pub(crate) fn fields_mut(&mut self) -> &mut FieldsT {
+        &mut self.state.fields
+    }
+
+    // The next item the command asked for, skipping warnings
+    pub(crate) fn next_output(&mut self) -> Result<Option<FieldsT::Output>, P4Error> {
+        let mut output = None;
+        if !self.next_output_into(&mut output)? {
+            return Ok(None);
+        }
+        Ok(output)
+    }
+
+    // Same as next_output, into `output`. Returns false once there are no more items.
+    pub(crate) fn next_output_into(
+        &mut self,
+        output: &mut Option<FieldsT::Output>,
+    ) -> Result<bool, P4Error> {
+        while let Some(kvp) = self.parser.get_next_kvp()? {
+            let mut finished = false;
+            if self
+                .previous_dict_index
+                .is_some_and(|index| index != kvp.dict_index)
+            {
+                finished = self.state.finish_record(output)?;
+            }
+            self.previous_dict_index = Some(kvp.dict_index);
+
+            // The pair still belongs to the next record, even when the previous one made an item
+            finished |= self.state.populate_field(kvp.key, kvp.value, output)?;
+            if finished {
+                return Ok(true);
+            }
+        }
+
+        // The final record
+        if self.previous_dict_index.take().is_some() {
+            return self.state.finish_record(output);
+        }
+
+        Ok(false)
+    }
+}
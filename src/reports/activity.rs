@@ -0,0 +1,164 @@
+// == Std crates
+use std::{collections::BTreeMap, ops::Range};
+
+// == Internal crates
+use crate::changes::*;
+use crate::context::*;
+use crate::dict::*;
+use crate::error::*;
+
+// What a user has been doing, e.g. for finding licenses to reclaim or checking a leaver has nothing open
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4UserActivity {
+    pub user: String,
+    // Empty for users who submitted in the range but no longer exist
+    pub full_name: String,
+    // standard, operator or service
+    pub user_type: String,
+    // When the user last ran a command, from `p4 users`
+    pub last_access: Option<u32>,
+    // The newest submit in the range
+    pub last_submit: Option<u32>,
+    pub submits: u64,
+    // Files opened in any client
+    pub open_files: u64,
+}
+
+// One entry per user from `p4 users -a`, `p4 changes` over the changelist range and `p4 opened -a`, by user name
+#[cfg(feature = "spawn")]
+pub fn user_activity(range: Range<u32>) -> Result<Vec<P4UserActivity>, P4Error> {
+    user_activity_from_context(&P4Context::default(), range)
+}
+
+pub fn user_activity_from_context(
+    context: &P4Context,
+    range: Range<u32>,
+) -> Result<Vec<P4UserActivity>, P4Error> {
+    let mut activity = BTreeMap::new();
+    for user in P4DictIterator::new_from_context(context, vec!["users", "-a"])? {
+        let user = user?;
+        let Some(name) = user.get("User") else {
+            continue;
+        };
+        let entry = user_entry(&mut activity, name);
+        entry.full_name = user.get("FullName").unwrap_or_default().to_string();
+        entry.user_type = user.get("Type").unwrap_or("standard").to_string();
+        entry.last_access = user.get("Access").and_then(|access| access.parse().ok());
+    }
+
+    let query = P4ChangesQuery::new()
+        .with_range(range)
+        .with_description_mode(P4DescriptionMode::Short);
+    for changelist in P4ChangesIterator::new_from_context(context, query)? {
+        let changelist = changelist?;
+        let entry = user_entry(&mut activity, &changelist.user);
+        entry.submits += 1;
+        entry.last_submit = entry.last_submit.max(Some(changelist.time));
+    }
+
+    for file in P4DictIterator::new_from_context(context, vec!["opened", "-a"])? {
+        if let Some(user) = file?.get("user") {
+            user_entry(&mut activity, user).open_files += 1;
+        }
+    }
+
+    Ok(activity.into_values().collect())
+}
+
+fn user_entry<'a>(
+    activity: &'a mut BTreeMap<String, P4UserActivity>,
+    user: &str,
+) -> &'a mut P4UserActivity {
+    activity
+        .entry(user.to_string())
+        .or_insert_with(|| P4UserActivity {
+            user: user.to_string(),
+            ..Default::default()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_user_activity() {
+        let change = |change: &'static str, user: &'static str, time: &'static str| {
+            [
+                ("code", "stat"),
+                ("change", change),
+                ("time", time),
+                ("user", user),
+                ("desc", "Fix the build"),
+            ]
+        };
+        let context = MockP4::new()
+            .with_records(
+                "users -a",
+                [
+                    [
+                        ("code", "stat"),
+                        ("User", "alice"),
+                        ("FullName", "Alice"),
+                        ("Type", "standard"),
+                        ("Access", "1743984000"),
+                    ],
+                    [
+                        ("code", "stat"),
+                        ("User", "build"),
+                        ("FullName", "Build Bot"),
+                        ("Type", "service"),
+                        ("Access", "1743990000"),
+                    ],
+                ],
+            )
+            .with_records(
+                "changes -s submitted @100,200",
+                [
+                    change("150", "alice", "1743900000"),
+                    change("120", "alice", "1743800000"),
+                    change("110", "carol", "1743700000"),
+                ],
+            )
+            .with_records(
+                "opened -a",
+                [
+                    [
+                        ("code", "stat"),
+                        ("depotFile", "//depot/a.txt"),
+                        ("user", "build"),
+                    ],
+                    [
+                        ("code", "stat"),
+                        ("depotFile", "//depot/b.txt"),
+                        ("user", "build"),
+                    ],
+                ],
+            )
+            .into_context();
+
+        let activity = user_activity_from_context(&context, 100..200).unwrap();
+        let summary = activity
+            .iter()
+            .map(|user| {
+                (
+                    user.user.as_str(),
+                    user.user_type.as_str(),
+                    user.submits,
+                    user.last_submit,
+                    user.open_files,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("alice", "standard", 2, Some(1743900000), 0),
+                ("build", "service", 0, None, 2),
+                ("carol", "", 1, Some(1743700000), 0),
+            ]
+        );
+        assert_eq!(activity[0].last_access, Some(1743984000));
+    }
+}
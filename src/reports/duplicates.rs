@@ -0,0 +1,144 @@
+// == Std crates
+use std::collections::HashMap;
+
+// == Internal crates
+use crate::context::*;
+use crate::error::*;
+use crate::fstat::*;
+
+// Head revisions with the same content, by digest and size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4DuplicateGroup {
+    pub digest: [u8; 16],
+    pub file_size: u64,
+    // Sorted
+    pub depot_paths: Vec<String>,
+}
+
+impl P4DuplicateGroup {
+    // What storing the content once would save
+    pub fn wasted_bytes(&self) -> u64 {
+        self.file_size * (self.depot_paths.len() as u64 - 1)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4DuplicateReport {
+    pub files_scanned: u64,
+    // The most wasted bytes first
+    pub groups: Vec<P4DuplicateGroup>,
+    pub potential_savings: u64,
+}
+
+impl P4DuplicateReport {
+    // Groups entries with -Ol fields, skipping deleted head revisions and entries without a digest
+    pub fn from_entries<ErrorT>(
+        entries: impl IntoIterator<Item = Result<P4FstatEntry, ErrorT>>,
+    ) -> Result<Self, ErrorT> {
+        let mut files_scanned = 0;
+        let mut by_content = HashMap::<([u8; 16], u64), Vec<String>>::new();
+        for entry in entries {
+            let entry = entry?;
+            files_scanned += 1;
+            let deleted = entry
+                .head_action
+                .as_deref()
+                .is_some_and(|action| action.contains("delete"));
+            if let (Some(digest), false) = (entry.digest, deleted) {
+                by_content
+                    .entry((digest, entry.file_size.unwrap_or_default()))
+                    .or_default()
+                    .push(entry.depot_path);
+            }
+        }
+
+        let mut groups = by_content
+            .into_iter()
+            .filter(|(_, depot_paths)| depot_paths.len() > 1)
+            .map(|((digest, file_size), mut depot_paths)| {
+                depot_paths.sort();
+                P4DuplicateGroup {
+                    digest,
+                    file_size,
+                    depot_paths,
+                }
+            })
+            .collect::<Vec<_>>();
+        groups.sort_by(|a, b| {
+            b.wasted_bytes()
+                .cmp(&a.wasted_bytes())
+                .then_with(|| a.depot_paths.cmp(&b.depot_paths))
+        });
+
+        Ok(P4DuplicateReport {
+            files_scanned,
+            potential_savings: groups.iter().map(P4DuplicateGroup::wasted_bytes).sum(),
+            groups,
+        })
+    }
+}
+
+// Head revisions under the filespec with identical content, from the digests and sizes of `p4 fstat -Ol`
+#[cfg(feature = "spawn")]
+pub fn duplicates(filespec: &str) -> Result<P4DuplicateReport, P4Error> {
+    duplicates_from_context(&P4Context::default(), filespec)
+}
+
+pub fn duplicates_from_context(
+    context: &P4Context,
+    filespec: &str,
+) -> Result<P4DuplicateReport, P4Error> {
+    let query = P4FstatQuery::new(filespec).with_file_details();
+    P4DuplicateReport::from_entries(P4FstatIterator::new_from_context(context, query)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_duplicates() {
+        let file = |path: &'static str, action: &'static str, digest: &'static str, size| {
+            [
+                ("code", "stat"),
+                ("depotFile", path),
+                ("headAction", action),
+                ("digest", digest),
+                ("fileSize", size),
+            ]
+        };
+        let logo = "B1946AC92492D2347C6235B4D2611184";
+        let readme = "A9E93320E1FC469228D707C9124C878C";
+        let context = MockP4::new()
+            .with_records(
+                "fstat -Ol //depot/...",
+                [
+                    file("//depot/main/logo.png", "add", logo, "5000"),
+                    file("//depot/rel/logo.png", "branch", logo, "5000"),
+                    file("//depot/dev/logo.png", "integrate", logo, "5000"),
+                    file("//depot/old/logo.png", "delete", logo, "5000"),
+                    file("//depot/main/README", "edit", readme, "10"),
+                    file("//depot/rel/README", "branch", readme, "10"),
+                    // Same digest, different size
+                    file("//depot/dev/README", "edit", readme, "11"),
+                ],
+            )
+            .into_context();
+
+        let report = duplicates_from_context(&context, "//depot/...").unwrap();
+        assert_eq!(report.files_scanned, 7);
+        assert_eq!(report.groups.len(), 2);
+        assert_eq!(
+            report.groups[0].depot_paths,
+            [
+                "//depot/dev/logo.png",
+                "//depot/main/logo.png",
+                "//depot/rel/logo.png"
+            ]
+        );
+        assert_eq!(report.groups[0].wasted_bytes(), 10000);
+        assert_eq!(report.groups[1].file_size, 10);
+        assert_eq!(report.potential_savings, 10010);
+    }
+}
@@ -0,0 +1,13 @@
+#[cfg(feature = "process")]
+pub mod activity;
+#[cfg(feature = "process")]
+pub mod duplicates;
+
+#[cfg(feature = "spawn")]
+pub use activity::user_activity;
+#[cfg(feature = "process")]
+pub use activity::{P4UserActivity, user_activity_from_context};
+#[cfg(feature = "spawn")]
+pub use duplicates::duplicates;
+#[cfg(feature = "process")]
+pub use duplicates::{P4DuplicateGroup, P4DuplicateReport, duplicates_from_context};
@@ -0,0 +1,138 @@
+// == Std crates
+use std::{io, time::Duration};
+
+// == Internal crates
+use crate::error::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // Total number of attempts, including the first one, so 1 means "never retry"
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub backoff_multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            backoff_multiplier: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, initial_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_backoff_multiplier(mut self, backoff_multiplier: u32) -> Self {
+        self.backoff_multiplier = backoff_multiplier;
+        self
+    }
+
+    // How long to wait after the given (zero based) failed attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.backoff_multiplier.saturating_pow(attempt);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    pub fn should_retry(&self, attempt: u32, error: &P4Error) -> bool {
+        attempt + 1 < self.max_attempts && is_transient(error)
+    }
+}
+
+// Spawn failures caused by resource exhaustion and server errors caused by a flaky link or an overloaded
+// server are worth retrying, everything else will fail the same way again
+pub fn is_transient(error: &P4Error) -> bool {
+    match error {
+        P4Error::Spawn(e) => !matches!(
+            e.kind(),
+            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied | io::ErrorKind::InvalidInput
+        ),
+        P4Error::Server(message) => is_transient_message(message),
+        _ => false,
+    }
+}
+
+pub fn is_transient_message(message: &P4ServerMessage) -> bool {
+    const TRANSIENT_FRAGMENTS: [&str; 5] = [
+        "connect to server failed",
+        "tcp connect to",
+        "too many clients",
+        "connection reset",
+        "partner exited unexpectedly",
+    ];
+
+    if message.generic == EV_COMM {
+        return true;
+    }
+    // The text is only a fallback for messages that come without a code, e.g. from the p4 client itself
+    if message.generic != EV_NONE || message.is_structured() {
+        return false;
+    }
+
+    let data = message.data.to_ascii_lowercase();
+    TRANSIENT_FRAGMENTS
+        .iter()
+        .any(|fragment| data.contains(fragment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_and_transient_detection() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(500));
+
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+
+        let connect_failure = P4Error::Server(P4ServerMessage {
+            severity: E_FATAL,
+            generic: 0,
+            data: "Perforce client error:\n\tConnect to server failed; check $P4PORT.\n\tTCP connect to perforce:1666 failed.\n".into(),
+            ..Default::default()
+        });
+        let no_such_change = P4Error::Server(P4ServerMessage {
+            severity: E_FAILED,
+            generic: 0x11,
+            data: "1234 - no such changelist.\n".into(),
+            ..Default::default()
+        });
+
+        assert!(policy.should_retry(0, &connect_failure));
+        assert!(!policy.should_retry(4, &connect_failure));
+        assert!(!policy.should_retry(0, &no_such_change));
+        assert!(!policy.should_retry(0, &P4Error::Spawn(io::Error::from(io::ErrorKind::NotFound))));
+        assert!(!RetryPolicy::default().should_retry(0, &connect_failure));
+
+        // A code is trusted over the text
+        let reset_in_description = P4Error::Server(P4ServerMessage {
+            severity: E_FAILED,
+            generic: EV_USAGE,
+            data: "Invalid description: 'Retry on connection reset'\n".into(),
+            ..Default::default()
+        });
+        assert!(!policy.should_retry(0, &reset_in_description));
+    }
+}
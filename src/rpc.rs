@@ -0,0 +1,770 @@
+// A backend that talks to the server over the Perforce client protocol instead of spawning p4.
+//
+// Every message on the wire is a 5 byte header (a checksum byte, then the little endian length of the body)
+// followed by variables, each encoded as `name \0 <u32le length> value \0`. The `func` variable says what
+// the message is. A command is sent as `user-<command>` and the server answers with callbacks such as
+// `client-FstatInfo` (a tagged record) and `client-Message` (an error or info message), until `release`.
+
+// == Std crates
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// == Internal crates
+use crate::backend::*;
+use crate::budget::*;
+use crate::error::*;
+use crate::parsers::py_dict::write_py_dict;
+use crate::port::*;
+
+// == External crates
+use md5::Md5;
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, WebPkiSupportedAlgorithms},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    server::ParsedCertificate,
+};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+const CLIENT_PROTOCOL_LEVEL: &str = "82";
+const MAX_MESSAGE_LEN: usize = 0x1fff_ffff;
+
+type RpcVars = Vec<(String, Vec<u8>)>;
+
+#[derive(Debug)]
+pub struct RpcBackend {
+    port: P4Port,
+    user: String,
+    client: Option<String>,
+    // Password or ticket, sent when the server prompts for it
+    password: Option<String>,
+    timeout: Option<Duration>,
+    // The key fingerprint of an ssl: server, as `p4 trust` shows it
+    trusted_fingerprint: Option<Vec<u8>>,
+    memory_budget: Option<Arc<MemoryBudget>>,
+    // A connection is reused once the command on it has been read to the end, a command started while
+    // another is being read gets a connection of its own
+    connection: Arc<Mutex<Option<RpcStream>>>,
+}
+
+impl RpcBackend {
+    // `port` is a P4PORT value, e.g. "perforce:1666", "tcp:perforce:1666" or "ssl:perforce:1666"
+    pub fn new(port: &str, user: &str) -> io::Result<Self> {
+        let port = port
+            .parse::<P4Port>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        Ok(RpcBackend {
+            port,
+            user: user.to_string(),
+            client: None,
+            password: None,
+            timeout: None,
+            trusted_fingerprint: None,
+            memory_budget: None,
+            connection: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn with_client(mut self, client: &str) -> Self {
+        self.client = Some(client.to_string());
+        self
+    }
+
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    // Read/write timeout on the connection
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // The SHA-1 or SHA-256 fingerprint of an ssl: server's public key, e.g. "5A:8A:...". Without one the
+    // connection fails with the server's SHA-1 fingerprint in the error, to be checked with the admin.
+    pub fn with_trusted_fingerprint(mut self, fingerprint: &str) -> io::Result<Self> {
+        let fingerprint = const_hex::decode(fingerprint.replace(':', ""))
+            .ok()
+            .filter(|fingerprint| matches!(fingerprint.len(), 20 | 32))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Invalid ssl fingerprint")
+            })?;
+        self.trusted_fingerprint = Some(fingerprint);
+        Ok(self)
+    }
+
+    // Limits what is read of a command's output ahead of the parser, one server message at a time
+    pub fn with_memory_budget(mut self, memory_budget: MemoryBudget) -> Self {
+        self.memory_budget = Some(Arc::new(memory_budget));
+        self
+    }
+
+    fn connect(&self) -> io::Result<RpcStream> {
+        let tcp = TcpStream::connect(self.port.address())?;
+        tcp.set_nodelay(true)?;
+        tcp.set_read_timeout(self.timeout)?;
+        tcp.set_write_timeout(self.timeout)?;
+
+        let mut stream = if self.port.is_ssl() {
+            RpcStream::Ssl(Box::new(self.connect_ssl(tcp)?))
+        } else {
+            RpcStream::Tcp(tcp)
+        };
+        write_message(
+            &mut stream,
+            "protocol",
+            &[
+                ("client", CLIENT_PROTOCOL_LEVEL.as_bytes()),
+                ("api", b"99999"),
+                ("enableStreams", b""),
+                ("enableGraph", b""),
+            ],
+        )?;
+        Ok(stream)
+    }
+
+    // Servers usually have a self-signed certificate, so the key is checked against the trusted fingerprint
+    // rather than a certificate authority
+    fn connect_ssl(&self, tcp: TcpStream) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let verifier = FingerprintVerifier {
+            fingerprint: self.trusted_fingerprint.clone(),
+            algorithms: provider.signature_verification_algorithms,
+        };
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+
+        let host = self.port.host().unwrap_or("localhost").to_string();
+        let server_name = ServerName::try_from(host)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection =
+            ClientConnection::new(Arc::new(config), server_name).map_err(io::Error::other)?;
+        let mut stream = StreamOwned::new(connection, tcp);
+        // Handshake now, so an untrusted server fails the connect rather than the first read
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        Ok(stream)
+    }
+
+    fn send_command(&self, stream: &mut RpcStream, args: &[&str]) -> Result<(), P4Error> {
+        let (command, args) = args
+            .split_first()
+            .ok_or(P4Error::InvalidRecord("Empty command"))?;
+
+        let host = host_name();
+        let cwd = env::current_dir()
+            .map(|cwd| cwd.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut vars: Vec<(&str, &[u8])> = vec![
+            ("tag", b""),
+            ("user", self.user.as_bytes()),
+            ("client", self.client.as_deref().unwrap_or(&host).as_bytes()),
+            ("host", host.as_bytes()),
+            ("cwd", cwd.as_bytes()),
+            ("prog", env!("CARGO_PKG_NAME").as_bytes()),
+            ("version", env!("CARGO_PKG_VERSION").as_bytes()),
+            ("os", env::consts::OS.as_bytes()),
+        ];
+        vars.extend(args.iter().map(|arg| ("", arg.as_bytes())));
+        write_message(stream, &format!("user-{}", command), &vars)?;
+        Ok(())
+    }
+}
+
+impl P4Backend for RpcBackend {
+    fn run(&self, args: &[&str]) -> Result<Box<dyn io::Read + Send>, P4Error> {
+        let connection = self.connection.lock().unwrap().take();
+        let mut stream = match connection {
+            Some(stream) => stream,
+            None => self.connect().map_err(P4Error::Spawn)?,
+        };
+        self.send_command(&mut stream, args)?;
+
+        Ok(Box::new(RpcOutput {
+            stream: Some(stream),
+            connection: self.connection.clone(),
+            password: self.password.clone(),
+            pending: io::Cursor::default(),
+            reservation: BudgetReservation::new(
+                self.memory_budget.clone(),
+                P4BudgetResource::BufferedBytes,
+            ),
+        }))
+    }
+}
+
+#[derive(Debug)]
+enum RpcStream {
+    Tcp(TcpStream),
+    Ssl(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for RpcStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RpcStream::Tcp(stream) => stream.read(buf),
+            RpcStream::Ssl(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for RpcStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RpcStream::Tcp(stream) => stream.write(buf),
+            RpcStream::Ssl(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RpcStream::Tcp(stream) => stream.flush(),
+            RpcStream::Ssl(stream) => stream.flush(),
+        }
+    }
+}
+
+// The server's callbacks for one command converted into the same -G records the p4 executable would have
+// printed, a message at a time as the parser reads them
+struct RpcOutput {
+    // None once the server released the command
+    stream: Option<RpcStream>,
+    connection: Arc<Mutex<Option<RpcStream>>>,
+    password: Option<String>,
+    pending: io::Cursor<Vec<u8>>,
+    // Covers the message being read and the records converted from it
+    reservation: BudgetReservation,
+}
+
+impl RpcOutput {
+    fn read_callback(&mut self) -> io::Result<()> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(());
+        };
+        let (func, vars) = read_message(stream, &mut self.reservation)?;
+
+        let mut output = Vec::new();
+        match func.as_str() {
+            "client-FstatInfo" | "client-OutputStat" => {
+                let record = vars
+                    .iter()
+                    .map(|(key, value)| (key.as_bytes(), value.as_slice()));
+                write_py_dict(
+                    &mut output,
+                    [(&b"code"[..], &b"stat"[..])].into_iter().chain(record),
+                )?;
+            }
+            "client-Message" => {
+                write_py_dict(&mut output, message_record(&vars)?)?;
+            }
+            "client-Prompt" => {
+                let password = self.password.as_deref().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "Server asked for a password",
+                    )
+                })?;
+                let confirm = find_var(&vars, "confirm").unwrap_or(b"dm-Login");
+                let confirm = String::from_utf8_lossy(confirm).into_owned();
+                write_message(stream, &confirm, &[("data", password.as_bytes())])?;
+            }
+            // The server's challenge for the ticket, e.g. before a command that needs the user logged in
+            "client-Crypto" => {
+                let challenge = find_var(&vars, "token").unwrap_or_default();
+                let token = crypto_token(challenge, self.password.as_deref().unwrap_or_default());
+                let confirm = find_var(&vars, "confirm").unwrap_or(b"crypto");
+                let confirm = String::from_utf8_lossy(confirm).into_owned();
+                write_message(stream, &confirm, &[("token", token.as_bytes())])?;
+            }
+            "flush1" => {
+                let vars = vars
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_slice()))
+                    .collect::<Vec<_>>();
+                write_message(stream, "flush2", &vars)?;
+            }
+            // The connection is only put back once a command completed cleanly
+            "release" | "release2" => {
+                let stream = self.stream.take();
+                self.connection.lock().unwrap().get_or_insert_with(|| {
+                    stream.expect("The stream is taken only once the command is released")
+                });
+            }
+            _ => {}
+        }
+
+        self.reservation
+            .resize(output.len() as u64)
+            .map_err(io::Error::other)?;
+        self.pending = io::Cursor::new(output);
+        Ok(())
+    }
+}
+
+impl Read for RpcOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.pending.read(buf)?;
+            if read > 0 || buf.is_empty() || self.stream.is_none() {
+                return Ok(read);
+            }
+            self.read_callback()?;
+        }
+    }
+}
+
+// Checks the server's public key against the fingerprint from `p4 trust`, which hashes the key bits of the
+// certificate rather than the whole certificate
+#[derive(Debug)]
+struct FingerprintVerifier {
+    fingerprint: Option<Vec<u8>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let key = ParsedCertificate::try_from(end_entity)?
+            .subject_public_key_info()
+            .to_vec();
+        let key = public_key_bits(&key)
+            .ok_or_else(|| rustls::Error::General("Invalid server public key".to_string()))?;
+        let trusted =
+            self.fingerprint
+                .as_ref()
+                .is_some_and(|fingerprint| match fingerprint.len() {
+                    20 => Sha1::digest(key)[..] == fingerprint[..],
+                    _ => Sha256::digest(key)[..] == fingerprint[..],
+                });
+        if !trusted {
+            return Err(rustls::Error::General(format!(
+                "The server's fingerprint {} isn't trusted",
+                format_fingerprint(&Sha1::digest(key))
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+// The BIT STRING of a DER SubjectPublicKeyInfo, after its algorithm identifier
+fn public_key_bits(spki: &[u8]) -> Option<&[u8]> {
+    let (tag, spki, _) = der_element(spki)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (_, _, rest) = der_element(spki)?;
+    let (tag, bits, _) = der_element(rest)?;
+    if tag != 0x03 {
+        return None;
+    }
+    // The first byte is the number of unused bits, always 0 for a key
+    bits.get(1..)
+}
+
+// Returns the tag, content and what follows of the element at the start of `input`
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        len if len < 0x80 => (len as usize, rest),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let len = rest
+                .get(..count)?
+                .iter()
+                .fold(0, |len, b| (len << 8) | *b as usize);
+            (len, &rest[count..])
+        }
+        _ => return None,
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+// e.g. 5A:8A:..., as p4 trust prints it
+fn format_fingerprint(fingerprint: &[u8]) -> String {
+    fingerprint
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn write_message(writer: &mut impl Write, func: &str, vars: &[(&str, &[u8])]) -> io::Result<()> {
+    let mut body = Vec::new();
+    for (name, value) in [("func", func.as_bytes())].iter().chain(vars) {
+        let len = u32::try_from(value.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Variable too long"))?;
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(&len.to_le_bytes());
+        body.extend_from_slice(value);
+        body.push(0);
+    }
+
+    let len = u32::try_from(body.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too long"))?
+        .to_le_bytes();
+    let checksum = len.iter().fold(0, |checksum, b| checksum ^ b);
+    writer.write_all(&[checksum, len[0], len[1], len[2], len[3]])?;
+    writer.write_all(&body)?;
+    writer.flush()
+}
+
+// Returns the function name and the remaining variables. The body is held in `reservation` while it's read.
+fn read_message(
+    reader: &mut impl Read,
+    reservation: &mut BudgetReservation,
+) -> io::Result<(String, RpcVars)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    if header[0] != header[1] ^ header[2] ^ header[3] ^ header[4] {
+        return Err(invalid("Bad rpc message checksum"));
+    }
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(invalid("Rpc message too long"));
+    }
+
+    reservation.resize(len as u64).map_err(io::Error::other)?;
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+
+    let mut func = None;
+    let mut vars = Vec::new();
+    let mut rest = &body[..];
+    while !rest.is_empty() {
+        let name_end = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| invalid("Unterminated rpc variable name"))?;
+        let name = String::from_utf8_lossy(&rest[..name_end]).into_owned();
+        rest = &rest[name_end + 1..];
+
+        if rest.len() < 4 {
+            return Err(invalid("Truncated rpc variable"));
+        }
+        let value_len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < 4 + value_len + 1 {
+            return Err(invalid("Truncated rpc variable"));
+        }
+        let value = rest[4..4 + value_len].to_vec();
+        rest = &rest[4 + value_len + 1..];
+
+        if name == "func" {
+            func = Some(String::from_utf8_lossy(&value).into_owned());
+        } else {
+            vars.push((name, value));
+        }
+    }
+
+    Ok((
+        func.ok_or_else(|| invalid("Rpc message without func"))?,
+        vars,
+    ))
+}
+
+// The MD5 of the challenge and the ticket, in uppercase hex. A password rather than a 32 digit ticket is hashed
+// the same way first, as the server only keeps its hash.
+fn crypto_token(challenge: &[u8], secret: &str) -> String {
+    let is_ticket = secret.len() == 32 && secret.bytes().all(|byte| byte.is_ascii_hexdigit());
+    let secret = if is_ticket {
+        secret.to_string()
+    } else {
+        const_hex::encode_upper(Md5::digest(secret))
+    };
+    let mut md5 = Md5::new();
+    md5.update(challenge);
+    md5.update(secret.as_bytes());
+    const_hex::encode_upper(md5.finalize())
+}
+
+// The name p4 sends as the host and default client. Shells set HOSTNAME without exporting it, so it is asked
+// of the system.
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    // gethostname truncates to the buffer, which is writable for its whole length
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return String::new();
+    }
+    let len = buffer
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+fn find_var<'a>(vars: &'a RpcVars, name: &str) -> Option<&'a [u8]> {
+    vars.iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_slice())
+}
+
+// Turns a client-Message into an error record. The code packs the severity and generic error codes, the text
+// is the format string with %var% references replaced by the message variables.
+fn message_record(vars: &RpcVars) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let code: u32 = find_var(vars, "code0")
+        .and_then(|code| std::str::from_utf8(code).ok())
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Message without code"))?;
+    let format = String::from_utf8_lossy(find_var(vars, "fmt0").unwrap_or_default());
+
+    let mut data = String::new();
+    let mut parts = format.split('%');
+    data.push_str(parts.next().unwrap_or_default());
+    while let Some(name) = parts.next() {
+        match find_var(vars, name) {
+            Some(value) => data.push_str(&String::from_utf8_lossy(value)),
+            None => {
+                data.push('%');
+                data.push_str(name);
+            }
+        }
+        data.push_str(parts.next().unwrap_or_default());
+    }
+    data.push('\n');
+
+    let severity = code >> 28;
+    let generic = (code >> 16) & 0xff;
+    let record_code = if severity >= E_WARN { "error" } else { "info" };
+    let mut record = vec![
+        ("code".to_string(), record_code.into()),
+        ("data".to_string(), data.into()),
+        ("severity".to_string(), severity.to_string().into()),
+        ("generic".to_string(), generic.to_string().into()),
+        // What p4 -e adds, the server always sends it
+        ("code0".to_string(), code.to_string().into()),
+        ("fmt0".to_string(), format.as_bytes().to_vec()),
+    ];
+    record.extend(
+        vars.iter()
+            .filter(|(name, _)| name != "code0" && name != "fmt0")
+            .cloned(),
+    );
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::*;
+    use rustls::{
+        ServerConfig, ServerConnection,
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+    };
+    use std::{fs, net::TcpListener, thread};
+
+    // Answers `p4 changes` with two changelists
+    fn serve_changes(stream: &mut (impl Read + Write)) {
+        let (func, vars) = read_message(stream, &mut BudgetReservation::default()).unwrap();
+        assert_eq!(func, "user-changes");
+        assert_eq!(find_var(&vars, "user"), Some(&b"david"[..]));
+        for change in ["2", "1"] {
+            write_message(
+                stream,
+                "client-FstatInfo",
+                &[
+                    ("change", change.as_bytes()),
+                    ("time", b"1700000000"),
+                    ("user", b"david"),
+                    ("desc", b"Some change\n"),
+                ],
+            )
+            .unwrap();
+        }
+        write_message(stream, "release", &[]).unwrap();
+    }
+
+    fn changelists(backend: &RpcBackend) -> Result<Vec<u32>, P4Error> {
+        P4ChangesIterator::new_from_reader(backend.run(&["changes"])?)
+            .map(|change| change.map(|change| change.changelist))
+            .collect()
+    }
+
+    const TICKET: &str = "0123456789ABCDEF0123456789ABCDEF";
+
+    #[test]
+    fn test_rpc_backend() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().to_string();
+
+        // A minimal server that answers two commands on the same connection
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (func, _) = read_message(&mut stream, &mut BudgetReservation::default()).unwrap();
+            assert_eq!(func, "protocol");
+            serve_changes(&mut stream);
+
+            let (func, vars) =
+                read_message(&mut stream, &mut BudgetReservation::default()).unwrap();
+            assert_eq!(func, "user-changes");
+            assert_eq!(find_var(&vars, "host"), Some(host_name().as_bytes()));
+            assert!(!host_name().is_empty());
+
+            // The ticket challenge is answered with the MD5 of the challenge and the ticket
+            write_message(&mut stream, "client-Crypto", &[("token", b"3F0A1C")]).unwrap();
+            let (func, vars) =
+                read_message(&mut stream, &mut BudgetReservation::default()).unwrap();
+            assert_eq!(func, "crypto");
+            let expected = const_hex::encode_upper(Md5::digest(format!("3F0A1C{}", TICKET)));
+            assert_eq!(find_var(&vars, "token"), Some(expected.as_bytes()));
+
+            let code = ((E_FAILED << 28) | (1 << 24) | (0x11 << 16)).to_string();
+            write_message(
+                &mut stream,
+                "client-Message",
+                &[
+                    ("code0", code.as_bytes()),
+                    ("fmt0", b"%depotFile% - no such file(s)."),
+                    ("depotFile", b"//depot/missing"),
+                ],
+            )
+            .unwrap();
+            write_message(&mut stream, "release", &[]).unwrap();
+        });
+
+        let backend = RpcBackend::new(&port, "david")
+            .unwrap()
+            .with_password(TICKET);
+        assert_eq!(changelists(&backend).unwrap(), [2, 1]);
+
+        match changelists(&backend) {
+            Err(P4Error::Server(message)) => {
+                assert_eq!(message.severity, E_FAILED);
+                assert_eq!(message.generic, 0x11);
+                assert_eq!(message.data, "//depot/missing - no such file(s).\n");
+                assert_eq!(message.format, "%depotFile% - no such file(s).");
+                assert_eq!(message.arg("depotFile"), Some("//depot/missing"));
+            }
+            other => panic!("Expected a server error, got {:?}", other),
+        }
+
+        server.join().unwrap();
+
+        // A password is hashed before it goes into the token
+        let hashed = const_hex::encode_upper(Md5::digest("secret"));
+        assert_eq!(
+            crypto_token(b"3F0A1C", "secret"),
+            crypto_token(b"3F0A1C", &hashed)
+        );
+    }
+
+    #[test]
+    fn test_rpc_memory_budget() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_message(&mut stream, &mut BudgetReservation::default()).unwrap();
+            serve_changes(&mut stream);
+        });
+
+        let backend = RpcBackend::new(&port, "david")
+            .unwrap()
+            .with_memory_budget(MemoryBudget::new().with_max_buffered_bytes(64));
+        assert!(matches!(
+            changelists(&backend),
+            Err(P4Error::BudgetExceeded(_))
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_ssl() {
+        let cert = CertificateDer::from(fs::read("./test_data/rpc_ssl_cert.der").unwrap());
+        let key = PrivateKeyDer::from(PrivatePkcs8KeyDer::from(
+            fs::read("./test_data/rpc_ssl_key.der").unwrap(),
+        ));
+        let config = Arc::new(
+            ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert], key)
+                .unwrap(),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("ssl:{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            // The first client doesn't trust the key and gives up during the handshake
+            for trusted in [false, true] {
+                let (tcp, _) = listener.accept().unwrap();
+                let connection = ServerConnection::new(config.clone()).unwrap();
+                let mut stream = StreamOwned::new(connection, tcp);
+                let protocol = read_message(&mut stream, &mut BudgetReservation::default());
+                assert_eq!(protocol.is_ok(), trusted);
+                if trusted {
+                    serve_changes(&mut stream);
+                }
+            }
+        });
+
+        let fingerprint = "00:70:AE:ED:6E:15:C0:C2:73:25:F2:56:ED:A6:73:1D:94:6C:D8:4A";
+        let backend = RpcBackend::new(&port, "david").unwrap();
+        match changelists(&backend) {
+            Err(P4Error::Spawn(error)) => assert!(error.to_string().contains(fingerprint)),
+            other => panic!("Expected an untrusted server, got {:?}", other),
+        }
+
+        let backend = backend.with_trusted_fingerprint(fingerprint).unwrap();
+        assert_eq!(changelists(&backend).unwrap(), [2, 1]);
+        server.join().unwrap();
+
+        assert!(
+            RpcBackend::new(&port, "david")
+                .unwrap()
+                .with_trusted_fingerprint("00:70")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,76 @@
+// == Std crates
+use std::io;
+
+// == Internal crates
+use crate::context::*;
+use crate::error::*;
+use crate::print::*;
+
+// Writes the shelved revision of a file in a pending changelist to `writer` with `p4 print //path@=change`,
+// without unshelving it into a workspace. The returned file has the revision's details and empty content,
+// None if the file isn't shelved in the changelist.
+#[cfg(feature = "spawn")]
+pub fn print_shelved(
+    change: u32,
+    depot_path: &str,
+    writer: &mut dyn io::Write,
+) -> Result<Option<P4PrintedFile>, P4Error> {
+    print_shelved_from_context(&P4Context::default(), change, depot_path, writer)
+}
+
+pub fn print_shelved_from_context(
+    context: &P4Context,
+    change: u32,
+    depot_path: &str,
+    writer: &mut dyn io::Write,
+) -> Result<Option<P4PrintedFile>, P4Error> {
+    let filespec = format!("{}@={}", depot_path, change);
+    let mut files = P4PrintIterator::new_from_context(context, &[&filespec])?;
+    let file = files.next_to_writer(writer).transpose()?;
+    files.try_for_each(|file| file.map(drop))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_print_shelved() {
+        let context = MockP4::new()
+            .with_records(
+                "print //depot/a.txt@=42",
+                [
+                    vec![
+                        ("code", "stat"),
+                        ("depotFile", "//depot/a.txt"),
+                        ("rev", "3"),
+                        ("change", "42"),
+                        ("action", "edit"),
+                        ("type", "text"),
+                        ("time", "1743724741"),
+                    ],
+                    vec![("code", "text"), ("data", "shelved\n")],
+                ],
+            )
+            .with_error(
+                "print //depot/b.txt@=42",
+                2,
+                17,
+                "//depot/b.txt@=42 - no such file(s).\n",
+            )
+            .into_context();
+
+        let mut content = Vec::new();
+        let file = print_shelved_from_context(&context, 42, "//depot/a.txt", &mut content)
+            .unwrap()
+            .unwrap();
+        assert_eq!((file.revision, file.change), (3, 42));
+        assert!(file.content.is_empty());
+        assert_eq!(content, b"shelved\n");
+
+        let file = print_shelved_from_context(&context, 42, "//depot/b.txt", &mut content);
+        assert_eq!(file.unwrap(), None);
+    }
+}
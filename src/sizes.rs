@@ -0,0 +1,272 @@
+// == Std crates
+use std::collections::BTreeMap;
+
+// == Internal crates
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::*;
+
+// A file revision's size as listed by `p4 sizes`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4FileSize {
+    pub depot_path: String,
+    pub revision: u32,
+    // Zero for deleted revisions
+    pub file_size: u64,
+}
+
+// The options of a `p4 sizes` command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4SizesQuery {
+    filespecs: Vec<String>,
+    all_revisions: bool,
+    exclude_lazy_copies: bool,
+}
+
+impl P4SizesQuery {
+    pub fn new(filespec: &str) -> Self {
+        P4SizesQuery {
+            filespecs: vec![filespec.to_string()],
+            ..Default::default()
+        }
+    }
+
+    pub fn with_filespec(mut self, filespec: &str) -> Self {
+        self.filespecs.push(filespec.to_string());
+        self
+    }
+
+    // -a, every revision rather than only the head
+    pub fn with_all_revisions(mut self) -> Self {
+        self.all_revisions = true;
+        self
+    }
+
+    // -z, leave out revisions that share their archive with another file, e.g. after a branch
+    pub fn with_exclude_lazy_copies(mut self) -> Self {
+        self.exclude_lazy_copies = true;
+        self
+    }
+}
+
+impl From<&str> for P4SizesQuery {
+    fn from(filespec: &str) -> Self {
+        P4SizesQuery::new(filespec)
+    }
+}
+
+// The files and bytes under a directory, with the directories below it down to the rollup depth
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4DirectorySize {
+    // e.g. //depot/main/src
+    pub path: String,
+    // File revisions anywhere under the directory
+    pub files: u64,
+    pub bytes: u64,
+    // By directory name
+    pub children: BTreeMap<String, P4DirectorySize>,
+}
+
+impl P4DirectorySize {
+    // `root` is a depot path without a wildcard, e.g. //depot/main. Files deeper than `depth` directories below
+    // it are counted in their directory at that depth, files outside it are ignored.
+    pub fn from_sizes<ErrorT>(
+        root: &str,
+        depth: u32,
+        sizes: impl IntoIterator<Item = Result<P4FileSize, ErrorT>>,
+    ) -> Result<Self, ErrorT> {
+        let root = root.trim_end_matches('/');
+        let mut tree = P4DirectorySize {
+            path: root.to_string(),
+            ..Default::default()
+        };
+        let prefix = format!("{}/", root);
+        for size in sizes {
+            let size = size?;
+            if let Some(relative) = size.depot_path.strip_prefix(&prefix) {
+                tree.add(relative, depth, size.file_size);
+            }
+        }
+        Ok(tree)
+    }
+
+    fn add(&mut self, relative_path: &str, depth: u32, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+        if depth == 0 {
+            return;
+        }
+        if let Some((dir, rest)) = relative_path.split_once('/') {
+            let path = format!("{}/{}", self.path, dir);
+            self.children
+                .entry(dir.to_string())
+                .or_insert_with(|| P4DirectorySize {
+                    path,
+                    ..Default::default()
+                })
+                .add(rest, depth - 1, bytes);
+        }
+    }
+
+    // The directories directly below this one, biggest first
+    pub fn largest_children(&self) -> Vec<&P4DirectorySize> {
+        let mut children = self.children.values().collect::<Vec<_>>();
+        children.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.path.cmp(&b.path)));
+        children
+    }
+}
+
+// Adds up every revision's size under `path`, e.g. //depot, by directory down to `depth` levels below it
+#[cfg(feature = "spawn")]
+pub fn rollup(path: &str, depth: u32) -> Result<P4DirectorySize, P4Error> {
+    rollup_from_context(&P4Context::default(), path, depth)
+}
+
+#[cfg(feature = "process")]
+pub fn rollup_from_context(
+    context: &P4Context,
+    path: &str,
+    depth: u32,
+) -> Result<P4DirectorySize, P4Error> {
+    let filespec = format!("{}/...", path.trim_end_matches('/'));
+    let query = P4SizesQuery::new(&filespec).with_all_revisions();
+    P4DirectorySize::from_sizes(
+        path,
+        depth,
+        P4SizesIterator::new_from_context(context, query)?,
+    )
+}
+
+impl P4Command for P4SizesQuery {
+    type Item = P4FileSize;
+    type Fields = InterimP4FileSize;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["sizes".to_string()];
+        if self.all_revisions {
+            args.push("-a".to_string());
+        }
+        if self.exclude_lazy_copies {
+            args.push("-z".to_string());
+        }
+        args.extend(self.filespecs.iter().cloned());
+        args
+    }
+}
+
+pub type P4SizesIterator<ReadT> = P4CommandIterator<P4SizesQuery, ReadT>;
+
+#[cfg(feature = "process")]
+impl P4SizesIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(query: impl Into<P4SizesQuery>) -> Result<P4SizesIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), query)
+    }
+
+    // `query` is a P4SizesQuery, or just a filespec
+    pub fn new_from_context(
+        context: &P4Context,
+        query: impl Into<P4SizesQuery>,
+    ) -> Result<P4SizesIterator<P4Output>, P4Error> {
+        Self::spawn(context, query.into())
+    }
+}
+
+// The parsing state of a P4FileSize
+#[derive(Debug, Default)]
+pub struct InterimP4FileSize {
+    depot_path: Option<String>,
+    revision: Option<u32>,
+    file_size: Option<u64>,
+}
+
+impl P4RecordFields for InterimP4FileSize {
+    type Output = P4FileSize;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "depotFile" => self.depot_path = Some(value.to_string()),
+            "rev" => self.revision = Some(parse_field(value, "Invalid revision")?),
+            "fileSize" => self.file_size = Some(parse_field(value, "Invalid file size")?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4FileSize, P4Error> {
+        Ok(P4FileSize {
+            depot_path: self
+                .depot_path
+                .ok_or(P4Error::InvalidRecord("Missing depot path"))?,
+            revision: self
+                .revision
+                .ok_or(P4Error::InvalidRecord("Missing revision"))?,
+            file_size: self.file_size.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_rollup() {
+        assert_eq!(
+            P4SizesQuery::new("//depot/...")
+                .with_all_revisions()
+                .with_exclude_lazy_copies()
+                .args(),
+            ["sizes", "-a", "-z", "//depot/..."]
+        );
+
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/main/assets/tex/big.png"),
+                ("rev", "2"),
+                ("fileSize", "5000"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/main/assets/tex/big.png"),
+                ("rev", "1"),
+                ("fileSize", "4000"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/main/src/lib.rs"),
+                ("rev", "1"),
+                ("fileSize", "100"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/main/README"),
+                ("rev", "2"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/rel/README"),
+                ("rev", "1"),
+                ("fileSize", "10"),
+            ],
+        ]);
+
+        let sizes = P4SizesIterator::new_from_reader(&data[..]);
+        let tree = P4DirectorySize::from_sizes("//depot/main/", 1, sizes).unwrap();
+        assert_eq!((tree.files, tree.bytes), (4, 9100));
+        let largest = tree.largest_children();
+        assert_eq!(largest.len(), 2);
+        assert_eq!(
+            (largest[0].path.as_str(), largest[0].files, largest[0].bytes),
+            ("//depot/main/assets", 2, 9000)
+        );
+        assert!(largest[0].children.is_empty());
+        assert_eq!(largest[1].bytes, 100);
+    }
+}
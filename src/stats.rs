@@ -0,0 +1,243 @@
+// == Std crates
+use std::collections::{BTreeMap, HashMap};
+
+// == Internal crates
+use crate::*;
+
+// Aggregates over changelists with their files, e.g. from describe_each or a P4HistoryScan
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4ChangelistStats {
+    pub changelists: u64,
+    pub files: u64,
+    // e.g. "edit" => 120
+    pub files_per_action: BTreeMap<String, u64>,
+    // Sorted by user, then week
+    pub submits_per_user_week: Vec<P4UserWeek>,
+    // The most files first
+    pub biggest_changelists: Vec<P4ChangelistSize>,
+    // The directories with the most file changes, most first
+    pub busiest_paths: Vec<P4PathActivity>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4UserWeek {
+    pub user: String,
+    // The p4 timestamp of midnight UTC on the Monday the week starts
+    pub week_start: u32,
+    pub submits: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4ChangelistSize {
+    pub changelist: u32,
+    pub user: String,
+    pub files: u64,
+    // The sizes of the files' new revisions, as far as describe reports them
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4PathActivity {
+    // A directory, e.g. //depot/main/src
+    pub path: String,
+    pub file_changes: u64,
+}
+
+// Adds up changelists one at a time, keeping only the counts and the current top entries
+#[derive(Debug, Clone)]
+pub struct P4StatsCollector {
+    top: usize,
+    changelists: u64,
+    files: u64,
+    files_per_action: BTreeMap<String, u64>,
+    submits_per_user_week: BTreeMap<(String, u32), u64>,
+    biggest_changelists: Vec<P4ChangelistSize>,
+    changes_per_path: HashMap<String, u64>,
+}
+
+impl Default for P4StatsCollector {
+    fn default() -> Self {
+        P4StatsCollector::new(10)
+    }
+}
+
+impl P4StatsCollector {
+    // `top` is how many of the biggest changelists and busiest paths are kept
+    pub fn new(top: usize) -> Self {
+        P4StatsCollector {
+            top,
+            changelists: 0,
+            files: 0,
+            files_per_action: BTreeMap::new(),
+            submits_per_user_week: BTreeMap::new(),
+            biggest_changelists: Vec::new(),
+            changes_per_path: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, changelist: &P4Changelist) {
+        self.changelists += 1;
+        self.files += changelist.files.len() as u64;
+        *self
+            .submits_per_user_week
+            .entry((changelist.user.clone(), week_start(changelist.time)))
+            .or_default() += 1;
+
+        for file in &changelist.files {
+            *self
+                .files_per_action
+                .entry(file.action.clone())
+                .or_default() += 1;
+            let dir = file
+                .depot_path
+                .rsplit_once('/')
+                .map_or(file.depot_path.as_str(), |(dir, _)| dir);
+            *self.changes_per_path.entry(dir.to_string()).or_default() += 1;
+        }
+
+        let size = P4ChangelistSize {
+            changelist: changelist.changelist,
+            user: changelist.user.clone(),
+            files: changelist.files.len() as u64,
+            bytes: changelist
+                .files
+                .iter()
+                .filter_map(|file| file.file_size)
+                .sum(),
+        };
+        let position = self
+            .biggest_changelists
+            .partition_point(|other| other.files >= size.files);
+        if position < self.top {
+            self.biggest_changelists.insert(position, size);
+            self.biggest_changelists.truncate(self.top);
+        }
+    }
+
+    pub fn finish(self) -> P4ChangelistStats {
+        let mut busiest_paths = self
+            .changes_per_path
+            .into_iter()
+            .map(|(path, file_changes)| P4PathActivity { path, file_changes })
+            .collect::<Vec<_>>();
+        busiest_paths.sort_by(|a, b| {
+            b.file_changes
+                .cmp(&a.file_changes)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        busiest_paths.truncate(self.top);
+
+        P4ChangelistStats {
+            changelists: self.changelists,
+            files: self.files,
+            files_per_action: self.files_per_action,
+            submits_per_user_week: self
+                .submits_per_user_week
+                .into_iter()
+                .map(|((user, week_start), submits)| P4UserWeek {
+                    user,
+                    week_start,
+                    submits,
+                })
+                .collect(),
+            biggest_changelists: self.biggest_changelists,
+            busiest_paths,
+        }
+    }
+}
+
+// Reads the changelists to the end, stopping at the first error
+pub fn collect<ErrorT>(
+    changelists: impl IntoIterator<Item = Result<P4Changelist, ErrorT>>,
+    top: usize,
+) -> Result<P4ChangelistStats, ErrorT> {
+    let mut collector = P4StatsCollector::new(top);
+    for changelist in changelists {
+        collector.add(&changelist?);
+    }
+    Ok(collector.finish())
+}
+
+// 1970-01-01 was a Thursday, three days after a Monday
+fn week_start(time: u32) -> u32 {
+    let days = time / 86400;
+    (days - (days + 3) % 7) * 86400
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let file = |depot_path: &str, action: &str, file_size: u64| P4File {
+            depot_path: depot_path.to_string(),
+            action: action.to_string(),
+            revision: Some(1),
+            file_size: Some(file_size),
+            digest: Some([0; 16]),
+        };
+        let changelist =
+            |changelist: u32, user: &str, time: u32, files: Vec<P4File>| P4Changelist {
+                changelist,
+                user: user.to_string(),
+                time,
+                files,
+                ..Default::default()
+            };
+        // Wednesday 2025-04-02, then Monday 2025-04-07 and a minute before it
+        let changelists = [
+            changelist(
+                1,
+                "alice",
+                1743552000,
+                vec![
+                    file("//depot/main/src/a.rs", "add", 10),
+                    file("//depot/main/src/b.rs", "add", 20),
+                ],
+            ),
+            changelist(
+                2,
+                "alice",
+                1743984000,
+                vec![file("//depot/main/src/a.rs", "edit", 12)],
+            ),
+            changelist(
+                3,
+                "alice",
+                1743983940,
+                vec![file("//depot/main/README", "edit", 5)],
+            ),
+            changelist(4, "bob", 1743984000, vec![]),
+        ];
+
+        let stats = collect(changelists.into_iter().map(Ok::<_, ()>), 2).unwrap();
+        assert_eq!((stats.changelists, stats.files), (4, 4));
+        assert_eq!(
+            stats.files_per_action,
+            BTreeMap::from([("add".to_string(), 2), ("edit".to_string(), 2)])
+        );
+        let weeks = stats
+            .submits_per_user_week
+            .iter()
+            .map(|week| (week.user.as_str(), week.week_start, week.submits))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            weeks,
+            [
+                ("alice", 1743379200, 2),
+                ("alice", 1743984000, 1),
+                ("bob", 1743984000, 1)
+            ]
+        );
+        let biggest = stats
+            .biggest_changelists
+            .iter()
+            .map(|size| (size.changelist, size.files, size.bytes))
+            .collect::<Vec<_>>();
+        assert_eq!(biggest, [(1, 2, 30), (2, 1, 12)]);
+        assert_eq!(stats.busiest_paths[0].path, "//depot/main/src");
+        assert_eq!(stats.busiest_paths[0].file_changes, 3);
+        assert_eq!(stats.busiest_paths.len(), 2);
+    }
+}
@@ -0,0 +1,248 @@
+// == Internal crates
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+use crate::error::*;
+use crate::view::*;
+
+// How a stream's files map to a workspace, from the Paths and Remapped fields of its spec. The right side of
+// each view line is relative to the workspace root, so two streams' views meet in the middle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4StreamMap {
+    // e.g. //streams/main
+    pub stream: String,
+    // The Parent field, None for mainline streams
+    pub parent: Option<String>,
+    pub parent_view: P4ParentView,
+    pub view: P4View,
+}
+
+// The ParentView field of a stream spec. With inherit the stream's view is narrowed by its parent's, which
+// the view here doesn't include, with noinherit it stands on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P4ParentView {
+    #[default]
+    Inherit,
+    NoInherit,
+}
+
+impl P4StreamMap {
+    // From the fields of `p4 stream -o`, i.e. Stream, Paths0 to PathsN and Remapped0 to RemappedN. Paths lines
+    // are `share src/...` or `import lib/... //depot/lib/...@120`, Remapped lines `src/... source/...`.
+    // Remapped lines apply to paths the stream itself owns, imported paths keep their own location.
+    pub fn from_spec<'a>(
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, P4ValueParseError> {
+        let mut stream = String::new();
+        let mut parent = None;
+        let mut parent_view = P4ParentView::default();
+        let mut paths = Vec::new();
+        let mut remapped = Vec::new();
+        for (key, value) in fields {
+            if key == "Stream" {
+                stream = value.to_string();
+            } else if key == "Parent" {
+                parent = Some(value)
+                    .filter(|parent| *parent != "none")
+                    .map(str::to_string);
+            } else if key == "ParentView" {
+                parent_view = match value {
+                    "noinherit" => P4ParentView::NoInherit,
+                    _ => P4ParentView::Inherit,
+                };
+            } else if let Some(index) = indexed_field(key, "Paths") {
+                paths.push((index, value));
+            } else if let Some(index) = indexed_field(key, "Remapped") {
+                remapped.push((index, value));
+            }
+        }
+        paths.sort_by_key(|(index, _)| *index);
+        remapped.sort_by_key(|(index, _)| *index);
+
+        let invalid =
+            |value: &str, reason| P4ValueParseError::InvalidViewLine(value.to_string(), reason);
+        let mut lines = Vec::new();
+        for (_, value) in paths {
+            let mut words = value.split_whitespace();
+            let (Some(path_type), Some(path)) = (words.next(), words.next()) else {
+                return Err(invalid(value, "expected a path type and a path"));
+            };
+            let stream_path = format!("{}/{}", stream, path);
+            let (left, exclude) = match path_type {
+                "share" | "isolate" | "public" | "private" => (stream_path, false),
+                // Without a depot path the file comes from the parent at the same place
+                "import" | "import+" | "import&" => match words.next() {
+                    Some(depot_path) => (
+                        depot_path
+                            .split('@')
+                            .next()
+                            .unwrap_or(depot_path)
+                            .to_string(),
+                        false,
+                    ),
+                    None => (stream_path, false),
+                },
+                "exclude" => (stream_path, true),
+                _ => return Err(invalid(value, "unknown path type")),
+            };
+            lines.push(P4ViewLine {
+                left,
+                right: path.to_string(),
+                exclude,
+                overlay: false,
+            });
+        }
+        for (_, value) in remapped {
+            let mut words = value.split_whitespace();
+            let (Some(from), Some(to)) = (words.next(), words.next()) else {
+                return Err(invalid(
+                    value,
+                    "expected a path and where it is remapped to",
+                ));
+            };
+            lines.push(P4ViewLine::new(&format!("{}/{}", stream, from), to));
+        }
+
+        Ok(P4StreamMap {
+            stream,
+            parent,
+            parent_view,
+            view: P4View { lines },
+        })
+    }
+
+    // Where a depot path in the stream lands, relative to the workspace root
+    pub fn workspace_path(&self, depot_path: &str) -> Option<String> {
+        self.view.translate(depot_path)
+    }
+
+    // The depot path a workspace relative path comes from
+    pub fn depot_path(&self, workspace_path: &str) -> Option<String> {
+        self.view.reversed().translate(workspace_path)
+    }
+
+    // The counterpart in `to` of a depot path in this stream, going through the workspace layout both streams
+    // share. None if either stream doesn't map it.
+    pub fn translate_to(&self, to: &P4StreamMap, depot_path: &str) -> Option<String> {
+        to.depot_path(&self.workspace_path(depot_path)?)
+    }
+}
+
+fn indexed_field(key: &str, name: &str) -> Option<usize> {
+    key.strip_prefix(name)?.parse().ok()
+}
+
+#[cfg(feature = "spawn")]
+pub fn fetch(stream: &str) -> Result<P4StreamMap, P4Error> {
+    fetch_from_context(&P4Context::default(), stream)
+}
+
+#[cfg(feature = "process")]
+pub fn fetch_from_context(context: &P4Context, stream: &str) -> Result<P4StreamMap, P4Error> {
+    let spec = P4DictIterator::new_from_context(context, vec!["stream", "-o", stream])?
+        .next()
+        .ok_or(P4Error::InvalidRecord("Missing stream spec"))??;
+    P4StreamMap::from_spec(spec.iter())
+        .map_err(|_| P4Error::InvalidRecord("Invalid stream path line"))
+}
+
+// Translates a depot path from one stream to another, e.g. for cherry-picking a change across streams
+#[cfg(feature = "spawn")]
+pub fn translate(
+    from_stream: &str,
+    to_stream: &str,
+    depot_path: &str,
+) -> Result<Option<String>, P4Error> {
+    translate_from_context(&P4Context::default(), from_stream, to_stream, depot_path)
+}
+
+#[cfg(feature = "process")]
+pub fn translate_from_context(
+    context: &P4Context,
+    from_stream: &str,
+    to_stream: &str,
+    depot_path: &str,
+) -> Result<Option<String>, P4Error> {
+    let from = fetch_from_context(context, from_stream)?;
+    let to = fetch_from_context(context, to_stream)?;
+    Ok(from.translate_to(&to, depot_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_translate() {
+        let main = P4StreamMap::from_spec([
+            ("Stream", "//streams/main"),
+            ("Paths0", "share ..."),
+            ("Paths1", "import lib/... //depot/lib/...@120"),
+            ("Paths2", "exclude tmp/..."),
+        ])
+        .unwrap();
+        let dev = P4StreamMap::from_spec([
+            ("Stream", "//streams/dev"),
+            ("Parent", "//streams/main"),
+            ("ParentView", "noinherit"),
+            ("Remapped0", "source/... src/..."),
+            ("Paths0", "share ..."),
+        ])
+        .unwrap();
+
+        assert_eq!(main.parent, None);
+        assert_eq!(dev.parent.as_deref(), Some("//streams/main"));
+        assert_eq!(dev.parent_view, P4ParentView::NoInherit);
+        assert_eq!(
+            main.translate_to(&dev, "//streams/main/src/a.rs")
+                .as_deref(),
+            Some("//streams/dev/source/a.rs")
+        );
+        assert_eq!(
+            dev.translate_to(&main, "//streams/dev/source/a.rs")
+                .as_deref(),
+            Some("//streams/main/src/a.rs")
+        );
+        assert_eq!(
+            main.translate_to(&dev, "//depot/lib/util.rs").as_deref(),
+            Some("//streams/dev/lib/util.rs")
+        );
+        assert_eq!(main.translate_to(&dev, "//streams/main/tmp/x"), None);
+        assert_eq!(main.translate_to(&dev, "//streams/other/a.rs"), None);
+        assert!(P4StreamMap::from_spec([("Stream", "//s/a"), ("Paths0", "mirror ...")]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_translate_from_context() {
+        use crate::testing::*;
+
+        let context = MockP4::new()
+            .with_records(
+                "stream -o //streams/main",
+                [[
+                    ("code", "stat"),
+                    ("Stream", "//streams/main"),
+                    ("Paths0", "share ..."),
+                ]],
+            )
+            .with_records(
+                "stream -o //streams/rel",
+                [[
+                    ("code", "stat"),
+                    ("Stream", "//streams/rel"),
+                    ("Paths0", "isolate ..."),
+                ]],
+            )
+            .into_context();
+
+        let translated = translate_from_context(
+            &context,
+            "//streams/main",
+            "//streams/rel",
+            "//streams/main/a.txt",
+        );
+        assert_eq!(translated.unwrap().as_deref(), Some("//streams/rel/a.txt"));
+    }
+}
@@ -0,0 +1,930 @@
+// == Std crates
+#[cfg(feature = "process")]
+use std::thread;
+#[cfg(feature = "process")]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Condvar, Mutex},
+};
+
+// == Internal crates
+use crate::cancel::*;
+use crate::command::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+use crate::error::*;
+use crate::files::*;
+#[cfg(feature = "process")]
+use crate::fstat::*;
+use crate::have::*;
+use crate::metrics::*;
+#[cfg(feature = "process")]
+use crate::output::*;
+use crate::parsers::py_dict::*;
+#[cfg(feature = "process")]
+use crate::progress::*;
+use crate::*;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4SyncOptions {
+    // Transfer files over this many connections, see `p4 help sync` for the server side requirements
+    pub parallel_threads: Option<u32>,
+    // -f, resync files that are already up to date
+    pub force: bool,
+    // -n, report what would be synced without transferring anything
+    pub preview: bool,
+}
+
+impl P4SyncOptions {
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(threads) = self.parallel_threads {
+            args.push(format!("--parallel=threads={}", threads));
+        }
+        if self.force {
+            args.push("-f".to_string());
+        }
+        if self.preview {
+            args.push("-n".to_string());
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4SyncedFile {
+    pub depot_path: String,
+    pub client_path: String,
+    pub revision: u32,
+    // added, updated, deleted, refreshed...
+    pub action: String,
+    pub file_size: u64,
+    // None as sync doesn't report file types, see fill_file_kinds
+    pub file_kind: Option<P4FileKind>,
+}
+
+// Totals come from the first record p4 prints, so they are None until then
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct P4SyncProgress {
+    pub files_done: u64,
+    pub files_total: Option<u64>,
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+}
+
+// What a sync would do, from `p4 sync -n`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4SyncPlan {
+    pub adds: Vec<P4SyncedFile>,
+    // Includes files that would be refreshed or replaced
+    pub updates: Vec<P4SyncedFile>,
+    pub deletes: Vec<P4SyncedFile>,
+    // Size of the files that would be transferred, i.e. adds and updates
+    pub total_bytes: u64,
+}
+
+impl P4SyncPlan {
+    pub fn from_files(
+        files: impl IntoIterator<Item = Result<P4SyncedFile, P4Error>>,
+    ) -> Result<Self, P4Error> {
+        let mut plan = P4SyncPlan::default();
+        for file in files {
+            let file = file?;
+            match file.action.as_str() {
+                "deleted" => plan.deletes.push(file),
+                action => {
+                    plan.total_bytes += file.file_size;
+                    if action == "added" {
+                        plan.adds.push(file);
+                    } else {
+                        plan.updates.push(file);
+                    }
+                }
+            }
+        }
+        Ok(plan)
+    }
+}
+
+#[cfg(feature = "spawn")]
+pub fn preview(filespec: &str) -> Result<P4SyncPlan, P4Error> {
+    preview_from_context(&P4Context::default(), filespec)
+}
+
+#[cfg(feature = "process")]
+pub fn preview_from_context(context: &P4Context, filespec: &str) -> Result<P4SyncPlan, P4Error> {
+    let options = P4SyncOptions {
+        preview: true,
+        ..Default::default()
+    };
+    P4SyncPlan::from_files(P4SyncIterator::new_from_context(
+        context, filespec, &options,
+    )?)
+}
+
+// Looks up the kind of each file at its synced revision with one `p4 -x - fstat`, so mirroring tools can
+// recreate symlinks rather than writing their targets out as file bodies
+#[cfg(feature = "spawn")]
+pub fn fill_file_kinds(files: &mut [P4SyncedFile]) -> Result<(), P4Error> {
+    fill_file_kinds_from_context(&P4Context::default(), files)
+}
+
+#[cfg(feature = "process")]
+pub fn fill_file_kinds_from_context(
+    context: &P4Context,
+    files: &mut [P4SyncedFile],
+) -> Result<(), P4Error> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let paths = files
+        .iter()
+        .map(|file| format!("{}#{}", file.depot_path, file.revision))
+        .collect::<Vec<_>>();
+    let mut kinds = HashMap::new();
+    for entry in
+        P4FstatIterator::new_from_context_with_paths(context, P4FstatQuery::default(), paths)?
+    {
+        let entry = entry?;
+        if let Some(head_type) = entry.head_type {
+            kinds.insert(entry.depot_path, P4FileKind::from_file_type(&head_type));
+        }
+    }
+    for file in files {
+        file.file_kind = kinds.get(&file.depot_path).copied();
+    }
+    Ok(())
+}
+
+// A file synced at a different revision than the target
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4OutdatedFile {
+    pub have: P4HaveFile,
+    pub target: P4DepotFile,
+}
+
+// Which files in a workspace differ from the depot at a target revision, computed from `p4 have` and
+// `p4 files` so it is cheaper for the server than `p4 sync -n` on huge trees
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4SyncDiff {
+    pub outdated: Vec<P4OutdatedFile>,
+    // In the depot but not in the workspace
+    pub added: Vec<P4DepotFile>,
+    // In the workspace but deleted (or not mapped) at the target
+    pub removed: Vec<P4HaveFile>,
+}
+
+impl P4SyncDiff {
+    pub fn compute(
+        have: impl IntoIterator<Item = Result<P4HaveFile, P4Error>>,
+        target: impl IntoIterator<Item = Result<P4DepotFile, P4Error>>,
+    ) -> Result<Self, P4Error> {
+        let mut have = have
+            .into_iter()
+            .map(|file| file.map(|file| (file.depot_path.clone(), file)))
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let mut diff = P4SyncDiff::default();
+        for file in target {
+            let file = file?;
+            match (have.remove(&file.depot_path), file.is_deleted()) {
+                (Some(have), true) => diff.removed.push(have),
+                (Some(have), false) if have.revision != file.revision => {
+                    diff.outdated.push(P4OutdatedFile { have, target: file })
+                }
+                (None, false) => diff.added.push(file),
+                _ => {}
+            }
+        }
+
+        diff.removed.extend(have.into_values());
+        diff.removed.sort_by(|a, b| a.depot_path.cmp(&b.depot_path));
+        Ok(diff)
+    }
+}
+
+// `changelist` is the target, None for the head revisions
+#[cfg(feature = "spawn")]
+pub fn diff(filespec: &str, changelist: Option<u32>) -> Result<P4SyncDiff, P4Error> {
+    diff_from_context(&P4Context::default(), filespec, changelist)
+}
+
+#[cfg(feature = "process")]
+pub fn diff_from_context(
+    context: &P4Context,
+    filespec: &str,
+    changelist: Option<u32>,
+) -> Result<P4SyncDiff, P4Error> {
+    let target = match changelist {
+        Some(changelist) => format!("{}@{}", filespec, changelist),
+        None => format!("{}#head", filespec),
+    };
+    P4SyncDiff::compute(
+        P4HaveIterator::new_from_context(context, filespec)?,
+        P4FilesIterator::new_from_context(context, &target)?,
+    )
+}
+
+// A daily period in which background syncs may run, in seconds since midnight. A window that ends before it
+// starts runs over midnight, e.g. 22:00 to 06:00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct P4SyncWindow {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl P4SyncWindow {
+    pub fn new(start_hour: u32, end_hour: u32) -> Self {
+        P4SyncWindow {
+            start: start_hour % 24 * 3600,
+            end: end_hour % 24 * 3600,
+        }
+    }
+
+    pub fn contains(&self, second_of_day: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&second_of_day)
+        } else {
+            second_of_day >= self.start || second_of_day < self.end
+        }
+    }
+
+    // How long until the window next opens, zero while it is open
+    pub fn seconds_until_open(&self, second_of_day: u32) -> u32 {
+        if self.contains(second_of_day) {
+            return 0;
+        }
+        (self.start + 86400 - second_of_day) % 86400
+    }
+}
+
+// Limits on a background sync, see P4SyncScheduler
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4SyncSchedule {
+    // Average transfer rate over each batch, in bytes per second
+    pub bandwidth: Option<u64>,
+    // Batches only start inside one of these, any time if empty
+    pub windows: Vec<P4SyncWindow>,
+    // Added to UTC to get the local time the windows are in, e.g. 3600 for CET
+    pub utc_offset: i32,
+    // How much each `p4 sync` transfers before the scheduler can pace, pause or wait for a window
+    pub batch_bytes: u64,
+}
+
+impl Default for P4SyncSchedule {
+    fn default() -> Self {
+        P4SyncSchedule {
+            bandwidth: None,
+            windows: Vec::new(),
+            utc_offset: 0,
+            batch_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl P4SyncSchedule {
+    // Seconds to wait before a batch may start at `time`, a p4 timestamp
+    pub fn seconds_until_allowed(&self, time: u64) -> u32 {
+        let local_time = time as i64 + i64::from(self.utc_offset);
+        let second_of_day = local_time.rem_euclid(86400) as u32;
+        self.windows
+            .iter()
+            .map(|window| window.seconds_until_open(second_of_day))
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+// Pauses and resumes a running P4SyncScheduler from another thread. A pause takes effect between batches.
+#[derive(Debug, Clone, Default)]
+pub struct P4SyncControl {
+    paused: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl P4SyncControl {
+    pub fn pause(&self) {
+        *self.paused.0.lock().expect("Pause lock poisoned") = true;
+    }
+
+    pub fn resume(&self) {
+        *self.paused.0.lock().expect("Pause lock poisoned") = false;
+        self.paused.1.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.0.lock().expect("Pause lock poisoned")
+    }
+
+    // Returns early when the cancellation token is set
+    #[cfg(feature = "process")]
+    fn wait_while_paused(&self, cancellation: Option<&CancellationToken>) {
+        let (lock, resumed) = &*self.paused;
+        let mut paused = lock.lock().expect("Pause lock poisoned");
+        while *paused && !cancellation.is_some_and(CancellationToken::is_cancelled) {
+            paused = resumed
+                .wait_timeout(paused, Duration::from_millis(200))
+                .expect("Pause lock poisoned")
+                .0;
+        }
+    }
+}
+
+// Syncs in batches so a background mirror doesn't saturate the network: the files to sync come from
+// `p4 sync -n`, each batch is synced with `p4 -x - sync` and followed by a pause that keeps the average rate
+// under the bandwidth cap, and batches only start inside the time windows and while not paused. p4 transfers
+// each batch at full speed, so keep batch_bytes small next to what the link carries in a few seconds.
+#[cfg(feature = "process")]
+pub struct P4SyncScheduler {
+    context: P4Context,
+    filespec: String,
+    options: P4SyncOptions,
+    schedule: P4SyncSchedule,
+    control: P4SyncControl,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+#[cfg(feature = "process")]
+impl P4SyncScheduler {
+    pub fn new(context: &P4Context, filespec: &str, schedule: P4SyncSchedule) -> Self {
+        P4SyncScheduler {
+            context: context.clone(),
+            filespec: filespec.to_string(),
+            options: P4SyncOptions::default(),
+            schedule,
+            control: P4SyncControl::default(),
+            progress: None,
+        }
+    }
+
+    pub fn with_options(mut self, options: P4SyncOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Reports the preview, then each synced file against the totals from it
+    pub fn with_progress(mut self, sink: impl ProgressSink + 'static) -> Self {
+        self.progress = Some(Arc::new(sink));
+        self
+    }
+
+    // A handle for pausing and resuming, e.g. while the user is in a video call
+    pub fn control(&self) -> P4SyncControl {
+        self.control.clone()
+    }
+
+    // Calls `on_file` for each synced file and returns the totals. Stops between batches when the context's
+    // cancellation token is set.
+    pub fn run(&self, mut on_file: impl FnMut(&P4SyncedFile)) -> Result<P4SyncProgress, P4Error> {
+        let mut tracker = ProgressTracker::new(self.progress.clone());
+        tracker.start_phase("preview", None, None);
+        let preview_options = P4SyncOptions {
+            preview: true,
+            ..self.options.clone()
+        };
+        let plan =
+            P4SyncIterator::new_from_context(&self.context, &self.filespec, &preview_options)?
+                .collect::<Result<Vec<_>, _>>()?;
+        let mut progress = P4SyncProgress {
+            files_total: Some(plan.len() as u64),
+            bytes_total: Some(plan.iter().map(|file| file.file_size).sum()),
+            ..Default::default()
+        };
+        tracker.start_phase("sync", progress.files_total, progress.bytes_total);
+
+        let cancellation = self.context.cancellation_token();
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+        let mut files = plan.into_iter().peekable();
+        while let Some(file) = files.next() {
+            batch_bytes += file.file_size;
+            batch.push(format!("{}#{}", file.depot_path, file.revision));
+            if batch_bytes < self.schedule.batch_bytes && files.peek().is_some() {
+                continue;
+            }
+
+            self.wait_for_turn(cancellation);
+            if cancellation.is_some_and(CancellationToken::is_cancelled) {
+                return Err(P4Error::Cancelled {
+                    records_yielded: progress.files_done,
+                });
+            }
+
+            let started = Instant::now();
+            let paths = std::mem::take(&mut batch);
+            for file in
+                P4SyncIterator::new_from_context_with_paths(&self.context, paths, &self.options)?
+            {
+                let file = file?;
+                progress.files_done += 1;
+                progress.bytes_done += file.file_size;
+                tracker.record(&file.depot_path, file.file_size);
+                on_file(&file);
+            }
+
+            if let Some(bandwidth) = self.schedule.bandwidth.filter(|bandwidth| *bandwidth > 0) {
+                let budget = Duration::from_secs_f64(batch_bytes as f64 / bandwidth as f64);
+                thread::sleep(budget.saturating_sub(started.elapsed()));
+            }
+            batch_bytes = 0;
+        }
+        Ok(progress)
+    }
+
+    // Waits out a pause and the time outside the windows, checking again every second so a resume or
+    // cancellation is noticed
+    fn wait_for_turn(&self, cancellation: Option<&CancellationToken>) {
+        loop {
+            self.control.wait_while_paused(cancellation);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs());
+            let wait = self.schedule.seconds_until_allowed(now);
+            if wait == 0 || cancellation.is_some_and(CancellationToken::is_cancelled) {
+                return;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+// `p4 sync` with its options, followed by a filespec or given them through `-x -`
+struct P4SyncCommand {
+    options: P4SyncOptions,
+    filespec: Option<String>,
+}
+
+impl P4Command for P4SyncCommand {
+    type Item = P4SyncRecord;
+    type Fields = InterimP4SyncedFile;
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["sync".to_string()];
+        args.extend(self.options.args());
+        args.extend(self.filespec.iter().cloned());
+        args
+    }
+}
+
+pub struct P4SyncIterator<ReadT: io::Read> {
+    records: P4CommandIterator<P4SyncCommand, ReadT>,
+    progress: P4SyncProgress,
+}
+
+#[cfg(feature = "process")]
+impl P4SyncIterator<P4Output> {
+    #[cfg(feature = "spawn")]
+    pub fn new(
+        filespec: &str,
+        options: &P4SyncOptions,
+    ) -> Result<P4SyncIterator<P4Output>, P4Error> {
+        Self::new_from_context(&P4Context::default(), filespec, options)
+    }
+
+    // With parallel threads p4 still prints one record per file, in the order the transfers complete
+    pub fn new_from_context(
+        context: &P4Context,
+        filespec: &str,
+        options: &P4SyncOptions,
+    ) -> Result<P4SyncIterator<P4Output>, P4Error> {
+        let command = P4SyncCommand {
+            options: options.clone(),
+            filespec: Some(filespec.to_string()),
+        };
+        Ok(Self::new_from_records(P4CommandIterator::spawn(
+            context, command,
+        )?))
+    }
+
+    #[cfg(feature = "spawn")]
+    pub fn new_with_paths(
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+        options: &P4SyncOptions,
+    ) -> Result<P4SyncIterator<P4Output>, P4Error> {
+        Self::new_from_context_with_paths(&P4Context::default(), paths, options)
+    }
+
+    // Any number of filespecs, streamed to `p4 -x -` rather than put on the command line
+    pub fn new_from_context_with_paths(
+        context: &P4Context,
+        paths: impl IntoIterator<Item = String, IntoIter: Send + 'static>,
+        options: &P4SyncOptions,
+    ) -> Result<P4SyncIterator<P4Output>, P4Error> {
+        let command = P4SyncCommand {
+            options: options.clone(),
+            filespec: None,
+        };
+        let records = P4CommandIterator::spawn_with_paths(context, command, paths)?;
+        Ok(Self::new_from_records(records))
+    }
+}
+
+impl<ReadT: io::Read> P4SyncIterator<ReadT> {
+    pub fn new_from_reader(reader: ReadT) -> P4SyncIterator<ReadT> {
+        Self::new_from_records(P4CommandIterator::new_from_reader(reader))
+    }
+
+    pub fn new_from_parser(parser: P4PyDictParser<ReadT>) -> P4SyncIterator<ReadT> {
+        Self::new_from_records(P4CommandIterator::new_from_parser(parser))
+    }
+
+    fn new_from_records(records: P4CommandIterator<P4SyncCommand, ReadT>) -> Self {
+        P4SyncIterator {
+            records,
+            progress: P4SyncProgress::default(),
+        }
+    }
+
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.records = self.records.with_cancellation_token(cancellation);
+        self
+    }
+
+    pub fn records_yielded(&self) -> u64 {
+        self.records.records_yielded()
+    }
+
+    pub fn summary(&self) -> Option<P4IteratorSummary> {
+        self.records.summary()
+    }
+
+    pub fn progress(&self) -> P4SyncProgress {
+        self.progress
+    }
+}
+
+impl<ReadT: io::Read> Iterator for P4SyncIterator<ReadT> {
+    type Item = Result<P4SyncedFile, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        if let Some(total_size) = record.total_size {
+            self.progress.bytes_total = Some(total_size);
+        }
+        if let Some(total_count) = record.total_count {
+            self.progress.files_total = Some(total_count);
+        }
+        self.progress.files_done += 1;
+        self.progress.bytes_done += record.file.file_size;
+        Some(Ok(record.file))
+    }
+}
+
+// A synced file with the totals p4 sends along with the first one
+struct P4SyncRecord {
+    file: P4SyncedFile,
+    total_size: Option<u64>,
+    total_count: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct InterimP4SyncedFile {
+    depot_path: Option<String>,
+    client_path: Option<String>,
+    revision: Option<u32>,
+    action: Option<String>,
+    file_size: Option<u64>,
+    total_size: Option<u64>,
+    total_count: Option<u64>,
+}
+
+impl P4RecordFields for InterimP4SyncedFile {
+    type Output = P4SyncRecord;
+
+    fn populate_field(&mut self, key: &str, value: &str) -> Result<(), P4Error> {
+        match key {
+            "depotFile" => self.depot_path = Some(value.to_string()),
+            "clientFile" => self.client_path = Some(value.to_string()),
+            "rev" => self.revision = Some(parse_field(value, "Invalid revision")?),
+            "action" => self.action = Some(value.to_string()),
+            "fileSize" => self.file_size = Some(parse_field(value, "Invalid file size")?),
+            "totalFileSize" => self.total_size = Some(parse_field(value, "Invalid total size")?),
+            "totalFileCount" => self.total_count = Some(parse_field(value, "Invalid total count")?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<P4SyncRecord, P4Error> {
+        let file = P4SyncedFile {
+            depot_path: self
+                .depot_path
+                .ok_or(P4Error::InvalidRecord("Missing depot path"))?,
+            client_path: self
+                .client_path
+                .ok_or(P4Error::InvalidRecord("Missing client path"))?,
+            revision: self
+                .revision
+                .ok_or(P4Error::InvalidRecord("Missing revision"))?,
+            action: self
+                .action
+                .ok_or(P4Error::InvalidRecord("Missing action"))?,
+            // Deleted files have no size
+            file_size: self.file_size.unwrap_or(0),
+            file_kind: None,
+        };
+        Ok(P4SyncRecord {
+            file,
+            total_size: self.total_size,
+            total_count: self.total_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::py_dict::to_py_dict_bytes;
+
+    #[test]
+    fn test_parallel_sync() {
+        // Parallel transfers complete out of order, the totals only come with the first record
+        let data = to_py_dict_bytes(&[
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/b.txt"),
+                ("clientFile", "/ws/b.txt"),
+                ("rev", "3"),
+                ("action", "updated"),
+                ("fileSize", "200"),
+                ("totalFileSize", "300"),
+                ("totalFileCount", "3"),
+                ("change", "12"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/a.txt"),
+                ("clientFile", "/ws/a.txt"),
+                ("rev", "1"),
+                ("action", "added"),
+                ("fileSize", "100"),
+            ],
+            &[
+                ("code", "stat"),
+                ("depotFile", "//depot/c.txt"),
+                ("clientFile", "/ws/c.txt"),
+                ("rev", "2"),
+                ("action", "deleted"),
+            ],
+        ]);
+
+        let mut sync = P4SyncIterator::new_from_reader(&data[..]);
+        assert_eq!(sync.progress().files_total, None);
+
+        let first = sync.next().unwrap().unwrap();
+        assert_eq!(first.depot_path, "//depot/b.txt");
+        assert_eq!(
+            sync.progress(),
+            P4SyncProgress {
+                files_done: 1,
+                files_total: Some(3),
+                bytes_done: 200,
+                bytes_total: Some(300),
+            }
+        );
+
+        let rest = sync.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[1].action, "deleted");
+        assert_eq!(sync.progress().bytes_done, 300);
+
+        // Nothing to do is not an error
+        let data = to_py_dict_bytes(&[&[
+            ("code", "error"),
+            ("data", "//depot/... - file(s) up-to-date.\n"),
+            ("severity", "2"),
+            ("generic", "17"),
+        ]]);
+        assert_eq!(P4SyncIterator::new_from_reader(&data[..]).count(), 0);
+
+        let options = P4SyncOptions {
+            parallel_threads: Some(4),
+            force: true,
+            ..Default::default()
+        };
+        assert_eq!(options.args(), ["--parallel=threads=4", "-f"]);
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_sync_preview() {
+        let mock = crate::testing::MockP4::new()
+            .with_records(
+                "sync -n //depot/...",
+                [
+                    [
+                        ("code", "stat"),
+                        ("depotFile", "//depot/a"),
+                        ("clientFile", "/ws/a"),
+                        ("rev", "1"),
+                        ("action", "added"),
+                        ("fileSize", "10"),
+                    ],
+                    [
+                        ("code", "stat"),
+                        ("depotFile", "//depot/b"),
+                        ("clientFile", "/ws/b"),
+                        ("rev", "4"),
+                        ("action", "updated"),
+                        ("fileSize", "20"),
+                    ],
+                    [
+                        ("code", "stat"),
+                        ("depotFile", "//depot/c"),
+                        ("clientFile", "/ws/c"),
+                        ("rev", "2"),
+                        ("action", "deleted"),
+                        ("fileSize", "30"),
+                    ],
+                ],
+            )
+            .with_records(
+                "-x - fstat",
+                [
+                    [
+                        ("code", "stat"),
+                        ("depotFile", "//depot/a"),
+                        ("headType", "symlink"),
+                    ],
+                    [
+                        ("code", "stat"),
+                        ("depotFile", "//depot/b"),
+                        ("headType", "text+x"),
+                    ],
+                ],
+            );
+        let context = mock.into_context();
+
+        let mut plan = preview_from_context(&context, "//depot/...").unwrap();
+        assert_eq!(plan.adds.len(), 1);
+        assert_eq!(plan.updates[0].revision, 4);
+        assert_eq!(plan.deletes[0].depot_path, "//depot/c");
+        assert_eq!(plan.total_bytes, 30);
+
+        assert_eq!(plan.adds[0].file_kind, None);
+        fill_file_kinds_from_context(&context, &mut plan.adds).unwrap();
+        fill_file_kinds_from_context(&context, &mut plan.updates).unwrap();
+        assert_eq!(plan.adds[0].file_kind, Some(P4FileKind::Symlink));
+        assert_eq!(plan.updates[0].file_kind, Some(P4FileKind::Regular));
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn test_sync_diff() {
+        let have = |path: &str, revision: &str| {
+            [
+                ("code", "stat".to_string()),
+                ("depotFile", format!("//depot/{}", path)),
+                ("path", format!("/ws/{}", path)),
+                ("haveRev", revision.to_string()),
+            ]
+        };
+        let file = |path: &str, revision: &str, action: &str| {
+            [
+                ("code", "stat".to_string()),
+                ("depotFile", format!("//depot/{}", path)),
+                ("rev", revision.to_string()),
+                ("change", "20".to_string()),
+                ("action", action.to_string()),
+                ("type", "text".to_string()),
+                ("time", "1743724741".to_string()),
+            ]
+        };
+
+        let mock = crate::testing::MockP4::new()
+            .with_records(
+                "have //depot/...",
+                [
+                    have("same", "1"),
+                    have("old", "1"),
+                    have("gone", "2"),
+                    have("unmapped", "1"),
+                ],
+            )
+            .with_records(
+                "files //depot/...@20",
+                [
+                    file("same", "1", "add"),
+                    file("old", "3", "edit"),
+                    file("gone", "3", "delete"),
+                    file("new", "1", "add"),
+                    file("never", "2", "delete"),
+                ],
+            );
+
+        let diff = diff_from_context(&mock.into_context(), "//depot/...", Some(20)).unwrap();
+        assert_eq!(diff.outdated.len(), 1);
+        assert_eq!(diff.outdated[0].have.revision, 1.into());
+        assert_eq!(diff.outdated[0].target.revision, 3.into());
+        assert_eq!(
+            diff.added
+                .iter()
+                .map(|file| file.depot_path.as_str())
+                .collect::<Vec<_>>(),
+            ["//depot/new"]
+        );
+        assert_eq!(
+            diff.removed
+                .iter()
+                .map(|file| file.depot_path.as_str())
+                .collect::<Vec<_>>(),
+            ["//depot/gone", "//depot/unmapped"]
+        );
+    }
+
+    #[test]
+    fn test_sync_schedule() {
+        // 22:00 to 06:00 runs over midnight
+        let night = P4SyncWindow::new(22, 6);
+        assert!(night.contains(23 * 3600));
+        assert!(night.contains(3600));
+        assert!(!night.contains(12 * 3600));
+        assert_eq!(night.seconds_until_open(21 * 3600), 3600);
+        assert_eq!(night.seconds_until_open(7 * 3600), 15 * 3600);
+
+        let schedule = P4SyncSchedule {
+            windows: vec![night, P4SyncWindow::new(12, 13)],
+            utc_offset: 3600,
+            ..Default::default()
+        };
+        // 2025-04-07 10:30 UTC is 11:30 at the offset
+        assert_eq!(schedule.seconds_until_allowed(1744021800), 1800);
+        assert_eq!(
+            P4SyncSchedule::default().seconds_until_allowed(1744021800),
+            0
+        );
+
+        let control = P4SyncControl::default();
+        control.pause();
+        assert!(control.clone().is_paused());
+        control.resume();
+        assert!(!control.is_paused());
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_sync_scheduler() {
+        use crate::testing::*;
+
+        let file = |depot_path: &'static str, rev: &'static str, size: &'static str| {
+            [
+                ("code", "stat"),
+                ("depotFile", depot_path),
+                ("clientFile", "/ws/file"),
+                ("rev", rev),
+                ("action", "updated"),
+                ("fileSize", size),
+            ]
+        };
+        let mock = Arc::new(
+            MockP4::new()
+                .with_records(
+                    "sync -n //depot/...",
+                    [
+                        file("//depot/a.txt", "2", "60"),
+                        file("//depot/b.txt", "1", "60"),
+                        file("//depot/c.txt", "4", "10"),
+                    ],
+                )
+                .with_records("-x - sync", [file("//depot/a.txt", "2", "60")]),
+        );
+        let context = P4Context::new().with_backend(mock.clone());
+
+        let schedule = P4SyncSchedule {
+            batch_bytes: 100,
+            ..Default::default()
+        };
+        let log = ProgressLog::new();
+        let scheduler =
+            P4SyncScheduler::new(&context, "//depot/...", schedule).with_progress(log.clone());
+        let mut synced = 0;
+        let progress = scheduler.run(|_| synced += 1).unwrap();
+        assert_eq!(synced, 2);
+        assert_eq!(progress.files_total, Some(3));
+        assert_eq!(progress.bytes_total, Some(130));
+        assert_eq!(
+            log.updates(),
+            [
+                ("preview", 0, 0, None),
+                ("sync", 0, 0, None),
+                ("sync", 1, 60, Some("//depot/a.txt".to_string())),
+                ("sync", 2, 120, Some("//depot/a.txt".to_string()))
+            ]
+        );
+
+        // a and b fill the first batch, c is left for the second
+        assert_eq!(mock.calls().len(), 3);
+        assert_eq!(
+            mock.inputs(),
+            [
+                b"//depot/a.txt#2\n//depot/b.txt#1\n".to_vec(),
+                b"//depot/c.txt#4\n".to_vec()
+            ]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+// == Std crates
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// == Internal crates
+use crate::backend::*;
+use crate::context::*;
+use crate::error::*;
+use crate::parsers::py_dict::write_py_dict;
+
+// A fake p4 that serves canned -G output, so code built on the iterators can be tested without a server.
+// Responses are looked up by the full argument list first, then by the command name alone.
+#[derive(Debug, Default)]
+pub struct MockP4 {
+    responses: HashMap<String, Vec<u8>>,
+    calls: Mutex<Vec<Vec<String>>>,
+    inputs: Mutex<Vec<Vec<u8>>>,
+}
+
+impl MockP4 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `command` is either a command name ("changes") or a full argument list ("describe -s 5")
+    pub fn with_records<KeyT, ValueT>(
+        mut self,
+        command: &str,
+        records: impl IntoIterator<Item = impl IntoIterator<Item = (KeyT, ValueT)>>,
+    ) -> Self
+    where
+        KeyT: AsRef<[u8]>,
+        ValueT: AsRef<[u8]>,
+    {
+        let mut data = Vec::new();
+        for record in records {
+            write_py_dict(&mut data, record).expect("Writing to a Vec can't fail");
+        }
+        self.responses.insert(command.to_string(), data);
+        self
+    }
+
+    // Serves the raw bytes of a -G capture, e.g. one of the files in test_data
+    pub fn with_fixture(mut self, command: &str, path: impl AsRef<Path>) -> io::Result<Self> {
+        self.responses.insert(command.to_string(), fs::read(path)?);
+        Ok(self)
+    }
+
+    pub fn with_error(self, command: &str, severity: u32, generic: u32, data: &str) -> Self {
+        let (severity, generic) = (severity.to_string(), generic.to_string());
+        self.with_records(
+            command,
+            [[
+                ("code", "error"),
+                ("data", data),
+                ("severity", &severity),
+                ("generic", &generic),
+            ]],
+        )
+    }
+
+    // Every command that has been run against the mock, in order
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    // What was sent to the stdin of each command that read input, in order
+    pub fn inputs(&self) -> Vec<Vec<u8>> {
+        self.inputs.lock().unwrap().clone()
+    }
+
+    // A context serving all commands from this mock
+    pub fn into_context(self) -> P4Context {
+        self.into_shared_context().0
+    }
+
+    // Also returns the mock, for checking calls() and inputs() after running commands through the context
+    pub fn into_shared_context(self) -> (P4Context, Arc<MockP4>) {
+        let mock = Arc::new(self);
+        (P4Context::new().with_backend(mock.clone()), mock)
+    }
+}
+
+impl P4Backend for MockP4 {
+    fn run(&self, args: &[&str]) -> Result<Box<dyn io::Read + Send>, P4Error> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(args.iter().map(|arg| arg.to_string()).collect());
+
+        let response = self
+            .responses
+            .get(&args.join(" "))
+            .or_else(|| {
+                args.first()
+                    .and_then(|command| self.responses.get(*command))
+            })
+            .ok_or_else(|| {
+                P4Error::Spawn(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No mock response for '{}'", args.join(" ")),
+                ))
+            })?;
+
+        Ok(Box::new(io::Cursor::new(response.clone())))
+    }
+
+    // The input is recorded, responses are looked up the same way
+    fn run_with_input(
+        &self,
+        args: &[&str],
+        input: &[u8],
+    ) -> Result<Box<dyn io::Read + Send>, P4Error> {
+        self.inputs.lock().unwrap().push(input.to_vec());
+        self.run(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{changes::*, describe::*};
+
+    #[test]
+    fn test_mock_p4() {
+        let mock = MockP4::new()
+            .with_fixture("changes", "./test_data/changes.pyc")
+            .unwrap()
+            .with_fixture("describe -s 5", "./test_data/describe.pyc")
+            .unwrap()
+            .with_error("describe -s 6", 3, 0x11, "6 - no such changelist.\n");
+        let (context, mock) = mock.into_shared_context();
+
+        let changes = P4ChangesIterator::new_from_context(&context, None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(changes.len(), 8);
+
+        let describe = P4DescribeIterator::new_from_context(&context, 5).unwrap();
+        assert_eq!(describe.get_changelist().changelist, 5);
+        assert_eq!(describe.count(), 10);
+
+        match P4DescribeIterator::new_from_context(&context, 6) {
+            Err(P4Error::Server(message)) => assert_eq!(message.data, "6 - no such changelist.\n"),
+            _ => panic!("Expected a server error"),
+        }
+
+        assert!(P4DescribeIterator::new_from_context(&context, 7).is_err());
+        assert_eq!(context.metrics().snapshot().commands_run, 4);
+        assert_eq!(mock.calls().len(), 4);
+        assert_eq!(mock.calls()[1], ["describe", "-s", "5"]);
+    }
+}
@@ -0,0 +1,122 @@
+// == Std crates
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+const P4TICKETS_VAR: &str = "P4TICKETS";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct P4Ticket {
+    // As written by p4 login, usually host:port without the protocol
+    pub server: String,
+    pub user: String,
+    pub ticket: String,
+}
+
+// The tickets saved by p4 login, one `server=user:ticket` entry per line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct P4Tickets {
+    tickets: Vec<P4Ticket>,
+}
+
+impl P4Tickets {
+    // P4TICKETS if set, otherwise the default location for the platform
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os(P4TICKETS_VAR).filter(|path| !path.is_empty()) {
+            return Some(path.into());
+        }
+
+        if cfg!(windows) {
+            env::var_os("USERPROFILE").map(|home| Path::new(&home).join("p4tickets.txt"))
+        } else {
+            env::var_os("HOME").map(|home| Path::new(&home).join(".p4tickets"))
+        }
+    }
+
+    // Loads the tickets from the default location, no file means no tickets
+    pub fn load() -> io::Result<Self> {
+        match Self::default_path() {
+            Some(path) => match Self::read(path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+                result => result,
+            },
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let tickets = text
+            .lines()
+            .filter_map(|line| {
+                let (server, rest) = line.trim().split_once('=')?;
+                let (user, ticket) = rest.rsplit_once(':')?;
+                Some(P4Ticket {
+                    server: server.to_string(),
+                    user: user.to_string(),
+                    ticket: ticket.to_string(),
+                })
+            })
+            .collect();
+
+        P4Tickets { tickets }
+    }
+
+    pub fn tickets(&self) -> &[P4Ticket] {
+        &self.tickets
+    }
+
+    // `port` may be a full P4PORT value, the protocol prefix is ignored when matching
+    pub fn find(&self, port: &str, user: &str) -> Option<&P4Ticket> {
+        let server = strip_protocol(port);
+        self.tickets
+            .iter()
+            .find(|ticket| strip_protocol(&ticket.server) == server && ticket.user == user)
+    }
+
+    // Whether there is a ticket at all, it may still have expired on the server
+    pub fn has_ticket(&self, port: &str, user: &str) -> bool {
+        self.find(port, user).is_some()
+    }
+}
+
+fn strip_protocol(port: &str) -> &str {
+    match port.split_once(':') {
+        Some((
+            "tcp" | "tcp4" | "tcp6" | "tcp46" | "tcp64" | "ssl" | "ssl4" | "ssl6" | "ssl46"
+            | "ssl64",
+            address,
+        )) => address,
+        _ => port,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tickets() {
+        let tickets = P4Tickets::parse(
+            "perforce:1666=david:A9E93320E1FC469228D707C9124C878C\n\
+             10.0.0.5:1666=build:svc:0F1E2D3C4B5A69788796A5B4C3D2E1F0\n\
+             garbage\n",
+        );
+
+        assert_eq!(tickets.tickets().len(), 2);
+        assert_eq!(
+            tickets.find("ssl:perforce:1666", "david").unwrap().ticket,
+            "A9E93320E1FC469228D707C9124C878C"
+        );
+        assert_eq!(
+            tickets.find("10.0.0.5:1666", "build:svc").unwrap().user,
+            "build:svc"
+        );
+        assert!(!tickets.has_ticket("perforce:1666", "build:svc"));
+        assert!(!tickets.has_ticket("other:1666", "david"));
+    }
+}
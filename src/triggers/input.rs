@@ -0,0 +1,219 @@
+// == Std crates
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+// == Internal crates
+use crate::error::*;
+use crate::parsers::spec::*;
+use crate::*;
+
+// The trigger variables, passed on the trigger's command line as name=value arguments, e.g. a trigger table
+// entry of `check change-submit //... "/usr/bin/check change=%change% user=%user% formfile=%formfile%"`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4TriggerContext {
+    pub trigger_name: Option<String>,
+    pub trigger_type: Option<String>,
+    // None for a new changelist
+    pub change: Option<u32>,
+    pub user: Option<String>,
+    pub client: Option<String>,
+    pub client_host: Option<String>,
+    pub client_ip: Option<String>,
+    pub command: Option<String>,
+    pub server_port: Option<String>,
+    pub form_file: Option<PathBuf>,
+    pub form_name: Option<String>,
+    pub form_type: Option<String>,
+    // Any other name=value arguments, e.g. args or clientcwd
+    pub other: BTreeMap<String, String>,
+}
+
+impl P4TriggerContext {
+    // Usually from std::env::args().skip(1). Arguments without an = are ignored.
+    pub fn from_args<ArgT: AsRef<str>>(
+        args: impl IntoIterator<Item = ArgT>,
+    ) -> Result<Self, P4Error> {
+        let mut context = P4TriggerContext::default();
+        for arg in args {
+            let Some((name, value)) = arg.as_ref().split_once('=') else {
+                continue;
+            };
+            let value = value.to_string();
+            match name {
+                "triggername" => context.trigger_name = Some(value),
+                "triggertype" => context.trigger_type = Some(value),
+                "change" if value == "new" || value == "default" => context.change = None,
+                "change" => context.change = Some(parse_field(&value, "Invalid changelist")?),
+                "user" => context.user = Some(value),
+                "client" => context.client = Some(value),
+                "clienthost" => context.client_host = Some(value),
+                "clientip" => context.client_ip = Some(value),
+                "command" => context.command = Some(value),
+                "serverport" => context.server_port = Some(value),
+                "formfile" => context.form_file = Some(PathBuf::from(value)),
+                "formname" => context.form_name = Some(value),
+                "formtype" => context.form_type = Some(value),
+                _ => {
+                    context.other.insert(name.to_string(), value);
+                }
+            }
+        }
+        Ok(context)
+    }
+
+    // Reads %formfile%, for form-save, form-in and form-out triggers
+    pub fn read_form(&self) -> io::Result<P4Form> {
+        let path = self.form_file.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "The trigger wasn't given formfile=%formfile%",
+            )
+        })?;
+        Ok(P4Form::parse(&fs::read_to_string(path)?))
+    }
+
+    // Writes the form back for the server to use, for form-in and form-out triggers
+    pub fn write_form(&self, form: &P4Form) -> io::Result<()> {
+        let path = self.form_file.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "The trigger wasn't given formfile=%formfile%",
+            )
+        })?;
+        fs::write(path, form.to_text())
+    }
+}
+
+// A spec form with the fields -G would give, see parse_spec
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4Form {
+    pub fields: Vec<(String, String)>,
+}
+
+impl P4Form {
+    pub fn parse(text: &str) -> Self {
+        P4Form {
+            fields: parse_spec(text),
+        }
+    }
+
+    // e.g. the form a trigger gets on stdin
+    pub fn from_reader(mut reader: impl io::Read) -> io::Result<Self> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok(Self::parse(&text))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The entries of a list field, e.g. "Files" for Files0, Files1...
+    pub fn get_list(&self, key: &str) -> Vec<&str> {
+        (0..)
+            .map_while(|index| self.get(&format!("{}{}", key, index)))
+            .collect()
+    }
+
+    // Replaces the field, or adds it at the end
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.fields.iter_mut().find(|(name, _)| name == key) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.fields.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format_spec(
+            self.fields
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+    }
+
+    // The form as a change form
+    pub fn change(&self) -> Result<P4ChangeForm, P4Error> {
+        let change = match self.get("Change") {
+            None | Some("new") => None,
+            Some(change) => Some(parse_field(change, "Invalid changelist")?),
+        };
+        let to_strings = |values: Vec<&str>| values.into_iter().map(str::to_string).collect();
+        Ok(P4ChangeForm {
+            change,
+            client: self.get("Client").unwrap_or_default().to_string(),
+            user: self.get("User").unwrap_or_default().to_string(),
+            status: self.get("Status").unwrap_or_default().to_string(),
+            description: self.get("Description").unwrap_or_default().to_string(),
+            files: to_strings(self.get_list("Files")),
+            jobs: to_strings(self.get_list("Jobs")),
+        })
+    }
+}
+
+// The fields of a change form
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4ChangeForm {
+    // None for a new changelist
+    pub change: Option<u32>,
+    pub client: String,
+    pub user: String,
+    // pending, shelved or submitted
+    pub status: String,
+    pub description: String,
+    pub files: Vec<String>,
+    pub jobs: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger_input() {
+        let path = std::env::temp_dir().join(format!("p4_helper_form_{}", std::process::id()));
+        let args = [
+            "triggername=check".to_string(),
+            "change=1234".to_string(),
+            "user=alice".to_string(),
+            format!("formfile={}", path.display()),
+            "clientcwd=/home/alice".to_string(),
+            "ignored".to_string(),
+        ];
+        let context = P4TriggerContext::from_args(&args).unwrap();
+        assert_eq!(context.trigger_name.as_deref(), Some("check"));
+        assert_eq!(
+            (context.change, context.user.as_deref()),
+            (Some(1234), Some("alice"))
+        );
+        assert_eq!(
+            context.other.get("clientcwd").map(String::as_str),
+            Some("/home/alice")
+        );
+        assert!(P4TriggerContext::from_args(["change=abc"]).is_err());
+
+        fs::write(
+            &path,
+            "# A Perforce Change Specification.\n\nChange:\t1234\n\nClient:\tws\n\nUser:\talice\n\n\
+             Status:\tpending\n\nDescription:\n\tFix the loader\n\nFiles:\n\t//depot/a.txt\t# edit\n",
+        )
+        .unwrap();
+        let mut form = context.read_form().unwrap();
+        let change = form.change().unwrap();
+        assert_eq!(change.change, Some(1234));
+        assert_eq!(change.description, "Fix the loader\n");
+        assert_eq!(change.files, ["//depot/a.txt"]);
+        assert!(change.jobs.is_empty());
+
+        form.set("Description", "Fix the loader\n\nReviewed\n");
+        context.write_form(&form).unwrap();
+        let form = P4Form::from_reader(fs::File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            form.get("Description"),
+            Some("Fix the loader\n\nReviewed\n")
+        );
+        assert_eq!(form.get_list("Files"), ["//depot/a.txt"]);
+    }
+}
@@ -0,0 +1,2 @@
+// Helpers for writing server triggers against this crate
+pub mod input;
@@ -0,0 +1,547 @@
+// == Std crates
+use std::fmt;
+
+// == Internal crates
+use crate::error::*;
+use crate::filter::*;
+use crate::fstat::*;
+use crate::*;
+
+// A file of the changelist as the rules see it. Files from describe have no type, fstat's do.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4ValidatedFile {
+    pub depot_path: String,
+    pub action: String,
+    pub file_type: Option<String>,
+    // None when p4 didn't give the size, e.g. for files opened without `fstat -Ol`
+    pub file_size: Option<u64>,
+}
+
+impl From<&P4File> for P4ValidatedFile {
+    fn from(file: &P4File) -> Self {
+        P4ValidatedFile {
+            depot_path: file.depot_path.clone(),
+            action: file.action.clone(),
+            file_type: None,
+            file_size: file.file_size,
+        }
+    }
+}
+
+// For the files of a pending changelist, e.g. from `p4 fstat -e 1234 -Ol //...`
+impl From<&P4FstatEntry> for P4ValidatedFile {
+    fn from(entry: &P4FstatEntry) -> Self {
+        P4ValidatedFile {
+            depot_path: entry.depot_path.clone(),
+            action: entry
+                .action
+                .clone()
+                .or(entry.head_action.clone())
+                .unwrap_or_default(),
+            file_type: entry.file_type.clone().or(entry.head_type.clone()),
+            file_size: entry.file_size,
+        }
+    }
+}
+
+// A check run against each changelist. Returning messages rather than a bool lets one rule report several
+// problems, e.g. one per offending file.
+pub trait P4ValidationRule: Send + Sync {
+    // Shown in the report, e.g. "max-file-size"
+    fn name(&self) -> &str;
+
+    fn check_changelist(&self, _changelist: &P4Changelist) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn check_file(&self, _file: &P4ValidatedFile) -> Option<String> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4Violation {
+    pub rule: String,
+    // None for rules about the changelist as a whole
+    pub depot_path: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for P4Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.depot_path {
+            Some(depot_path) => write!(f, "{}: {}: {}", self.rule, depot_path, self.message),
+            None => write!(f, "{}: {}", self.rule, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4ValidationReport {
+    pub changelist: u32,
+    pub files_checked: u64,
+    // In the order the rules were added, changelist checks before file checks
+    pub violations: Vec<P4Violation>,
+}
+
+impl P4ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+// The rules a changelist has to pass, e.g. in a change-submit trigger or a review bot
+#[derive(Default)]
+pub struct P4ChangelistValidator {
+    rules: Vec<Box<dyn P4ValidationRule>>,
+}
+
+impl P4ChangelistValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: impl P4ValidationRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    // The description has to match `pattern`, a regex subset: literals, ., [a-z] and [^...] classes, \d \w \s
+    // and their negations, the * + ? quantifiers and ^ $ anchors. Unanchored patterns match anywhere.
+    pub fn with_description_pattern(self, pattern: &str) -> Result<Self, P4ValueParseError> {
+        Ok(self.with_rule(DescriptionRule {
+            source: pattern.to_string(),
+            pattern: Pattern::new(pattern)?,
+        }))
+    }
+
+    // No file may match the filespec, e.g. //depot/main/.../*.tmp
+    pub fn with_forbidden_path(self, filespec: &str) -> Self {
+        self.with_rule(ForbiddenPathRule(filespec.to_string()))
+    }
+
+    // Deleted files are not checked
+    pub fn with_max_file_size(self, bytes: u64) -> Self {
+        self.with_rule(MaxFileSizeRule(bytes))
+    }
+
+    // Files matching the filespec need the file type, e.g. ("//....png", "binary+l"). Files of unknown type
+    // are not checked.
+    pub fn with_required_file_type(self, filespec: &str, file_type: &str) -> Self {
+        self.with_rule(RequiredFileTypeRule {
+            filespec: filespec.to_string(),
+            file_type: file_type.to_string(),
+        })
+    }
+
+    pub fn validate<FileT: Into<P4ValidatedFile>>(
+        &self,
+        changelist: &P4Changelist,
+        files: impl IntoIterator<Item = FileT>,
+    ) -> P4ValidationReport {
+        let mut report = P4ValidationReport {
+            changelist: changelist.changelist,
+            ..Default::default()
+        };
+        for rule in &self.rules {
+            for message in rule.check_changelist(changelist) {
+                report.violations.push(P4Violation {
+                    rule: rule.name().to_string(),
+                    depot_path: None,
+                    message,
+                });
+            }
+        }
+        for file in files {
+            let file = file.into();
+            report.files_checked += 1;
+            for rule in &self.rules {
+                if let Some(message) = rule.check_file(&file) {
+                    report.violations.push(P4Violation {
+                        rule: rule.name().to_string(),
+                        depot_path: Some(file.depot_path.clone()),
+                        message,
+                    });
+                }
+            }
+        }
+        report
+    }
+
+    // Checks the changelist's own files
+    pub fn validate_changelist(&self, changelist: &P4Changelist) -> P4ValidationReport {
+        self.validate(changelist, &changelist.files)
+    }
+}
+
+struct DescriptionRule {
+    source: String,
+    pattern: Pattern,
+}
+
+impl P4ValidationRule for DescriptionRule {
+    fn name(&self) -> &str {
+        "description"
+    }
+
+    fn check_changelist(&self, changelist: &P4Changelist) -> Vec<String> {
+        if self.pattern.is_match(&changelist.description) {
+            return Vec::new();
+        }
+        vec![format!("Description doesn't match '{}'", self.source)]
+    }
+}
+
+struct ForbiddenPathRule(String);
+
+impl P4ValidationRule for ForbiddenPathRule {
+    fn name(&self) -> &str {
+        "forbidden-path"
+    }
+
+    fn check_file(&self, file: &P4ValidatedFile) -> Option<String> {
+        matches_filespec(&self.0, &file.depot_path)
+            .then(|| format!("Files under {} can't be submitted", self.0))
+    }
+}
+
+struct MaxFileSizeRule(u64);
+
+impl P4ValidationRule for MaxFileSizeRule {
+    fn name(&self) -> &str {
+        "max-file-size"
+    }
+
+    // A file whose size isn't known fails, rather than passing unchecked
+    fn check_file(&self, file: &P4ValidatedFile) -> Option<String> {
+        if is_deleted_action(&file.action) {
+            return None;
+        }
+        match file.file_size {
+            Some(file_size) => (file_size > self.0)
+                .then(|| format!("{} bytes is over the limit of {}", file_size, self.0)),
+            None => Some(format!("Size unknown, the limit is {}", self.0)),
+        }
+    }
+}
+
+struct RequiredFileTypeRule {
+    filespec: String,
+    file_type: String,
+}
+
+impl P4ValidationRule for RequiredFileTypeRule {
+    fn name(&self) -> &str {
+        "file-type"
+    }
+
+    fn check_file(&self, file: &P4ValidatedFile) -> Option<String> {
+        let file_type = file.file_type.as_deref()?;
+        (matches_filespec(&self.filespec, &file.depot_path) && file_type != self.file_type).then(
+            || {
+                format!(
+                    "Type is {}, files under {} need {}",
+                    file_type, self.filespec, self.file_type
+                )
+            },
+        )
+    }
+}
+
+// A regex, with the regex feature
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+struct Pattern(regex::Regex);
+
+#[cfg(feature = "regex")]
+impl Pattern {
+    fn new(pattern: &str) -> Result<Self, P4ValueParseError> {
+        regex::Regex::new(pattern)
+            .map(Pattern)
+            .map_err(|_| P4ValueParseError::InvalidPattern(pattern.to_string(), "invalid regex"))
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+#[derive(Debug, Clone)]
+enum Atom {
+    Char(char),
+    Any,
+    // Ranges, and whether the class is negated
+    Class(Vec<(char, char)>, bool),
+}
+
+#[cfg(not(feature = "regex"))]
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => c == *expected,
+            Atom::Any => c != '\n',
+            Atom::Class(ranges, negated) => {
+                ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+// How an atom repeats, + is an atom followed by the same atom repeated
+#[cfg(not(feature = "regex"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    Once,
+    Optional,
+    Any,
+}
+
+// The small regex subset with_description_pattern accepts without the regex feature
+#[cfg(not(feature = "regex"))]
+#[derive(Debug, Clone)]
+struct Pattern {
+    items: Vec<(Atom, Repeat)>,
+    anchored_start: bool,
+    anchored_end: bool,
+}
+
+#[cfg(not(feature = "regex"))]
+impl Pattern {
+    fn new(pattern: &str) -> Result<Self, P4ValueParseError> {
+        let invalid = |reason| P4ValueParseError::InvalidPattern(pattern.to_string(), reason);
+        let mut chars = pattern.chars().peekable();
+        let anchored_start = chars.next_if_eq(&'^').is_some();
+        let mut anchored_end = false;
+        let mut items: Vec<(Atom, Repeat)> = Vec::new();
+
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '$' if chars.peek().is_none() => {
+                    anchored_end = true;
+                    break;
+                }
+                '.' => Atom::Any,
+                '\\' => escape(chars.next().ok_or_else(|| invalid("trailing \\"))?),
+                '[' => {
+                    let negated = chars.next_if_eq(&'^').is_some();
+                    let mut ranges = Vec::new();
+                    loop {
+                        let low = match chars.next().ok_or_else(|| invalid("unterminated ["))? {
+                            ']' if !ranges.is_empty() => break,
+                            '\\' => {
+                                match escape(chars.next().ok_or_else(|| invalid("trailing \\"))?) {
+                                    Atom::Char(c) => c,
+                                    Atom::Class(class, false) => {
+                                        ranges.extend(class);
+                                        continue;
+                                    }
+                                    _ => return Err(invalid("negated classes can't be nested")),
+                                }
+                            }
+                            c => c,
+                        };
+                        let high = if chars.next_if_eq(&'-').is_some() {
+                            match chars.next() {
+                                Some(']') | None => return Err(invalid("unterminated range")),
+                                Some(high) => high,
+                            }
+                        } else {
+                            low
+                        };
+                        ranges.push((low, high));
+                    }
+                    Atom::Class(ranges, negated)
+                }
+                '*' | '+' | '?' => {
+                    let last = items
+                        .last_mut()
+                        .filter(|(_, repeat)| *repeat == Repeat::Once);
+                    let Some((atom, repeat)) = last else {
+                        return Err(invalid("quantifier without something to repeat"));
+                    };
+                    match c {
+                        '*' => *repeat = Repeat::Any,
+                        '?' => *repeat = Repeat::Optional,
+                        _ => {
+                            let atom = atom.clone();
+                            items.push((atom, Repeat::Any));
+                        }
+                    }
+                    continue;
+                }
+                '(' | ')' | '|' | '{' => {
+                    return Err(invalid("groups, alternation and counts aren't supported"));
+                }
+                c => Atom::Char(c),
+            };
+            items.push((atom, Repeat::Once));
+        }
+
+        Ok(Pattern {
+            items,
+            anchored_start,
+            anchored_end,
+        })
+    }
+
+    // Whether the items from each one on match the text from each position, worked out from the end so it
+    // takes time in proportion to the items times the text rather than backtracking
+    fn is_match(&self, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        let length = text.len();
+        let mut matches = vec![vec![false; length + 1]; self.items.len() + 1];
+        for (position, matched) in matches[self.items.len()].iter_mut().enumerate() {
+            *matched = !self.anchored_end || position == length;
+        }
+        for (index, (atom, repeat)) in self.items.iter().enumerate().rev() {
+            for position in (0..=length).rev() {
+                let takes = text.get(position).is_some_and(|c| atom.matches(*c));
+                matches[index][position] = match repeat {
+                    Repeat::Once => takes && matches[index + 1][position + 1],
+                    Repeat::Optional => {
+                        matches[index + 1][position] || (takes && matches[index + 1][position + 1])
+                    }
+                    Repeat::Any => {
+                        matches[index + 1][position] || (takes && matches[index][position + 1])
+                    }
+                };
+            }
+        }
+
+        if self.anchored_start {
+            return matches[0][0];
+        }
+        matches[0].iter().any(|matched| *matched)
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+fn escape(c: char) -> Atom {
+    let digits = vec![('0', '9')];
+    let word = vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+    let space = vec![(' ', ' '), ('\t', '\r')];
+    match c {
+        'd' => Atom::Class(digits, false),
+        'D' => Atom::Class(digits, true),
+        'w' => Atom::Class(word, false),
+        'W' => Atom::Class(word, true),
+        's' => Atom::Class(space, false),
+        'S' => Atom::Class(space, true),
+        'n' => Atom::Char('\n'),
+        't' => Atom::Char('\t'),
+        c => Atom::Char(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let file = |depot_path: &str, action: &str, file_size: u64| P4File {
+            depot_path: depot_path.to_string(),
+            action: action.to_string(),
+            file_size: Some(file_size),
+            ..Default::default()
+        };
+        // A Monday
+        let changelist = P4Changelist {
+            changelist: 42,
+            time: 1743984000,
+            description: "Fix the loader\n\nJIRA-17\n".to_string(),
+            files: vec![
+                file("//depot/main/src/loader.rs", "edit", 2_000),
+                file("//depot/main/build/out.tmp", "add", 10),
+                file("//depot/main/assets/huge.bin", "add", 5_000_000),
+                file("//depot/main/assets/old.bin", "delete", 9_000_000),
+            ],
+            ..Default::default()
+        };
+
+        struct NoMondays;
+        impl P4ValidationRule for NoMondays {
+            fn name(&self) -> &str {
+                "no-mondays"
+            }
+            fn check_changelist(&self, changelist: &P4Changelist) -> Vec<String> {
+                let monday = (changelist.time / 86400 + 3).is_multiple_of(7);
+                monday
+                    .then(|| "Not on a Monday".to_string())
+                    .into_iter()
+                    .collect()
+            }
+        }
+
+        let error = P4ChangelistValidator::new()
+            .with_description_pattern(r"^[A-Z][^\n]+\n(.|\n*")
+            .map(drop)
+            .unwrap_err();
+        assert!(matches!(error, P4ValueParseError::InvalidPattern(..)));
+
+        let validator = P4ChangelistValidator::new()
+            .with_description_pattern(r"\n[A-Z]+-\d+\s*$")
+            .unwrap()
+            .with_forbidden_path("//depot/....tmp")
+            .with_max_file_size(1_000_000)
+            .with_required_file_type("//....bin", "binary+l")
+            .with_rule(NoMondays);
+
+        let report = validator.validate_changelist(&changelist);
+        assert_eq!(report.changelist, 42);
+        assert_eq!(report.files_checked, 4);
+        let violations = report
+            .violations
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            violations,
+            [
+                "no-mondays: Not on a Monday",
+                "forbidden-path: //depot/main/build/out.tmp: Files under //depot/....tmp can't be submitted",
+                "max-file-size: //depot/main/assets/huge.bin: 5000000 bytes is over the limit of 1000000",
+            ]
+        );
+        assert!(!report.passed());
+
+        // With types, from fstat
+        let typed = [
+            P4FstatEntry {
+                depot_path: "//depot/main/assets/huge.bin".to_string(),
+                action: Some("add".to_string()),
+                file_type: Some("binary".to_string()),
+                file_size: Some(10),
+                ..Default::default()
+            },
+            // Without -Ol there's no size, which fails the limit rather than passing it
+            P4FstatEntry {
+                depot_path: "//depot/main/readme.txt".to_string(),
+                action: Some("edit".to_string()),
+                file_type: Some("text".to_string()),
+                ..Default::default()
+            },
+        ];
+        let changelist = P4Changelist {
+            time: 1743984000 + 86400,
+            description: "No issue key".to_string(),
+            ..changelist
+        };
+        let report = validator.validate(&changelist, &typed);
+        let rules = report
+            .violations
+            .iter()
+            .map(|violation| violation.rule.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(rules, ["description", "file-type", "max-file-size"]);
+
+        let pattern = Pattern::new("^a[b-d]?x*\\.$").unwrap();
+        assert!(pattern.is_match("a.") && pattern.is_match("acxx.") && !pattern.is_match("aex."));
+        let pattern = Pattern::new("[A-Z]+-\\d+$").unwrap();
+        assert!(pattern.is_match("Fix\nABC-12") && !pattern.is_match("ABC-12 "));
+        // Repeats that can't match don't backtrack
+        let pattern = Pattern::new(&format!("^{}b", "a*".repeat(30))).unwrap();
+        assert!(!pattern.is_match(&"a".repeat(100)));
+    }
+}
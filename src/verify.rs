@@ -0,0 +1,360 @@
+// == Std crates
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    sync::{Mutex, mpsc},
+    thread,
+};
+
+// == Internal crates
+use crate::client::*;
+use crate::context::*;
+use crate::error::*;
+use crate::filespec::*;
+use crate::fstat::*;
+use crate::*;
+
+// == External crates
+use md5::{Digest, Md5};
+
+// A synced file that doesn't match the revision the workspace has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4WorkspaceFile {
+    pub depot_path: String,
+    pub local_path: String,
+    pub revision: Revision,
+}
+
+// What `p4 diff -se` and `p4 diff -sd` would report, sorted by depot path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4WorkspaceReport {
+    // Files that were hashed and matched
+    pub verified: u64,
+    // Changed without being opened, -se
+    pub modified: Vec<P4WorkspaceFile>,
+    // Deleted without being opened, -sd
+    pub missing: Vec<P4WorkspaceFile>,
+    // Present but not hashed, see P4WorkspaceVerifier
+    pub unchecked: Vec<P4WorkspaceFile>,
+    // Opened in the client, which p4 diff -se and -sd leave out, so not compared
+    pub opened: Vec<P4WorkspaceFile>,
+}
+
+impl P4WorkspaceReport {
+    pub fn is_clean(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty()
+    }
+}
+
+// Compares the files synced to the workspace with the digests of their have revisions, hashing them on
+// several threads. Symlinks, utf16 and +k files are stored differently from how they are synced, so those
+// are only checked for being there and reported as unchecked.
+pub struct P4WorkspaceVerifier {
+    context: P4Context,
+    filespec: String,
+    concurrency: usize,
+    line_end: Option<P4LineEnd>,
+}
+
+// Verifies the files with a thread per core, e.g. for //my-client/...
+#[cfg(feature = "spawn")]
+pub fn verify_workspace(filespec: &str) -> Result<P4WorkspaceReport, P4Error> {
+    verify_workspace_from_context(&P4Context::default(), filespec)
+}
+
+pub fn verify_workspace_from_context(
+    context: &P4Context,
+    filespec: &str,
+) -> Result<P4WorkspaceReport, P4Error> {
+    P4WorkspaceVerifier::new(context, filespec).run()
+}
+
+enum FileCheck {
+    Verified,
+    Modified,
+    Missing,
+    Unchecked,
+    Opened,
+}
+
+impl P4WorkspaceVerifier {
+    pub fn new(context: &P4Context, filespec: &str) -> Self {
+        P4WorkspaceVerifier {
+            context: context.clone(),
+            filespec: filespec.to_string(),
+            concurrency: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            line_end: None,
+        }
+    }
+
+    // Number of files hashed at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    // The line endings text files were synced with, the LineEnd of the client spec if not set
+    pub fn with_line_end(mut self, line_end: P4LineEnd) -> Self {
+        self.line_end = Some(line_end);
+        self
+    }
+
+    pub fn run(&self) -> Result<P4WorkspaceReport, P4Error> {
+        let line_end = match self.line_end {
+            Some(line_end) => line_end,
+            None => fetch_current_from_context(&self.context)?.line_end,
+        };
+
+        // The have revision, its digest and the local path of each file, checked as they arrive
+        let have_filespec = format!("{}#have", self.filespec);
+        let query = P4FstatQuery::new(&have_filespec)
+            .with_file_details()
+            .with_local_paths();
+
+        let report = Mutex::new(P4WorkspaceReport::default());
+        let (sender, receiver) = mpsc::sync_channel::<P4FstatEntry>(self.concurrency * 16);
+        let receiver = Mutex::new(receiver);
+        thread::scope(|scope| -> Result<(), P4Error> {
+            for _ in 0..self.concurrency {
+                scope.spawn(|| {
+                    loop {
+                        let next = receiver.lock().expect("Receiver lock poisoned").recv();
+                        let Ok(entry) = next else {
+                            break;
+                        };
+                        let check = match (&entry.action, &entry.local_path) {
+                            (Some(_), _) => FileCheck::Opened,
+                            (None, Some(local_path)) => check_file(
+                                local_path,
+                                entry.digest,
+                                entry.head_type.as_deref(),
+                                line_end,
+                            ),
+                            (None, None) => FileCheck::Unchecked,
+                        };
+                        report
+                            .lock()
+                            .expect("Report lock poisoned")
+                            .add(entry, check);
+                    }
+                });
+            }
+
+            // Dropping the sender, also on an error, ends the workers
+            let sender = sender;
+            for entry in P4FstatIterator::new_from_context(&self.context, query)? {
+                let entry = entry?;
+                // Synced to #none, so there's nothing on disk to check
+                if matches!(entry.have_rev, None | Some(Revision::None)) {
+                    continue;
+                }
+                if sender.send(entry).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })?;
+
+        let mut report = report.into_inner().expect("Report lock poisoned");
+        for files in [
+            &mut report.modified,
+            &mut report.missing,
+            &mut report.unchecked,
+            &mut report.opened,
+        ] {
+            files.sort_by(|a, b| a.depot_path.cmp(&b.depot_path));
+        }
+        Ok(report)
+    }
+}
+
+impl P4WorkspaceReport {
+    fn add(&mut self, entry: P4FstatEntry, check: FileCheck) {
+        let file = P4WorkspaceFile {
+            depot_path: entry.depot_path,
+            local_path: entry.local_path.unwrap_or_default(),
+            revision: entry.have_rev.unwrap_or(Revision::None),
+        };
+        match check {
+            FileCheck::Verified => self.verified += 1,
+            FileCheck::Modified => self.modified.push(file),
+            FileCheck::Missing => self.missing.push(file),
+            FileCheck::Unchecked => self.unchecked.push(file),
+            FileCheck::Opened => self.opened.push(file),
+        }
+    }
+}
+
+fn check_file(
+    local_path: &str,
+    digest: Option<[u8; 16]>,
+    file_type: Option<&str>,
+    line_end: P4LineEnd,
+) -> FileCheck {
+    let path = Path::new(local_path);
+    if fs::symlink_metadata(path).is_err() {
+        return FileCheck::Missing;
+    }
+    let file_type = file_type.unwrap_or_default();
+    let base_type = file_type
+        .split_once('+')
+        .map_or(file_type, |(base_type, _)| base_type);
+    let Some(digest) = digest else {
+        return FileCheck::Unchecked;
+    };
+    if !digest_matches_content(file_type) {
+        return FileCheck::Unchecked;
+    }
+
+    let is_text = base_type.contains("text") || base_type == "unicode" || base_type == "utf8";
+    let line_end = if is_text { line_end.bytes() } else { b"\n" };
+    match hash_file(path, line_end) {
+        Ok(local_digest) if local_digest == digest => FileCheck::Verified,
+        Ok(_) => FileCheck::Modified,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => FileCheck::Missing,
+        Err(_) => FileCheck::Unchecked,
+    }
+}
+
+// Text is hashed the way the server stores it, with the client's CRLF or CR line endings turned back into LF
+fn hash_file(path: &Path, line_end: &[u8]) -> io::Result<[u8; 16]> {
+    let mut file = fs::File::open(path)?;
+    let mut md5 = Md5::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut pending_cr = false;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        let chunk = &buffer[..read];
+        match line_end {
+            b"\r\n" => {}
+            b"\r" => {
+                let normalized = chunk.iter().map(|&byte| match byte {
+                    b'\r' => b'\n',
+                    byte => byte,
+                });
+                md5.update(normalized.collect::<Vec<_>>());
+                continue;
+            }
+            _ => {
+                md5.update(chunk);
+                continue;
+            }
+        }
+
+        let mut normalized = Vec::with_capacity(chunk.len() + 1);
+        if pending_cr && chunk[0] != b'\n' {
+            normalized.push(b'\r');
+        }
+        pending_cr = false;
+        for (index, &byte) in chunk.iter().enumerate() {
+            if byte != b'\r' {
+                normalized.push(byte);
+            } else if index + 1 == chunk.len() {
+                pending_cr = true;
+            } else if chunk[index + 1] != b'\n' {
+                normalized.push(byte);
+            }
+        }
+        md5.update(&normalized);
+    }
+    if pending_cr {
+        md5.update(b"\r");
+    }
+    Ok(md5.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_verify_workspace() {
+        let dir = std::env::temp_dir().join(format!("p4_helper_verify_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("a.txt"), "hello\n").unwrap();
+        fs::write(path("b.txt"), "changed\n").unwrap();
+        fs::write(path("k.txt"), "$Id$\n").unwrap();
+        fs::write(path("o.txt"), "edited\n").unwrap();
+
+        // The digest of "hello\n"
+        let hello = "B1946AC92492D2347C6235B4D2611184";
+        let fstat = |name: &str, file_type: &str| {
+            vec![
+                ("code", "stat".to_string()),
+                ("depotFile", format!("//depot/{}", name)),
+                ("path", path(name)),
+                ("haveRev", "1".to_string()),
+                ("headType", file_type.to_string()),
+                ("digest", hello.to_string()),
+            ]
+        };
+        let mut opened = fstat("o.txt", "text");
+        opened.push(("action", "edit".to_string()));
+        let context = MockP4::new()
+            .with_records(
+                "client -o",
+                [[
+                    ("code", "stat"),
+                    ("Client", "my-client"),
+                    ("LineEnd", "unix"),
+                ]],
+            )
+            .with_records(
+                "fstat -Olp //depot/...#have",
+                [
+                    fstat("a.txt", "text"),
+                    fstat("b.txt", "text"),
+                    fstat("c.txt", "binary"),
+                    fstat("k.txt", "text+k"),
+                    opened,
+                ],
+            )
+            .into_context();
+
+        let report = P4WorkspaceVerifier::new(&context, "//depot/...")
+            .with_concurrency(2)
+            .run()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.verified, 1);
+        let paths = |files: &[P4WorkspaceFile]| {
+            files
+                .iter()
+                .map(|file| file.depot_path.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(&report.modified), ["//depot/b.txt"]);
+        assert_eq!(paths(&report.missing), ["//depot/c.txt"]);
+        assert_eq!(paths(&report.unchecked), ["//depot/k.txt"]);
+        assert_eq!(paths(&report.opened), ["//depot/o.txt"]);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn test_hash_file_line_endings() {
+        let dir = std::env::temp_dir().join(format!("p4_helper_hash_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hash = |content: &[u8], line_end: P4LineEnd| {
+            let path = dir.join("a.txt");
+            fs::write(&path, content).unwrap();
+            const_hex::encode(hash_file(&path, line_end.bytes()).unwrap())
+        };
+
+        // The digest of "a\nb\n"
+        let unix = "dd8c6a395b5dd36c56d23275028f526c";
+        assert_eq!(hash(b"a\nb\n", P4LineEnd::Unix), unix);
+        assert_eq!(hash(b"a\r\nb\r\n", P4LineEnd::Win), unix);
+        assert_eq!(hash(b"a\rb\r", P4LineEnd::Mac), unix);
+        // A CR that isn't part of a line ending is kept
+        assert_ne!(hash(b"a\rb\r\n", P4LineEnd::Win), unix);
+        assert_ne!(hash(b"a\r\nb\r\n", P4LineEnd::Unix), unix);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
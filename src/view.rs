@@ -0,0 +1,365 @@
+// == Std crates
+use std::{fmt, str::FromStr};
+
+// == Internal crates
+use crate::error::*;
+
+// One line of a client, branch or label view, e.g. `-//depot/main/docs/... //ws/docs/...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4ViewLine {
+    pub left: String,
+    pub right: String,
+    // A - line, paths it matches are unmapped
+    pub exclude: bool,
+    // A + line, which adds to the lines above rather than replacing them
+    pub overlay: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wildcard {
+    Dots,
+    Star,
+    // %%1 to %%9, which match like *
+    Positional(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Literal(&'a str),
+    Wildcard(Wildcard),
+}
+
+impl P4ViewLine {
+    pub fn new(left: &str, right: &str) -> Self {
+        P4ViewLine {
+            left: left.to_string(),
+            right: right.to_string(),
+            exclude: false,
+            overlay: false,
+        }
+    }
+
+    // The right side of the line for a path its left side matches
+    pub fn translate(&self, path: &str) -> Option<String> {
+        let left = tokenize(&self.left);
+        let captures = match_tokens(&left, path)?;
+
+        // The nth ... or * on the right takes what the nth on the left matched, %%n takes what %%n matched
+        let captured = wildcards(&left)
+            .into_iter()
+            .zip(captures)
+            .collect::<Vec<_>>();
+        let mut translated = String::new();
+        let (mut dots, mut stars) = (0, 0);
+        for token in tokenize(&self.right) {
+            let wildcard = match token {
+                Token::Literal(literal) => {
+                    translated.push_str(literal);
+                    continue;
+                }
+                Token::Wildcard(wildcard) => wildcard,
+            };
+            let occurrence = match wildcard {
+                Wildcard::Dots => &mut dots,
+                Wildcard::Star => &mut stars,
+                Wildcard::Positional(_) => &mut 0,
+            };
+            let (_, value) = captured
+                .iter()
+                .filter(|(left_wildcard, _)| *left_wildcard == wildcard)
+                .nth(*occurrence)?;
+            *occurrence += 1;
+            translated.push_str(value);
+        }
+        Some(translated)
+    }
+
+    // The same line mapping right to left
+    pub fn reversed(&self) -> Self {
+        P4ViewLine {
+            left: self.right.clone(),
+            right: self.left.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+fn wildcards(tokens: &[Token<'_>]) -> Vec<Wildcard> {
+    tokens
+        .iter()
+        .filter_map(|token| match token {
+            Token::Wildcard(wildcard) => Some(*wildcard),
+            Token::Literal(_) => None,
+        })
+        .collect()
+}
+
+fn tokenize(pattern: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut literal_start = 0;
+    let mut index = 0;
+    let bytes = pattern.as_bytes();
+    while index < bytes.len() {
+        let wildcard = if pattern[index..].starts_with("...") {
+            Some((Wildcard::Dots, 3))
+        } else if bytes[index] == b'*' {
+            Some((Wildcard::Star, 1))
+        } else if pattern[index..].starts_with("%%")
+            && bytes.get(index + 2).is_some_and(u8::is_ascii_digit)
+        {
+            Some((Wildcard::Positional(bytes[index + 2] - b'0'), 3))
+        } else {
+            None
+        };
+
+        match wildcard {
+            Some((wildcard, length)) => {
+                if literal_start < index {
+                    tokens.push(Token::Literal(&pattern[literal_start..index]));
+                }
+                tokens.push(Token::Wildcard(wildcard));
+                index += length;
+                literal_start = index;
+            }
+            None => index += 1,
+        }
+    }
+    if literal_start < pattern.len() {
+        tokens.push(Token::Literal(&pattern[literal_start..]));
+    }
+    tokens
+}
+
+// Whether a path matches a pattern with ..., * or %%n wildcards, e.g. the filespec of a filter
+pub(crate) fn matches_pattern(pattern: &str, path: &str) -> bool {
+    match_tokens(&tokenize(pattern), path).is_some()
+}
+
+// What each wildcard matched, in the order they appear. ... prefers the shortest match, as the filespec filters
+// do. Whether the tokens from each one on match the path from each position is worked out once, from the end,
+// so patterns with many wildcards don't backtrack.
+fn match_tokens<'a>(tokens: &[Token<'_>], path: &'a str) -> Option<Vec<&'a str>> {
+    let length = path.len();
+    let mut matches = vec![vec![false; length + 1]; tokens.len() + 1];
+    matches[tokens.len()][length] = true;
+    for (index, token) in tokens.iter().enumerate().rev() {
+        for position in (0..=length)
+            .rev()
+            .filter(|&position| path.is_char_boundary(position))
+        {
+            matches[index][position] = match token {
+                Token::Literal(literal) => {
+                    path[position..].starts_with(literal)
+                        && matches[index + 1][position + literal.len()]
+                }
+                // Nothing, or the next character and the wildcard again
+                Token::Wildcard(wildcard) => {
+                    matches[index + 1][position]
+                        || next_position(*wildcard, path, position)
+                            .is_some_and(|next| matches[index][next])
+                }
+            };
+        }
+    }
+    if !matches[0][0] {
+        return None;
+    }
+
+    let mut captures = Vec::new();
+    let mut position = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Literal(literal) => position += literal.len(),
+            Token::Wildcard(wildcard) => {
+                let start = position;
+                while !matches[index + 1][position] {
+                    position = next_position(*wildcard, path, position)?;
+                }
+                captures.push(&path[start..position]);
+            }
+        }
+    }
+    Some(captures)
+}
+
+// After the character at `position`, when the wildcard can take it
+fn next_position(wildcard: Wildcard, path: &str, position: usize) -> Option<usize> {
+    let next = path[position..].chars().next()?;
+    (wildcard == Wildcard::Dots || next != '/').then_some(position + next.len_utf8())
+}
+
+impl FromStr for P4ViewLine {
+    type Err = P4ValueParseError;
+
+    // `//depot/main/... //depot/rel/...`, with either side in double quotes if it has spaces
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| P4ValueParseError::InvalidViewLine(line.to_string(), reason);
+
+        let line = line.trim();
+        let (exclude, overlay, rest) = match line.as_bytes().first() {
+            Some(b'-') => (true, false, &line[1..]),
+            Some(b'+') => (false, true, &line[1..]),
+            _ => (false, false, line),
+        };
+
+        let mut sides = Vec::new();
+        let mut rest = rest.trim_start();
+        while !rest.is_empty() {
+            let (side, remainder) = match rest.strip_prefix('"') {
+                Some(quoted) => quoted
+                    .split_once('"')
+                    .ok_or_else(|| invalid("unterminated quote"))?,
+                None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+            };
+            sides.push(side);
+            rest = remainder.trim_start();
+        }
+        let [left, right] = sides[..] else {
+            return Err(invalid("expected a left and a right side"));
+        };
+
+        // Each side needs the same wildcards, though they may be in a different order
+        let sorted_wildcards = |side| {
+            let mut wildcards = wildcards(&tokenize(side))
+                .into_iter()
+                .map(|wildcard| match wildcard {
+                    Wildcard::Dots => 0,
+                    Wildcard::Star => 1,
+                    Wildcard::Positional(n) => 2 + n,
+                })
+                .collect::<Vec<_>>();
+            wildcards.sort();
+            wildcards
+        };
+        if sorted_wildcards(left) != sorted_wildcards(right) {
+            return Err(invalid("the wildcards on each side don't match"));
+        }
+
+        Ok(P4ViewLine {
+            left: left.to_string(),
+            right: right.to_string(),
+            exclude,
+            overlay,
+        })
+    }
+}
+
+impl fmt::Display for P4ViewLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = match (self.exclude, self.overlay) {
+            (true, _) => "-",
+            (_, true) => "+",
+            _ => "",
+        };
+        let quote = |side: &str| {
+            if side.contains(' ') {
+                format!("\"{}\"", side)
+            } else {
+                side.to_string()
+            }
+        };
+        write!(f, "{}{} {}", prefix, quote(&self.left), quote(&self.right))
+    }
+}
+
+// The lines of a view in order, where a later line takes precedence over the lines above it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct P4View {
+    pub lines: Vec<P4ViewLine>,
+}
+
+impl P4View {
+    pub fn from_lines<'a>(
+        lines: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, P4ValueParseError> {
+        Ok(P4View {
+            lines: lines
+                .into_iter()
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    // Where the path maps to, by the last line whose left side matches it. None when that line is an
+    // exclusion or no line matches.
+    pub fn translate(&self, path: &str) -> Option<String> {
+        self.lines.iter().rev().find_map(|line| {
+            let translated = line.translate(path)?;
+            Some((!line.exclude).then_some(translated))
+        })?
+    }
+
+    // The view mapping right to left, e.g. from a branch's target back to its source
+    pub fn reversed(&self) -> Self {
+        P4View {
+            lines: self.lines.iter().map(P4ViewLine::reversed).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_view_translate() {
+        let view = P4View::from_lines([
+            "//depot/main/... //depot/rel/...",
+            "-//depot/main/docs/... //depot/rel/docs/...",
+            "//depot/main/docs/README //depot/rel/docs/README",
+            "//depot/main/*.txt //depot/rel/text/*.txt",
+            "//depot/main/%%1/%%2.c //depot/rel/src/%%2/%%1.c",
+            "\"//depot/main/a b/...\" \"//depot/rel/a c/...\"",
+        ])
+        .unwrap();
+
+        let translate = |path: &str| view.translate(path);
+        assert_eq!(
+            translate("//depot/main/src/a.rs").as_deref(),
+            Some("//depot/rel/src/a.rs")
+        );
+        assert_eq!(translate("//depot/main/docs/guide.md"), None);
+        assert_eq!(
+            translate("//depot/main/docs/README").as_deref(),
+            Some("//depot/rel/docs/README")
+        );
+        assert_eq!(
+            translate("//depot/main/notes.txt").as_deref(),
+            Some("//depot/rel/text/notes.txt")
+        );
+        assert_eq!(
+            translate("//depot/main/lib/util.c").as_deref(),
+            Some("//depot/rel/src/util/lib.c")
+        );
+        assert_eq!(
+            translate("//depot/main/a b/x.txt").as_deref(),
+            Some("//depot/rel/a c/x.txt")
+        );
+        assert_eq!(translate("//depot/dev/a.rs"), None);
+
+        assert_eq!(
+            view.reversed().translate("//depot/rel/src/a.rs").as_deref(),
+            Some("//depot/main/src/a.rs")
+        );
+        assert_eq!(
+            view.lines[1].to_string(),
+            "-//depot/main/docs/... //depot/rel/docs/..."
+        );
+        assert_eq!(
+            view.lines[5].to_string(),
+            "\"//depot/main/a b/...\" \"//depot/rel/a c/...\""
+        );
+
+        assert!(
+            "//depot/main/... //depot/rel/*"
+                .parse::<P4ViewLine>()
+                .is_err()
+        );
+        assert!("//depot/main/...".parse::<P4ViewLine>().is_err());
+        assert!(
+            "\"//depot/main/... //depot/rel/..."
+                .parse::<P4ViewLine>()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// == Std crates
+use std::{collections::VecDeque, sync::Arc, thread};
+
+// == Internal crates
+use crate::budget::*;
+use crate::context::*;
+use crate::depots::*;
+use crate::dirs::*;
+use crate::error::*;
+use crate::files::*;
+use crate::progress::*;
+
+// Walks the depot breadth first with `p4 dirs` and `p4 files`, one directory level per command, so a
+// subtree can be inventoried without a workspace. Files are yielded as their directory is listed.
+pub struct DepotWalker {
+    context: P4Context,
+    // Directories still to list, with their depth below the root
+    queue: VecDeque<(String, u32)>,
+    pending: VecDeque<P4DepotFile>,
+    max_depth: Option<u32>,
+    prefixes: Vec<String>,
+    concurrency: usize,
+    include_deleted: bool,
+    failed: bool,
+    // What the queued directories and files hold of the memory budget
+    reservation: BudgetReservation,
+    progress: ProgressTracker,
+}
+
+impl DepotWalker {
+    // `root` is a depot path without a wildcard, e.g. //depot or //depot/main, or // for every depot
+    pub fn new(context: &P4Context, root: &str) -> Self {
+        DepotWalker {
+            context: context.clone(),
+            queue: VecDeque::from([(root.trim_end_matches('/').to_string(), 0)]),
+            pending: VecDeque::new(),
+            max_depth: None,
+            prefixes: Vec::new(),
+            concurrency: 1,
+            include_deleted: false,
+            failed: false,
+            reservation: context.budget_reservation(P4BudgetResource::QueuedRecords),
+            progress: ProgressTracker::default(),
+        }
+    }
+
+    // Files directly in the root are at depth 0
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    // Only descend into (and yield files from) paths under one of the prefixes, e.g. //depot/main/
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefixes.push(prefix.to_string());
+        self
+    }
+
+    // Number of directories listed at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    // Also yield files whose head revision is deleted
+    pub fn with_deleted(mut self, include_deleted: bool) -> Self {
+        self.include_deleted = include_deleted;
+        self
+    }
+
+    // Reports each file as it is yielded
+    pub fn with_progress(mut self, sink: impl ProgressSink + 'static) -> Self {
+        self.progress = ProgressTracker::new(Some(Arc::new(sink)));
+        self
+    }
+
+    fn path_allowed(&self, path: &str) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| path.starts_with(prefix))
+    }
+
+    // A directory is worth listing if it is under a prefix, or a prefix is under it
+    fn dir_allowed(&self, dir: &str) -> bool {
+        self.prefixes.is_empty()
+            || self.prefixes.iter().any(|prefix| {
+                let dir = format!("{}/", dir);
+                dir.starts_with(prefix.as_str()) || prefix.starts_with(&dir)
+            })
+    }
+
+    // Lists one directory, reserving each kept file and subdirectory before holding on to it
+    fn list_dir(&self, dir: &str, depth: u32) -> Result<P4WalkListing, P4Error> {
+        let mut listing = P4WalkListing {
+            files: Vec::new(),
+            dirs: Vec::new(),
+            reservation: self
+                .context
+                .budget_reservation(P4BudgetResource::QueuedRecords),
+        };
+        // Below // there are only depots, and no files
+        if dir.is_empty() {
+            for depot in P4DepotsIterator::new_from_context(&self.context)? {
+                let depot = format!("//{}", depot?.name);
+                if self.dir_allowed(&depot) {
+                    listing.reserve_one()?;
+                    listing.dirs.push(depot);
+                }
+            }
+            return Ok(listing);
+        }
+
+        let filespec = format!("{}/*", dir);
+        for file in P4FilesIterator::new_from_context(&self.context, &filespec)? {
+            let file = file?;
+            if (self.include_deleted || !file.is_deleted()) && self.path_allowed(&file.depot_path) {
+                listing.reserve_one()?;
+                listing.files.push(file);
+            }
+        }
+        if self.max_depth.is_none_or(|max_depth| depth < max_depth) {
+            for dir in P4DirsIterator::new_from_context(&self.context, &filespec)? {
+                let dir = dir?;
+                if self.dir_allowed(&dir) {
+                    listing.reserve_one()?;
+                    listing.dirs.push(dir);
+                }
+            }
+        }
+        Ok(listing)
+    }
+
+    fn list_next_level(&mut self) -> Result<(), P4Error> {
+        let count = self.concurrency.min(self.queue.len());
+        let batch = self.queue.drain(..count).collect::<Vec<_>>();
+
+        let walker = &*self;
+        let results = thread::scope(|scope| {
+            let handles = batch
+                .iter()
+                .map(|(dir, depth)| scope.spawn(move || walker.list_dir(dir, *depth)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Listing thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        // Results are handled in queue order, so the walk stays breadth first
+        for ((_, depth), result) in batch.into_iter().zip(results) {
+            let listing = result?;
+            self.reservation.absorb(listing.reservation);
+            self.pending.extend(listing.files);
+            self.queue
+                .extend(listing.dirs.into_iter().map(|dir| (dir, depth + 1)));
+        }
+        Ok(())
+    }
+}
+
+struct P4WalkListing {
+    files: Vec<P4DepotFile>,
+    dirs: Vec<String>,
+    reservation: BudgetReservation,
+}
+
+impl P4WalkListing {
+    fn reserve_one(&mut self) -> Result<(), P4Error> {
+        let held = self.files.len() + self.dirs.len() + 1;
+        self.reservation
+            .resize(held as u64)
+            .map_err(P4Error::BudgetExceeded)
+    }
+}
+
+impl Iterator for DepotWalker {
+    type Item = Result<P4DepotFile, P4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(file) = self.pending.pop_front() {
+                let _ = self
+                    .reservation
+                    .resize((self.queue.len() + self.pending.len()) as u64);
+                self.progress.ensure_phase("walk");
+                self.progress.record(&file.depot_path, 0);
+                return Some(Ok(file));
+            }
+            if self.failed || self.queue.is_empty() {
+                return None;
+            }
+            if let Err(e) = self.list_next_level() {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_depot_walker() {
+        let file = |path: &str, action: &str| {
+            [
+                ("code", "stat".to_string()),
+                ("depotFile", path.to_string()),
+                ("rev", "1".to_string()),
+                ("change", "1".to_string()),
+                ("action", action.to_string()),
+                ("type", "text".to_string()),
+                ("time", "1743724741".to_string()),
+            ]
+        };
+        let dir = |path: &'static str| [("code", "stat"), ("dir", path)];
+        let none = [[
+            ("code", "error"),
+            ("data", "no such file(s).\n"),
+            ("severity", "2"),
+            ("generic", "17"),
+        ]];
+
+        let mock = MockP4::new()
+            .with_records(
+                "depots",
+                [[("code", "stat"), ("name", "depot"), ("type", "local")]],
+            )
+            .with_records("files //depot/*", [file("//depot/readme", "add")])
+            .with_records("dirs //depot/*", [dir("//depot/main"), dir("//depot/rel")])
+            .with_records(
+                "files //depot/main/*",
+                [
+                    file("//depot/main/a", "add"),
+                    file("//depot/main/old", "delete"),
+                ],
+            )
+            .with_records("dirs //depot/main/*", [dir("//depot/main/src")])
+            .with_records("files //depot/rel/*", [file("//depot/rel/b", "add")])
+            .with_records("dirs //depot/rel/*", none)
+            .with_records(
+                "files //depot/main/src/*",
+                [file("//depot/main/src/c", "add")],
+            )
+            .with_records("dirs //depot/main/src/*", none);
+        let context = mock.into_context();
+
+        let paths = |walker: DepotWalker| {
+            walker
+                .map(|file| file.unwrap().depot_path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            paths(DepotWalker::new(&context, "//depot").with_concurrency(4)),
+            [
+                "//depot/readme",
+                "//depot/main/a",
+                "//depot/rel/b",
+                "//depot/main/src/c"
+            ]
+        );
+        assert_eq!(
+            paths(DepotWalker::new(&context, "//").with_max_depth(1)),
+            ["//depot/readme"]
+        );
+        assert_eq!(
+            paths(DepotWalker::new(&context, "//depot").with_max_depth(1)),
+            ["//depot/readme", "//depot/main/a", "//depot/rel/b"]
+        );
+        assert_eq!(
+            paths(DepotWalker::new(&context, "//depot").with_prefix("//depot/main/")),
+            ["//depot/main/a", "//depot/main/src/c"]
+        );
+
+        // The root level holds a file and two directories, one more than the budget
+        let budgeted = context
+            .clone()
+            .with_memory_budget(MemoryBudget::new().with_max_queued_records(2));
+        let mut walker = DepotWalker::new(&budgeted, "//depot");
+        assert!(matches!(
+            walker.next(),
+            Some(Err(P4Error::BudgetExceeded(_)))
+        ));
+        assert_eq!(budgeted.memory_budget().unwrap().queued_records(), 0);
+
+        let progress = ProgressLog::new();
+        let walker = DepotWalker::new(&context, "//depot")
+            .with_max_depth(0)
+            .with_progress(progress.clone());
+        assert_eq!(paths(walker), ["//depot/readme"]);
+        assert_eq!(
+            progress.updates(),
+            [
+                ("walk", 0, 0, None),
+                ("walk", 1, 0, Some("//depot/readme".to_string()))
+            ]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+// == Std crates
+use std::{fs, io, path::PathBuf};
+
+// == Internal crates
+use crate::changes::*;
+#[cfg(feature = "process")]
+use crate::context::*;
+#[cfg(feature = "process")]
+use crate::dict::*;
+use crate::error::*;
+#[cfg(feature = "process")]
+use crate::*;
+
+// Where a P4Watermark keeps the last changelist processed
+pub trait P4WatermarkStore: Send {
+    // None before anything was stored
+    fn load(&mut self) -> Result<Option<u32>, P4Error>;
+    fn store(&mut self, changelist: u32) -> Result<(), P4Error>;
+}
+
+// A file holding the changelist number. Each store writes a temporary file next to it and renames it over
+// the old one, so a crash leaves either the old or the new value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P4FileWatermarkStore {
+    path: PathBuf,
+}
+
+impl P4FileWatermarkStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        P4FileWatermarkStore { path: path.into() }
+    }
+}
+
+impl P4WatermarkStore for P4FileWatermarkStore {
+    fn load(&mut self) -> Result<Option<u32>, P4Error> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let changelist = text.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid watermark in {}: {}",
+                    self.path.display(),
+                    text.trim()
+                ),
+            )
+        })?;
+        Ok(Some(changelist))
+    }
+
+    fn store(&mut self, changelist: u32) -> Result<(), P4Error> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, format!("{}\n", changelist))?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+// A counter on the server, see `p4 help counter`, so any machine running the job picks up where the last
+// one stopped. Counters read as 0 until set, which is taken as nothing stored.
+#[cfg(feature = "process")]
+#[derive(Clone)]
+pub struct P4CounterWatermarkStore {
+    context: P4Context,
+    name: String,
+}
+
+#[cfg(feature = "process")]
+impl P4CounterWatermarkStore {
+    pub fn new(context: &P4Context, name: &str) -> Self {
+        P4CounterWatermarkStore {
+            context: context.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    fn run(&self, args: Vec<&str>) -> Result<Option<u32>, P4Error> {
+        let mut value = None;
+        for record in P4DictIterator::new_from_context(&self.context, args)? {
+            if let Some(counter_value) = record?.get("value") {
+                value = Some(parse_field(counter_value, "Invalid counter value")?);
+            }
+        }
+        Ok(value.filter(|value| *value != 0))
+    }
+}
+
+#[cfg(feature = "process")]
+impl P4WatermarkStore for P4CounterWatermarkStore {
+    fn load(&mut self) -> Result<Option<u32>, P4Error> {
+        self.run(vec!["counter", &self.name])
+    }
+
+    fn store(&mut self, changelist: u32) -> Result<(), P4Error> {
+        self.run(vec!["counter", &self.name, &changelist.to_string()])
+            .map(|_| ())
+    }
+}
+
+// The newest changelist a job has finished with. Each changelist is committed once it has been processed,
+// so after a crash the job starts again at the first changelist it hadn't finished.
+pub struct P4Watermark {
+    store: Box<dyn P4WatermarkStore>,
+    last: Option<u32>,
+}
+
+impl P4Watermark {
+    // Loads the last changelist from the store
+    pub fn new(mut store: impl P4WatermarkStore + 'static) -> Result<Self, P4Error> {
+        let last = store.load()?;
+        Ok(P4Watermark {
+            store: Box::new(store),
+            last,
+        })
+    }
+
+    pub fn last(&self) -> Option<u32> {
+        self.last
+    }
+
+    // Records the changelist as processed. Changelists at or below the watermark are ignored, so it never
+    // moves backwards.
+    pub fn commit(&mut self, changelist: u32) -> Result<(), P4Error> {
+        if self.last.is_some_and(|last| changelist <= last) {
+            return Ok(());
+        }
+        self.store.store(changelist)?;
+        self.last = Some(changelist);
+        Ok(())
+    }
+
+    // The query limited to the changelists after the watermark, oldest first
+    pub fn pending_query(&self, query: P4ChangesQuery) -> P4ChangesQuery {
+        let start = self.last.map_or(1, |last| last.saturating_add(1));
+        query.with_range(start..u32::MAX).with_oldest_first()
+    }
+
+    // Calls `process` with each changelist after the watermark from oldest to newest, committing each one it
+    // returns Ok for. Stops at the first error, which is returned with the watermark at the changelist
+    // before it.
+    #[cfg(feature = "process")]
+    pub fn for_each_new(
+        &mut self,
+        context: &P4Context,
+        query: P4ChangesQuery,
+        mut process: impl FnMut(&P4Changelist) -> Result<(), P4Error>,
+    ) -> Result<u64, P4Error> {
+        let mut processed = 0;
+        for changelist in P4ChangesIterator::new_from_context(context, self.pending_query(query))? {
+            let changelist = changelist?;
+            process(&changelist)?;
+            self.commit(changelist.changelist)?;
+            processed += 1;
+        }
+        Ok(processed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_watermark() {
+        let path = std::env::temp_dir().join(format!("p4_helper_watermark_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut watermark = P4Watermark::new(P4FileWatermarkStore::new(&path)).unwrap();
+        assert_eq!(watermark.last(), None);
+        watermark.commit(120).unwrap();
+        watermark.commit(110).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "120\n");
+
+        let watermark = P4Watermark::new(P4FileWatermarkStore::new(&path)).unwrap();
+        assert_eq!(watermark.last(), Some(120));
+        assert_eq!(
+            watermark.pending_query(P4ChangesQuery::new()),
+            P4ChangesQuery::new()
+                .with_range(121..u32::MAX)
+                .with_oldest_first()
+        );
+
+        fs::write(&path, "not a number").unwrap();
+        assert!(P4Watermark::new(P4FileWatermarkStore::new(&path)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "process")]
+    fn test_counter_watermark() {
+        use crate::testing::*;
+        use std::sync::Arc;
+
+        let change = |change: &'static str| {
+            [
+                ("code", "stat"),
+                ("change", change),
+                ("time", "1743900000"),
+                ("user", "alice"),
+                ("desc", "Fix the build"),
+            ]
+        };
+        let mock = Arc::new(
+            MockP4::new()
+                .with_records(
+                    "counter mirror",
+                    [[("counter", "mirror"), ("value", "100")]],
+                )
+                .with_records("counter", [[("counter", "mirror"), ("value", "0")]])
+                .with_records("changes", [change("101"), change("102"), change("103")]),
+        );
+        let context = P4Context::new().with_backend(mock.clone());
+
+        let store = P4CounterWatermarkStore::new(&context, "mirror");
+        let mut watermark = P4Watermark::new(store).unwrap();
+        assert_eq!(watermark.last(), Some(100));
+
+        let mut seen = Vec::new();
+        let result = watermark.for_each_new(&context, P4ChangesQuery::new(), |changelist| {
+            if changelist.changelist == 103 {
+                return Err(P4Error::InvalidRecord("Downstream failed"));
+            }
+            seen.push(changelist.changelist);
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(seen, [101, 102]);
+        assert_eq!(watermark.last(), Some(102));
+
+        let calls = mock
+            .calls()
+            .into_iter()
+            .map(|call| call.join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(calls[0], "counter mirror");
+        assert!(calls[1].starts_with("changes") && calls[1].contains("@101,"));
+        assert_eq!(&calls[2..], ["counter mirror 101", "counter mirror 102"]);
+    }
+}